@@ -20,6 +20,15 @@ impl BenchDB {
         Self { _dir: dir, db }
     }
 
+    /// Creates a database without caching of index metadata.
+    pub(crate) fn without_metadata_cache() -> Self {
+        let Self { _dir, db } = Self::new();
+        Self {
+            _dir,
+            db: db.without_metadata_cache(),
+        }
+    }
+
     pub(crate) fn fork(&self) -> Fork {
         self.db.fork()
     }
@@ -186,6 +186,23 @@ impl<T: Access> WrapperSchema<T> {
     }
 }
 
+impl<T: Access> WrapperSchema<T> {
+    /// Reads data touched by the transaction, returning the number of read entries.
+    fn read(&self, transaction: &Transaction) -> u64 {
+        let mut count = u64::from(self.transactions().contains(&12));
+        for &divisor in DIVISORS {
+            let group_id = transaction.value % divisor;
+            count += self.hot_group(group_id).len();
+            count += u64::from(self.hot_index().contains(&group_id));
+
+            let cold_group_id = transaction.value % COLD_DIVISOR;
+            count += self.cold_group(cold_group_id).len();
+            count += u64::from(self.cold_index().contains(&cold_group_id));
+        }
+        count + u64::from(self.other_cold_index().contains(&transaction.value))
+    }
+}
+
 impl<T: Access> WrapperSchema<T>
 where
     T::Base: RawAccessMut,
@@ -239,11 +256,21 @@ fn gen_random_transactions(count: usize) -> Vec<Transaction> {
         .collect()
 }
 
-fn bench<T: ExecuteTransaction>(bencher: &mut Bencher<'_>, prefixed: bool) {
+/// Creates a database with or without caching of index metadata.
+fn create_db(cached: bool) -> BenchDB {
+    if cached {
+        BenchDB::new()
+    } else {
+        BenchDB::without_metadata_cache()
+    }
+}
+
+fn bench<T: ExecuteTransaction>(bencher: &mut Bencher<'_>, prefixed: bool, cached: bool) {
     const PREFIX: &str = "moderately_long_prefix";
 
     let transactions = gen_random_transactions(TX_COUNT);
-    bencher.iter_with_setup(BenchDB::default, |db| {
+    let setup = || create_db(cached);
+    bencher.iter_with_setup(setup, |db| {
         let fork = db.fork();
         if prefixed {
             for transaction in &transactions {
@@ -259,19 +286,62 @@ fn bench<T: ExecuteTransaction>(bencher: &mut Bencher<'_>, prefixed: bool) {
     })
 }
 
+/// Benchmarks reading from the database after the transactions were executed. If `cached`
+/// is set, index metadata is cached across snapshots of the same database state.
+fn bench_read(bencher: &mut Bencher<'_>, snapshot_per_tx: bool, cached: bool) {
+    let transactions = gen_random_transactions(TX_COUNT);
+    let db = create_db(cached);
+    let fork = db.fork();
+    for transaction in &transactions {
+        WrapperStyle::execute(&fork, transaction);
+    }
+    db.merge(fork.into_patch()).unwrap();
+
+    bencher.iter(|| {
+        let snapshot = db.snapshot();
+        for transaction in &transactions {
+            let count = if snapshot_per_tx {
+                WrapperSchema::new(black_box(db.snapshot().as_ref())).read(transaction)
+            } else {
+                WrapperSchema::new(black_box(snapshot.as_ref())).read(transaction)
+            };
+            black_box(count);
+        }
+    })
+}
+
 pub fn bench_schema_patterns(c: &mut Criterion) {
     let mut group = c.benchmark_group("schema_patterns");
-    group.bench_function("eager", |b| bench::<EagerStyle>(b, false));
-    group.bench_function("lazy", |b| bench::<LazyStyle>(b, false));
-    group.bench_function("wrapper", |b| bench::<WrapperStyle>(b, false));
+    group.bench_function("eager", |b| bench::<EagerStyle>(b, false, true));
+    group.bench_function("eager_uncached", |b| bench::<EagerStyle>(b, false, false));
+    group.bench_function("lazy", |b| bench::<LazyStyle>(b, false, true));
+    group.bench_function("lazy_uncached", |b| bench::<LazyStyle>(b, false, false));
+    group.bench_function("wrapper", |b| bench::<WrapperStyle>(b, false, true));
+    group.bench_function("wrapper_uncached", |b| {
+        bench::<WrapperStyle>(b, false, false)
+    });
     group.throughput(Throughput::Elements(TX_COUNT as u64));
     group.sample_size(SAMPLE_SIZE);
     group.finish();
 
     let mut group = c.benchmark_group("schema_patterns/prefixed");
-    group.bench_function("eager", |b| bench::<EagerStyle>(b, true));
-    group.bench_function("lazy", |b| bench::<LazyStyle>(b, true));
-    group.bench_function("wrapper", |b| bench::<WrapperStyle>(b, true));
+    group.bench_function("eager", |b| bench::<EagerStyle>(b, true, true));
+    group.bench_function("eager_uncached", |b| bench::<EagerStyle>(b, true, false));
+    group.bench_function("lazy", |b| bench::<LazyStyle>(b, true, true));
+    group.bench_function("lazy_uncached", |b| bench::<LazyStyle>(b, true, false));
+    group.bench_function("wrapper", |b| bench::<WrapperStyle>(b, true, true));
+    group.bench_function("wrapper_uncached", |b| {
+        bench::<WrapperStyle>(b, true, false)
+    });
+    group.throughput(Throughput::Elements(TX_COUNT as u64));
+    group.sample_size(SAMPLE_SIZE);
+    group.finish();
+
+    let mut group = c.benchmark_group("schema_patterns/read");
+    group.bench_function("single_snapshot", |b| bench_read(b, false, true));
+    group.bench_function("single_snapshot_uncached", |b| bench_read(b, false, false));
+    group.bench_function("snapshot_per_tx", |b| bench_read(b, true, true));
+    group.bench_function("snapshot_per_tx_uncached", |b| bench_read(b, true, false));
     group.throughput(Throughput::Elements(TX_COUNT as u64));
     group.sample_size(SAMPLE_SIZE);
     group.finish();
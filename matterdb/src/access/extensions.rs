@@ -3,8 +3,8 @@
 use crate::{
     access::{Access, FromAccess},
     views::IndexType,
-    BinaryKey, BinaryValue, Entry, Group, IndexAddress, KeySetIndex, ListIndex, MapIndex,
-    SparseListIndex,
+    BinaryKey, BinaryValue, BitmapIndex, BlobIndex, CounterIndex, Entry, Group, IndexAddress,
+    KeySetIndex, ListIndex, MapIndex, MultiMapIndex, SparseListIndex,
 };
 
 /// Extension trait allowing for easy access to indexes from any type implementing
@@ -138,6 +138,61 @@ pub trait CopyAccessExt: Access + Copy {
             .unwrap_or_else(|e| panic!("MerkleDB error: {}", e))
     }
 
+    /// Gets a counter index with the specified address.
+    ///
+    /// # Panics
+    ///
+    /// If the index exists, but is not a counter index.
+    fn get_counter<I, K>(self, addr: I) -> CounterIndex<Self::Base, K>
+    where
+        I: Into<IndexAddress>,
+        K: BinaryKey + ?Sized,
+    {
+        CounterIndex::from_access(self, addr.into())
+            .unwrap_or_else(|e| panic!("MerkleDB error: {}", e))
+    }
+
+    /// Gets a bitmap index with the specified address.
+    ///
+    /// # Panics
+    ///
+    /// If the index exists, but is not a bitmap index.
+    fn get_bitmap<I>(self, addr: I) -> BitmapIndex<Self::Base>
+    where
+        I: Into<IndexAddress>,
+    {
+        BitmapIndex::from_access(self, addr.into())
+            .unwrap_or_else(|e| panic!("MerkleDB error: {}", e))
+    }
+
+    /// Gets a blob index with the specified address.
+    ///
+    /// # Panics
+    ///
+    /// If the index exists, but is not a blob index.
+    fn get_blob<I>(self, addr: I) -> BlobIndex<Self::Base>
+    where
+        I: Into<IndexAddress>,
+    {
+        BlobIndex::from_access(self, addr.into())
+            .unwrap_or_else(|e| panic!("MerkleDB error: {}", e))
+    }
+
+    /// Gets a multimap index with the specified address.
+    ///
+    /// # Panics
+    ///
+    /// If the index exists, but is not a multimap index.
+    fn get_multimap<I, K, V>(self, addr: I) -> MultiMapIndex<Self::Base, K, V>
+    where
+        I: Into<IndexAddress>,
+        K: BinaryKey + ?Sized,
+        V: BinaryKey + ?Sized,
+    {
+        MultiMapIndex::from_access(self, addr.into())
+            .unwrap_or_else(|e| panic!("MerkleDB error: {}", e))
+    }
+
     /// Gets index type at the specified address, or `None` if there is no index.
     fn index_type<I>(self, addr: I) -> Option<IndexType>
     where
@@ -252,6 +307,61 @@ pub trait AccessExt: Access {
             .unwrap_or_else(|e| panic!("MerkleDB error: {}", e))
     }
 
+    /// Gets a counter index with the specified address.
+    ///
+    /// # Panics
+    ///
+    /// If the index exists, but is not a counter index.
+    fn get_counter<I, K>(&self, addr: I) -> CounterIndex<Self::Base, K>
+    where
+        I: Into<IndexAddress>,
+        K: BinaryKey + ?Sized,
+    {
+        CounterIndex::from_access(self.clone(), addr.into())
+            .unwrap_or_else(|e| panic!("MerkleDB error: {}", e))
+    }
+
+    /// Gets a bitmap index with the specified address.
+    ///
+    /// # Panics
+    ///
+    /// If the index exists, but is not a bitmap index.
+    fn get_bitmap<I>(&self, addr: I) -> BitmapIndex<Self::Base>
+    where
+        I: Into<IndexAddress>,
+    {
+        BitmapIndex::from_access(self.clone(), addr.into())
+            .unwrap_or_else(|e| panic!("MerkleDB error: {}", e))
+    }
+
+    /// Gets a blob index with the specified address.
+    ///
+    /// # Panics
+    ///
+    /// If the index exists, but is not a blob index.
+    fn get_blob<I>(&self, addr: I) -> BlobIndex<Self::Base>
+    where
+        I: Into<IndexAddress>,
+    {
+        BlobIndex::from_access(self.clone(), addr.into())
+            .unwrap_or_else(|e| panic!("MerkleDB error: {}", e))
+    }
+
+    /// Gets a multimap index with the specified address.
+    ///
+    /// # Panics
+    ///
+    /// If the index exists, but is not a multimap index.
+    fn get_multimap<I, K, V>(&self, addr: I) -> MultiMapIndex<Self::Base, K, V>
+    where
+        I: Into<IndexAddress>,
+        K: BinaryKey + ?Sized,
+        V: BinaryKey + ?Sized,
+    {
+        MultiMapIndex::from_access(self.clone(), addr.into())
+            .unwrap_or_else(|e| panic!("MerkleDB error: {}", e))
+    }
+
     /// Gets index type at the specified address, or `None` if there is no index.
     fn index_type<I>(&self, addr: I) -> Option<IndexType>
     where
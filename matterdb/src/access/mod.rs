@@ -43,13 +43,13 @@
 
 use thiserror::Error;
 
-use std::fmt;
+use std::{fmt, ops::RangeBounds};
 
 pub use self::extensions::{AccessExt, CopyAccessExt};
 pub use crate::views::{AsReadonly, RawAccess, RawAccessMut};
 
 use crate::{
-    validation::assert_valid_name_component,
+    validation::{assert_valid_name_component, check_index_valid_full_name},
     views::{GroupKeys, IndexAddress, IndexMetadata, IndexType, ViewWithMetadata},
     BinaryKey,
 };
@@ -97,16 +97,36 @@ pub trait Access: Clone {
         index_type: IndexType,
     ) -> Result<ViewWithMetadata<Self::Base>, AccessError>;
 
-    /// Returns an iterator over keys in a group with the specified address.
+    /// Returns an iterator over keys in a group with the specified address. Only keys
+    /// falling into the specified `range` are returned.
     ///
-    /// The iterator buffers keys in memory and may become inconsistent for accesses
-    /// based on [`ReadonlyFork`].
-    ///
-    /// [`ReadonlyFork`]: ../struct.ReadonlyFork.html
-    fn group_keys<K>(self, base_addr: IndexAddress) -> GroupKeys<Self::Base, K>
+    /// The iterator buffers keys in memory and may become inconsistent if indexes
+    /// in the group are created or removed while the iteration is in progress.
+    fn group_keys<K, R>(self, base_addr: IndexAddress, range: R) -> GroupKeys<Self::Base, K>
     where
         K: BinaryKey + ?Sized,
-        Self::Base: AsReadonly<Readonly = Self::Base>;
+        R: RangeBounds<K>;
+
+    /// Removes an index with the specified address together with its data. Indexes
+    /// in a group with the same address are not affected. If the index does not exist,
+    /// this method does nothing.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the removed index is borrowed.
+    fn remove_index(self, addr: IndexAddress) -> Result<(), AccessError>
+    where
+        Self::Base: RawAccessMut;
+
+    /// Removes all indexes in a group with the specified address together with their data.
+    /// This includes indexes in nested groups.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the removed indexes are borrowed.
+    fn remove_group(self, base_addr: IndexAddress) -> Result<(), AccessError>
+    where
+        Self::Base: RawAccessMut;
 }
 
 impl<T: RawAccess> Access for T {
@@ -124,12 +144,36 @@ impl<T: RawAccess> Access for T {
         ViewWithMetadata::get_or_create(self, &addr, index_type)
     }
 
-    fn group_keys<K>(self, base_addr: IndexAddress) -> GroupKeys<Self::Base, K>
+    fn group_keys<K, R>(self, base_addr: IndexAddress, range: R) -> GroupKeys<Self::Base, K>
     where
         K: BinaryKey + ?Sized,
-        Self::Base: AsReadonly<Readonly = Self::Base>,
+        R: RangeBounds<K>,
+    {
+        GroupKeys::with_range(self, &base_addr, range)
+    }
+
+    fn remove_index(self, addr: IndexAddress) -> Result<(), AccessError>
+    where
+        Self::Base: RawAccessMut,
     {
-        GroupKeys::new(self, &base_addr)
+        check_index_valid_full_name(addr.name()).map_err(|kind| AccessError {
+            addr: addr.clone(),
+            kind,
+        })?;
+        ViewWithMetadata::remove_unchecked(self, &addr);
+        Ok(())
+    }
+
+    fn remove_group(self, base_addr: IndexAddress) -> Result<(), AccessError>
+    where
+        Self::Base: RawAccessMut,
+    {
+        check_index_valid_full_name(base_addr.name()).map_err(|kind| AccessError {
+            addr: base_addr.clone(),
+            kind,
+        })?;
+        ViewWithMetadata::remove_group_unchecked(&self, &base_addr);
+        Ok(())
     }
 }
 
@@ -207,13 +251,29 @@ impl<T: RawAccess> Access for Prefixed<T> {
         self.access.get_or_create_view(prefixed_addr, index_type)
     }
 
-    fn group_keys<K>(self, base_addr: IndexAddress) -> GroupKeys<Self::Base, K>
+    fn group_keys<K, R>(self, base_addr: IndexAddress, range: R) -> GroupKeys<Self::Base, K>
     where
         K: BinaryKey + ?Sized,
-        Self::Base: AsReadonly<Readonly = Self::Base>,
+        R: RangeBounds<K>,
+    {
+        let prefixed_addr = base_addr.prepend_name(self.prefix.as_ref());
+        self.access.group_keys(prefixed_addr, range)
+    }
+
+    fn remove_index(self, addr: IndexAddress) -> Result<(), AccessError>
+    where
+        Self::Base: RawAccessMut,
+    {
+        let prefixed_addr = addr.prepend_name(self.prefix.as_ref());
+        self.access.remove_index(prefixed_addr)
+    }
+
+    fn remove_group(self, base_addr: IndexAddress) -> Result<(), AccessError>
+    where
+        Self::Base: RawAccessMut,
     {
         let prefixed_addr = base_addr.prepend_name(self.prefix.as_ref());
-        self.access.group_keys(prefixed_addr)
+        self.access.remove_group(prefixed_addr)
     }
 }
 
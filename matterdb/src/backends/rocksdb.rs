@@ -10,21 +10,41 @@ pub mod backup {
 
 use crossbeam::sync::{ShardedLock, ShardedLockReadGuard};
 use rocksdb::{
-    self, checkpoint::Checkpoint, Cache as RocksDBCache, ColumnFamily, DBIterator,
-    Options as RocksDBOptions, WriteBatch, WriteOptions as RocksDBWriteOptions,
+    self, checkpoint::Checkpoint, Cache as RocksDBCache, ColumnFamily, ColumnFamilyDescriptor,
+    DBIterator, MergeOperands, Options as RocksDBOptions, SstFileWriter, WriteBatch,
+    WriteOptions as RocksDBWriteOptions,
 };
 use smallvec::SmallVec;
-use std::{fmt, iter, iter::Peekable, mem, path::Path, sync::Arc};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fmt, iter,
+    iter::Peekable,
+    mem,
+    path::Path,
+    sync::Arc,
+};
 
 use crate::{
-    db::{check_database, Change},
-    DBOptions, Database, Iter, Iterator, Patch, ResolvedAddress, Snapshot,
+    bulk,
+    cache::{ValueCache, ValueCacheConfig, ValueCacheRef},
+    db::{check_database, try_merge_counter, Change, DB_METADATA},
+    views::{IndexesPool, MetadataCache, SharedMetadataCache, View},
+    BinaryValue, ColumnFamilyMapping, DBOptions, Database, Fork, Iter, Iterator, Patch,
+    PinnedValue, ResolvedAddress, Snapshot,
 };
 
 /// Size of a byte representation of an index ID, which is used to prefix index keys
 /// in a column family.
 pub const ID_SIZE: usize = mem::size_of::<u64>();
 
+/// Prefix of the names of column families shared by indexes.
+const SHARED_CF_PREFIX: &str = "__INDEXES_CF_";
+/// Key in the database metadata storing the mapping of indexes to column families.
+const CF_MAPPING_NAME: &str = "column_families";
+
+/// Name of the merge operator used to apply `Change::Merge` operands.
+const MERGE_OPERATOR_NAME: &str = "matterdb_counter_merge";
+
 /// Database implementation on top of [`RocksDB`](https://rocksdb.org)
 /// backend.
 ///
@@ -35,6 +55,10 @@ pub const ID_SIZE: usize = mem::size_of::<u64>();
 pub struct RocksDB {
     db: Arc<ShardedLock<rocksdb::DB>>,
     options: DBOptions,
+    cf_mapping: CfMapping,
+    metadata_cache: Arc<SharedMetadataCache>,
+    cache_metadata: bool,
+    value_cache: Option<Arc<ValueCache>>,
 }
 
 impl From<DBOptions> for RocksDBOptions {
@@ -56,14 +80,68 @@ impl From<&DBOptions> for RocksDBOptions {
                     .expect("Failed to instantiate `Cache` for `RocksDB`"),
             );
         }
+        defaults.set_merge_operator_associative(MERGE_OPERATOR_NAME, counter_merge);
         defaults
     }
 }
 
+/// Merge operator applying `Change::Merge` operands. Since the operator is associative,
+/// it is used both for full and partial merges.
+fn counter_merge(
+    _key: &[u8],
+    existing: Option<&[u8]>,
+    operands: &MergeOperands,
+) -> Option<Vec<u8>> {
+    let mut value = existing.map(<[u8]>::to_vec);
+    for operand in operands {
+        value = Some(try_merge_counter(value.as_deref(), operand)?);
+    }
+    value
+}
+
+/// Creates column families with the specified names in the database, unless they
+/// already exist. The exclusive lock on the database is only taken if there are
+/// missing column families.
+fn create_column_families<I>(
+    db: &ShardedLock<rocksdb::DB>,
+    options: &DBOptions,
+    cf_names: I,
+) -> crate::Result<()>
+where
+    I: IntoIterator,
+    I::Item: AsRef<str>,
+{
+    let missing_names: Vec<_> = {
+        let db_reader = db.read().expect("Failed to get read lock to DB");
+        cf_names
+            .into_iter()
+            .filter(|name| db_reader.cf_handle(name.as_ref()).is_none())
+            .collect()
+    };
+    if missing_names.is_empty() {
+        return Ok(());
+    }
+
+    let mut db_writer = db.write().expect("Failed to get write lock to DB");
+    for name in missing_names {
+        let name = name.as_ref();
+        // The column family may have been created while the write lock was not held.
+        if db_writer.cf_handle(name).is_none() {
+            db_writer.create_cf(name, &options.into())?;
+        }
+    }
+    Ok(())
+}
+
 /// A snapshot of a `RocksDB`.
 pub struct RocksDBSnapshot {
     snapshot: rocksdb::Snapshot<'static>,
     db: Arc<ShardedLock<rocksdb::DB>>,
+    options: DBOptions,
+    sequence_number: u64,
+    cf_mapping: CfMapping,
+    metadata_cache: Option<Arc<MetadataCache>>,
+    value_cache: Option<(Arc<ValueCache>, u64)>,
 }
 
 /// An iterator over the entries of a `RocksDB`.
@@ -81,11 +159,70 @@ impl RocksDB {
     /// If the database does not exist at the indicated path and the option
     /// `create_if_missing` is switched on in `DBOptions`, a new database will
     /// be created at the indicated path.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the mapping of indexes to column families specified in `options`
+    /// differs from the mapping of the existing database. Use [`migrate_column_families`]
+    /// to change the mapping.
+    ///
+    /// # Bulk loading
+    ///
+    /// Entries written by [bulk loads] interrupted before the index metadata was created
+    /// are removed when the database is opened.
+    ///
+    /// [`migrate_column_families`]: #method.migrate_column_families
+    /// [bulk loads]: ../bulk/index.html
     pub fn open<P: AsRef<Path>>(path: P, options: &DBOptions) -> crate::Result<Self> {
+        let (db, stored_mapping) = Self::open_with_stored_mapping(path, options)?;
+        if stored_mapping != options.column_families {
+            return Err(crate::Error::new(format!(
+                "Column family mapping doesn't match: actual {:?}, expected {:?}. \
+                 Use `RocksDB::migrate_column_families` to change the mapping",
+                stored_mapping, options.column_families
+            )));
+        }
+        bulk::discard_pending_loads(&db)?;
+        Ok(db)
+    }
+
+    /// Changes the mapping of indexes to column families for the database stored
+    /// at the specified path to the mapping specified in `options`.
+    ///
+    /// Data of the indexes which change their column family is copied in batches.
+    /// Afterwards, the new mapping is saved and the data is removed from the old column
+    /// families in a single atomic write. Thus, if the migration is interrupted,
+    /// the database remains usable with the old mapping, and the migration can be restarted.
+    /// Column families no longer used by any index are dropped.
+    ///
+    /// The database must not be opened elsewhere during the migration.
+    pub fn migrate_column_families<P: AsRef<Path>>(
+        path: P,
+        options: &DBOptions,
+    ) -> crate::Result<()> {
+        let (mut db, stored_mapping) = Self::open_with_stored_mapping(path, options)?;
+        if stored_mapping == options.column_families {
+            return Ok(());
+        }
+        let new_mapping = CfMapping::new(options.column_families)?;
+        db.cf_mapping = CfMapping::new(stored_mapping)?;
+        db.remap_column_families(&new_mapping)
+    }
+
+    /// Opens the database and returns it together with the stored mapping of indexes
+    /// to column families. The returned database uses the mapping from `options`.
+    fn open_with_stored_mapping<P: AsRef<Path>>(
+        path: P,
+        options: &DBOptions,
+    ) -> crate::Result<(Self, ColumnFamilyMapping)> {
         let inner = {
             if let Ok(names) = rocksdb::DB::list_cf(&RocksDBOptions::default(), &path) {
-                let cf_names = names.iter().map(String::as_str).collect::<Vec<_>>();
-                rocksdb::DB::open_cf(&options.into(), path, cf_names)?
+                // Column families need to be opened with the same options as the database,
+                // in particular, to apply `Change::Merge` operands.
+                let cf_descriptors = names
+                    .iter()
+                    .map(|name| ColumnFamilyDescriptor::new(name, options.into()));
+                rocksdb::DB::open_cf_descriptors(&options.into(), path, cf_descriptors)?
             } else {
                 rocksdb::DB::open(&options.into(), path)?
             }
@@ -93,9 +230,56 @@ impl RocksDB {
         let mut db = Self {
             db: Arc::new(ShardedLock::new(inner)),
             options: *options,
+            cf_mapping: CfMapping::new(options.column_families)?,
+            metadata_cache: Arc::default(),
+            cache_metadata: true,
+            value_cache: None,
         };
+        let is_new = db.get_db_lock_guard().cf_handle(DB_METADATA).is_none();
         check_database(&mut db)?;
-        Ok(db)
+
+        let snapshot = db.snapshot();
+        let stored = View::new(&snapshot, ResolvedAddress::system(DB_METADATA))
+            .get::<_, u16>(CF_MAPPING_NAME)
+            .map(CfMapping::decode);
+        let stored_mapping = match stored {
+            Some(mapping) => mapping,
+            None if is_new => {
+                let fork = db.fork();
+                View::new(&fork, ResolvedAddress::system(DB_METADATA))
+                    .put(CF_MAPPING_NAME, CfMapping::encode(options.column_families));
+                db.merge(fork.into_patch())?;
+                options.column_families
+            }
+            // The database was created before the mapping was configurable.
+            None => ColumnFamilyMapping::PerIndex,
+        };
+        Ok((db, stored_mapping))
+    }
+
+    /// Disables caching of index metadata in snapshots and forks of the database.
+    /// Each index access then reads the index metadata from the storage, which is useful
+    /// as a baseline when measuring the effect of the cache.
+    #[must_use]
+    pub fn without_metadata_cache(mut self) -> Self {
+        self.cache_metadata = false;
+        self
+    }
+
+    /// Attaches a cache of decoded values with the specified configuration to the database.
+    /// The cache is shared by all clones of the returned database. See the [`cache`] module
+    /// for details.
+    ///
+    /// [`cache`]: ../cache/index.html
+    #[must_use]
+    pub fn with_value_cache(mut self, config: ValueCacheConfig) -> Self {
+        self.value_cache = Some(Arc::new(ValueCache::new(config)));
+        self
+    }
+
+    /// Returns the cache of decoded values attached to the database, if any.
+    pub fn value_cache(&self) -> Option<&ValueCache> {
+        self.value_cache.as_deref()
     }
 
     /// Creates checkpoint of this database in the given directory. See [`RocksDB` docs] for
@@ -112,29 +296,92 @@ impl RocksDB {
     }
 
     /// Retrieves read lock guard containing underlying `rocksdb::DB`.
+    ///
+    /// The database should not be modified via the guard, since such modifications
+    /// are not reflected in the cache of index metadata used by snapshots.
     pub fn get_db_lock_guard(&self) -> ShardedLockReadGuard<'_, rocksdb::DB> {
         self.db.read().expect("Failed to get read lock to DB")
     }
 
-    fn cf_exists(&self, cf_name: &str) -> bool {
-        self.get_db_lock_guard().cf_handle(cf_name).is_some()
+    /// Creates column families with the specified names, unless they already exist.
+    ///
+    /// Column families are created automatically when a fork touching a new column family
+    /// is converted into a patch, rather than when the patch is merged. Creating a column
+    /// family requires exclusive access to the database, which blocks concurrent merges
+    /// and snapshot creation; thus, it may be beneficial to create the column families
+    /// for the known indexes ahead of time.
+    pub fn create_column_families<I>(&self, cf_names: I) -> crate::Result<()>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        create_column_families(&self.db, &self.options, cf_names)
     }
 
-    fn create_cf(&self, cf_name: &str) -> crate::Result<()> {
-        self.db
-            .write()
-            .expect("Failed to get write lock to DB")
-            .create_cf(cf_name, &self.options.into())
-            .map_err(Into::into)
+    /// Moves data of indexes from the column families of the current mapping to the column
+    /// families of the `new_mapping`.
+    fn remap_column_families(&self, new_mapping: &CfMapping) -> crate::Result<()> {
+        /// Maximum number of entries in a single batch when copying data.
+        const BATCH_SIZE: usize = 10_000;
+
+        let snapshot = self.snapshot();
+        let addresses = IndexesPool::new(&snapshot).resolved_addresses();
+        let moved: Vec<_> = addresses
+            .iter()
+            .filter(|addr| self.cf_mapping.cf_name(addr) != new_mapping.cf_name(addr))
+            .collect();
+        self.create_column_families(moved.iter().map(|addr| new_mapping.cf_name(addr)))?;
+
+        let db_reader = self.get_db_lock_guard();
+        for &addr in &moved {
+            let cf = db_reader.cf_handle(new_mapping.cf_name(addr)).unwrap();
+            let mut batch = WriteBatch::default();
+            // Remove the data copied by a previous interrupted migration, if any.
+            Self::clear_prefix(&db_reader, &mut batch, cf, addr);
+            let mut iter = snapshot.iter(addr, &[]);
+            while let Some((key, value)) = iter.next() {
+                batch.put_cf(cf, addr.keyed(key), value);
+                if batch.len() >= BATCH_SIZE {
+                    db_reader.write(mem::take(&mut batch))?;
+                }
+            }
+            db_reader.write(batch)?;
+        }
+
+        // Atomically switch to the new mapping and remove the data from old column families.
+        let mut batch = WriteBatch::default();
+        let metadata_cf = db_reader.cf_handle(DB_METADATA).unwrap();
+        let encoded_mapping = CfMapping::encode(new_mapping.mapping);
+        batch.put_cf(metadata_cf, CF_MAPPING_NAME, encoded_mapping.to_bytes());
+        for &addr in &moved {
+            let cf = db_reader.cf_handle(self.cf_mapping.cf_name(addr)).unwrap();
+            Self::clear_prefix(&db_reader, &mut batch, cf, addr);
+        }
+        db_reader.write(batch)?;
+        drop(db_reader);
+
+        let used_names: HashSet<_> = addresses
+            .iter()
+            .map(|addr| new_mapping.cf_name(addr))
+            .collect();
+        let unused_names: BTreeSet<_> = moved
+            .iter()
+            .map(|addr| self.cf_mapping.cf_name(addr))
+            .filter(|name| !used_names.contains(name))
+            .collect();
+        let mut db_writer = self.db.write().expect("Failed to get write lock to DB");
+        for name in unused_names {
+            db_writer.drop_cf(name)?;
+        }
+        Ok(())
     }
 
     /// Clears the column family completely, removing all keys from it.
-    pub(super) fn clear_column_family(&self, batch: &mut WriteBatch, cf: &ColumnFamily) {
+    pub(super) fn clear_column_family(db: &rocksdb::DB, batch: &mut WriteBatch, cf: &ColumnFamily) {
         /// Some lexicographically large key.
         const LARGER_KEY: &[u8] = &[u8::max_value(); 1_024];
 
-        let db_reader = self.get_db_lock_guard();
-        let mut iter = db_reader.raw_iterator_cf(cf);
+        let mut iter = db.raw_iterator_cf(cf);
         iter.seek_to_last();
         if iter.valid() {
             if let Some(key) = iter.key() {
@@ -154,17 +401,25 @@ impl RocksDB {
     }
 
     fn do_merge(&self, patch: Patch, w_opts: &RocksDBWriteOptions) -> crate::Result<()> {
-        let mut batch = WriteBatch::default();
-        for (resolved, changes) in patch.into_changes() {
-            if !self.cf_exists(&resolved.name) {
-                self.create_cf(&resolved.name)?;
-            }
+        let _merge = self.metadata_cache.start_merge();
+        let value_merge = self.value_cache.as_ref().map(|cache| cache.start_merge());
+        let changes = patch.into_changes();
 
-            let db_reader = self.get_db_lock_guard();
-            let cf = db_reader.cf_handle(&resolved.name).unwrap();
+        // Column families are created when the patch is created (see `prepare_views`),
+        // so the merge only needs shared access to the database.
+        let db_reader = self.get_db_lock_guard();
+        let mut batch = WriteBatch::default();
+        for (resolved, changes) in changes {
+            let cf_name = self.cf_mapping.cf_name(&resolved);
+            let cf = db_reader.cf_handle(cf_name).ok_or_else(|| {
+                crate::Error::new(format!("Column family `{}` does not exist", cf_name))
+            })?;
 
             if changes.is_cleared() {
-                self.clear_prefix(&mut batch, cf, &resolved);
+                if let Some(merge) = &value_merge {
+                    merge.invalidate_view(&resolved);
+                }
+                Self::clear_prefix(&db_reader, &mut batch, cf, &resolved);
             }
 
             if let Some(id_bytes) = resolved.id_to_bytes() {
@@ -177,54 +432,98 @@ impl RocksDB {
                 buffer.extend_from_slice(&id_bytes);
 
                 for (key, change) in changes.into_data() {
+                    if let Some(merge) = &value_merge {
+                        merge.invalidate(&resolved, &key);
+                    }
                     buffer.truncate(ID_SIZE);
                     buffer.extend_from_slice(&key);
                     match change {
                         Change::Put(ref value) => batch.put_cf(cf, &buffer, value),
                         Change::Delete => batch.delete_cf(cf, &buffer),
+                        Change::Merge(ref operand) => batch.merge_cf(cf, &buffer, operand),
                     }
                 }
             } else {
                 // Write changes to the column family as-is.
                 for (key, change) in changes.into_data() {
+                    if let Some(merge) = &value_merge {
+                        merge.invalidate(&resolved, &key);
+                    }
                     match change {
                         Change::Put(ref value) => batch.put_cf(cf, &key, value),
                         Change::Delete => batch.delete_cf(cf, &key),
+                        Change::Merge(ref operand) => batch.merge_cf(cf, &key, operand),
                     }
                 }
             }
         }
 
-        self.get_db_lock_guard()
-            .write_opt(batch, w_opts)
-            .map_err(Into::into)
+        db_reader.write_opt(batch, w_opts).map_err(Into::into)
     }
 
     /// Removes all keys with the specified prefix from a column family.
-    fn clear_prefix(&self, batch: &mut WriteBatch, cf: &ColumnFamily, resolved: &ResolvedAddress) {
+    fn clear_prefix(
+        db: &rocksdb::DB,
+        batch: &mut WriteBatch,
+        cf: &ColumnFamily,
+        resolved: &ResolvedAddress,
+    ) {
         if let Some(id_bytes) = resolved.id_to_bytes() {
             let next_bytes = next_id_bytes(id_bytes);
             batch.delete_range_cf(cf, id_bytes, next_bytes);
         } else {
-            self.clear_column_family(batch, cf);
+            Self::clear_column_family(db, batch, cf);
         }
     }
 
-    #[allow(unsafe_code)]
-    #[allow(clippy::useless_transmute)]
     pub(super) fn rocksdb_snapshot(&self) -> RocksDBSnapshot {
-        RocksDBSnapshot {
-            // SAFETY:
-            // The snapshot carries an `Arc` to the database to make sure that database
-            // is not dropped before the snapshot. Additionally, the pointer to `rocksdb::DB`
-            // is stable within `Arc<ShardedLock<rocksdb::DB>>` and its part used in dropping
-            // the snapshot (`*mut ffi::rocksdb_t`) is never changed, i.e., not affected
-            // by potential incoherence if the `ShardedLock` is being concurrently written to.
-            // FIXME: Investigate changing `rocksdb::Snapshot` / `DB` to remove `unsafe` (ECR-4273).
-            snapshot: unsafe { mem::transmute(self.get_db_lock_guard().snapshot()) },
-            db: Arc::clone(&self.db),
+        if let Some(cache) = &self.value_cache {
+            let (mut snapshot, generation) = cache.snapshot(|| self.plain_snapshot());
+            snapshot.value_cache = generation.map(|gen| (Arc::clone(cache), gen));
+            snapshot
+        } else {
+            self.plain_snapshot()
         }
     }
+
+    /// Creates a snapshot without a reference to the value cache.
+    #[allow(unsafe_code)]
+    #[allow(clippy::useless_transmute)]
+    fn plain_snapshot(&self) -> RocksDBSnapshot {
+        let (mut snapshot, metadata_cache) = self.metadata_cache.snapshot(|| {
+            let lock_guard = self.get_db_lock_guard();
+            // RocksDB does not expose the sequence number of a snapshot, so it is read before
+            // and after the snapshot is taken. Sequence numbers are monotonic, so if both reads
+            // coincide, the snapshot corresponds to exactly this sequence number; otherwise,
+            // a concurrent write has happened and the snapshot is retaken.
+            let (snapshot, sequence_number) = loop {
+                let sequence_number = lock_guard.latest_sequence_number();
+                let snapshot = lock_guard.snapshot();
+                if lock_guard.latest_sequence_number() == sequence_number {
+                    break (snapshot, sequence_number);
+                }
+            };
+            RocksDBSnapshot {
+                // SAFETY:
+                // The snapshot carries an `Arc` to the database to make sure that database
+                // is not dropped before the snapshot. Additionally, the pointer to `rocksdb::DB`
+                // is stable within `Arc<ShardedLock<rocksdb::DB>>` and its part used in dropping
+                // the snapshot (`*mut ffi::rocksdb_t`) is never changed, i.e., not affected
+                // by potential incoherence if the `ShardedLock` is being concurrently written to.
+                // FIXME: Investigate changing `rocksdb::Snapshot` / `DB` to remove `unsafe`
+                // (ECR-4273).
+                snapshot: unsafe { mem::transmute(snapshot) },
+                db: Arc::clone(&self.db),
+                options: self.options,
+                sequence_number,
+                cf_mapping: self.cf_mapping.clone(),
+                metadata_cache: None,
+                value_cache: None,
+            }
+        });
+        snapshot.metadata_cache = metadata_cache.filter(|_| self.cache_metadata);
+        snapshot
+    }
 }
 
 impl RocksDBSnapshot {
@@ -236,7 +535,10 @@ impl RocksDBSnapshot {
         use rocksdb::{Direction, IteratorMode};
 
         let from = name.keyed(from);
-        let iter = match self.get_lock_guard().cf_handle(&name.name) {
+        let iter = match self
+            .get_lock_guard()
+            .cf_handle(self.cf_mapping.cf_name(name))
+        {
             Some(cf) => self
                 .snapshot
                 .iterator_cf(cf, IteratorMode::From(from.as_ref(), Direction::Forward)),
@@ -257,6 +559,15 @@ impl Database for RocksDB {
         Box::new(self.rocksdb_snapshot())
     }
 
+    fn fork(&self) -> Fork {
+        let patch = Patch::from_changes(self.snapshot(), HashMap::new());
+        if self.cache_metadata {
+            Fork::from(patch)
+        } else {
+            Fork::from(patch.without_metadata_cache())
+        }
+    }
+
     fn merge(&self, patch: Patch) -> crate::Result<()> {
         let w_opts = RocksDBWriteOptions::default();
         self.do_merge(patch, &w_opts)
@@ -267,24 +578,78 @@ impl Database for RocksDB {
         w_opts.set_sync(true);
         self.do_merge(patch, &w_opts)
     }
+
+    fn ingest_sorted(
+        &self,
+        address: &ResolvedAddress,
+        entries: &mut dyn iter::Iterator<Item = (Vec<u8>, Vec<u8>)>,
+    ) -> crate::Result<()> {
+        /// Approximate maximum size of a single SST file.
+        const MAX_SST_FILE_SIZE: u64 = 256 * 1_024 * 1_024;
+
+        let value_merge = self.value_cache.as_ref().map(|cache| cache.start_merge());
+        if let Some(merge) = &value_merge {
+            merge.invalidate_view(address);
+        }
+        self.create_column_families(iter::once(self.cf_mapping.cf_name(address)))?;
+
+        let dir = tempfile::tempdir()?;
+        let options = RocksDBOptions::from(&self.options);
+        let mut paths = vec![];
+        let mut writer = None;
+        for (key, value) in entries {
+            if writer.is_none() {
+                let path = dir.path().join(format!("{}.sst", paths.len()));
+                let mut sst_writer = SstFileWriter::create(&options);
+                sst_writer.open(&path)?;
+                paths.push(path);
+                writer = Some(sst_writer);
+            }
+            let sst_writer = writer.as_mut().unwrap();
+            sst_writer.put(address.keyed(&key), value)?;
+            if sst_writer.file_size() >= MAX_SST_FILE_SIZE {
+                sst_writer.finish()?;
+                writer = None;
+            }
+        }
+        if let Some(mut sst_writer) = writer {
+            sst_writer.finish()?;
+        }
+        if paths.is_empty() {
+            return Ok(());
+        }
+
+        let db = self.get_db_lock_guard();
+        let cf = db.cf_handle(self.cf_mapping.cf_name(address)).unwrap();
+        db.ingest_external_file_cf(cf, paths).map_err(Into::into)
+    }
 }
 
 impl Snapshot for RocksDBSnapshot {
     fn get(&self, resolved_addr: &ResolvedAddress, key: &[u8]) -> Option<Vec<u8>> {
         let lock = self.get_lock_guard();
-        let cf = lock.cf_handle(&resolved_addr.name)?;
+        let cf = lock.cf_handle(self.cf_mapping.cf_name(resolved_addr))?;
         self.snapshot
             .get_cf(cf, resolved_addr.keyed(key))
             .unwrap_or_else(|e| panic!("{}", e))
     }
 
+    fn get_pinned(&self, resolved_addr: &ResolvedAddress, key: &[u8]) -> Option<PinnedValue<'_>> {
+        let lock = self.get_lock_guard();
+        let cf = lock.cf_handle(self.cf_mapping.cf_name(resolved_addr))?;
+        self.snapshot
+            .get_pinned_cf(cf, resolved_addr.keyed(key))
+            .unwrap_or_else(|e| panic!("{}", e))
+            .map(PinnedValue::foreign)
+    }
+
     fn multi_get<'a>(
         &self,
         resolved_addr: &ResolvedAddress,
         keys: &'a mut dyn iter::Iterator<Item = &'a [u8]>,
     ) -> Vec<Option<Vec<u8>>> {
         let lock = self.get_lock_guard();
-        let cf = if let Some(cf) = lock.cf_handle(&resolved_addr.name) {
+        let cf = if let Some(cf) = lock.cf_handle(self.cf_mapping.cf_name(resolved_addr)) {
             cf
         } else {
             return vec![None; keys.count()];
@@ -300,6 +665,27 @@ impl Snapshot for RocksDBSnapshot {
     fn iter(&self, name: &ResolvedAddress, from: &[u8]) -> Iter<'_> {
         Box::new(self.rocksdb_iter(name, from))
     }
+
+    fn sequence_number(&self) -> Option<u64> {
+        Some(self.sequence_number)
+    }
+
+    fn metadata_cache(&self) -> Option<&MetadataCache> {
+        self.metadata_cache.as_deref()
+    }
+
+    fn value_cache(&self) -> Option<ValueCacheRef<'_>> {
+        self.value_cache
+            .as_ref()
+            .map(|(cache, generation)| ValueCacheRef::new(cache, *generation))
+    }
+
+    fn prepare_views(&self, addrs: &mut dyn iter::Iterator<Item = &ResolvedAddress>) {
+        let cf_names = addrs.map(|addr| self.cf_mapping.cf_name(addr));
+        // If column families cannot be created, the error will surface when the patch
+        // is merged.
+        create_column_families(&self.db, &self.options, cf_names).ok();
+    }
 }
 
 impl<'a> Iterator for RocksDBIterator<'a> {
@@ -363,6 +749,66 @@ impl fmt::Debug for RocksDBSnapshot {
     }
 }
 
+/// Mapping of indexes to column families together with precomputed names of the shared
+/// column families.
+#[derive(Debug, Clone)]
+struct CfMapping {
+    mapping: ColumnFamilyMapping,
+    shared_names: Arc<[String]>,
+}
+
+impl CfMapping {
+    fn new(mapping: ColumnFamilyMapping) -> crate::Result<Self> {
+        let shared_names = match mapping {
+            ColumnFamilyMapping::PerIndex => vec![],
+            ColumnFamilyMapping::Hashed { count: 0 } => {
+                return Err(crate::Error::new(
+                    "Number of column families in the mapping must be positive",
+                ));
+            }
+            ColumnFamilyMapping::Hashed { count } => (0..count)
+                .map(|i| format!("{}{}__", SHARED_CF_PREFIX, i))
+                .collect(),
+        };
+        Ok(Self {
+            mapping,
+            shared_names: shared_names.into(),
+        })
+    }
+
+    /// Encodes the mapping for storage in the database metadata.
+    fn encode(mapping: ColumnFamilyMapping) -> u16 {
+        match mapping {
+            ColumnFamilyMapping::PerIndex => 0,
+            ColumnFamilyMapping::Hashed { count } => count,
+        }
+    }
+
+    fn decode(value: u16) -> ColumnFamilyMapping {
+        match value {
+            0 => ColumnFamilyMapping::PerIndex,
+            count => ColumnFamilyMapping::Hashed { count },
+        }
+    }
+
+    /// Returns the name of the column family storing the view with the specified address.
+    fn cf_name<'a>(&'a self, resolved: &'a ResolvedAddress) -> &'a str {
+        if resolved.id.is_none() || self.shared_names.is_empty() {
+            // System views are always stored in separate column families.
+            return &resolved.name;
+        }
+
+        // FNV-1a hash, which is stable across platforms and releases.
+        let hash = resolved
+            .name
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+            });
+        &self.shared_names[(hash % self.shared_names.len() as u64) as usize]
+    }
+}
+
 /// Generates the sequence of bytes lexicographically following the provided one. Assumes that
 /// the provided sequence is less than `[u8::max_value(); ID_SIZE]`.
 pub fn next_id_bytes(id_bytes: [u8; ID_SIZE]) -> [u8; ID_SIZE] {
@@ -401,3 +847,126 @@ fn test_next_id_bytes() {
         [1, 2, 3, 4, 6, 0, 0, 0]
     );
 }
+
+#[test]
+fn test_create_column_families() {
+    use crate::access::CopyAccessExt;
+
+    let dir = tempfile::TempDir::new().unwrap();
+    let db = RocksDB::open(dir.path(), &DBOptions::default()).unwrap();
+    db.create_column_families(vec!["foo", "bar"]).unwrap();
+    db.create_column_families(vec!["foo", "baz"]).unwrap();
+    {
+        let db_reader = db.get_db_lock_guard();
+        for &name in &["foo", "bar", "baz"] {
+            assert!(db_reader.cf_handle(name).is_some());
+        }
+        assert!(db_reader.cf_handle("list").is_none());
+    }
+
+    let fork = db.fork();
+    fork.get_list("foo").push(1_u8);
+    fork.get_list("list").push(2_u8);
+    // Column families are created before the patch is merged.
+    let patch = fork.into_patch();
+    assert!(db.get_db_lock_guard().cf_handle("list").is_some());
+    db.merge(patch).unwrap();
+    let snapshot = db.snapshot();
+    assert_eq!(snapshot.get_list::<_, u8>("foo").get(0), Some(1));
+    assert_eq!(snapshot.get_list::<_, u8>("list").get(0), Some(2));
+}
+
+#[cfg(test)]
+fn fill_indexes(db: &RocksDB) {
+    use crate::access::CopyAccessExt;
+
+    let fork = db.fork();
+    for i in 0_u8..10 {
+        fork.get_list(format!("list_{}", i).as_str()).extend(0..i);
+        fork.get_map(("group", &i)).put(&i, u64::from(i));
+    }
+    db.merge(fork.into_patch()).unwrap();
+}
+
+#[cfg(test)]
+fn check_indexes(db: &RocksDB) {
+    use crate::access::CopyAccessExt;
+
+    let snapshot = db.snapshot();
+    for i in 0_u8..10 {
+        let list = snapshot.get_list::<_, u8>(format!("list_{}", i).as_str());
+        assert_eq!(list.iter().collect::<Vec<_>>(), (0..i).collect::<Vec<_>>());
+        let map = snapshot.get_map::<_, u8, u64>(("group", &i));
+        assert_eq!(map.iter().collect::<Vec<_>>(), vec![(i, u64::from(i))]);
+    }
+}
+
+#[test]
+fn test_hashed_column_families() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let mut options = DBOptions {
+        column_families: ColumnFamilyMapping::Hashed { count: 3 },
+        ..DBOptions::default()
+    };
+    let db = RocksDB::open(dir.path(), &options).unwrap();
+    fill_indexes(&db);
+    check_indexes(&db);
+    {
+        let db_reader = db.get_db_lock_guard();
+        assert!(db_reader.cf_handle("list_1").is_none());
+        assert!(db_reader.cf_handle("group").is_none());
+        assert!(db_reader.cf_handle("__INDEXES_CF_0__").is_some());
+    }
+    drop(db);
+
+    let err = RocksDB::open(dir.path(), &DBOptions::default()).unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("Column family mapping doesn't match"));
+    let db = RocksDB::open(dir.path(), &options).unwrap();
+    check_indexes(&db);
+
+    options.column_families = ColumnFamilyMapping::Hashed { count: 0 };
+    let other_dir = tempfile::TempDir::new().unwrap();
+    assert!(RocksDB::open(other_dir.path(), &options).is_err());
+}
+
+#[test]
+fn test_migrating_column_families() {
+    use crate::access::CopyAccessExt;
+
+    let dir = tempfile::TempDir::new().unwrap();
+    let db = RocksDB::open(dir.path(), &DBOptions::default()).unwrap();
+    fill_indexes(&db);
+    drop(db);
+
+    let mut options = DBOptions {
+        column_families: ColumnFamilyMapping::Hashed { count: 4 },
+        ..DBOptions::default()
+    };
+    RocksDB::migrate_column_families(dir.path(), &options).unwrap();
+    let db = RocksDB::open(dir.path(), &options).unwrap();
+    check_indexes(&db);
+    {
+        let db_reader = db.get_db_lock_guard();
+        assert!(db_reader.cf_handle("list_1").is_none());
+        assert!(db_reader.cf_handle("group").is_none());
+    }
+    // The database can be modified after the migration.
+    let fork = db.fork();
+    fork.get_entry("entry").set(42_u64);
+    db.merge(fork.into_patch()).unwrap();
+    drop(db);
+
+    // Migrate to a different number of shared column families, and back to the original mapping.
+    options.column_families = ColumnFamilyMapping::Hashed { count: 2 };
+    RocksDB::migrate_column_families(dir.path(), &options).unwrap();
+    check_indexes(&RocksDB::open(dir.path(), &options).unwrap());
+    RocksDB::migrate_column_families(dir.path(), &DBOptions::default()).unwrap();
+    let db = RocksDB::open(dir.path(), &DBOptions::default()).unwrap();
+    check_indexes(&db);
+    assert!(db
+        .get_db_lock_guard()
+        .cf_handle("__INDEXES_CF_0__")
+        .is_none());
+}
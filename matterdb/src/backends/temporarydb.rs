@@ -6,13 +6,18 @@ use std::{
     collections::{btree_map::Range, BTreeMap, HashMap},
     iter,
     iter::{Iterator, Peekable},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
 };
 
 use crate::{
     backends::rocksdb::{next_id_bytes, ID_SIZE},
-    db::{check_database, Change, Iterator as DBIterator},
-    Database, Iter, Patch, ResolvedAddress, Result, Snapshot,
+    cache::{ValueCache, ValueCacheConfig, ValueCacheRef},
+    db::{check_database, merge_counter, Change, Iterator as DBIterator},
+    views::{MetadataCache, SharedMetadataCache},
+    Database, Fork, Iter, Patch, PinnedValue, ResolvedAddress, Result, Snapshot,
 };
 
 type MemoryDB = HashMap<ResolvedAddress, BTreeMap<Vec<u8>, Vec<u8>>>;
@@ -22,10 +27,18 @@ type MemoryDB = HashMap<ResolvedAddress, BTreeMap<Vec<u8>, Vec<u8>>>;
 #[derive(Debug)]
 pub struct TemporaryDB {
     inner: Arc<ShardedLock<MemoryDB>>,
+    /// Number of merges performed on the database. Modified only under the write lock.
+    sequence_number: AtomicU64,
+    metadata_cache: SharedMetadataCache,
+    cache_metadata: bool,
+    value_cache: Option<Arc<ValueCache>>,
 }
 
 struct TemporarySnapshot {
     snapshot: MemoryDB,
+    sequence_number: u64,
+    metadata_cache: Option<Arc<MetadataCache>>,
+    value_cache: Option<(Arc<ValueCache>, u64)>,
 }
 
 struct TemporaryDBIterator<'a> {
@@ -41,25 +54,80 @@ impl TemporaryDB {
 
         db.insert(ResolvedAddress::system("default"), BTreeMap::new());
         let inner = Arc::new(ShardedLock::new(db));
-        let mut db = Self { inner };
+        let mut db = Self {
+            inner,
+            sequence_number: AtomicU64::new(0),
+            metadata_cache: SharedMetadataCache::new(),
+            cache_metadata: true,
+            value_cache: None,
+        };
         check_database(&mut db).unwrap();
         db
     }
 
+    /// Disables caching of index metadata in snapshots and forks of the database.
+    /// Each index access then reads the index metadata from the storage, which is useful
+    /// as a baseline when measuring the effect of the cache.
+    #[must_use]
+    pub fn without_metadata_cache(mut self) -> Self {
+        self.cache_metadata = false;
+        self
+    }
+
+    /// Attaches a cache of decoded values with the specified configuration to the database.
+    /// See the [`cache`] module for details.
+    ///
+    /// [`cache`]: ../cache/index.html
+    #[must_use]
+    pub fn with_value_cache(mut self, config: ValueCacheConfig) -> Self {
+        self.value_cache = Some(Arc::new(ValueCache::new(config)));
+        self
+    }
+
+    /// Returns the cache of decoded values attached to the database, if any.
+    pub fn value_cache(&self) -> Option<&ValueCache> {
+        self.value_cache.as_deref()
+    }
+
     /// Clears the contents of the database.
     pub fn clear(&self) -> crate::Result<()> {
+        let _merge = self.metadata_cache.start_merge();
+        let _value_merge = self.value_cache.as_ref().map(|cache| {
+            let merge = cache.start_merge();
+            cache.clear();
+            merge
+        });
         let mut rw_lock = self.inner.write().expect("Couldn't get read-write lock");
 
         for collection in rw_lock.values_mut() {
             collection.clear();
         }
+        self.sequence_number.fetch_add(1, Ordering::SeqCst);
 
         Ok(())
     }
 
     fn temporary_snapshot(&self) -> TemporarySnapshot {
+        let create_snapshot = || {
+            self.metadata_cache.snapshot(|| {
+                let inner = self.inner.read().expect("Couldn't get read lock");
+                (inner.clone(), self.sequence_number.load(Ordering::SeqCst))
+            })
+        };
+
+        let (((snapshot, sequence_number), metadata_cache), value_cache) =
+            if let Some(cache) = &self.value_cache {
+                let (snapshot, generation) = cache.snapshot(create_snapshot);
+                (snapshot, generation.map(|gen| (Arc::clone(cache), gen)))
+            } else {
+                (create_snapshot(), None)
+            };
+
         TemporarySnapshot {
-            snapshot: self.inner.read().expect("Couldn't get read lock").clone(),
+            snapshot,
+            sequence_number,
+            metadata_cache: metadata_cache.filter(|_| self.cache_metadata),
+            value_cache,
         }
     }
 }
@@ -69,8 +137,20 @@ impl Database for TemporaryDB {
         Box::new(self.temporary_snapshot())
     }
 
+    fn fork(&self) -> Fork {
+        let patch = Patch::from_changes(self.snapshot(), HashMap::new());
+        if self.cache_metadata {
+            Fork::from(patch)
+        } else {
+            Fork::from(patch.without_metadata_cache())
+        }
+    }
+
     fn merge(&self, patch: Patch) -> Result<()> {
+        let _merge = self.metadata_cache.start_merge();
+        let value_merge = self.value_cache.as_ref().map(|cache| cache.start_merge());
         let mut inner = self.inner.write().expect("Couldn't get write lock");
+        self.sequence_number.fetch_add(1, Ordering::SeqCst);
         for (resolved, changes) in patch.into_changes() {
             if !inner.contains_key(&resolved) {
                 inner.insert(resolved.clone(), BTreeMap::new());
@@ -79,6 +159,9 @@ impl Database for TemporaryDB {
             let collection: &mut BTreeMap<Vec<u8>, Vec<u8>> = inner.get_mut(&resolved).unwrap();
 
             if changes.is_cleared() {
+                if let Some(merge) = &value_merge {
+                    merge.invalidate_view(&resolved);
+                }
                 if let Some(id_bytes) = resolved.id_to_bytes() {
                     let next_bytes = next_id_bytes(id_bytes);
                     let mut middle_and_tail = collection.split_off(id_bytes.as_ref());
@@ -99,20 +182,36 @@ impl Database for TemporaryDB {
                 buffer.extend_from_slice(&id_bytes);
 
                 for (key, change) in changes.into_data() {
+                    if let Some(merge) = &value_merge {
+                        merge.invalidate(&resolved, &key);
+                    }
                     buffer.truncate(ID_SIZE);
                     buffer.extend_from_slice(&key);
 
                     match change {
                         Change::Put(value) => collection.insert(buffer.to_vec(), value),
                         Change::Delete => collection.remove(buffer.as_ref()),
+                        Change::Merge(operand) => {
+                            let existing = collection.get(buffer.as_ref()).map(Vec::as_slice);
+                            let value = merge_counter(existing, &operand);
+                            collection.insert(buffer.to_vec(), value)
+                        }
                     };
                 }
             } else {
                 // Write changes to the column family as-is.
                 for (key, change) in changes.into_data() {
+                    if let Some(merge) = &value_merge {
+                        merge.invalidate(&resolved, &key);
+                    }
                     match change {
                         Change::Put(value) => collection.insert(key, value),
                         Change::Delete => collection.remove(&key),
+                        Change::Merge(operand) => {
+                            let existing = collection.get(&key).map(Vec::as_slice);
+                            let value = merge_counter(existing, &operand);
+                            collection.insert(key, value)
+                        }
                     };
                 }
             }
@@ -123,6 +222,24 @@ impl Database for TemporaryDB {
     fn merge_sync(&self, patch: Patch) -> Result<()> {
         self.merge(patch)
     }
+
+    fn ingest_sorted(
+        &self,
+        address: &ResolvedAddress,
+        entries: &mut dyn Iterator<Item = (Vec<u8>, Vec<u8>)>,
+    ) -> Result<()> {
+        let value_merge = self.value_cache.as_ref().map(|cache| cache.start_merge());
+        if let Some(merge) = &value_merge {
+            merge.invalidate_view(address);
+        }
+        let mut inner = self.inner.write().expect("Couldn't get write lock");
+        self.sequence_number.fetch_add(1, Ordering::SeqCst);
+        let collection = inner.entry(address.clone()).or_default();
+        for (key, value) in entries {
+            collection.insert(address.keyed(&key).into_owned(), value);
+        }
+        Ok(())
+    }
 }
 
 impl<'a> DBIterator for TemporaryDBIterator<'a> {
@@ -175,6 +292,13 @@ impl Snapshot for TemporarySnapshot {
         collection.get(name.keyed(key).as_ref()).cloned()
     }
 
+    fn get_pinned(&self, name: &ResolvedAddress, key: &[u8]) -> Option<PinnedValue<'_>> {
+        let collection = self.snapshot.get(name)?;
+        collection
+            .get(name.keyed(key).as_ref())
+            .map(|value| PinnedValue::borrowed(value))
+    }
+
     fn multi_get<'a>(
         &self,
         name: &ResolvedAddress,
@@ -205,6 +329,20 @@ impl Snapshot for TemporarySnapshot {
             ended: false,
         })
     }
+
+    fn sequence_number(&self) -> Option<u64> {
+        Some(self.sequence_number)
+    }
+
+    fn metadata_cache(&self) -> Option<&MetadataCache> {
+        self.metadata_cache.as_deref()
+    }
+
+    fn value_cache(&self) -> Option<ValueCacheRef<'_>> {
+        self.value_cache
+            .as_ref()
+            .map(|(cache, generation)| ValueCacheRef::new(cache, *generation))
+    }
 }
 
 impl Default for TemporaryDB {
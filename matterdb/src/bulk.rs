@@ -0,0 +1,421 @@
+//! Bulk loading of indexes.
+//!
+//! # Stability
+//!
+//! The entirety of this module is considered unstable. While the supported functionality
+//! is unlikely to break, the implementation details may change in the following releases.
+//!
+//! # Overview
+//!
+//! Importing a large amount of data by putting it into a `Fork` and merging the resulting
+//! patch is slow and requires to keep all changes in memory (or spill them to disk).
+//! A [`BulkLoader`] instead writes a sorted stream of entries directly into the storage:
+//! `RocksDB` builds SST files with the index identifier prefix already applied and ingests
+//! them into the column family, and `TemporaryDB` inserts the entries into its in-memory maps.
+//!
+//! The loaded index becomes visible atomically. The loader first reserves an identifier
+//! for the index in the indexes pool, then writes entries under this identifier, and only
+//! then creates the index metadata in a single merge. Until the metadata is merged,
+//! the written entries are not referenced by any index and cannot be observed.
+//!
+//! Note that these steps are not performed in a single atomic write. If the process
+//! is interrupted after the identifier is reserved, but before the metadata is created,
+//! the written entries remain in the storage without an index referencing them. To be able
+//! to remove such entries, the loader records the pending load in the database together
+//! with the identifier reservation and removes the record together with the metadata
+//! creation. Interrupted loads are discarded with [`discard_pending_loads`], which is called
+//! automatically when a `RocksDB` database is opened.
+//!
+//! Like with [`merge`], the caller must ensure that no patches creating new indexes
+//! are produced from forks created before the loading is complete; otherwise, identifiers
+//! of the indexes may clash.
+//!
+//! # Examples
+//!
+//! ```
+//! # use matterdb::{access::CopyAccessExt, Database, IndexType, TemporaryDB};
+//! # use matterdb::bulk::BulkLoader;
+//! let db = TemporaryDB::new();
+//! let entries = (0_u64..1_000).map(|i| (i, i.to_string()));
+//! let loaded = BulkLoader::new(&db, "numbers", IndexType::Map)
+//!     .load(entries)
+//!     .unwrap();
+//! assert_eq!(loaded, 1_000);
+//!
+//! let snapshot = db.snapshot();
+//! let map = snapshot.get_map::<_, u64, String>("numbers");
+//! assert_eq!(map.get(&42).unwrap(), "42");
+//! ```
+//!
+//! [`BulkLoader`]: struct.BulkLoader.html
+//! [`discard_pending_loads`]: fn.discard_pending_loads.html
+//! [`merge`]: ../trait.Database.html#tymethod.merge
+
+use thiserror::Error;
+
+use std::num::NonZeroU64;
+
+use crate::{
+    access::AccessError,
+    views::{key_bytes, IndexesPool, RawAccess, View, ViewWithMetadata},
+    BinaryKey, BinaryValue, Database, Fork, IndexAddress, IndexType, ResolvedAddress,
+};
+
+/// Errors that can occur during bulk loading.
+#[derive(Debug, Error)]
+pub enum BulkLoadError {
+    /// Index type does not support bulk loading.
+    #[error("Index type {:?} does not support bulk loading", _0)]
+    UnsupportedIndexType(IndexType),
+
+    /// Index address is invalid.
+    #[error("{}", _0)]
+    Access(#[from] AccessError),
+
+    /// Index with the specified address already exists.
+    #[error("Index {:?} already exists", _0)]
+    IndexExists(IndexAddress),
+
+    /// Entries are not sorted by key in the strictly ascending order.
+    #[error("Entries are not sorted by key in the strictly ascending order")]
+    Unsorted,
+
+    /// Pending load was discarded before the index metadata was created.
+    #[error("Pending load was discarded before the index metadata was created")]
+    Discarded,
+
+    /// Storage error.
+    #[error("Storage error: {}", _0)]
+    Storage(#[from] crate::Error),
+}
+
+/// Name of the system view recording loads whose index metadata is not created yet.
+/// Keys of the view are reserved index identifiers, and values are index names.
+const PENDING_LOADS_NAME: &str = "__BULK_LOADS__";
+
+fn pending_loads<T: RawAccess>(access: T) -> View<T> {
+    View::new(access, ResolvedAddress::system(PENDING_LOADS_NAME))
+}
+
+/// Removes entries written by bulk loads which were interrupted before the index metadata
+/// was created, e.g., because the process has crashed. Returns the number of discarded loads.
+///
+/// This function must not be called concurrently with bulk loading, since it would discard
+/// the loads in progress as well. `RocksDB::open` calls this function automatically.
+pub fn discard_pending_loads<D: Database + ?Sized>(db: &D) -> crate::Result<usize> {
+    let fork = db.fork();
+    let loads: Vec<_> = pending_loads(&fork).iter::<_, u64, String>(&()).collect();
+    if loads.is_empty() {
+        return Ok(0);
+    }
+    for (identifier, name) in &loads {
+        View::new(
+            &fork,
+            ResolvedAddress::new(name, NonZeroU64::new(*identifier)),
+        )
+        .clear();
+    }
+    pending_loads(&fork).clear();
+    db.merge(fork.into_patch())?;
+    Ok(loads.len())
+}
+
+/// Loader of a sorted stream of entries into a new index.
+///
+/// Maps and key sets can be loaded; for a key set, entries should have `()` as a value.
+/// Keys must be sorted in the ascending order of their binary representation
+/// (i.e., the order in which they are iterated over in the index) and must not repeat.
+///
+/// See the [module docs](index.html) for details.
+#[derive(Debug)]
+pub struct BulkLoader<'a, D: ?Sized> {
+    db: &'a D,
+    address: IndexAddress,
+    index_type: IndexType,
+    track_len: bool,
+}
+
+impl<'a, D: Database + ?Sized> BulkLoader<'a, D> {
+    /// Creates a loader for the index with the specified address and type.
+    pub fn new(db: &'a D, address: impl Into<IndexAddress>, index_type: IndexType) -> Self {
+        Self {
+            db,
+            address: address.into(),
+            index_type,
+            track_len: false,
+        }
+    }
+
+    /// Makes the loaded index track the number of its entries, as if `track_len()`
+    /// was called on the index before the entries were inserted.
+    #[must_use]
+    pub fn track_len(mut self) -> Self {
+        self.track_len = true;
+        self
+    }
+
+    /// Loads entries into the index. Returns the number of loaded entries.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the index already exists, or if the entries are not sorted.
+    /// In the latter case, the entries written before the error was detected are removed.
+    pub fn load<I, K, V>(self, entries: I) -> Result<u64, BulkLoadError>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: BinaryKey,
+        V: BinaryValue,
+    {
+        match self.index_type {
+            IndexType::Map | IndexType::KeySet => { /* Supported index types. */ }
+            other => return Err(BulkLoadError::UnsupportedIndexType(other)),
+        }
+
+        let resolved = self.reserve()?;
+        let mut entries = SortedEntries {
+            inner: entries
+                .into_iter()
+                .map(|(key, value)| (key_bytes(&key), value.into_bytes())),
+            last_key: None,
+            is_unsorted: false,
+            count: 0,
+        };
+        let ingest_result = self.db.ingest_sorted(&resolved, &mut entries);
+        let result = match ingest_result {
+            Err(err) => Err(err.into()),
+            Ok(()) if entries.is_unsorted => Err(BulkLoadError::Unsorted),
+            Ok(()) => self.publish(&resolved, entries.count),
+        };
+
+        if result.is_err() {
+            let fork = self.db.fork();
+            pending_loads(&fork).remove(&resolved.id.unwrap().get());
+            View::new(&fork, resolved).clear();
+            self.db.merge(fork.into_patch())?;
+        }
+        result.map(|()| entries.count)
+    }
+
+    /// Reserves an identifier for the index and records the pending load.
+    fn reserve(&self) -> Result<ResolvedAddress, BulkLoadError> {
+        let fork = self.db.fork();
+        self.check_not_exists(&fork)?;
+        let identifier = IndexesPool::new(&fork).reserve_identifier();
+        pending_loads(&fork).put(&identifier.get(), self.address.name().to_owned());
+        self.db.merge(fork.into_patch())?;
+        Ok(ResolvedAddress::new(self.address.name(), Some(identifier)))
+    }
+
+    fn check_not_exists(&self, fork: &Fork) -> Result<(), BulkLoadError> {
+        if ViewWithMetadata::get_metadata(fork, &self.address)?.is_some() {
+            Err(BulkLoadError::IndexExists(self.address.clone()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Creates metadata for the loaded index and removes the record of the pending load.
+    /// If the index tracks its length, the metadata includes the number of entries in the index.
+    fn publish(&self, resolved: &ResolvedAddress, count: u64) -> Result<(), BulkLoadError> {
+        let fork = self.db.fork();
+        // The index could be created concurrently while the entries were being written.
+        self.check_not_exists(&fork)?;
+        let identifier = resolved.id.unwrap();
+        if !pending_loads(&fork).contains(&identifier.get()) {
+            return Err(BulkLoadError::Discarded);
+        }
+        pending_loads(&fork).remove(&identifier.get());
+        IndexesPool::new(&fork).insert_index_metadata(
+            &self.address,
+            identifier,
+            self.index_type,
+            Some(count).filter(|_| self.track_len),
+        );
+        self.db.merge(fork.into_patch()).map_err(From::from)
+    }
+}
+
+/// Iterator over entries checking that they are sorted. The iterator stops on the first
+/// unsorted entry.
+struct SortedEntries<I> {
+    inner: I,
+    last_key: Option<Vec<u8>>,
+    is_unsorted: bool,
+    count: u64,
+}
+
+impl<I> Iterator for SortedEntries<I>
+where
+    I: Iterator<Item = (Vec<u8>, Vec<u8>)>,
+{
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.is_unsorted {
+            return None;
+        }
+        let (key, value) = self.inner.next()?;
+        if let Some(last_key) = &self.last_key {
+            if *last_key >= key {
+                self.is_unsorted = true;
+                return None;
+            }
+        }
+        self.last_key = Some(key.clone());
+        self.count += 1;
+        Some((key, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{access::CopyAccessExt, indexes::IndexIterator, DBOptions, RocksDB, TemporaryDB};
+
+    fn test_loading(db: &impl Database) {
+        let fork = db.fork();
+        fork.get_list("list").push(1_u8);
+        db.merge(fork.into_patch()).unwrap();
+
+        let entries = (0_u32..20_000).map(|i| (i, u64::from(i) * 2));
+        let loaded = BulkLoader::new(db, "map", IndexType::Map)
+            .track_len()
+            .load(entries)
+            .unwrap();
+        assert_eq!(loaded, 20_000);
+        let keys = vec!["a".to_owned(), "b".to_owned(), "c".to_owned()];
+        BulkLoader::new(db, ("set", &1_u8), IndexType::KeySet)
+            .load(keys.into_iter().map(|key| (key, ())))
+            .unwrap();
+
+        let snapshot = db.snapshot();
+        let map = snapshot.get_map::<_, u32, u64>("map");
+        assert_eq!(map.get(&100), Some(200));
+        assert_eq!(map.iter().count(), 20_000);
+        assert_eq!(map.estimated_len(), Some(20_000));
+        assert!(map.values().enumerate().all(|(i, x)| x == i as u64 * 2));
+        let set = snapshot.get_key_set::<_, str>(("set", &1_u8));
+        assert!(set.contains("b"));
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec!["a", "b", "c"]);
+        assert_eq!(set.estimated_len(), None);
+        assert_eq!(snapshot.get_list::<_, u8>("list").len(), 1);
+
+        // Loaded indexes can be modified as usual.
+        let fork = db.fork();
+        fork.get_map::<_, u32, u64>("map").put(&20_000, 0);
+        fork.get_list("list").push(2_u8);
+        db.merge(fork.into_patch()).unwrap();
+        let snapshot = db.snapshot();
+        let map = snapshot.get_map::<_, u32, u64>("map");
+        assert_eq!(map.iter().count(), 20_001);
+        assert_eq!(map.len(), 20_001);
+        assert_eq!(snapshot.get_list::<_, u8>("list").len(), 2);
+    }
+
+    fn test_loading_errors(db: &impl Database) {
+        let fork = db.fork();
+        fork.get_entry("entry").set(1_u8);
+        db.merge(fork.into_patch()).unwrap();
+
+        let err = BulkLoader::new(db, "entry", IndexType::Map)
+            .load(vec![(1_u8, 1_u8)])
+            .unwrap_err();
+        assert!(matches!(err, BulkLoadError::IndexExists(_)));
+        let err = BulkLoader::new(db, "list", IndexType::List)
+            .load(vec![(1_u8, 1_u8)])
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            BulkLoadError::UnsupportedIndexType(IndexType::List)
+        ));
+        let err = BulkLoader::new(db, "__system", IndexType::Map)
+            .load(vec![(1_u8, 1_u8)])
+            .unwrap_err();
+        assert!(matches!(err, BulkLoadError::Access(_)));
+
+        let entries = vec![(1_u8, 1_u8), (2, 2), (2, 3)];
+        let err = BulkLoader::new(db, "map", IndexType::Map)
+            .load(entries)
+            .unwrap_err();
+        assert!(matches!(err, BulkLoadError::Unsorted));
+        let snapshot = db.snapshot();
+        assert!(snapshot.index_type("map").is_none());
+        // Partially written entries are removed.
+        let resolved = ResolvedAddress::new("map", std::num::NonZeroU64::new(2));
+        assert!(snapshot.iter(&resolved, &[]).next().is_none());
+
+        // The index can be loaded after the failure.
+        BulkLoader::new(db, "map", IndexType::Map)
+            .load(vec![(1_u8, 1_u8), (3, 3)])
+            .unwrap();
+        let snapshot = db.snapshot();
+        let map = snapshot.get_map::<_, u8, u8>("map");
+        assert_eq!(map.iter().collect::<Vec<_>>(), vec![(1, 1), (3, 3)]);
+    }
+
+    #[test]
+    fn loading_with_temporary_db() {
+        test_loading(&TemporaryDB::new());
+        test_loading_errors(&TemporaryDB::new());
+    }
+
+    #[test]
+    fn loading_with_rocksdb() {
+        let dir = tempfile::TempDir::new().unwrap();
+        test_loading(&RocksDB::open(dir.path().join("a"), &DBOptions::default()).unwrap());
+        test_loading_errors(&RocksDB::open(dir.path().join("b"), &DBOptions::default()).unwrap());
+    }
+
+    /// Emulates a crash after the entries are written, but before the index metadata is created.
+    fn interrupted_load(db: &impl Database, name: &str) -> ResolvedAddress {
+        let resolved = BulkLoader::new(db, name, IndexType::Map).reserve().unwrap();
+        let mut entries = (0_u8..10).map(|i| (vec![i], vec![i]));
+        db.ingest_sorted(&resolved, &mut entries).unwrap();
+        resolved
+    }
+
+    #[test]
+    fn interrupted_loads_are_discarded() {
+        let db = TemporaryDB::new();
+        let resolved = interrupted_load(&db, "map");
+        let snapshot = db.snapshot();
+        assert!(snapshot.index_type("map").is_none());
+        assert!(snapshot.iter(&resolved, &[]).next().is_some());
+
+        assert_eq!(discard_pending_loads(&db).unwrap(), 1);
+        let snapshot = db.snapshot();
+        assert!(snapshot.iter(&resolved, &[]).next().is_none());
+        assert_eq!(discard_pending_loads(&db).unwrap(), 0);
+
+        // A discarded load cannot be published.
+        let loader = BulkLoader::new(&db, "other", IndexType::Map);
+        let resolved = loader.reserve().unwrap();
+        discard_pending_loads(&db).unwrap();
+        let err = loader.publish(&resolved, 0).unwrap_err();
+        assert!(matches!(err, BulkLoadError::Discarded));
+        assert!(db.snapshot().index_type("other").is_none());
+
+        // The index can be loaded after the failure.
+        BulkLoader::new(&db, "map", IndexType::Map)
+            .load(vec![(1_u8, 1_u8)])
+            .unwrap();
+        let snapshot = db.snapshot();
+        let map = snapshot.get_map::<_, u8, u8>("map");
+        assert_eq!(map.iter().collect::<Vec<_>>(), vec![(1, 1)]);
+    }
+
+    #[test]
+    fn interrupted_loads_are_discarded_on_open() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let resolved = {
+            let db = RocksDB::open(dir.path(), &DBOptions::default()).unwrap();
+            interrupted_load(&db, "map")
+        };
+
+        let db = RocksDB::open(dir.path(), &DBOptions::default()).unwrap();
+        let snapshot = db.snapshot();
+        assert!(snapshot.index_type("map").is_none());
+        assert!(snapshot.iter(&resolved, &[]).next().is_none());
+        assert_eq!(discard_pending_loads(&db).unwrap(), 0);
+    }
+}
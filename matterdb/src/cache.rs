@@ -0,0 +1,529 @@
+//! Read-through cache of decoded index values.
+//!
+//! # Stability
+//!
+//! The entirety of this module is considered unstable. While the supported functionality
+//! is unlikely to break, the implementation details may change in the following releases.
+//!
+//! # Overview
+//!
+//! Reading a value from an index requires to retrieve its bytes from the storage
+//! and to decode them with [`BinaryValue::from_bytes`]. For hot values, both steps
+//! can be avoided with a [`ValueCache`] attached to the database. The cache stores decoded
+//! values keyed by the resolved address of the index and the key within the index, and
+//! is shared by all snapshots of the database.
+//!
+//! The cache is read-through: values are read via it with the `get_cached` methods
+//! of indexes (e.g., [`MapIndex::get_cached`]). The usual `get` methods do not use the cache.
+//! Caching is enabled for specific indexes and/or index types in [`ValueCacheConfig`].
+//! Forks do not use the cache, since values read from a fork may be affected by its changes.
+//!
+//! When a patch is merged into the database, cached values for the keys changed
+//! in the patch are evicted. Each cached value records the database generation it was
+//! read at; snapshots created before the merge continue using cached values
+//! which were not changed since the snapshot was created.
+//!
+//! # Examples
+//!
+//! ```
+//! # use matterdb::{access::CopyAccessExt, Database, IndexType, TemporaryDB};
+//! # use matterdb::cache::ValueCacheConfig;
+//! let config = ValueCacheConfig::new(1_000).cache_index_type(IndexType::Map);
+//! let db = TemporaryDB::new().with_value_cache(config);
+//! let fork = db.fork();
+//! fork.get_map("map").put(&1_u64, "foo".to_owned());
+//! db.merge(fork.into_patch()).unwrap();
+//!
+//! let snapshot = db.snapshot();
+//! let map = snapshot.get_map::<_, u64, String>("map");
+//! assert_eq!(map.get_cached(&1).unwrap(), "foo");
+//! assert_eq!(map.get_cached(&1).unwrap(), "foo");
+//! let metrics = db.value_cache().unwrap().metrics();
+//! assert_eq!((metrics.hits, metrics.misses), (1, 1));
+//! ```
+//!
+//! [`BinaryValue::from_bytes`]: ../trait.BinaryValue.html#tymethod.from_bytes
+//! [`ValueCache`]: struct.ValueCache.html
+//! [`ValueCacheConfig`]: struct.ValueCacheConfig.html
+//! [`MapIndex::get_cached`]: ../indexes/struct.MapIndex.html#method.get_cached
+
+use std::{
+    any::Any,
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt, mem,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::{IndexType, ResolvedAddress};
+
+/// Configuration of a [`ValueCache`].
+///
+/// Values are cached only for indexes enabled either by their name or by their type.
+/// For indexes in a group, the name of the group is used.
+///
+/// [`ValueCache`]: struct.ValueCache.html
+#[derive(Debug, Clone)]
+pub struct ValueCacheConfig {
+    capacity: usize,
+    index_types: Vec<IndexType>,
+    index_names: HashSet<String>,
+}
+
+impl ValueCacheConfig {
+    /// Creates a configuration for a cache with the specified maximum number of entries.
+    /// Caching is not enabled for any index.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            index_types: Vec::new(),
+            index_names: HashSet::new(),
+        }
+    }
+
+    /// Enables caching for all indexes of the specified type.
+    #[must_use]
+    pub fn cache_index_type(mut self, index_type: IndexType) -> Self {
+        if !self.index_types.contains(&index_type) {
+            self.index_types.push(index_type);
+        }
+        self
+    }
+
+    /// Enables caching for the index or the group of indexes with the specified name.
+    #[must_use]
+    pub fn cache_index(mut self, name: impl Into<String>) -> Self {
+        self.index_names.insert(name.into());
+        self
+    }
+
+    fn is_enabled(&self, address: &ResolvedAddress, index_type: IndexType) -> bool {
+        self.index_types.contains(&index_type) || self.index_names.contains(&address.name)
+    }
+}
+
+/// Metrics of a [`ValueCache`].
+///
+/// [`ValueCache`]: struct.ValueCache.html
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct CacheMetrics {
+    /// Number of reads served from the cache.
+    pub hits: u64,
+    /// Number of reads for which the value was retrieved from the storage.
+    pub misses: u64,
+    /// Number of entries evicted from the cache because of its limited capacity.
+    pub evictions: u64,
+    /// Current number of entries in the cache.
+    pub len: usize,
+}
+
+/// Cached value together with the information necessary for invalidation and eviction.
+struct CacheEntry {
+    /// Decoded value, or `None` if the value is absent in the storage.
+    value: Option<Arc<dyn Any + Send + Sync>>,
+    /// Database generation the value was read at.
+    generation: u64,
+    /// Tick of the last access to the entry.
+    tick: u64,
+}
+
+#[derive(Default)]
+struct CacheEntries {
+    views: HashMap<ResolvedAddress, HashMap<Vec<u8>, CacheEntry>>,
+    /// Entries ordered by the last access.
+    lru: BTreeMap<u64, (ResolvedAddress, Vec<u8>)>,
+    next_tick: u64,
+}
+
+impl CacheEntries {
+    fn remove(&mut self, address: &ResolvedAddress, key: &[u8]) {
+        if let Some(view) = self.views.get_mut(address) {
+            if let Some(entry) = view.remove(key) {
+                self.lru.remove(&entry.tick);
+            }
+            if view.is_empty() {
+                self.views.remove(address);
+            }
+        }
+    }
+
+    fn remove_view(&mut self, address: &ResolvedAddress) {
+        if let Some(view) = self.views.remove(address) {
+            for entry in view.values() {
+                self.lru.remove(&entry.tick);
+            }
+        }
+    }
+
+    /// Removes the least recently used entry.
+    fn evict(&mut self) {
+        let tick = match self.lru.keys().next() {
+            Some(&tick) => tick,
+            None => return,
+        };
+        let (address, key) = self.lru.remove(&tick).unwrap();
+        self.remove(&address, &key);
+    }
+}
+
+/// LRU cache of decoded index values shared by snapshots of a database.
+///
+/// See the [module docs](index.html) for details.
+pub struct ValueCache {
+    config: ValueCacheConfig,
+    entries: Mutex<CacheEntries>,
+    generation: AtomicU64,
+    merges_in_progress: AtomicUsize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl fmt::Debug for ValueCache {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("ValueCache")
+            .field("config", &self.config)
+            .field("metrics", &self.metrics())
+            .finish()
+    }
+}
+
+impl ValueCache {
+    /// Creates an empty cache with the specified configuration.
+    pub(crate) fn new(config: ValueCacheConfig) -> Self {
+        Self {
+            config,
+            entries: Mutex::default(),
+            generation: AtomicU64::new(0),
+            merges_in_progress: AtomicUsize::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the configuration of the cache.
+    pub fn config(&self) -> &ValueCacheConfig {
+        &self.config
+    }
+
+    /// Returns current metrics of the cache.
+    pub fn metrics(&self) -> CacheMetrics {
+        let len = self.lock().lru.len();
+        CacheMetrics {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            len,
+        }
+    }
+
+    /// Removes all entries from the cache. Metrics are retained.
+    pub fn clear(&self) {
+        let mut entries = self.lock();
+        entries.views.clear();
+        entries.lru.clear();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CacheEntries> {
+        self.entries.lock().expect("Couldn't lock value cache")
+    }
+
+    /// Signals that the database is about to be modified. Until the returned guard is dropped,
+    /// newly created snapshots do not use the cache. The keys modified by the merge should be
+    /// invalidated via the guard before the changes become visible.
+    pub(crate) fn start_merge(&self) -> ValueCacheMerge<'_> {
+        self.merges_in_progress.fetch_add(1, Ordering::SeqCst);
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        ValueCacheMerge { cache: self }
+    }
+
+    /// Creates a snapshot using the provided closure and returns it together with
+    /// the database generation, if the cache can be used with the snapshot.
+    pub(crate) fn snapshot<S>(&self, create_snapshot: impl FnOnce() -> S) -> (S, Option<u64>) {
+        let generation = self.generation.load(Ordering::SeqCst);
+        let is_merging = self.merges_in_progress.load(Ordering::SeqCst) > 0;
+        let snapshot = create_snapshot();
+        let is_consistent = !is_merging && self.generation.load(Ordering::SeqCst) == generation;
+        (
+            snapshot,
+            if is_consistent {
+                Some(generation)
+            } else {
+                None
+            },
+        )
+    }
+
+    fn get_or_insert_with<V>(
+        &self,
+        generation: u64,
+        address: &ResolvedAddress,
+        key: &[u8],
+        load: impl FnOnce() -> Option<V>,
+    ) -> Option<V>
+    where
+        V: Clone + Send + Sync + 'static,
+    {
+        {
+            let mut entries = self.lock();
+            let tick = entries.next_tick;
+            let mut hit = None;
+            let entry = entries
+                .views
+                .get_mut(address)
+                .and_then(|view| view.get_mut(key));
+            // Values read after the snapshot was created cannot be used, since they
+            // may have been changed after the snapshot.
+            if let Some(entry) = entry.filter(|entry| entry.generation <= generation) {
+                let value = match &entry.value {
+                    None => Some(None),
+                    Some(value) => value.downcast_ref::<V>().map(|value| Some(value.clone())),
+                };
+                if let Some(value) = value {
+                    hit = Some((value, mem::replace(&mut entry.tick, tick)));
+                }
+            }
+
+            if let Some((value, old_tick)) = hit {
+                let lru_key = entries.lru.remove(&old_tick).unwrap();
+                entries.lru.insert(tick, lru_key);
+                entries.next_tick += 1;
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return value;
+            }
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let value = load();
+        self.insert(generation, address, key, value.clone());
+        value
+    }
+
+    fn insert<V>(&self, generation: u64, address: &ResolvedAddress, key: &[u8], value: Option<V>)
+    where
+        V: Send + Sync + 'static,
+    {
+        if self.config.capacity == 0 {
+            return;
+        }
+
+        let mut entries = self.lock();
+        // Only values read at the latest generation can be inserted; otherwise, the value
+        // may be already changed by a merge and would never be invalidated.
+        let is_latest = self.merges_in_progress.load(Ordering::SeqCst) == 0
+            && self.generation.load(Ordering::SeqCst) == generation;
+        if !is_latest {
+            return;
+        }
+
+        entries.remove(address, key);
+        while entries.lru.len() >= self.config.capacity {
+            entries.evict();
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let tick = entries.next_tick;
+        entries.next_tick += 1;
+        let entry = CacheEntry {
+            value: value.map(|value| Arc::new(value) as Arc<dyn Any + Send + Sync>),
+            generation,
+            tick,
+        };
+        entries
+            .views
+            .entry(address.clone())
+            .or_default()
+            .insert(key.to_vec(), entry);
+        entries.lru.insert(tick, (address.clone(), key.to_vec()));
+    }
+}
+
+/// Guard returned by [`ValueCache::start_merge`]. The merge is considered finished
+/// when the guard is dropped.
+///
+/// [`ValueCache::start_merge`]: struct.ValueCache.html#method.start_merge
+#[derive(Debug)]
+pub struct ValueCacheMerge<'a> {
+    cache: &'a ValueCache,
+}
+
+impl ValueCacheMerge<'_> {
+    /// Invalidates the cached value for the specified key.
+    pub(crate) fn invalidate(&self, address: &ResolvedAddress, key: &[u8]) {
+        self.cache.lock().remove(address, key);
+    }
+
+    /// Invalidates all cached values of the view with the specified address.
+    pub(crate) fn invalidate_view(&self, address: &ResolvedAddress) {
+        self.cache.lock().remove_view(address);
+    }
+}
+
+impl Drop for ValueCacheMerge<'_> {
+    fn drop(&mut self) {
+        self.cache.merges_in_progress.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Reference to the value cache from a snapshot.
+#[derive(Debug, Clone, Copy)]
+pub struct ValueCacheRef<'a> {
+    cache: &'a ValueCache,
+    generation: u64,
+}
+
+impl<'a> ValueCacheRef<'a> {
+    /// Creates a reference to the cache for a snapshot created at the specified generation.
+    pub(crate) fn new(cache: &'a ValueCache, generation: u64) -> Self {
+        Self { cache, generation }
+    }
+
+    /// Returns the value for the key in the view, loading it with the provided closure
+    /// if the value is not cached. If caching is not enabled for the view, the value
+    /// is always loaded.
+    pub(crate) fn get_or_insert_with<V>(
+        self,
+        address: &ResolvedAddress,
+        index_type: IndexType,
+        key: &[u8],
+        load: impl FnOnce() -> Option<V>,
+    ) -> Option<V>
+    where
+        V: Clone + Send + Sync + 'static,
+    {
+        if self.cache.config.is_enabled(address, index_type) {
+            self.cache
+                .get_or_insert_with(self.generation, address, key, load)
+        } else {
+            load()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{access::CopyAccessExt, Database, TemporaryDB};
+
+    fn metrics(db: &TemporaryDB) -> (u64, u64) {
+        let metrics = db.value_cache().unwrap().metrics();
+        (metrics.hits, metrics.misses)
+    }
+
+    #[test]
+    fn cached_values_are_invalidated_on_merge() {
+        let config = ValueCacheConfig::new(100).cache_index("map");
+        let db = TemporaryDB::new().with_value_cache(config);
+        let fork = db.fork();
+        fork.get_map("map").put(&1_u8, 10_u32);
+        fork.get_map("map").put(&2_u8, 20_u32);
+        db.merge(fork.into_patch()).unwrap();
+
+        let snapshot = db.snapshot();
+        let map = snapshot.get_map::<_, u8, u32>("map");
+        assert_eq!(map.get_cached(&1), Some(10));
+        assert_eq!(map.get_cached(&2), Some(20));
+        assert_eq!(map.get_cached(&3), None);
+        assert_eq!(map.get_cached(&1), Some(10));
+        assert_eq!(map.get_cached(&3), None);
+        assert_eq!(metrics(&db), (2, 3));
+
+        let fork = db.fork();
+        fork.get_map("map").put(&1_u8, 11_u32);
+        fork.get_map("map").put(&3_u8, 30_u32);
+        db.merge(fork.into_patch()).unwrap();
+        assert_eq!(db.value_cache().unwrap().metrics().len, 1);
+
+        let new_snapshot = db.snapshot();
+        let new_map = new_snapshot.get_map::<_, u8, u32>("map");
+        assert_eq!(new_map.get_cached(&1), Some(11));
+        assert_eq!(new_map.get_cached(&2), Some(20));
+        assert_eq!(new_map.get_cached(&3), Some(30));
+        assert_eq!(metrics(&db), (3, 5));
+
+        // The old snapshot does not use values read after it was created.
+        assert_eq!(map.get_cached(&1), Some(10));
+        assert_eq!(map.get_cached(&3), None);
+        assert_eq!(map.get_cached(&2), Some(20));
+        assert_eq!(metrics(&db), (4, 7));
+
+        // Clearing the index invalidates all its values.
+        let fork = db.fork();
+        fork.get_map::<_, u8, u32>("map").clear();
+        db.merge(fork.into_patch()).unwrap();
+        assert_eq!(db.value_cache().unwrap().metrics().len, 0);
+        let snapshot = db.snapshot();
+        assert_eq!(snapshot.get_map::<_, u8, u32>("map").get_cached(&2), None);
+    }
+
+    #[test]
+    fn caching_is_configured_per_index() {
+        let config = ValueCacheConfig::new(100).cache_index_type(IndexType::Entry);
+        let db = TemporaryDB::new().with_value_cache(config);
+        let fork = db.fork();
+        fork.get_entry("entry").set(1_u64);
+        fork.get_list("list").push(2_u64);
+        db.merge(fork.into_patch()).unwrap();
+
+        let snapshot = db.snapshot();
+        for _ in 0..2 {
+            assert_eq!(snapshot.get_entry::<_, u64>("entry").get_cached(), Some(1));
+            assert_eq!(snapshot.get_list::<_, u64>("list").get_cached(0), Some(2));
+        }
+        assert_eq!(metrics(&db), (1, 1));
+
+        // Forks do not use the cache.
+        let fork = db.fork();
+        assert_eq!(fork.get_entry::<_, u64>("entry").get_cached(), Some(1));
+        fork.get_entry("entry").set(3_u64);
+        assert_eq!(fork.get_entry::<_, u64>("entry").get_cached(), Some(3));
+        assert_eq!(metrics(&db), (1, 1));
+    }
+
+    #[test]
+    fn least_recently_used_values_are_evicted() {
+        let config = ValueCacheConfig::new(2).cache_index_type(IndexType::Map);
+        let db = TemporaryDB::new().with_value_cache(config);
+        let fork = db.fork();
+        let mut map = fork.get_map("map");
+        for i in 0_u8..3 {
+            map.put(&i, u32::from(i));
+        }
+        drop(map);
+        db.merge(fork.into_patch()).unwrap();
+
+        let snapshot = db.snapshot();
+        let map = snapshot.get_map::<_, u8, u32>("map");
+        assert_eq!(map.get_cached(&0), Some(0));
+        assert_eq!(map.get_cached(&1), Some(1));
+        assert_eq!(map.get_cached(&0), Some(0));
+        // Evicts the value for key 1.
+        assert_eq!(map.get_cached(&2), Some(2));
+        assert_eq!(map.get_cached(&0), Some(0));
+        assert_eq!(map.get_cached(&1), Some(1));
+
+        let metrics = db.value_cache().unwrap().metrics();
+        assert_eq!(metrics.hits, 2);
+        assert_eq!(metrics.misses, 4);
+        assert_eq!(metrics.evictions, 2);
+        assert_eq!(metrics.len, 2);
+    }
+
+    #[test]
+    fn snapshots_during_merge_do_not_use_cache() {
+        let cache = ValueCache::new(ValueCacheConfig::new(10));
+        let ((), generation) = cache.snapshot(|| ());
+        assert_eq!(generation, Some(0));
+        let merge = cache.start_merge();
+        let ((), generation) = cache.snapshot(|| ());
+        assert!(generation.is_none());
+        drop(merge);
+        let ((), generation) = cache.snapshot(|| ());
+        assert_eq!(generation, Some(1));
+    }
+}
@@ -1,6 +1,7 @@
 use std::{
     cell::RefCell,
     collections::{BTreeMap, HashMap},
+    convert::TryFrom,
     fmt, iter,
     iter::{Iterator as StdIterator, Peekable},
     marker::PhantomData,
@@ -10,10 +11,18 @@ use std::{
     result::Result as StdResult,
 };
 
+use serde::{Deserialize, Serialize};
+
 use crate::{
+    cache::ValueCacheRef,
+    detached::DetachedPatch,
+    migration,
+    spill::{CombinedStream, SpilledChanges},
     validation::assert_valid_name_component,
-    views::{AsReadonly, ChangesIter, IndexesPool, RawAccess, ResolvedAddress, View},
-    Error, Result,
+    views::{
+        AsReadonly, ChangesIter, IndexesPool, MetadataCache, RawAccess, ResolvedAddress, View,
+    },
+    BorrowedBinaryValue, Error, Result,
 };
 
 /// Changes related to a specific `View`.
@@ -39,23 +48,51 @@ impl ViewChanges {
         self.is_cleared = true;
     }
 
+    pub(crate) fn from_parts(data: BTreeMap<Vec<u8>, Change>, is_cleared: bool) -> Self {
+        Self { data, is_cleared }
+    }
+
     pub(crate) fn into_data(self) -> BTreeMap<Vec<u8>, Change> {
         self.data
     }
 
+    /// Returns the approximate number of bytes occupied by the changes.
+    fn approximate_size(&self) -> usize {
+        /// Approximate overhead of storing a single change in a `BTreeMap`.
+        const ENTRY_OVERHEAD: usize = 64;
+
+        self.data
+            .iter()
+            .map(|(key, change)| {
+                let value_len = match change {
+                    Change::Put(value) | Change::Merge(value) => value.len(),
+                    Change::Delete => 0,
+                };
+                key.len() + value_len + ENTRY_OVERHEAD
+            })
+            .sum()
+    }
+
     /// Returns a value for the specified key, or an `Err(_)` if the value should be determined
-    /// by the underlying snapshot.
-    pub fn get(&self, key: &[u8]) -> StdResult<Option<Vec<u8>>, ()> {
+    /// by the underlying snapshot. In the latter case, the error contains a merge operand
+    /// which should be applied to the snapshot value, if any.
+    pub fn get(&self, key: &[u8]) -> StdResult<Option<Vec<u8>>, Option<&[u8]>> {
+        self.get_ref(key).map(|value| value.map(<[u8]>::to_vec))
+    }
+
+    /// Same as `get`, but returns a reference to the value instead of copying it.
+    pub fn get_ref(&self, key: &[u8]) -> StdResult<Option<&[u8]>, Option<&[u8]>> {
         if let Some(change) = self.data.get(key) {
-            return Ok(match *change {
-                Change::Put(ref v) => Some(v.clone()),
-                Change::Delete => None,
-            });
+            return match *change {
+                Change::Put(ref v) => Ok(Some(v)),
+                Change::Delete => Ok(None),
+                Change::Merge(ref operand) => Err(Some(operand)),
+            };
         }
         if self.is_cleared() {
             return Ok(None);
         }
-        Err(())
+        Err(None)
     }
 
     /// Returns whether the view contains the specified `key`. An `Err(_)` is returned if this
@@ -63,7 +100,7 @@ impl ViewChanges {
     pub fn contains(&self, key: &[u8]) -> StdResult<bool, ()> {
         if let Some(change) = self.data.get(key) {
             return Ok(match *change {
-                Change::Put(..) => true,
+                Change::Put(..) | Change::Merge(..) => true,
                 Change::Delete => false,
             });
         }
@@ -73,6 +110,72 @@ impl ViewChanges {
         }
         Err(())
     }
+
+    /// Records a merge `operand` for the specified key. If the value for the key is already
+    /// known from the changes, the operand is applied to it right away; otherwise,
+    /// the operand is stored as is and will be applied to the value in the underlying snapshot.
+    pub(crate) fn merge(&mut self, key: Vec<u8>, operand: &[u8]) {
+        let change = merge_change(self.data.get(&key), self.is_cleared, operand);
+        self.data.insert(key, change);
+    }
+
+    /// Extends these changes with newer changes for the same view. Newer `Put`s and `Delete`s
+    /// override older changes, while newer merge operands are applied on top of older changes.
+    fn extend(&mut self, newer: BTreeMap<Vec<u8>, Change>) {
+        for (key, change) in newer {
+            if let Change::Merge(operand) = change {
+                self.merge(key, &operand);
+            } else {
+                self.data.insert(key, change);
+            }
+        }
+    }
+}
+
+/// Applies a merge `operand` on top of an `older` change for the same key. `is_cleared` signifies
+/// whether the older changes were made in a cleared view.
+pub fn merge_change(older: Option<&Change>, is_cleared: bool, operand: &[u8]) -> Change {
+    match older {
+        Some(Change::Put(value)) => Change::Put(merge_counter(Some(value), operand)),
+        Some(Change::Delete) => Change::Put(merge_counter(None, operand)),
+        Some(Change::Merge(prev_operand)) => {
+            Change::Merge(merge_counter(Some(prev_operand), operand))
+        }
+        None if is_cleared => Change::Put(merge_counter(None, operand)),
+        None => Change::Merge(operand.to_vec()),
+    }
+}
+
+/// Applies a merge `operand` to the `existing` value of a counter. Both the value and operand
+/// are little-endian 64-bit signed integers; an absent value is treated as zero.
+///
+/// This operation is associative and commutative, which allows to merge operands from
+/// non-sequential forks in any order.
+///
+/// # Panics
+///
+/// Panics if either the existing value or the operand are not 8 bytes long.
+pub fn merge_counter(existing: Option<&[u8]>, operand: &[u8]) -> Vec<u8> {
+    try_merge_counter(existing, operand).expect("Invalid counter value or merge operand")
+}
+
+/// Fallible version of `merge_counter`.
+pub fn try_merge_counter(existing: Option<&[u8]>, operand: &[u8]) -> Option<Vec<u8>> {
+    fn decode(bytes: &[u8]) -> Option<i64> {
+        <[u8; 8]>::try_from(bytes).ok().map(i64::from_le_bytes)
+    }
+
+    let existing = existing.map_or(Some(0), decode)?;
+    let operand = decode(operand)?;
+    Some(existing.wrapping_add(operand).to_le_bytes().to_vec())
+}
+
+/// Applies an optional merge `operand` to the value retrieved from a snapshot.
+pub fn resolve_merge(value: Option<Vec<u8>>, operand: Option<&[u8]>) -> Option<Vec<u8>> {
+    match operand {
+        Some(operand) => Some(merge_counter(value.as_deref(), operand)),
+        None => value,
+    }
 }
 
 /// Cell holding changes for a specific view. Mutable view borrows take changes out
@@ -169,6 +272,16 @@ impl WorkingPatch {
         }
     }
 
+    /// Returns the approximate number of bytes occupied by the changes.
+    fn approximate_size(&self) -> usize {
+        self.changes
+            .borrow()
+            .values()
+            .flatten()
+            .map(|changes| changes.approximate_size())
+            .sum()
+    }
+
     /// Takes a cell with changes for a specific `View` out of the patch.
     /// The returned cell is guaranteed to contain an `Rc` with an exclusive ownership.
     fn take_view_changes(&self, address: &ResolvedAddress) -> ChangesCell {
@@ -218,7 +331,7 @@ impl WorkingPatch {
     }
 
     // TODO: verify that this method updates `Change`s already in the `Patch` [ECR-2834]
-    fn merge_into(self, patch: &mut Patch) {
+    fn merge_into(self, patch: &mut Patch, mut savepoint: Option<&mut SavepointLayer>) {
         for (address, changes) in self.changes.into_inner() {
             // Check that changes are not borrowed mutably (in this case, the corresponding
             // `ChangesCell` is `None`).
@@ -242,6 +355,20 @@ impl WorkingPatch {
                 );
             });
 
+            // If there is an active savepoint, remember the state of the patch changes
+            // that are about to be overwritten.
+            if let Some(savepoint) = savepoint.as_mut() {
+                if changes.is_cleared() {
+                    savepoint.record_view(&address, patch.changes.get(&address));
+                } else {
+                    savepoint.record_keys(&address, patch.changes.get(&address), &changes);
+                }
+            }
+
+            if let Some(cache) = &patch.metadata_cache {
+                cache.invalidate(&address, &changes);
+            }
+
             // The patch may already contain changes related to the `address`. If it does,
             // we extend these changes with the new changes (relying on the fact that
             // newer changes override older ones), unless the view was cleared (in which case,
@@ -253,7 +380,136 @@ impl WorkingPatch {
             if changes.is_cleared() {
                 *patch_changes = changes;
             } else {
-                patch_changes.data.extend(changes.data);
+                patch_changes.extend(changes.data);
+            }
+        }
+    }
+}
+
+/// Changes necessary to restore the state of a single view in a `Patch` to the moment
+/// a savepoint was created.
+#[derive(Debug)]
+enum ViewUndo {
+    /// Previous state of all changes for the view, or `None` if the patch did not contain
+    /// any changes for the view.
+    View(Option<ViewChanges>),
+    /// Previous changes for separate keys. `None` signifies that the key was not changed.
+    Keys(BTreeMap<Vec<u8>, Option<Change>>),
+}
+
+impl ViewUndo {
+    /// Reverts `Keys` undo information on top of the `changes`.
+    fn apply_keys(changes: &mut ViewChanges, keys: BTreeMap<Vec<u8>, Option<Change>>) {
+        for (key, change) in keys {
+            if let Some(change) = change {
+                changes.data.insert(key, change);
+            } else {
+                changes.data.remove(&key);
+            }
+        }
+    }
+
+    /// Combines this undo information with the older information from the parent savepoint.
+    fn merge_into_older(self, older: &mut Self) {
+        match (older, self) {
+            (Self::View(_), _) => { /* Older information takes precedence. */ }
+            (Self::Keys(older_keys), Self::Keys(keys)) => {
+                for (key, change) in keys {
+                    older_keys.entry(key).or_insert(change);
+                }
+            }
+            (older @ Self::Keys(_), Self::View(view)) => {
+                let mut view = view.unwrap_or_default();
+                if let Self::Keys(older_keys) = mem::replace(older, Self::View(None)) {
+                    Self::apply_keys(&mut view, older_keys);
+                }
+                *older = Self::View(Some(view));
+            }
+        }
+    }
+}
+
+/// Layer of undo information corresponding to a single savepoint in a `Fork`.
+///
+/// Changes made after the savepoint are flushed into the fork patch as usual; the layer
+/// records the overwritten parts of the patch, so that the savepoint could be rolled back
+/// in time proportional to the number of changes made after it.
+#[derive(Debug)]
+struct SavepointLayer {
+    id: u64,
+    undo: HashMap<ResolvedAddress, ViewUndo>,
+}
+
+impl SavepointLayer {
+    fn new(id: u64) -> Self {
+        Self {
+            id,
+            undo: HashMap::new(),
+        }
+    }
+
+    /// Records the state of the view changes before they are completely replaced.
+    fn record_view(&mut self, address: &ResolvedAddress, current: Option<&ViewChanges>) {
+        match self.undo.get_mut(address) {
+            Some(undo @ ViewUndo::Keys(_)) => {
+                ViewUndo::View(current.cloned()).merge_into_older(undo);
+            }
+            Some(ViewUndo::View(_)) => { /* The state is already recorded. */ }
+            None => {
+                self.undo
+                    .insert(address.clone(), ViewUndo::View(current.cloned()));
+            }
+        }
+    }
+
+    /// Records the state of the view changes for keys affected by `new_changes`.
+    fn record_keys(
+        &mut self,
+        address: &ResolvedAddress,
+        current: Option<&ViewChanges>,
+        new_changes: &ViewChanges,
+    ) {
+        let undo = self
+            .undo
+            .entry(address.clone())
+            .or_insert_with(|| ViewUndo::Keys(BTreeMap::new()));
+        if let ViewUndo::Keys(keys) = undo {
+            for key in new_changes.data.keys() {
+                keys.entry(key.clone())
+                    .or_insert_with(|| current.and_then(|changes| changes.data.get(key).cloned()));
+            }
+        }
+    }
+
+    /// Restores the state of the `patch` to the moment the savepoint was created.
+    fn restore(self, patch: &mut Patch) {
+        if let Some(cache) = &patch.metadata_cache {
+            cache.clear();
+        }
+        for (address, undo) in self.undo {
+            match undo {
+                ViewUndo::View(Some(changes)) => {
+                    patch.changes.insert(address, changes);
+                }
+                ViewUndo::View(None) => {
+                    patch.changes.remove(&address);
+                }
+                ViewUndo::Keys(keys) => {
+                    if let Some(changes) = patch.changes.get_mut(&address) {
+                        ViewUndo::apply_keys(changes, keys);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Merges undo information from a nested savepoint into this layer.
+    fn absorb(&mut self, nested: Self) {
+        for (address, undo) in nested.undo {
+            if let Some(older) = self.undo.get_mut(&address) {
+                undo.merge_into_older(older);
+            } else {
+                self.undo.insert(address, undo);
             }
         }
     }
@@ -262,14 +518,94 @@ impl WorkingPatch {
 /// A generalized iterator over the storage views.
 pub type Iter<'a> = Box<dyn Iterator + 'a>;
 
+/// Raw value retrieved from the storage, which avoids copying the value bytes if possible.
+///
+/// Depending on the backend and the state of the storage view, the value may borrow
+/// the bytes from the database snapshot, from the changes in a fork, or own them.
+/// The value can be decoded into a [`BorrowedBinaryValue`] via [`decode`], e.g.,
+/// as `&str` or `&[u8]` borrowing from the pinned value.
+///
+/// [`BorrowedBinaryValue`]: trait.BorrowedBinaryValue.html
+/// [`decode`]: #method.decode
+pub struct PinnedValue<'a>(PinnedValueInner<'a>);
+
+enum PinnedValueInner<'a> {
+    Borrowed(&'a [u8]),
+    Owned(Vec<u8>),
+    Foreign(Box<dyn AsRef<[u8]> + 'a>),
+}
+
+impl<'a> PinnedValue<'a> {
+    /// Creates a value borrowing the specified bytes.
+    pub fn borrowed(bytes: &'a [u8]) -> Self {
+        Self(PinnedValueInner::Borrowed(bytes))
+    }
+
+    /// Creates a value owning the specified bytes.
+    pub fn owned(bytes: Vec<u8>) -> Self {
+        Self(PinnedValueInner::Owned(bytes))
+    }
+
+    /// Creates a value from a backend-specific buffer, such as a pinned slice in `RocksDB`.
+    pub fn foreign(buffer: impl AsRef<[u8]> + 'a) -> Self {
+        Self(PinnedValueInner::Foreign(Box::new(buffer)))
+    }
+
+    /// Decodes the value.
+    pub fn decode<'b, V: BorrowedBinaryValue<'b>>(&'b self) -> anyhow::Result<V> {
+        V::from_borrowed_bytes(self)
+    }
+
+    /// Converts the value into a vector of bytes.
+    pub fn into_vec(self) -> Vec<u8> {
+        match self.0 {
+            PinnedValueInner::Owned(bytes) => bytes,
+            inner => Self(inner).to_vec(),
+        }
+    }
+}
+
+impl Deref for PinnedValue<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.0 {
+            PinnedValueInner::Borrowed(bytes) => bytes,
+            PinnedValueInner::Owned(bytes) => bytes,
+            PinnedValueInner::Foreign(buffer) => buffer.as_ref().as_ref(),
+        }
+    }
+}
+
+impl AsRef<[u8]> for PinnedValue<'_> {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl fmt::Debug for PinnedValue<'_> {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_tuple("PinnedValue")
+            .field(&self.as_ref())
+            .finish()
+    }
+}
+
 /// An enum that represents a type of change made to some key in the storage.
-#[derive(Debug, Clone, PartialEq)]
-#[cfg_attr(test, derive(Eq, Hash))] // needed for patch equality comparison
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(test, derive(Hash))] // needed for patch equality comparison
 pub enum Change {
     /// Put the specified value into the storage for the corresponding key.
     Put(Vec<u8>),
     /// Delete a value from the storage for the corresponding key.
     Delete,
+    /// Merge the specified operand into the value stored for the corresponding key.
+    ///
+    /// Unlike `Put`, merging does not require to know the current value, so merges
+    /// of the same key from non-sequential forks compose instead of overriding each other.
+    /// The operand is interpreted as an increment of a 64-bit signed counter.
+    Merge(Vec<u8>),
 }
 
 /// A combination of a database snapshot and changes on top of it.
@@ -303,6 +639,26 @@ pub enum Change {
 /// # assert_eq!(list.iter().collect::<Vec<_>>(), vec![1, 2]);
 /// ```
 ///
+/// For finer-grained control, a fork supports nested savepoints. A [`savepoint`] captures
+/// the current state of the fork (including the changes made after the latest `flush`).
+/// The fork can be later rolled back to this state with `rollback_to`, or the savepoint
+/// can be released with `release`, keeping all changes made after it:
+///
+/// ```
+/// # use matterdb::{access::CopyAccessExt, Database, TemporaryDB};
+/// let db = TemporaryDB::new();
+/// let mut fork = db.fork();
+/// fork.get_list("list").push(1_u32);
+/// let outer = fork.savepoint();
+/// fork.get_list("list").push(2_u32);
+/// let inner = fork.savepoint();
+/// fork.get_list("list").push(3_u32);
+/// fork.rollback_to(inner);
+/// fork.release(outer);
+/// let list = fork.get_list::<_, u32>("list");
+/// assert_eq!(list.iter().collect::<Vec<_>>(), vec![1, 2]);
+/// ```
+///
 /// In order to convert a fork into `&dyn Snapshot` presentation, convert it into a `Patch`
 /// and use a reference to it (`Patch` implements `Snapshot`). Using `<Fork as RawAccess>::snapshot`
 /// for this purpose is logically incorrect and may lead to hard-to-debug errors.
@@ -345,6 +701,10 @@ pub enum Change {
 /// a shared reference to an index if there is an exclusive reference to the same index,
 /// and vice versa.
 ///
+/// Like `flush` and `rollback`, savepoint operations require an exclusive reference
+/// to the fork. Thus, all indexes (including ones obtained from a readonly fork)
+/// need to be dropped before creating, rolling back or releasing a savepoint.
+///
 /// [`RawAccessMut`]: access/trait.RawAccessMut.html
 /// [`Snapshot`]: trait.Snapshot.html
 /// [`Patch`]: struct.Patch.html
@@ -354,11 +714,28 @@ pub enum Change {
 /// [`flush`]: #method.flush
 /// [`rollback`]: #method.rollback
 /// [`readonly`]: #method.readonly
+/// [`savepoint`]: #method.savepoint
 /// [`RefCell::borrow_mut()`]: https://doc.rust-lang.org/std/cell/struct.RefCell.html#method.borrow_mut
 #[derive(Debug)]
 pub struct Fork {
     patch: Patch,
     working_patch: WorkingPatch,
+    savepoints: Vec<SavepointLayer>,
+    next_savepoint_id: u64,
+    /// Approximate limit on the memory occupied by flushed changes.
+    memory_limit: Option<usize>,
+    /// Approximate memory occupied by flushed changes since the latest spill.
+    memory_usage: usize,
+}
+
+/// Token identifying a savepoint within a `Fork`. Savepoints are created with
+/// [`Fork::savepoint()`] and can be either rolled back or released.
+///
+/// [`Fork::savepoint()`]: struct.Fork.html#method.savepoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Savepoint {
+    depth: usize,
+    id: u64,
 }
 
 /// A set of changes that can be atomically applied to a `Database`.
@@ -384,11 +761,44 @@ pub struct Fork {
 pub struct Patch {
     snapshot: Box<dyn Snapshot>,
     changes: HashMap<ResolvedAddress, ViewChanges>,
+    /// Older changes spilled to disk, if any.
+    spilled: Option<SpilledChanges>,
+    /// Cache of index metadata reflecting the patch changes, or `None` if caching is disabled.
+    metadata_cache: Option<MetadataCache>,
+}
+
+/// Changes for a single view in a `Patch` ordered by keys.
+pub struct PatchChanges {
+    is_cleared: bool,
+    data: Box<dyn StdIterator<Item = (Vec<u8>, Change)>>,
+}
+
+impl fmt::Debug for PatchChanges {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("PatchChanges")
+            .field("is_cleared", &self.is_cleared)
+            .finish()
+    }
+}
+
+impl PatchChanges {
+    /// Was the view cleared as a part of changes?
+    pub fn is_cleared(&self) -> bool {
+        self.is_cleared
+    }
+
+    /// Returns changes within the view.
+    pub fn into_data(self) -> Box<dyn StdIterator<Item = (Vec<u8>, Change)>> {
+        self.data
+    }
 }
 
 pub(super) struct ForkIter<'a, T: StdIterator> {
     snapshot: Iter<'a>,
     changes: Option<Peekable<T>>,
+    /// Buffer for values obtained by applying merge operands.
+    merged_value: Vec<u8>,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -398,6 +808,8 @@ enum NextIterValue {
     Inserted,
     Deleted,
     MissDeleted,
+    Merged,
+    MergeInserted,
     Finished,
 }
 
@@ -484,9 +896,28 @@ enum NextIterValue {
 /// workflow should only be used for minor changes, for which the proof that a patch does not overlap
 /// with concurrent patches is tractable.
 ///
+/// The only exception to the rule above are increments of counters in a [`CounterIndex`].
+/// Such increments are stored in the patch as merge operands rather than values, and
+/// they compose when patches from non-sequential forks are merged:
+///
+/// ```
+/// # use matterdb::{access::CopyAccessExt, Database, TemporaryDB};
+/// let db = TemporaryDB::new();
+/// let first_fork = db.fork();
+/// first_fork.get_counter("counter").increment("visits", 2);
+/// let second_fork = db.fork();
+/// second_fork.get_counter("counter").increment("visits", 3);
+/// db.merge(first_fork.into_patch()).unwrap();
+/// db.merge(second_fork.into_patch()).unwrap();
+///
+/// let snapshot = db.snapshot();
+/// assert_eq!(snapshot.get_counter::<_, str>("counter").get("visits"), 5);
+/// ```
+///
 /// [`snapshot`]: #tymethod.snapshot
 /// [`fork`]: #method.fork
 /// [`merge`]: #tymethod.merge
+/// [`CounterIndex`]: indexes/struct.CounterIndex.html
 /// [interior-mut]: https://doc.rust-lang.org/book/ch15-05-interior-mutability.html
 pub trait Database: Send + Sync + 'static {
     /// Creates a new snapshot of the database from its current state.
@@ -495,11 +926,12 @@ pub trait Database: Send + Sync + 'static {
     /// Creates a new fork of the database from its current state.
     fn fork(&self) -> Fork {
         Fork {
-            patch: Patch {
-                snapshot: self.snapshot(),
-                changes: HashMap::new(),
-            },
+            patch: Patch::from_changes(self.snapshot(), HashMap::new()),
             working_patch: WorkingPatch::new(),
+            savepoints: Vec::new(),
+            next_savepoint_id: 0,
+            memory_limit: None,
+            memory_usage: 0,
         }
     }
 
@@ -538,6 +970,55 @@ pub trait Database: Send + Sync + 'static {
     /// will be returned. In case of an error, the method guarantees no changes are applied to
     /// the database.
     fn merge_sync(&self, patch: Patch) -> Result<()>;
+
+    /// Atomically applies changes from a detached patch to the database.
+    ///
+    /// The patch is subject to the same logical safety rules as patches passed
+    /// to [`merge`](#tymethod.merge). In particular, the patch should be created from
+    /// a database with the same state as this one; otherwise, index metadata may become
+    /// inconsistent.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the patch checksum does not match its contents, or in the same
+    /// situations as `merge()`. In case of an error, no changes are applied to the database.
+    fn merge_detached(&self, patch: DetachedPatch) -> Result<()> {
+        let changes = patch.into_changes()?;
+        self.merge(Patch::from_changes(self.snapshot(), changes))
+    }
+
+    /// Writes entries directly into the view with the specified resolved address, bypassing
+    /// forks. Entries must be sorted by key in the strictly ascending order; keys are provided
+    /// without the index identifier prefix.
+    ///
+    /// This is a low-level method used by the [bulk loader](bulk/index.html). Unlike `merge`,
+    /// it is not guaranteed to be atomic; the caller should ensure that the written entries
+    /// are not visible until the ingestion is complete (e.g., by writing them into an index
+    /// identifier not yet referenced by any index metadata).
+    ///
+    /// The default implementation writes entries via forks in batches. Backends may override it
+    /// with a more efficient implementation.
+    fn ingest_sorted(
+        &self,
+        address: &ResolvedAddress,
+        entries: &mut dyn StdIterator<Item = (Vec<u8>, Vec<u8>)>,
+    ) -> Result<()> {
+        /// Number of entries written in a single fork.
+        const BATCH_SIZE: usize = 10_000;
+
+        let mut entries = entries.peekable();
+        while entries.peek().is_some() {
+            let fork = self.fork();
+            {
+                let mut view = View::new(&fork, address.clone());
+                for (key, value) in entries.by_ref().take(BATCH_SIZE) {
+                    view.put(&key[..], value);
+                }
+            }
+            self.merge(fork.into_patch())?;
+        }
+        Ok(())
+    }
 }
 
 /// Extension trait for `Database`.
@@ -593,6 +1074,9 @@ pub trait DatabaseExt: Database {
     ///
     /// Returns an error in the same situations as `Database::merge()`.
     fn merge_with_backup(&self, patch: Patch) -> Result<Patch> {
+        // Reverse changes are kept in memory, so there is little point in streaming
+        // the original changes.
+        let patch = patch.load_spilled();
         let snapshot = self.snapshot();
         let mut rev_changes = HashMap::with_capacity(patch.changes.len());
 
@@ -620,10 +1104,7 @@ pub trait DatabaseExt: Database {
         }
 
         self.merge(patch)?;
-        Ok(Patch {
-            snapshot: self.snapshot(),
-            changes: rev_changes,
-        })
+        Ok(Patch::from_changes(self.snapshot(), rev_changes))
     }
 }
 
@@ -639,6 +1120,15 @@ pub trait Snapshot: Send + Sync + 'static {
     /// or `None` if it does not exist.
     fn get(&self, name: &ResolvedAddress, key: &[u8]) -> Option<Vec<u8>>;
 
+    /// Returns a value corresponding to the specified address and key, or `None` if it
+    /// does not exist. Unlike [`get`](#tymethod.get), the value may borrow from the snapshot
+    /// without copying.
+    ///
+    /// The default implementation wraps the value returned by `get`.
+    fn get_pinned(&self, name: &ResolvedAddress, key: &[u8]) -> Option<PinnedValue<'_>> {
+        self.get(name, key).map(PinnedValue::owned)
+    }
+
     /// Returns a value for each key corresponding to the specified address and this key as a raw vector of bytes,
     /// or `None` if it does not exist.
     fn multi_get<'a>(
@@ -657,6 +1147,44 @@ pub trait Snapshot: Send + Sync + 'static {
     /// Returns an iterator over the entries of the snapshot in ascending order starting from
     /// the specified key. The iterator element type is `(&[u8], &[u8])`.
     fn iter(&self, name: &ResolvedAddress, from: &[u8]) -> Iter<'_>;
+
+    /// Returns the sequence number of the database state captured by the snapshot, or `None`
+    /// if the backend does not support sequence numbers.
+    ///
+    /// Sequence numbers are specific to a database instance and increase with each merge.
+    /// The sequence number must correspond to the snapshot state exactly, even if a merge
+    /// is performed concurrently with creating the snapshot.
+    fn sequence_number(&self) -> Option<u64> {
+        None
+    }
+
+    /// Returns the cache of index metadata consistent with the snapshot contents, or `None`
+    /// if the snapshot does not support caching. The cache is used to avoid reading
+    /// the indexes pool each time an index is accessed.
+    ///
+    /// The default implementation returns `None`.
+    fn metadata_cache(&self) -> Option<&MetadataCache> {
+        None
+    }
+
+    /// Returns the cache of decoded values shared with other snapshots of the database,
+    /// or `None` if the snapshot does not use the value cache.
+    ///
+    /// The default implementation returns `None`.
+    fn value_cache(&self) -> Option<ValueCacheRef<'_>> {
+        None
+    }
+
+    /// Prepares the storage for writing changes to the views with the specified addresses,
+    /// e.g., by creating missing column families. The method is called when a patch based
+    /// on the snapshot is created, so that merging the patch does not need to change
+    /// the storage layout and does not block concurrent merges.
+    ///
+    /// Errors are not reported by this method; if the storage cannot be prepared,
+    /// merging the patch fails.
+    ///
+    /// The default implementation does nothing.
+    fn prepare_views(&self, _addrs: &mut dyn StdIterator<Item = &ResolvedAddress>) {}
 }
 
 /// A trait that defines a streaming iterator over storage view entries. Unlike
@@ -671,9 +1199,176 @@ pub trait Iterator {
 }
 
 impl Patch {
-    /// Iterates over changes in this patch.
-    pub(crate) fn into_changes(self) -> HashMap<ResolvedAddress, ViewChanges> {
-        self.changes
+    /// Creates a patch with the specified in-memory changes.
+    pub(crate) fn from_changes(
+        snapshot: Box<dyn Snapshot>,
+        changes: HashMap<ResolvedAddress, ViewChanges>,
+    ) -> Self {
+        snapshot.prepare_views(&mut changes.keys());
+        Self {
+            snapshot,
+            changes,
+            spilled: None,
+            metadata_cache: Some(MetadataCache::new()),
+        }
+    }
+
+    /// Disables caching of index metadata in the patch.
+    pub(crate) fn without_metadata_cache(mut self) -> Self {
+        self.metadata_cache = None;
+        self
+    }
+
+    /// Iterates over changes in this patch. Changes spilled to disk are streamed
+    /// rather than loaded into memory at once.
+    pub(crate) fn into_changes(self) -> Vec<(ResolvedAddress, PatchChanges)> {
+        Self::combine_changes(self.changes, self.spilled.as_ref())
+    }
+
+    /// Same as `into_changes`, but clones in-memory changes instead of consuming the patch.
+    pub(crate) fn to_changes(&self) -> Vec<(ResolvedAddress, PatchChanges)> {
+        Self::combine_changes(self.changes.clone(), self.spilled.as_ref())
+    }
+
+    /// Returns keys changed in views satisfying the `predicate`, together with the flag
+    /// indicating whether the view was cleared.
+    pub(crate) fn changed_keys(
+        &self,
+        predicate: impl Fn(&ResolvedAddress) -> bool,
+    ) -> Vec<(ResolvedAddress, bool, Vec<Vec<u8>>)> {
+        let changes = self
+            .changes
+            .iter()
+            .filter(|(address, _)| predicate(address))
+            .map(|(address, changes)| (address.clone(), changes.clone()))
+            .collect();
+
+        Self::combine_changes(changes, self.spilled.as_ref())
+            .into_iter()
+            .filter(|(address, _)| predicate(address))
+            .map(|(address, changes)| {
+                let is_cleared = changes.is_cleared();
+                let keys = changes.into_data().map(|(key, _)| key).collect();
+                (address, is_cleared, keys)
+            })
+            .collect()
+    }
+
+    /// Checks whether the patch contains changes to the view with the specified address.
+    pub(crate) fn changes_view(&self, address: &ResolvedAddress) -> bool {
+        self.changes.contains_key(address)
+            || self
+                .spilled
+                .as_ref()
+                .map_or(false, |spilled| spilled.contains_view(address))
+    }
+
+    /// Returns the snapshot the patch is based on.
+    pub(crate) fn base_snapshot(&self) -> &dyn Snapshot {
+        &*self.snapshot
+    }
+
+    /// Returns the sequence number of the snapshot the patch is based on.
+    pub(crate) fn base_sequence_number(&self) -> Option<u64> {
+        self.snapshot.sequence_number()
+    }
+
+    /// Copies changes from this patch into a [`DetachedPatch`], which is not tied
+    /// to the database snapshot and can be serialized.
+    ///
+    /// [`DetachedPatch`]: struct.DetachedPatch.html
+    pub fn to_detached(&self) -> DetachedPatch {
+        DetachedPatch::new(self)
+    }
+
+    fn combine_changes(
+        changes: HashMap<ResolvedAddress, ViewChanges>,
+        spilled: Option<&SpilledChanges>,
+    ) -> Vec<(ResolvedAddress, PatchChanges)> {
+        let mut spilled = spilled.map_or_else(HashMap::new, SpilledChanges::streams);
+        let mut combined = Vec::with_capacity(changes.len() + spilled.len());
+
+        for (address, changes) in changes {
+            let changes = match spilled.remove(&address) {
+                // Cleared in-memory changes override spilled changes completely.
+                Some(older) if !changes.is_cleared() => PatchChanges {
+                    is_cleared: older.is_cleared(),
+                    data: Box::new(CombinedStream::new(older, changes)),
+                },
+                _ => PatchChanges {
+                    is_cleared: changes.is_cleared(),
+                    data: Box::new(changes.into_data().into_iter()),
+                },
+            };
+            combined.push((address, changes));
+        }
+
+        for (address, older) in spilled {
+            let changes = PatchChanges {
+                is_cleared: older.is_cleared(),
+                data: Box::new(older),
+            };
+            combined.push((address, changes));
+        }
+        combined
+    }
+
+    /// Loads changes spilled to disk into memory.
+    fn load_spilled(self) -> Self {
+        if self.spilled.is_none() {
+            return self;
+        }
+
+        let changes = Self::combine_changes(self.changes, self.spilled.as_ref())
+            .into_iter()
+            .map(|(address, changes)| {
+                let is_cleared = changes.is_cleared();
+                let data = changes.into_data().collect();
+                (address, ViewChanges::from_parts(data, is_cleared))
+            })
+            .collect();
+        Self::from_changes(self.snapshot, changes)
+    }
+
+    /// Prepares the storage of the base snapshot for writing the changes in this patch.
+    fn prepare_storage(&self) {
+        let spilled = self.spilled.iter().flat_map(SpilledChanges::views);
+        self.snapshot
+            .prepare_views(&mut self.changes.keys().chain(spilled));
+    }
+
+    /// Moves in-memory changes to the on-disk store.
+    fn spill(&mut self) -> Result<()> {
+        let changes = mem::take(&mut self.changes);
+        if let Some(spilled) = self.spilled.as_mut() {
+            spilled.spill(changes)
+        } else {
+            let mut spilled = SpilledChanges::new()?;
+            spilled.spill(changes)?;
+            self.spilled = Some(spilled);
+            Ok(())
+        }
+    }
+
+    fn base_get(&self, name: &ResolvedAddress, key: &[u8]) -> Option<Vec<u8>> {
+        match self.spilled {
+            Some(ref spilled) => spilled.get(name, key, &*self.snapshot),
+            None => self.snapshot.get(name, key),
+        }
+    }
+
+    fn base_contains(&self, name: &ResolvedAddress, key: &[u8]) -> bool {
+        match self.spilled {
+            Some(ref spilled) => spilled.contains(name, key, &*self.snapshot),
+            None => self.snapshot.contains(name, key),
+        }
+    }
+
+    fn base_iter(&self, name: &ResolvedAddress, from: &[u8]) -> Iter<'_> {
+        match self.spilled {
+            Some(ref spilled) => spilled.iter(name, from, &*self.snapshot),
+            None => self.snapshot.iter(name, from),
+        }
     }
 }
 
@@ -681,9 +1376,23 @@ impl Snapshot for Patch {
     fn get(&self, name: &ResolvedAddress, key: &[u8]) -> Option<Vec<u8>> {
         self.changes
             .get(name)
-            .map_or(Err(()), |changes| changes.get(key))
+            .map_or(Err(None), |changes| changes.get(key))
             // At this point, `Err(_)` signifies that we need to retrieve data from the snapshot.
-            .unwrap_or_else(|()| self.snapshot.get(name, key))
+            .unwrap_or_else(|operand| resolve_merge(self.base_get(name, key), operand))
+    }
+
+    fn get_pinned(&self, name: &ResolvedAddress, key: &[u8]) -> Option<PinnedValue<'_>> {
+        let value = self
+            .changes
+            .get(name)
+            .map_or(Err(None), |changes| changes.get_ref(key));
+        match value {
+            Ok(value) => value.map(PinnedValue::borrowed),
+            Err(None) if self.spilled.is_none() => self.snapshot.get_pinned(name, key),
+            Err(operand) => {
+                resolve_merge(self.base_get(name, key), operand).map(PinnedValue::owned)
+            }
+        }
     }
 
     fn multi_get<'a>(
@@ -696,23 +1405,34 @@ impl Snapshot for Patch {
         let (mut res, db_keys) = keys.into_iter().enumerate().fold(
             (Vec::new(), Vec::new()),
             |(mut res, mut db_keys), (idx, key)| {
-                if let Some(Ok(item)) = changes.map(|changes| changes.get(key)) {
-                    res.push(item);
-                } else {
-                    res.push(None);
-                    db_keys.push((idx, key));
+                match changes.map_or(Err(None), |changes| changes.get(key)) {
+                    Ok(item) => res.push(item),
+                    Err(operand) => {
+                        res.push(None);
+                        db_keys.push((idx, key, operand));
+                    }
                 }
 
                 (res, db_keys)
             },
         );
 
-        let db_res = self
-            .snapshot
-            .multi_get(name, &mut db_keys.iter().map(|(_, key)| *key));
+        let is_spilled = self
+            .spilled
+            .as_ref()
+            .map_or(false, |spilled| spilled.contains_view(name));
+        let db_res = if is_spilled {
+            db_keys
+                .iter()
+                .map(|(_, key, _)| self.base_get(name, key))
+                .collect()
+        } else {
+            self.snapshot
+                .multi_get(name, &mut db_keys.iter().map(|(_, key, _)| *key))
+        };
 
-        for ((idx, _), item) in db_keys.into_iter().zip(db_res) {
-            res[idx] = item;
+        for ((idx, _, operand), item) in db_keys.into_iter().zip(db_res) {
+            res[idx] = resolve_merge(item, operand);
         }
 
         res
@@ -723,7 +1443,7 @@ impl Snapshot for Patch {
             .get(name)
             .map_or(Err(()), |changes| changes.contains(key))
             // At this point, `Err(_)` signifies that we need to retrieve data from the snapshot.
-            .unwrap_or_else(|()| self.snapshot.contains(name, key))
+            .unwrap_or_else(|()| self.base_contains(name, key))
     }
 
     fn iter(&self, name: &ResolvedAddress, from: &[u8]) -> Iter<'_> {
@@ -739,9 +1459,21 @@ impl Snapshot for Patch {
             // Ignore all changes from the snapshot.
             Box::new(ChangesIter::new(changes_iter.unwrap()))
         } else {
-            Box::new(ForkIter::new(self.snapshot.iter(name, from), changes_iter))
+            Box::new(ForkIter::new(self.base_iter(name, from), changes_iter))
         }
     }
+
+    fn sequence_number(&self) -> Option<u64> {
+        self.snapshot.sequence_number()
+    }
+
+    fn metadata_cache(&self) -> Option<&MetadataCache> {
+        self.metadata_cache.as_ref()
+    }
+
+    fn prepare_views(&self, addrs: &mut dyn StdIterator<Item = &ResolvedAddress>) {
+        self.snapshot.prepare_views(addrs);
+    }
 }
 
 impl RawAccess for &'_ Patch {
@@ -768,7 +1500,133 @@ impl Fork {
     /// made after creation of `Fork`.
     pub fn flush(&mut self) {
         let working_patch = mem::replace(&mut self.working_patch, WorkingPatch::new());
-        working_patch.merge_into(&mut self.patch);
+        if self.memory_limit.is_some() {
+            self.memory_usage += working_patch.approximate_size();
+        }
+        working_patch.merge_into(&mut self.patch, self.savepoints.last_mut());
+        self.spill_if_necessary();
+    }
+
+    /// Sets an approximate limit on the memory occupied by changes in this fork.
+    ///
+    /// Once the changes flushed into the fork exceed the limit, they are moved to a temporary
+    /// on-disk store; the changes remain readable from the fork and are streamed
+    /// from the store when the patch is merged into the database. Since the limit is checked
+    /// only when the fork is flushed, large imports should call [`flush`] periodically.
+    /// Changes are not spilled while there are active [savepoints](#method.savepoint).
+    ///
+    /// By default, forks have no memory limit.
+    ///
+    /// # Panics
+    ///
+    /// Spilling changes panics if the temporary store cannot be created or written to.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use matterdb::{access::CopyAccessExt, Database, TemporaryDB};
+    /// let db = TemporaryDB::new();
+    /// let mut fork = db.fork();
+    /// fork.set_memory_limit(1 << 20);
+    /// for i in 0_u64..10_000 {
+    ///     fork.get_list("list").push(i);
+    ///     if i % 1_000 == 0 {
+    ///         fork.flush();
+    ///     }
+    /// }
+    /// db.merge(fork.into_patch()).unwrap();
+    /// assert_eq!(db.snapshot().get_list::<_, u64>("list").len(), 10_000);
+    /// ```
+    ///
+    /// [`flush`]: #method.flush
+    pub fn set_memory_limit(&mut self, limit: usize) {
+        self.memory_limit = Some(limit);
+    }
+
+    #[cfg(test)]
+    pub(crate) fn has_spilled_changes(&self) -> bool {
+        self.patch.spilled.is_some()
+    }
+
+    fn spill_if_necessary(&mut self) {
+        let is_over_limit = self
+            .memory_limit
+            .map_or(false, |limit| self.memory_usage > limit);
+        if is_over_limit && self.savepoints.is_empty() {
+            self.patch
+                .spill()
+                .unwrap_or_else(|e| panic!("MerkleDB error: {}", e));
+            self.memory_usage = 0;
+        }
+    }
+
+    /// Creates a savepoint capturing the current state of the fork. The fork can be
+    /// rolled back to this state using [`rollback_to`]. Savepoints can be nested; creating
+    /// a savepoint flushes the fork.
+    ///
+    /// [`rollback_to`]: #method.rollback_to
+    pub fn savepoint(&mut self) -> Savepoint {
+        self.flush();
+        let id = self.next_savepoint_id;
+        self.next_savepoint_id += 1;
+        self.savepoints.push(SavepointLayer::new(id));
+        Savepoint {
+            depth: self.savepoints.len() - 1,
+            id,
+        }
+    }
+
+    /// Rolls back all changes made after the specified savepoint was created, including
+    /// the changes made after the latest `flush`. Savepoints nested in the specified one
+    /// are discarded, while the savepoint itself remains active; i.e., it is possible
+    /// to roll back to it again.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the savepoint was released, or discarded as a result of rolling back
+    /// to an outer savepoint.
+    pub fn rollback_to(&mut self, savepoint: Savepoint) {
+        let depth = self.savepoint_depth(savepoint);
+        self.working_patch = WorkingPatch::new();
+        while self.savepoints.len() > depth {
+            // `unwrap()` is safe: the loop condition guarantees that the stack is non-empty.
+            let layer = self.savepoints.pop().unwrap();
+            layer.restore(&mut self.patch);
+        }
+        self.savepoints.push(SavepointLayer::new(savepoint.id));
+    }
+
+    /// Releases the specified savepoint together with all savepoints nested in it.
+    /// Changes made after the savepoint are retained and become a part of the enclosing
+    /// savepoint, if any.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the savepoint was released, or discarded as a result of rolling back
+    /// to an outer savepoint.
+    pub fn release(&mut self, savepoint: Savepoint) {
+        let depth = self.savepoint_depth(savepoint);
+        while self.savepoints.len() > depth {
+            // `unwrap()` is safe: the loop condition guarantees that the stack is non-empty.
+            let layer = self.savepoints.pop().unwrap();
+            if let Some(parent) = self.savepoints.last_mut() {
+                parent.absorb(layer);
+            }
+        }
+    }
+
+    /// Returns the position of an active savepoint in the savepoint stack.
+    fn savepoint_depth(&self, savepoint: Savepoint) -> usize {
+        let is_active = self
+            .savepoints
+            .get(savepoint.depth)
+            .map_or(false, |layer| layer.id == savepoint.id);
+        assert!(
+            is_active,
+            "Savepoint {:?} is not active in the fork",
+            savepoint
+        );
+        savepoint.depth
     }
 
     /// Finishes a migration of indexes with the specified prefix.
@@ -780,6 +1638,9 @@ impl Fork {
 
         let removed_addrs = IndexesPool::new(&*self).flush_migration(prefix);
         for addr in removed_addrs {
+            if let Some(savepoint) = self.savepoints.last_mut() {
+                savepoint.record_view(&addr, self.patch.changes.get(&addr));
+            }
             self.patch.changes.entry(addr).or_default().clear();
         }
     }
@@ -797,13 +1658,37 @@ impl Fork {
         self.flush();
         let removed_addrs = IndexesPool::new(&*self).rollback_migration(prefix);
         for addr in &removed_addrs {
+            if let Some(savepoint) = self.savepoints.last_mut() {
+                savepoint.record_view(addr, self.patch.changes.get(addr));
+            }
             self.patch.changes.remove(addr);
+            if let Some(spilled) = self.patch.spilled.as_mut() {
+                spilled
+                    .discard_view(addr)
+                    .unwrap_or_else(|e| panic!("MerkleDB error: {}", e));
+            }
         }
     }
 
+    /// Returns the patch with the changes flushed in the fork.
+    pub(crate) fn flushed_patch(&self) -> &Patch {
+        &self.patch
+    }
+
     /// Converts the fork into `Patch` consuming the fork instance.
+    ///
+    /// If the fork changes old data of a migration which logs live changes, the changed
+    /// keys are recorded in the migration change log, so that they can be replayed
+    /// with [`MigrationHelper::catch_up`].
+    ///
+    /// The storage of the database is prepared for merging the patch at this point; e.g.,
+    /// column families for new indexes are created in `RocksDB`.
+    ///
+    /// [`MigrationHelper::catch_up`]: migration/struct.MigrationHelper.html#method.catch_up
     pub fn into_patch(mut self) -> Patch {
         self.flush();
+        migration::log_live_changes(&mut self);
+        self.patch.prepare_storage();
         self.patch
     }
 
@@ -825,6 +1710,10 @@ impl From<Patch> for Fork {
         Self {
             patch,
             working_patch: WorkingPatch::new(),
+            savepoints: Vec::new(),
+            next_savepoint_id: 0,
+            memory_limit: None,
+            memory_usage: 0,
         }
     }
 }
@@ -1004,6 +1893,10 @@ impl Snapshot for Box<dyn Snapshot> {
         self.as_ref().get(name, key)
     }
 
+    fn get_pinned(&self, name: &ResolvedAddress, key: &[u8]) -> Option<PinnedValue<'_>> {
+        self.as_ref().get_pinned(name, key)
+    }
+
     fn multi_get<'a>(
         &self,
         name: &ResolvedAddress,
@@ -1019,6 +1912,22 @@ impl Snapshot for Box<dyn Snapshot> {
     fn iter(&self, name: &ResolvedAddress, from: &[u8]) -> Iter<'_> {
         self.as_ref().iter(name, from)
     }
+
+    fn sequence_number(&self) -> Option<u64> {
+        self.as_ref().sequence_number()
+    }
+
+    fn metadata_cache(&self) -> Option<&MetadataCache> {
+        self.as_ref().metadata_cache()
+    }
+
+    fn value_cache(&self) -> Option<ValueCacheRef<'_>> {
+        self.as_ref().value_cache()
+    }
+
+    fn prepare_views(&self, addrs: &mut dyn StdIterator<Item = &ResolvedAddress>) {
+        self.as_ref().prepare_views(addrs);
+    }
 }
 
 impl<'a, T> ForkIter<'a, T>
@@ -1029,6 +1938,7 @@ where
         ForkIter {
             snapshot,
             changes: changes.map(StdIterator::peekable),
+            merged_value: Vec::new(),
         }
     }
 
@@ -1050,10 +1960,16 @@ where
                             Less => NextIterValue::MissDeleted,
                             Greater => NextIterValue::Stored,
                         },
+                        Change::Merge(..) => match k[..].cmp(key) {
+                            Equal => NextIterValue::Merged,
+                            Less => NextIterValue::MergeInserted,
+                            Greater => NextIterValue::Stored,
+                        },
                     },
                     None => match *change {
                         Change::Put(..) => NextIterValue::Inserted,
                         Change::Delete => NextIterValue::MissDeleted,
+                        Change::Merge(..) => NextIterValue::MergeInserted,
                     },
                 },
                 None => match self.snapshot.peek() {
@@ -1068,6 +1984,24 @@ where
             }
         }
     }
+
+    /// Applies the merge operand from the next change to the next value in the snapshot
+    /// (if `with_snapshot` is set) or to an absent value, and stores the result
+    /// in `merged_value`.
+    fn merge_next(&mut self, with_snapshot: bool) -> &'a [u8] {
+        let (key, change) = self.changes.as_mut().unwrap().peek().copied().unwrap();
+        let operand = match *change {
+            Change::Merge(ref operand) => operand,
+            _ => unreachable!(),
+        };
+        let existing = if with_snapshot {
+            self.snapshot.peek().map(|(_, value)| value)
+        } else {
+            None
+        };
+        self.merged_value = merge_counter(existing, operand);
+        key.as_slice()
+    }
 }
 
 impl<'a, T> Iterator for ForkIter<'a, T>
@@ -1085,7 +2019,7 @@ where
                             key.as_slice(),
                             match *change {
                                 Change::Put(ref value) => value.as_slice(),
-                                Change::Delete => unreachable!(),
+                                Change::Delete | Change::Merge(..) => unreachable!(),
                             },
                         )
                     });
@@ -1096,11 +2030,22 @@ where
                             key.as_slice(),
                             match *change {
                                 Change::Put(ref value) => value.as_slice(),
-                                Change::Delete => unreachable!(),
+                                Change::Delete | Change::Merge(..) => unreachable!(),
                             },
                         )
                     });
                 }
+                NextIterValue::Merged => {
+                    let key = self.merge_next(true);
+                    self.snapshot.next();
+                    self.changes.as_mut().unwrap().next();
+                    return Some((key, &self.merged_value));
+                }
+                NextIterValue::MergeInserted => {
+                    let key = self.merge_next(false);
+                    self.changes.as_mut().unwrap().next();
+                    return Some((key, &self.merged_value));
+                }
                 NextIterValue::Deleted => {
                     self.changes.as_mut().unwrap().next();
                     self.snapshot.next();
@@ -1123,11 +2068,19 @@ where
                             key.as_slice(),
                             match *change {
                                 Change::Put(ref value) => value.as_slice(),
-                                Change::Delete => unreachable!(),
+                                Change::Delete | Change::Merge(..) => unreachable!(),
                             },
                         )
                     });
                 }
+                NextIterValue::Merged => {
+                    let key = self.merge_next(true);
+                    return Some((key, &self.merged_value));
+                }
+                NextIterValue::MergeInserted => {
+                    let key = self.merge_next(false);
+                    return Some((key, &self.merged_value));
+                }
                 NextIterValue::Deleted => {
                     self.changes.as_mut().unwrap().next();
                     self.snapshot.next();
@@ -1497,4 +2450,122 @@ mod tests {
         // Since the index is already created, this should lead to a panic.
         let _readonly_entry = fork.readonly().get_entry::<_, u32>("entry");
     }
+
+    fn list_contents(fork: &Fork, name: &str) -> Vec<u32> {
+        fork.readonly().get_list::<_, u32>(name).iter().collect()
+    }
+
+    #[test]
+    fn nested_savepoints() {
+        let db = TemporaryDB::new();
+        let mut fork = db.fork();
+        fork.get_list("list").push(1_u32);
+
+        let outer = fork.savepoint();
+        fork.get_list("list").push(2_u32);
+        fork.get_entry("entry").set(1_u32);
+        let inner = fork.savepoint();
+        fork.get_list("list").push(3_u32);
+        fork.flush();
+        fork.get_list("other").push(4_u32);
+        assert_eq!(list_contents(&fork, "list"), vec![1, 2, 3]);
+
+        fork.rollback_to(inner);
+        assert_eq!(list_contents(&fork, "list"), vec![1, 2]);
+        assert!(list_contents(&fork, "other").is_empty());
+        assert_eq!(fork.get_entry::<_, u32>("entry").get(), Some(1));
+
+        // The savepoint remains active after rolling back to it.
+        fork.get_list("list").push(5_u32);
+        fork.rollback_to(inner);
+        assert_eq!(list_contents(&fork, "list"), vec![1, 2]);
+
+        fork.rollback_to(outer);
+        assert_eq!(list_contents(&fork, "list"), vec![1]);
+        assert_eq!(fork.get_entry::<_, u32>("entry").get(), None);
+
+        fork.get_list("list").push(6_u32);
+        fork.release(outer);
+        let patch = fork.into_patch();
+        let list = patch.get_list::<_, u32>("list");
+        assert_eq!(list.iter().collect::<Vec<_>>(), vec![1, 6]);
+    }
+
+    #[test]
+    fn released_savepoint_changes_belong_to_parent() {
+        let db = TemporaryDB::new();
+        let mut fork = db.fork();
+        fork.get_map("map").put(&1_u8, 1_u32);
+
+        let outer = fork.savepoint();
+        fork.get_map("map").put(&1_u8, 2_u32);
+        let inner = fork.savepoint();
+        {
+            let mut map = fork.get_map("map");
+            map.clear();
+            map.put(&2_u8, 3_u32);
+        }
+        let _innermost = fork.savepoint();
+        fork.get_map("map").put(&3_u8, 4_u32);
+        fork.release(inner);
+        {
+            let map = fork.readonly().get_map::<_, u8, u32>("map");
+            assert_eq!(map.iter().collect::<Vec<_>>(), vec![(2, 3), (3, 4)]);
+        }
+
+        fork.rollback_to(outer);
+        let map = fork.readonly().get_map::<_, u8, u32>("map");
+        assert_eq!(map.iter().collect::<Vec<_>>(), vec![(1, 1)]);
+    }
+
+    #[test]
+    fn savepoints_and_plain_rollback() {
+        let db = TemporaryDB::new();
+        let mut fork = db.fork();
+        let savepoint = fork.savepoint();
+        fork.get_list("list").push(1_u32);
+        fork.flush();
+        fork.get_list("list").push(2_u32);
+        fork.rollback();
+        assert_eq!(list_contents(&fork, "list"), vec![1]);
+        fork.rollback_to(savepoint);
+        assert!(list_contents(&fork, "list").is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "is not active")]
+    fn using_released_savepoint() {
+        let db = TemporaryDB::new();
+        let mut fork = db.fork();
+        let savepoint = fork.savepoint();
+        fork.release(savepoint);
+        // This savepoint has the same depth as the released one.
+        let _other = fork.savepoint();
+        fork.rollback_to(savepoint);
+    }
+
+    #[test]
+    #[should_panic(expected = "is not active")]
+    fn using_discarded_nested_savepoint() {
+        let db = TemporaryDB::new();
+        let mut fork = db.fork();
+        let outer = fork.savepoint();
+        let inner = fork.savepoint();
+        fork.rollback_to(outer);
+        fork.release(inner);
+    }
+
+    #[test]
+    fn savepoints_with_counter_merges() {
+        let db = TemporaryDB::new();
+        let mut fork = db.fork();
+        fork.get_counter("counter").increment(&1_u8, 1);
+        let savepoint = fork.savepoint();
+        fork.get_counter("counter").increment(&1_u8, 2);
+        fork.flush();
+        fork.get_counter("counter").increment(&1_u8, 3);
+        assert_eq!(fork.get_counter::<_, u8>("counter").get(&1), 6);
+        fork.rollback_to(savepoint);
+        assert_eq!(fork.get_counter::<_, u8>("counter").get(&1), 1);
+    }
 }
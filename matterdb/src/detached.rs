@@ -0,0 +1,362 @@
+//! Detached patches, i.e., patches that are not tied to a database snapshot.
+//!
+//! A `Patch` is bound to the snapshot it was created from, which makes it impossible
+//! to send it to another process. A `DetachedPatch` contains only the changes from the patch
+//! (resolved addresses of the changed views, their cleared flags and ordered changes
+//! within each view) and can be serialized with any `serde` format. The patch is protected
+//! by a checksum, which is verified when the patch is merged into a database.
+
+use serde::{Deserialize, Serialize};
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    num::NonZeroU64,
+};
+
+use crate::{
+    db::{Change, ViewChanges},
+    Error, Patch, ResolvedAddress, Result,
+};
+
+/// Changes of a single view within a [`DetachedPatch`].
+///
+/// [`DetachedPatch`]: struct.DetachedPatch.html
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DetachedView {
+    address: ResolvedAddress,
+    is_cleared: bool,
+    changes: Vec<(Vec<u8>, Change)>,
+}
+
+impl DetachedView {
+    /// Returns the resolved address of the view.
+    pub fn address(&self) -> &ResolvedAddress {
+        &self.address
+    }
+
+    /// Returns `true` if the view is cleared before applying changes.
+    pub fn is_cleared(&self) -> bool {
+        self.is_cleared
+    }
+
+    /// Returns changes within the view, ordered by key.
+    pub fn changes(&self) -> &[(Vec<u8>, Change)] {
+        &self.changes
+    }
+}
+
+/// Patch detached from the database snapshot it was created from.
+///
+/// A detached patch can be serialized, sent to another process (e.g., to a replica
+/// or an out-of-process writer) and applied there using [`Database::merge_detached`].
+/// The patch contains a CRC-32 checksum of its contents, which is checked on merge.
+///
+/// # Examples
+///
+/// ```
+/// # use matterdb::{access::CopyAccessExt, Database, DetachedPatch, TemporaryDB};
+/// let db = TemporaryDB::new();
+/// let fork = db.fork();
+/// fork.get_list("list").extend(vec![1_u32, 2, 3]);
+/// let detached: DetachedPatch = fork.into_patch().to_detached();
+///
+/// // The patch may be sent to another process here.
+/// let replica = TemporaryDB::new();
+/// replica.merge_detached(detached).unwrap();
+/// let snapshot = replica.snapshot();
+/// assert_eq!(snapshot.get_list::<_, u32>("list").len(), 3);
+/// ```
+///
+/// [`Database::merge_detached`]: trait.Database.html#method.merge_detached
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DetachedPatch {
+    base_sequence_number: Option<u64>,
+    views: Vec<DetachedView>,
+    checksum: u32,
+}
+
+impl DetachedPatch {
+    /// Creates a detached patch from the changes in the specified patch.
+    pub(crate) fn new(patch: &Patch) -> Self {
+        let base_sequence_number = patch.base_sequence_number();
+        let mut views: Vec<_> = patch
+            .to_changes()
+            .into_iter()
+            .map(|(address, changes)| DetachedView {
+                address,
+                is_cleared: changes.is_cleared(),
+                changes: changes.into_data().collect(),
+            })
+            .collect();
+        // Views are sorted to make the patch contents deterministic.
+        views.sort_unstable_by(|x, y| {
+            (&x.address.name, x.address.id).cmp(&(&y.address.name, y.address.id))
+        });
+
+        let checksum = Self::compute_checksum(base_sequence_number, &views);
+        Self {
+            base_sequence_number,
+            views,
+            checksum,
+        }
+    }
+
+    /// Returns the sequence number of the snapshot the patch was created from, if
+    /// the database backend supports sequence numbers.
+    ///
+    /// Sequence numbers are specific to a database instance; they are not checked when
+    /// the patch is merged. The caller may use them to ensure that a replica has applied
+    /// the same changes as the database the patch was created from.
+    pub fn base_sequence_number(&self) -> Option<u64> {
+        self.base_sequence_number
+    }
+
+    /// Returns changes in the patch grouped by view.
+    pub fn views(&self) -> &[DetachedView] {
+        &self.views
+    }
+
+    /// Returns the checksum of the patch.
+    pub fn checksum(&self) -> u32 {
+        self.checksum
+    }
+
+    /// Checks that the patch checksum corresponds to the patch contents.
+    pub fn verify(&self) -> Result<()> {
+        let expected = Self::compute_checksum(self.base_sequence_number, &self.views);
+        if expected == self.checksum {
+            Ok(())
+        } else {
+            Err(Error::new(format!(
+                "Detached patch checksum mismatch: expected {:#010x}, got {:#010x}",
+                expected, self.checksum
+            )))
+        }
+    }
+
+    /// Converts the patch into in-memory changes after verifying its checksum.
+    pub(crate) fn into_changes(self) -> Result<HashMap<ResolvedAddress, ViewChanges>> {
+        self.verify()?;
+        let changes = self
+            .views
+            .into_iter()
+            .map(|view| {
+                let data: BTreeMap<_, _> = view.changes.into_iter().collect();
+                (view.address, ViewChanges::from_parts(data, view.is_cleared))
+            })
+            .collect();
+        Ok(changes)
+    }
+
+    fn compute_checksum(base_sequence_number: Option<u64>, views: &[DetachedView]) -> u32 {
+        let mut hasher = Crc32::new();
+        match base_sequence_number {
+            Some(number) => {
+                hasher.update(&[1]);
+                hasher.update(&number.to_le_bytes());
+            }
+            None => hasher.update(&[0]),
+        }
+
+        hasher.update_len(views.len());
+        for view in views {
+            hasher.update_bytes(view.address.name.as_bytes());
+            let id = view.address.id.map_or(0, NonZeroU64::get);
+            hasher.update(&id.to_le_bytes());
+            hasher.update(&[u8::from(view.is_cleared)]);
+
+            hasher.update_len(view.changes.len());
+            for (key, change) in &view.changes {
+                hasher.update_bytes(key);
+                match change {
+                    Change::Put(value) => {
+                        hasher.update(&[0]);
+                        hasher.update_bytes(value);
+                    }
+                    Change::Delete => hasher.update(&[1]),
+                    Change::Merge(operand) => {
+                        hasher.update(&[2]);
+                        hasher.update_bytes(operand);
+                    }
+                }
+            }
+        }
+        hasher.finalize()
+    }
+}
+
+/// Streaming CRC-32 (IEEE 802.3) hasher.
+struct Crc32 {
+    state: u32,
+}
+
+impl Crc32 {
+    const POLYNOMIAL: u32 = 0xedb8_8320;
+    const TABLE: [u32; 256] = Self::table();
+
+    const fn table() -> [u32; 256] {
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut value = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                value = if value & 1 == 1 {
+                    (value >> 1) ^ Self::POLYNOMIAL
+                } else {
+                    value >> 1
+                };
+                bit += 1;
+            }
+            table[i] = value;
+            i += 1;
+        }
+        table
+    }
+
+    fn new() -> Self {
+        Self { state: !0 }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            let idx = (self.state ^ u32::from(byte)) & 0xff;
+            self.state = (self.state >> 8) ^ Self::TABLE[idx as usize];
+        }
+    }
+
+    fn update_len(&mut self, len: usize) {
+        self.update(&(len as u64).to_le_bytes());
+    }
+
+    /// Hashes a length-prefixed byte sequence.
+    fn update_bytes(&mut self, bytes: &[u8]) {
+        self.update_len(bytes.len());
+        self.update(bytes);
+    }
+
+    fn finalize(self) -> u32 {
+        !self.state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        access::CopyAccessExt, rocksdb::RocksDB, DBOptions, Database, Snapshot, TemporaryDB,
+    };
+
+    use tempfile::TempDir;
+
+    fn create_patch(db: &dyn Database) -> Patch {
+        let fork = db.fork();
+        fork.get_list("list").extend(vec![1_u32, 2, 3]);
+        fork.get_map("map").put(&1_u8, "foo".to_owned());
+        fork.get_counter("counter").increment(&1_u8, 5);
+        fork.get_entry("entry").set(42_u64);
+        fork.into_patch()
+    }
+
+    fn check_contents(snapshot: &dyn Snapshot) {
+        let list = snapshot.get_list::<_, u32>("list");
+        assert_eq!(list.iter().collect::<Vec<_>>(), vec![1, 2, 3]);
+        let map = snapshot.get_map::<_, u8, String>("map");
+        assert_eq!(map.get(&1).unwrap(), "foo");
+        assert_eq!(snapshot.get_counter::<_, u8>("counter").get(&1), 5);
+        assert_eq!(snapshot.get_entry::<_, u64>("entry").get(), Some(42));
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        let mut hasher = Crc32::new();
+        hasher.update(b"123456789");
+        assert_eq!(hasher.finalize(), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn detached_patch_round_trip() {
+        let db = TemporaryDB::new();
+        let patch = create_patch(&db);
+        let detached = patch.to_detached();
+        assert!(detached.verify().is_ok());
+        assert_eq!(
+            detached.base_sequence_number(),
+            db.snapshot().sequence_number()
+        );
+
+        let bytes = bincode::serialize(&detached).unwrap();
+        let restored: DetachedPatch = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(restored, detached);
+
+        let replica = TemporaryDB::new();
+        replica.merge_detached(restored).unwrap();
+        check_contents(&replica.snapshot());
+
+        // The original patch is still usable.
+        db.merge(patch).unwrap();
+        check_contents(&db.snapshot());
+    }
+
+    #[test]
+    fn detached_patch_for_rocksdb() {
+        let dir = TempDir::new().unwrap();
+        let db = RocksDB::open(&dir, &DBOptions::default()).unwrap();
+        let detached = create_patch(&db).to_detached();
+        assert!(detached.base_sequence_number().is_some());
+
+        let replica_dir = TempDir::new().unwrap();
+        let replica = RocksDB::open(&replica_dir, &DBOptions::default()).unwrap();
+        replica.merge_detached(detached.clone()).unwrap();
+        check_contents(&replica.snapshot());
+
+        let sequence_number = replica.snapshot().sequence_number().unwrap();
+        assert!(sequence_number > detached.base_sequence_number().unwrap());
+    }
+
+    #[test]
+    fn detached_patch_with_cleared_view() {
+        let db = TemporaryDB::new();
+        db.merge(create_patch(&db)).unwrap();
+        let replica = TemporaryDB::new();
+        replica.merge(create_patch(&replica)).unwrap();
+
+        let fork = db.fork();
+        {
+            let mut list = fork.get_list::<_, u32>("list");
+            list.clear();
+            list.push(4);
+        }
+        let detached = fork.into_patch().to_detached();
+        let view = detached
+            .views()
+            .iter()
+            .find(|view| view.is_cleared())
+            .unwrap();
+        assert_eq!(view.changes().len(), 1);
+
+        replica.merge_detached(detached).unwrap();
+        let snapshot = replica.snapshot();
+        let list = snapshot.get_list::<_, u32>("list");
+        assert_eq!(list.iter().collect::<Vec<_>>(), vec![4]);
+    }
+
+    #[test]
+    fn detached_patch_checksum_mismatch() {
+        let db = TemporaryDB::new();
+        let detached = create_patch(&db).to_detached();
+
+        let mut corrupted = detached.clone();
+        corrupted.views[0].changes[0].1 = Change::Delete;
+        let err = corrupted.verify().unwrap_err();
+        assert!(err.to_string().contains("checksum mismatch"));
+
+        let replica = TemporaryDB::new();
+        replica.merge_detached(corrupted).unwrap_err();
+        assert!(replica.snapshot().get_list::<_, u32>("list").is_empty());
+
+        let mut corrupted = detached;
+        corrupted.base_sequence_number = corrupted.base_sequence_number.map(|n| n + 1);
+        corrupted.verify().unwrap_err();
+    }
+}
@@ -27,3 +27,9 @@ impl From<rocksdb::Error> for Error {
         Self::new(err.to_string())
     }
 }
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Self::new(err.to_string())
+    }
+}
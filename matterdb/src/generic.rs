@@ -82,7 +82,7 @@
 //! db.merge(fork.into_patch()).unwrap();
 //! ```
 
-use std::rc::Rc;
+use std::{ops::RangeBounds, rc::Rc};
 
 use crate::{
     access::{Access, AccessError, AsReadonly, Prefixed},
@@ -318,16 +318,40 @@ impl<T: RawAccess> Access for GenericAccess<T> {
         }
     }
 
-    fn group_keys<K>(self, base_addr: IndexAddress) -> GroupKeys<Self::Base, K>
+    fn group_keys<K, R>(self, base_addr: IndexAddress, range: R) -> GroupKeys<Self::Base, K>
     where
         K: BinaryKey + ?Sized,
-        Self::Base: AsReadonly<Readonly = Self::Base>,
+        R: RangeBounds<K>,
     {
         match self {
-            Self::Raw(access) => access.group_keys(base_addr),
-            Self::Prefixed(access) => access.group_keys(base_addr),
-            Self::Migration(access) => access.group_keys(base_addr),
-            Self::Scratchpad(access) => access.group_keys(base_addr),
+            Self::Raw(access) => access.group_keys(base_addr, range),
+            Self::Prefixed(access) => access.group_keys(base_addr, range),
+            Self::Migration(access) => access.group_keys(base_addr, range),
+            Self::Scratchpad(access) => access.group_keys(base_addr, range),
+        }
+    }
+
+    fn remove_index(self, addr: IndexAddress) -> Result<(), AccessError>
+    where
+        Self::Base: RawAccessMut,
+    {
+        match self {
+            Self::Raw(access) => access.remove_index(addr),
+            Self::Prefixed(access) => access.remove_index(addr),
+            Self::Migration(access) => access.remove_index(addr),
+            Self::Scratchpad(access) => access.remove_index(addr),
+        }
+    }
+
+    fn remove_group(self, base_addr: IndexAddress) -> Result<(), AccessError>
+    where
+        Self::Base: RawAccessMut,
+    {
+        match self {
+            Self::Raw(access) => access.remove_group(base_addr),
+            Self::Prefixed(access) => access.remove_group(base_addr),
+            Self::Migration(access) => access.remove_group(base_addr),
+            Self::Scratchpad(access) => access.remove_group(base_addr),
         }
     }
 }
@@ -0,0 +1,934 @@
+//! An implementation of a compressed set of integers.
+//!
+//! `BitmapIndex` stores a set of `u64` integers in the [roaring bitmap] format. Integers
+//! are split into chunks by their high 48 bits; each chunk is stored as a single value
+//! (a *container*) in the underlying KV storage. A container holds up to 2^16 low bits
+//! of integers either as a sorted array (for sparse chunks) or as a bitmap (for dense
+//! chunks), so that dense integer sets take a fraction of the space of a `KeySetIndex`.
+//! The number of elements in each container is stored separately, so that rank queries
+//! do not need to read the containers themselves.
+//!
+//! [roaring bitmap]: https://roaringbitmap.org/
+
+use anyhow::ensure;
+use byteorder::{ByteOrder, LittleEndian};
+
+use std::{
+    borrow::Cow,
+    fmt,
+    iter::Peekable,
+    ops::{Bound, RangeBounds},
+};
+
+use crate::{
+    access::{Access, AccessError, FromAccess},
+    views::{
+        IndexAddress, IndexState, IndexType, Iter as ViewIter, RawAccess, RawAccessMut, View,
+        ViewWithMetadata,
+    },
+    BinaryValue,
+};
+
+/// Number of low bits stored in a container.
+const CONTAINER_BITS: u32 = 16;
+/// Maximum number of elements in an array container.
+const ARRAY_MAX_LEN: usize = 4_096;
+/// Number of 64-bit words in a bitmap container.
+const BITMAP_WORDS: usize = 1_024;
+
+const ARRAY_TAG: u8 = 0;
+const BITMAP_TAG: u8 = 1;
+
+/// Prefix of the keys of containers. Containers are keyed by the high bits of their elements,
+/// which never exceed 48 bits, so the first byte of the key is always zero.
+const CONTAINERS_PREFIX: u8 = 0;
+/// Offset of the keys storing cardinalities of containers. A cardinality is keyed by
+/// the high bits of the container plus the offset, so cardinalities are stored after all
+/// containers in the same order.
+const CARDINALITIES_OFFSET: u64 = 1 << 63;
+/// Prefix of the keys of container cardinalities.
+const CARDINALITIES_PREFIX: u8 = 0x80;
+
+fn split(value: u64) -> (u64, u16) {
+    (value >> CONTAINER_BITS, value as u16)
+}
+
+fn join(high: u64, low: u16) -> u64 {
+    (high << CONTAINER_BITS) | u64::from(low)
+}
+
+/// Set of low bits of integers sharing the same high bits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Container(ContainerInner);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ContainerInner {
+    /// Sorted array of elements.
+    Array(Vec<u16>),
+    /// Bitmap with 2^16 bits.
+    Bitmap(Box<[u64]>),
+}
+
+impl Container {
+    fn new() -> Self {
+        Self(ContainerInner::Array(vec![]))
+    }
+
+    fn from_words(words: Vec<u64>) -> Self {
+        debug_assert_eq!(words.len(), BITMAP_WORDS);
+        let mut container = Self(ContainerInner::Bitmap(words.into_boxed_slice()));
+        container.normalize();
+        container
+    }
+
+    fn len(&self) -> u64 {
+        match &self.0 {
+            ContainerInner::Array(values) => values.len() as u64,
+            ContainerInner::Bitmap(words) => {
+                words.iter().map(|word| u64::from(word.count_ones())).sum()
+            }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match &self.0 {
+            ContainerInner::Array(values) => values.is_empty(),
+            ContainerInner::Bitmap(words) => words.iter().all(|&word| word == 0),
+        }
+    }
+
+    fn contains(&self, low: u16) -> bool {
+        match &self.0 {
+            ContainerInner::Array(values) => values.binary_search(&low).is_ok(),
+            ContainerInner::Bitmap(words) => words[usize::from(low) / 64] & (1 << (low % 64)) != 0,
+        }
+    }
+
+    /// Returns the number of elements less than or equal to `low`.
+    fn rank(&self, low: u16) -> u64 {
+        match &self.0 {
+            ContainerInner::Array(values) => match values.binary_search(&low) {
+                Ok(pos) => pos as u64 + 1,
+                Err(pos) => pos as u64,
+            },
+            ContainerInner::Bitmap(words) => {
+                let word_index = usize::from(low) / 64;
+                let full_words: u64 = words[..word_index]
+                    .iter()
+                    .map(|word| u64::from(word.count_ones()))
+                    .sum();
+                let bit = low % 64;
+                let mask = if bit == 63 {
+                    u64::MAX
+                } else {
+                    (1 << (bit + 1)) - 1
+                };
+                full_words + u64::from((words[word_index] & mask).count_ones())
+            }
+        }
+    }
+
+    /// Inserts an element, returning `true` if it was not present.
+    fn insert(&mut self, low: u16) -> bool {
+        let inserted = match &mut self.0 {
+            ContainerInner::Array(values) => match values.binary_search(&low) {
+                Ok(_) => false,
+                Err(pos) => {
+                    values.insert(pos, low);
+                    true
+                }
+            },
+            ContainerInner::Bitmap(words) => {
+                let word = &mut words[usize::from(low) / 64];
+                let mask = 1 << (low % 64);
+                let inserted = *word & mask == 0;
+                *word |= mask;
+                inserted
+            }
+        };
+        self.normalize();
+        inserted
+    }
+
+    /// Removes an element, returning `true` if it was present.
+    fn remove(&mut self, low: u16) -> bool {
+        let removed = match &mut self.0 {
+            ContainerInner::Array(values) => values
+                .binary_search(&low)
+                .map(|pos| values.remove(pos))
+                .is_ok(),
+            ContainerInner::Bitmap(words) => {
+                let word = &mut words[usize::from(low) / 64];
+                let mask = 1 << (low % 64);
+                let removed = *word & mask != 0;
+                *word &= !mask;
+                removed
+            }
+        };
+        self.normalize();
+        removed
+    }
+
+    /// Converts the container to the most compact representation.
+    fn normalize(&mut self) {
+        let len = self.len() as usize;
+        match &self.0 {
+            ContainerInner::Array(values) if values.len() > ARRAY_MAX_LEN => {
+                self.0 = ContainerInner::Bitmap(self.words().into_boxed_slice());
+            }
+            ContainerInner::Bitmap(_) if len <= ARRAY_MAX_LEN => {
+                self.0 = ContainerInner::Array(self.values());
+            }
+            _ => { /* The representation is optimal. */ }
+        }
+    }
+
+    /// Returns the elements of the container as a bitmap.
+    fn words(&self) -> Vec<u64> {
+        match &self.0 {
+            ContainerInner::Array(values) => {
+                let mut words = vec![0_u64; BITMAP_WORDS];
+                for &low in values {
+                    words[usize::from(low) / 64] |= 1 << (low % 64);
+                }
+                words
+            }
+            ContainerInner::Bitmap(words) => words.to_vec(),
+        }
+    }
+
+    /// Returns the elements of the container in the ascending order.
+    fn values(&self) -> Vec<u16> {
+        match &self.0 {
+            ContainerInner::Array(values) => values.clone(),
+            ContainerInner::Bitmap(words) => {
+                let mut values = Vec::with_capacity(self.len() as usize);
+                for (i, &word) in words.iter().enumerate() {
+                    let mut word = word;
+                    while word != 0 {
+                        let bit = word.trailing_zeros();
+                        values.push((i * 64) as u16 + bit as u16);
+                        word &= word - 1;
+                    }
+                }
+                values
+            }
+        }
+    }
+
+    fn combine(&self, other: &Self, op: SetOp) -> Self {
+        let (this, other) = (self.words(), other.words());
+        let words = this
+            .iter()
+            .zip(&other)
+            .map(|(&this, &other)| match op {
+                SetOp::Union => this | other,
+                SetOp::Intersection => this & other,
+                SetOp::Difference => this & !other,
+            })
+            .collect();
+        Self::from_words(words)
+    }
+}
+
+impl BinaryValue for Container {
+    fn to_bytes(&self) -> Vec<u8> {
+        match &self.0 {
+            ContainerInner::Array(values) => {
+                let mut buffer = vec![0; 1 + values.len() * 2];
+                buffer[0] = ARRAY_TAG;
+                LittleEndian::write_u16_into(values, &mut buffer[1..]);
+                buffer
+            }
+            ContainerInner::Bitmap(words) => {
+                let mut buffer = vec![0; 1 + BITMAP_WORDS * 8];
+                buffer[0] = BITMAP_TAG;
+                LittleEndian::write_u64_into(words, &mut buffer[1..]);
+                buffer
+            }
+        }
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> anyhow::Result<Self> {
+        ensure!(!bytes.is_empty(), "Empty bitmap container");
+        let (tag, data) = (bytes[0], &bytes[1..]);
+        let inner = match tag {
+            ARRAY_TAG => {
+                ensure!(
+                    data.len() % 2 == 0 && data.len() / 2 <= ARRAY_MAX_LEN,
+                    "Invalid array container length: {}",
+                    data.len()
+                );
+                let mut values = vec![0; data.len() / 2];
+                LittleEndian::read_u16_into(data, &mut values);
+                ensure!(
+                    values.windows(2).all(|pair| pair[0] < pair[1]),
+                    "Array container is not sorted"
+                );
+                ContainerInner::Array(values)
+            }
+            BITMAP_TAG => {
+                ensure!(
+                    data.len() == BITMAP_WORDS * 8,
+                    "Invalid bitmap container length: {}",
+                    data.len()
+                );
+                let mut words = vec![0; BITMAP_WORDS];
+                LittleEndian::read_u64_into(data, &mut words);
+                ContainerInner::Bitmap(words.into_boxed_slice())
+            }
+            _ => anyhow::bail!("Unknown container tag: {}", tag),
+        };
+        Ok(Self(inner))
+    }
+}
+
+/// Set operation on bitmaps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SetOp {
+    Union,
+    Intersection,
+    Difference,
+}
+
+/// A set of `u64` integers stored as a roaring bitmap.
+///
+/// `BitmapIndex` is a space-efficient alternative to `KeySetIndex<_, u64>` for large
+/// sets of integers, such as sets of IDs. Besides the usual set operations, the index supports
+/// rank queries, range iteration and set algebra with other bitmap indexes.
+///
+/// The number of elements in the index is stored separately, so [`len`] is `O(1)`.
+///
+/// [`len`]: #method.len
+pub struct BitmapIndex<T: RawAccess> {
+    base: View<T>,
+    state: IndexState<T, u64>,
+}
+
+impl<T: RawAccess> fmt::Debug for BitmapIndex<T> {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("BitmapIndex")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+impl<T> FromAccess<T> for BitmapIndex<T::Base>
+where
+    T: Access,
+{
+    fn from_access(access: T, addr: IndexAddress) -> Result<Self, AccessError> {
+        let view = access.get_or_create_view(addr, IndexType::Bitmap)?;
+        Ok(Self::new(view))
+    }
+}
+
+impl<T> BitmapIndex<T>
+where
+    T: RawAccess,
+{
+    fn new(view: ViewWithMetadata<T>) -> Self {
+        let (base, state) = view.into_parts();
+        Self { base, state }
+    }
+
+    fn container(&self, high: u64) -> Option<Container> {
+        self.base.get(&high)
+    }
+
+    fn containers(&self, from: Option<u64>) -> ViewIter<'_, u64, Container> {
+        match from {
+            Some(from) => self.base.iter_from(&CONTAINERS_PREFIX, &from),
+            None => self.base.iter(&CONTAINERS_PREFIX),
+        }
+    }
+
+    fn container_len(&self, high: u64) -> u64 {
+        self.base
+            .get(&(CARDINALITIES_OFFSET | high))
+            .unwrap_or_default()
+    }
+
+    /// Returns `true` if the set contains the indicated value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, BitmapIndex};
+    ///
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_bitmap("name");
+    /// assert!(!index.contains(1));
+    ///
+    /// index.insert(1);
+    /// assert!(index.contains(1));
+    /// ```
+    pub fn contains(&self, value: u64) -> bool {
+        let (high, low) = split(value);
+        self.container(high)
+            .map_or(false, |container| container.contains(low))
+    }
+
+    /// Returns the number of elements in the set.
+    pub fn len(&self) -> u64 {
+        self.state.get().unwrap_or_default()
+    }
+
+    /// Returns `true` if the set is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of elements in the set less than or equal to `value`.
+    ///
+    /// The method reads the stored cardinalities of the containers preceding `value`
+    /// and a single container, so its cost is linear in the number of preceding containers,
+    /// but containers themselves are not decoded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, BitmapIndex};
+    ///
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_bitmap("name");
+    /// index.extend(vec![1, 5, 1 << 40]);
+    /// assert_eq!(index.rank(0), 0);
+    /// assert_eq!(index.rank(5), 2);
+    /// assert_eq!(index.rank(u64::MAX), 3);
+    /// ```
+    pub fn rank(&self, value: u64) -> u64 {
+        let (high, low) = split(value);
+        let preceding: u64 = self
+            .base
+            .iter::<_, u64, u64>(&CARDINALITIES_PREFIX)
+            .take_while(|&(key, _)| key < CARDINALITIES_OFFSET | high)
+            .map(|(_, len)| len)
+            .sum();
+        let container_rank = self
+            .container(high)
+            .map_or(0, |container| container.rank(low));
+        preceding + container_rank
+    }
+
+    /// Returns an iterator over the set elements in the ascending order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, BitmapIndex};
+    ///
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_bitmap("name");
+    /// index.extend(vec![3, 1, 1 << 20]);
+    /// assert_eq!(index.iter().collect::<Vec<_>>(), vec![1, 3, 1 << 20]);
+    /// ```
+    pub fn iter(&self) -> BitmapIter<'_> {
+        BitmapIter::new(Box::new(self.containers(None)))
+    }
+
+    /// Returns an iterator over the set elements within the specified range
+    /// in the ascending order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, BitmapIndex};
+    ///
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_bitmap("name");
+    /// index.extend(0..100);
+    /// assert_eq!(index.iter_range(10..13).collect::<Vec<_>>(), vec![10, 11, 12]);
+    /// assert_eq!(index.iter_range(98..).collect::<Vec<_>>(), vec![98, 99]);
+    /// ```
+    pub fn iter_range<R: RangeBounds<u64>>(&self, range: R) -> BitmapIter<'_> {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => Some(start),
+            Bound::Excluded(&start) => start.checked_add(1),
+            Bound::Unbounded => Some(0),
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => Some(end),
+            Bound::Excluded(&end) => end.checked_sub(1),
+            Bound::Unbounded => Some(u64::MAX),
+        };
+        match (start, end) {
+            (Some(start), Some(end)) if start <= end => {
+                let containers = self.containers(Some(split(start).0));
+                let mut iter = BitmapIter::new(Box::new(containers));
+                iter.range = (start, end);
+                iter
+            }
+            _ => BitmapIter::new(Box::new(std::iter::empty())),
+        }
+    }
+
+    /// Returns an iterator over elements contained in this set or the `other` set.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, BitmapIndex};
+    ///
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_bitmap("name");
+    /// index.extend(vec![1, 2, 3]);
+    /// let mut other = fork.get_bitmap("other");
+    /// other.extend(vec![3, 4]);
+    ///
+    /// assert_eq!(index.union(&other).collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+    /// assert_eq!(index.intersection(&other).collect::<Vec<_>>(), vec![3]);
+    /// assert_eq!(index.difference(&other).collect::<Vec<_>>(), vec![1, 2]);
+    /// ```
+    pub fn union<'a, U: RawAccess>(&'a self, other: &'a BitmapIndex<U>) -> BitmapIter<'a> {
+        self.combine(other, SetOp::Union)
+    }
+
+    /// Returns an iterator over elements contained both in this set and the `other` set.
+    pub fn intersection<'a, U: RawAccess>(&'a self, other: &'a BitmapIndex<U>) -> BitmapIter<'a> {
+        self.combine(other, SetOp::Intersection)
+    }
+
+    /// Returns an iterator over elements contained in this set, but not in the `other` set.
+    pub fn difference<'a, U: RawAccess>(&'a self, other: &'a BitmapIndex<U>) -> BitmapIter<'a> {
+        self.combine(other, SetOp::Difference)
+    }
+
+    fn combine<'a, U: RawAccess>(&'a self, other: &'a BitmapIndex<U>, op: SetOp) -> BitmapIter<'a> {
+        let merged = MergedContainers {
+            left: self.containers(None).peekable(),
+            right: other.containers(None).peekable(),
+        };
+        let containers = merged.filter_map(move |(high, left, right)| {
+            let container = match (left, right, op) {
+                (Some(left), Some(right), _) => left.combine(&right, op),
+                (Some(container), None, SetOp::Union | SetOp::Difference)
+                | (None, Some(container), SetOp::Union) => container,
+                _ => return None,
+            };
+            Some((high, container))
+        });
+        BitmapIter::new(Box::new(containers))
+    }
+}
+
+impl<T> BitmapIndex<T>
+where
+    T: RawAccessMut,
+{
+    fn set_len(&mut self, len: u64) {
+        self.state.set(len);
+    }
+
+    fn put_container(&mut self, high: u64, container: Container) {
+        if container.is_empty() {
+            self.base.remove(&high);
+            self.base.remove(&(CARDINALITIES_OFFSET | high));
+        } else {
+            self.base
+                .put(&(CARDINALITIES_OFFSET | high), container.len());
+            self.base.put(&high, container);
+        }
+    }
+
+    /// Replaces the container with the specified high bits and returns the updated length
+    /// of the set.
+    fn replace_container(&mut self, len: u64, high: u64, container: Container) -> u64 {
+        let len = len - self.container_len(high) + container.len();
+        self.put_container(high, container);
+        len
+    }
+
+    /// Adds a value to the set. Returns `true` if the value was not present in the set.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, BitmapIndex};
+    ///
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_bitmap("name");
+    /// assert!(index.insert(1));
+    /// assert!(!index.insert(1));
+    /// assert_eq!(index.len(), 1);
+    /// ```
+    pub fn insert(&mut self, value: u64) -> bool {
+        let (high, low) = split(value);
+        let mut container = self.container(high).unwrap_or_else(Container::new);
+        let inserted = container.insert(low);
+        if inserted {
+            self.put_container(high, container);
+            let len = self.len();
+            self.set_len(len + 1);
+        }
+        inserted
+    }
+
+    /// Removes a value from the set. Returns `true` if the value was present in the set.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, BitmapIndex};
+    ///
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_bitmap("name");
+    /// index.insert(1);
+    /// assert!(index.remove(1));
+    /// assert!(!index.remove(1));
+    /// assert!(index.is_empty());
+    /// ```
+    pub fn remove(&mut self, value: u64) -> bool {
+        let (high, low) = split(value);
+        let mut container = match self.container(high) {
+            Some(container) => container,
+            None => return false,
+        };
+        let removed = container.remove(low);
+        if removed {
+            self.put_container(high, container);
+            let len = self.len();
+            self.set_len(len - 1);
+        }
+        removed
+    }
+
+    /// Adds values from the iterator to the set. Values are grouped by containers, so it is
+    /// more efficient to add values in the ascending order.
+    pub fn extend<I>(&mut self, values: I)
+    where
+        I: IntoIterator<Item = u64>,
+    {
+        let mut current: Option<(u64, Container)> = None;
+        let mut len = self.len();
+        for value in values {
+            let (high, low) = split(value);
+            match &mut current {
+                Some((current_high, _)) if *current_high == high => {}
+                _ => {
+                    if let Some((current_high, container)) = current.take() {
+                        self.put_container(current_high, container);
+                    }
+                    let container = self.container(high).unwrap_or_else(Container::new);
+                    current = Some((high, container));
+                }
+            }
+            if current.as_mut().unwrap().1.insert(low) {
+                len += 1;
+            }
+        }
+        if let Some((high, container)) = current {
+            self.put_container(high, container);
+        }
+        self.set_len(len);
+    }
+
+    /// Adds all elements of the `other` set to this set.
+    pub fn union_with<U: RawAccess>(&mut self, other: &BitmapIndex<U>) {
+        let mut len = self.len();
+        for (high, container) in other.containers(None) {
+            let container = match self.container(high) {
+                Some(this) => this.combine(&container, SetOp::Union),
+                None => container,
+            };
+            len = self.replace_container(len, high, container);
+        }
+        self.set_len(len);
+    }
+
+    /// Removes all elements not contained in the `other` set from this set.
+    pub fn intersect_with<U: RawAccess>(&mut self, other: &BitmapIndex<U>) {
+        let mut len = self.len();
+        let mut from = 0;
+        loop {
+            // The containers are modified during iteration, so the iterator is recreated
+            // for each container.
+            let (high, this) = match self.containers(Some(from)).next() {
+                Some(entry) => entry,
+                None => break,
+            };
+            let container = match other.container(high) {
+                Some(container) => this.combine(&container, SetOp::Intersection),
+                None => Container::new(),
+            };
+            len = self.replace_container(len, high, container);
+            from = high + 1;
+        }
+        self.set_len(len);
+    }
+
+    /// Removes all elements contained in the `other` set from this set.
+    pub fn difference_with<U: RawAccess>(&mut self, other: &BitmapIndex<U>) {
+        let mut len = self.len();
+        for (high, container) in other.containers(None) {
+            if let Some(this) = self.container(high) {
+                let container = this.combine(&container, SetOp::Difference);
+                len = self.replace_container(len, high, container);
+            }
+        }
+        self.set_len(len);
+    }
+
+    /// Clears the set, removing all values.
+    ///
+    /// # Notes
+    ///
+    /// Currently, this method is not optimized to delete a large set of data. During the execution of
+    /// this method, the amount of allocated memory is linearly dependent on the number of elements
+    /// in the index.
+    pub fn clear(&mut self) {
+        self.base.clear();
+        self.state.unset();
+    }
+}
+
+impl<'a, T: RawAccess> IntoIterator for &'a BitmapIndex<T> {
+    type Item = u64;
+    type IntoIter = BitmapIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Iterator over pairs of containers with the same high bits in two bitmaps.
+struct MergedContainers<'a> {
+    left: Peekable<ViewIter<'a, u64, Container>>,
+    right: Peekable<ViewIter<'a, u64, Container>>,
+}
+
+impl Iterator for MergedContainers<'_> {
+    type Item = (u64, Option<Container>, Option<Container>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let left_high = self.left.peek().map(|(high, _)| *high);
+        let right_high = self.right.peek().map(|(high, _)| *high);
+        match (left_high, right_high) {
+            (None, None) => None,
+            (Some(left), Some(right)) if left == right => {
+                let (high, left) = self.left.next().unwrap();
+                let (_, right) = self.right.next().unwrap();
+                Some((high, Some(left), Some(right)))
+            }
+            (Some(left), Some(right)) if left > right => {
+                let (high, right) = self.right.next().unwrap();
+                Some((high, None, Some(right)))
+            }
+            (None, Some(_)) => {
+                let (high, right) = self.right.next().unwrap();
+                Some((high, None, Some(right)))
+            }
+            (Some(_), _) => {
+                let (high, left) = self.left.next().unwrap();
+                Some((high, Some(left), None))
+            }
+        }
+    }
+}
+
+/// Iterator over elements of a [`BitmapIndex`] or a combination of several bitmap indexes.
+///
+/// This struct is created by the [`iter`], [`iter_range`] and set operation methods
+/// on `BitmapIndex`.
+///
+/// [`BitmapIndex`]: struct.BitmapIndex.html
+/// [`iter`]: struct.BitmapIndex.html#method.iter
+/// [`iter_range`]: struct.BitmapIndex.html#method.iter_range
+pub struct BitmapIter<'a> {
+    containers: Box<dyn Iterator<Item = (u64, Container)> + 'a>,
+    current: Option<(u64, std::vec::IntoIter<u16>)>,
+    range: (u64, u64),
+}
+
+impl fmt::Debug for BitmapIter<'_> {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("BitmapIter")
+            .field("range", &self.range)
+            .finish()
+    }
+}
+
+impl<'a> BitmapIter<'a> {
+    fn new(containers: Box<dyn Iterator<Item = (u64, Container)> + 'a>) -> Self {
+        Self {
+            containers,
+            current: None,
+            range: (0, u64::MAX),
+        }
+    }
+}
+
+impl Iterator for BitmapIter<'_> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        loop {
+            if let Some((high, values)) = &mut self.current {
+                if let Some(low) = values.next() {
+                    let value = join(*high, low);
+                    if value < self.range.0 {
+                        continue;
+                    }
+                    if value > self.range.1 {
+                        self.current = None;
+                        self.containers = Box::new(std::iter::empty());
+                        return None;
+                    }
+                    return Some(value);
+                }
+            }
+            let (high, container) = self.containers.next()?;
+            self.current = Some((high, container.values().into_iter()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{access::CopyAccessExt, Database, Fork, TemporaryDB};
+
+    use std::collections::BTreeSet;
+
+    type InPlaceOp<'a> = fn(&mut BitmapIndex<&'a Fork>, &BitmapIndex<&'a Fork>);
+
+    #[test]
+    fn container_conversions() {
+        let mut container = Container::new();
+        for i in 0..=ARRAY_MAX_LEN as u16 {
+            assert!(container.insert(i * 2));
+        }
+        assert!(matches!(container.0, ContainerInner::Bitmap(_)));
+        assert_eq!(container.len(), ARRAY_MAX_LEN as u64 + 1);
+        assert_eq!(container.rank(100), 51);
+        assert_eq!(container.rank(101), 51);
+        let restored = Container::from_bytes(container.to_bytes().into()).unwrap();
+        assert_eq!(restored, container);
+
+        assert!(container.remove(0));
+        assert!(!container.remove(1));
+        assert!(matches!(container.0, ContainerInner::Array(_)));
+        assert_eq!(container.rank(100), 50);
+        assert_eq!(container.values()[..3], [2, 4, 6]);
+        let restored = Container::from_bytes(container.to_bytes().into()).unwrap();
+        assert_eq!(restored, container);
+
+        assert!(Container::from_bytes(vec![ARRAY_TAG, 2, 0, 1, 0].into()).is_err());
+        assert!(Container::from_bytes(vec![BITMAP_TAG, 0].into()).is_err());
+    }
+
+    #[test]
+    fn bitmap_operations() {
+        let db = TemporaryDB::new();
+        let fork = db.fork();
+        let mut index = fork.get_bitmap("bitmap");
+        let mut reference = BTreeSet::new();
+
+        let values = (0..10_000_u64)
+            .map(|i| i * 3)
+            .chain((0..100).map(|i| (i << 32) + 7))
+            .chain(vec![u64::MAX, 1 << 16, (1 << 16) - 1]);
+        for value in values {
+            assert_eq!(index.insert(value), reference.insert(value));
+        }
+        for value in (0..30_000).step_by(7) {
+            assert_eq!(index.remove(value), reference.remove(&value));
+        }
+        assert_eq!(index.len(), reference.len() as u64);
+        assert!(index.iter().eq(reference.iter().copied()));
+
+        for &value in &[0, 3, 65_535, 65_536, 29_997, 1 << 40, u64::MAX] {
+            assert_eq!(index.contains(value), reference.contains(&value));
+            let rank = reference.range(..=value).count() as u64;
+            assert_eq!(index.rank(value), rank, "rank({})", value);
+        }
+
+        let ranges = vec![
+            (Bound::Included(100), Bound::Excluded(70_000)),
+            (Bound::Excluded(65_535), Bound::Included(1 << 33)),
+            (Bound::Unbounded, Bound::Included(0)),
+            (Bound::Included(u64::MAX), Bound::Unbounded),
+            (Bound::Excluded(u64::MAX), Bound::Unbounded),
+            (Bound::Included(10), Bound::Excluded(10)),
+        ];
+        for range in ranges {
+            let expected: Vec<_> = reference.range(range).copied().collect();
+            assert_eq!(index.iter_range(range).collect::<Vec<_>>(), expected);
+        }
+
+        drop(index);
+        db.merge(fork.into_patch()).unwrap();
+        let snapshot = db.snapshot();
+        let index = snapshot.get_bitmap("bitmap");
+        assert_eq!(index.len(), reference.len() as u64);
+        assert!(index.iter().eq(reference.iter().copied()));
+    }
+
+    #[test]
+    fn set_algebra() {
+        let db = TemporaryDB::new();
+        let fork = db.fork();
+        let left_values: BTreeSet<_> = (0..20_000_u64).map(|i| i * 5).collect();
+        let right_values: BTreeSet<_> = (0..30_000_u64)
+            .map(|i| i * 2)
+            .chain(vec![1 << 50])
+            .collect();
+        fork.get_bitmap("left").extend(left_values.iter().copied());
+        fork.get_bitmap("right")
+            .extend(right_values.iter().copied());
+        assert_eq!(fork.get_bitmap("left").len(), left_values.len() as u64);
+
+        {
+            let left = fork.get_bitmap("left");
+            let right = fork.get_bitmap("right");
+            assert!(left
+                .union(&right)
+                .eq(left_values.union(&right_values).copied()));
+            assert!(left
+                .intersection(&right)
+                .eq(left_values.intersection(&right_values).copied()));
+            assert!(left
+                .difference(&right)
+                .eq(left_values.difference(&right_values).copied()));
+            assert!(right
+                .difference(&left)
+                .eq(right_values.difference(&left_values).copied()));
+        }
+
+        let operations: Vec<(InPlaceOp<'_>, BTreeSet<u64>)> = vec![
+            (
+                BitmapIndex::union_with,
+                left_values.union(&right_values).copied().collect(),
+            ),
+            (
+                BitmapIndex::intersect_with,
+                left_values.intersection(&right_values).copied().collect(),
+            ),
+            (
+                BitmapIndex::difference_with,
+                left_values.difference(&right_values).copied().collect(),
+            ),
+        ];
+        for (operation, expected) in operations {
+            let mut target = fork.get_bitmap("target");
+            target.clear();
+            target.extend(left_values.iter().copied());
+            operation(&mut target, &fork.get_bitmap("right"));
+            assert_eq!(target.len(), expected.len() as u64);
+            assert!(target.iter().eq(expected.iter().copied()));
+            for &value in &[0, 65_535, 65_536, 99_995, 1 << 50, u64::MAX] {
+                let rank = expected.range(..=value).count() as u64;
+                assert_eq!(target.rank(value), rank, "rank({})", value);
+            }
+        }
+    }
+}
@@ -0,0 +1,694 @@
+//! An implementation of an index storing a single large binary value.
+//!
+//! `BlobIndex` splits its contents into fixed-size chunks, each stored as a separate value
+//! in the underlying KV storage. Thus, large values do not need to be kept in memory
+//! as a whole and can be read, written and partially overwritten via the standard
+//! `std::io::{Read, Write, Seek}` interfaces.
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
+use sha2::{Digest, Sha256};
+
+use std::{
+    cmp,
+    collections::BTreeSet,
+    fmt,
+    io::{self, Read, Seek, SeekFrom, Write},
+    mem,
+};
+
+use crate::{
+    access::{Access, AccessError, FromAccess},
+    views::{
+        BinaryAttribute, IndexAddress, IndexState, IndexType, RawAccess, RawAccessMut, View,
+        ViewWithMetadata,
+    },
+};
+
+/// Size of a single chunk in bytes.
+pub const BLOB_CHUNK_SIZE: usize = 64 * 1_024;
+
+/// Key tag for chunk contents.
+const CHUNK_TAG: u8 = 0;
+/// Key tag for chunk hashes.
+const CHUNK_HASH_TAG: u8 = 1;
+/// Key tag for internal nodes of the Merkle tree over chunk hashes.
+const TREE_NODE_TAG: u8 = 2;
+/// Prefix of the hashed data for internal tree nodes, which distinguishes them from chunks.
+const TREE_NODE_PREFIX: u8 = 1;
+
+#[derive(Debug, Clone, Copy)]
+struct BlobState {
+    /// Length of the blob in bytes.
+    len: u64,
+    /// Content hash of the blob.
+    hash: [u8; 32],
+}
+
+impl Default for BlobState {
+    fn default() -> Self {
+        Self {
+            len: 0,
+            hash: content_hash(0, None),
+        }
+    }
+}
+
+impl BinaryAttribute for BlobState {
+    fn size(&self) -> usize {
+        40
+    }
+
+    fn write(&self, buffer: &mut Vec<u8>) {
+        buffer.write_u64::<LittleEndian>(self.len).unwrap();
+        buffer.extend_from_slice(&self.hash);
+    }
+
+    fn read(mut buffer: &[u8]) -> Result<Self, io::Error> {
+        let len = buffer.read_u64::<LittleEndian>()?;
+        let mut hash = [0; 32];
+        buffer.read_exact(&mut hash)?;
+        Ok(Self { len, hash })
+    }
+}
+
+fn chunk_key(tag: u8, index: u64) -> [u8; 9] {
+    let mut key = [tag; 9];
+    BigEndian::write_u64(&mut key[1..], index);
+    key
+}
+
+/// Returns the key of a Merkle tree node. Leaves of the tree (i.e., nodes at height 0)
+/// are chunk hashes.
+fn node_key(height: u8, index: u64) -> Vec<u8> {
+    if height == 0 {
+        return chunk_key(CHUNK_HASH_TAG, index).to_vec();
+    }
+    let mut key = vec![TREE_NODE_TAG, height];
+    key.extend_from_slice(&index.to_be_bytes());
+    key
+}
+
+// `u64::div_ceil` is not available in the minimum supported Rust version.
+#[allow(clippy::manual_div_ceil)]
+fn chunk_count(len: u64) -> u64 {
+    let chunk_size = BLOB_CHUNK_SIZE as u64;
+    (len + chunk_size - 1) / chunk_size
+}
+
+/// Returns the number of nodes at the tree level above the level with `level_len` nodes.
+#[allow(clippy::manual_div_ceil)] // See `chunk_count()`.
+fn parent_level_len(level_len: u64) -> u64 {
+    if level_len > 1 {
+        (level_len + 1) / 2
+    } else {
+        // The level consists of the tree root (or is empty).
+        0
+    }
+}
+
+fn content_hash(len: u64, tree_root: Option<&[u8]>) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(len.to_le_bytes());
+    if let Some(tree_root) = tree_root {
+        hasher.update(tree_root);
+    }
+    hasher.finalize().into()
+}
+
+fn seek_position(start: u64, offset: i64) -> io::Result<u64> {
+    let position = if offset >= 0 {
+        start.checked_add(offset as u64)
+    } else {
+        start.checked_sub(offset.unsigned_abs())
+    };
+    position.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Invalid seek to a negative or overflowing position",
+        )
+    })
+}
+
+/// A large binary value split into fixed-size chunks.
+///
+/// The blob is read via [`reader`] and modified via [`writer`], which implement the standard
+/// `Read`, `Write` and `Seek` traits. Only the chunks touched by a write are rewritten,
+/// so overwriting a small part of a large blob is cheap.
+///
+/// The index maintains the blob length and a content hash in its metadata. The hash is
+/// SHA-256 over the blob length and the root of a binary Merkle tree built over SHA-256 hashes
+/// of individual chunks. The tree nodes are stored in the index, so that overwriting a chunk
+/// updates only `O(log n)` nodes instead of rehashing the entire blob.
+///
+/// [`reader`]: #method.reader
+/// [`writer`]: #method.writer
+pub struct BlobIndex<T: RawAccess> {
+    base: View<T>,
+    state: IndexState<T, BlobState>,
+    /// Chunks changed since the content hash was last updated.
+    changed_chunks: BTreeSet<u64>,
+}
+
+impl<T: RawAccess> fmt::Debug for BlobIndex<T> {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("BlobIndex")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+impl<T> FromAccess<T> for BlobIndex<T::Base>
+where
+    T: Access,
+{
+    fn from_access(access: T, addr: IndexAddress) -> Result<Self, AccessError> {
+        let view = access.get_or_create_view(addr, IndexType::Blob)?;
+        Ok(Self::new(view))
+    }
+}
+
+impl<T> BlobIndex<T>
+where
+    T: RawAccess,
+{
+    fn new(view: ViewWithMetadata<T>) -> Self {
+        let (base, state) = view.into_parts();
+        Self {
+            base,
+            state,
+            changed_chunks: BTreeSet::new(),
+        }
+    }
+
+    fn blob_state(&self) -> BlobState {
+        self.state.get().unwrap_or_default()
+    }
+
+    fn chunk(&self, index: u64) -> Vec<u8> {
+        self.base
+            .get(&chunk_key(CHUNK_TAG, index)[..])
+            .unwrap_or_default()
+    }
+
+    /// Returns the length of the blob in bytes.
+    pub fn len(&self) -> u64 {
+        self.blob_state().len
+    }
+
+    /// Returns `true` if the blob is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the content hash of the blob.
+    pub fn content_hash(&self) -> [u8; 32] {
+        self.blob_state().hash
+    }
+
+    /// Returns a reader over the blob contents.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, BlobIndex};
+    /// use std::io::{Read, Seek, SeekFrom, Write};
+    ///
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_blob("name");
+    /// index.writer().write_all(b"Hello, world!").unwrap();
+    ///
+    /// let mut reader = index.reader();
+    /// reader.seek(SeekFrom::Start(7)).unwrap();
+    /// let mut buffer = String::new();
+    /// reader.read_to_string(&mut buffer).unwrap();
+    /// assert_eq!(buffer, "world!");
+    /// ```
+    pub fn reader(&self) -> BlobReader<'_, T> {
+        BlobReader {
+            blob: self,
+            len: self.len(),
+            position: 0,
+            chunk: None,
+        }
+    }
+
+    /// Reads the entire blob into memory.
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(self.len() as usize);
+        self.reader()
+            .read_to_end(&mut buffer)
+            .expect("Reading blob cannot fail");
+        buffer
+    }
+}
+
+impl<T> BlobIndex<T>
+where
+    T: RawAccessMut,
+{
+    fn put_chunk(&mut self, index: u64, data: Vec<u8>) {
+        let hash = Sha256::digest(&data).to_vec();
+        self.base.put(&chunk_key(CHUNK_TAG, index)[..], data);
+        self.base.put(&chunk_key(CHUNK_HASH_TAG, index)[..], hash);
+        self.changed_chunks.insert(index);
+    }
+
+    fn remove_chunk(&mut self, index: u64) {
+        self.base.remove(&chunk_key(CHUNK_TAG, index)[..]);
+        self.base.remove(&chunk_key(CHUNK_HASH_TAG, index)[..]);
+    }
+
+    /// Pads the blob with zeros from `len` to `new_len`.
+    fn pad(&mut self, len: u64, new_len: u64) {
+        let chunk_size = BLOB_CHUNK_SIZE as u64;
+        let mut position = len;
+        while position < new_len {
+            let index = position / chunk_size;
+            let mut data = self.chunk(index);
+            let chunk_end = cmp::min(new_len - index * chunk_size, chunk_size);
+            data.resize(chunk_end as usize, 0);
+            self.put_chunk(index, data);
+            position = index * chunk_size + chunk_end;
+        }
+    }
+
+    fn tree_node(&self, height: u8, index: u64) -> Vec<u8> {
+        self.base
+            .get(&node_key(height, index)[..])
+            .expect("Missing blob tree node")
+    }
+
+    /// Updates the blob length and its content hash. Only the tree nodes affected by
+    /// the changed chunks and by the change of the blob length are recomputed.
+    fn commit(&mut self, len: u64) {
+        let mut level_len = chunk_count(len);
+        let mut old_level_len = chunk_count(self.len());
+        let mut changed = mem::take(&mut self.changed_chunks);
+        changed.retain(|&index| index < level_len);
+        // The rightmost path in the tree depends on the number of chunks.
+        if level_len > 0 {
+            changed.insert(level_len - 1);
+        }
+
+        let mut height = 0;
+        let mut root_height = 0;
+        while level_len > 1 || old_level_len > 1 {
+            let parent_len = parent_level_len(level_len);
+            let old_parent_len = parent_level_len(old_level_len);
+            // Remove nodes not present in the tree after the blob was shortened.
+            for index in parent_len..old_parent_len {
+                self.base.remove(&node_key(height + 1, index)[..]);
+            }
+
+            changed = changed.into_iter().map(|index| index / 2).collect();
+            for &index in changed.iter().take_while(|&&index| index < parent_len) {
+                let mut hasher = Sha256::new();
+                hasher.update([TREE_NODE_PREFIX]);
+                hasher.update(self.tree_node(height, 2 * index));
+                if 2 * index + 1 < level_len {
+                    hasher.update(self.tree_node(height, 2 * index + 1));
+                }
+                let node = hasher.finalize().to_vec();
+                self.base.put(&node_key(height + 1, index)[..], node);
+            }
+
+            height += 1;
+            if parent_len == 1 {
+                root_height = height;
+            }
+            level_len = parent_len;
+            old_level_len = old_parent_len;
+        }
+
+        let tree_root = if len > 0 {
+            Some(self.tree_node(root_height, 0))
+        } else {
+            None
+        };
+        let hash = content_hash(len, tree_root.as_deref());
+        self.state.set(BlobState { len, hash });
+    }
+
+    /// Returns a writer for the blob. The writer starts at the beginning of the blob
+    /// and overwrites its contents; writing past the end of the blob extends it.
+    ///
+    /// Changes are committed to the index metadata when the writer is flushed or dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, BlobIndex};
+    /// use std::io::{Seek, SeekFrom, Write};
+    ///
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_blob("name");
+    /// index.writer().write_all(b"Hello, world!").unwrap();
+    ///
+    /// let mut writer = index.writer();
+    /// writer.seek(SeekFrom::End(-6)).unwrap();
+    /// writer.write_all(b"Rust!!").unwrap();
+    /// drop(writer);
+    /// assert_eq!(index.to_vec(), b"Hello, Rust!!");
+    /// ```
+    pub fn writer(&mut self) -> BlobWriter<'_, T> {
+        let len = self.len();
+        BlobWriter {
+            blob: self,
+            len,
+            position: 0,
+            chunk: None,
+            dirty: false,
+        }
+    }
+
+    /// Shortens or extends the blob to the specified length. If the blob is extended,
+    /// it is padded with zeros.
+    pub fn truncate(&mut self, new_len: u64) {
+        let len = self.len();
+        let chunk_size = BLOB_CHUNK_SIZE as u64;
+        if new_len < len {
+            let new_count = chunk_count(new_len);
+            for index in new_count..chunk_count(len) {
+                self.remove_chunk(index);
+            }
+            let last_chunk_len = new_len % chunk_size;
+            if last_chunk_len > 0 {
+                let mut data = self.chunk(new_count - 1);
+                data.truncate(last_chunk_len as usize);
+                self.put_chunk(new_count - 1, data);
+            }
+        } else {
+            self.pad(len, new_len);
+        }
+        self.commit(new_len);
+    }
+
+    /// Clears the blob, removing all its contents.
+    ///
+    /// # Notes
+    ///
+    /// Currently, this method is not optimized to delete a large set of data. During the execution of
+    /// this method, the amount of allocated memory is linearly dependent on the number of chunks
+    /// in the index.
+    pub fn clear(&mut self) {
+        self.base.clear();
+        self.state.unset();
+        self.changed_chunks.clear();
+    }
+}
+
+/// Reader over the contents of a [`BlobIndex`].
+///
+/// This struct is created by the [`reader`] method on `BlobIndex`.
+///
+/// [`BlobIndex`]: struct.BlobIndex.html
+/// [`reader`]: struct.BlobIndex.html#method.reader
+pub struct BlobReader<'a, T: RawAccess> {
+    blob: &'a BlobIndex<T>,
+    len: u64,
+    position: u64,
+    chunk: Option<(u64, Vec<u8>)>,
+}
+
+impl<T: RawAccess> fmt::Debug for BlobReader<'_, T> {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("BlobReader")
+            .field("len", &self.len)
+            .field("position", &self.position)
+            .finish()
+    }
+}
+
+impl<T: RawAccess> Read for BlobReader<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let chunk_size = BLOB_CHUNK_SIZE as u64;
+        let index = self.position / chunk_size;
+        let offset = (self.position % chunk_size) as usize;
+        let blob = self.blob;
+        let data = match &mut self.chunk {
+            Some((chunk_index, data)) if *chunk_index == index => data,
+            chunk => &chunk.insert((index, blob.chunk(index))).1,
+        };
+
+        let available = data.len().saturating_sub(offset);
+        let read = cmp::min(available, buf.len());
+        buf[..read].copy_from_slice(&data[offset..offset + read]);
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<T: RawAccess> Seek for BlobReader<'_, T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = match pos {
+            SeekFrom::Start(position) => position,
+            SeekFrom::End(offset) => seek_position(self.len, offset)?,
+            SeekFrom::Current(offset) => seek_position(self.position, offset)?,
+        };
+        Ok(self.position)
+    }
+}
+
+/// Writer for the contents of a [`BlobIndex`].
+///
+/// This struct is created by the [`writer`] method on `BlobIndex`. The writer buffers
+/// the currently written chunk; changes are committed to the index when the writer
+/// is flushed or dropped.
+///
+/// [`BlobIndex`]: struct.BlobIndex.html
+/// [`writer`]: struct.BlobIndex.html#method.writer
+pub struct BlobWriter<'a, T: RawAccessMut> {
+    blob: &'a mut BlobIndex<T>,
+    len: u64,
+    position: u64,
+    chunk: Option<(u64, Vec<u8>)>,
+    dirty: bool,
+}
+
+impl<T: RawAccessMut> fmt::Debug for BlobWriter<'_, T> {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("BlobWriter")
+            .field("len", &self.len)
+            .field("position", &self.position)
+            .finish()
+    }
+}
+
+impl<T: RawAccessMut> BlobWriter<'_, T> {
+    fn store_chunk(&mut self) {
+        if let Some((index, data)) = self.chunk.take() {
+            self.blob.put_chunk(index, data);
+        }
+    }
+}
+
+impl<T: RawAccessMut> Write for BlobWriter<'_, T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.dirty = true;
+        if self.position > self.len {
+            self.store_chunk();
+            self.blob.pad(self.len, self.position);
+            self.len = self.position;
+        }
+
+        let chunk_size = BLOB_CHUNK_SIZE as u64;
+        let index = self.position / chunk_size;
+        let offset = (self.position % chunk_size) as usize;
+        if self.chunk.as_ref().map(|(chunk_index, _)| *chunk_index) != Some(index) {
+            self.store_chunk();
+            self.chunk = Some((index, self.blob.chunk(index)));
+        }
+        let data = &mut self.chunk.as_mut().unwrap().1;
+
+        let written = cmp::min(buf.len(), BLOB_CHUNK_SIZE - offset);
+        if data.len() < offset + written {
+            data.resize(offset + written, 0);
+        }
+        data[offset..offset + written].copy_from_slice(&buf[..written]);
+        self.position += written as u64;
+        self.len = cmp::max(self.len, self.position);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.dirty {
+            self.store_chunk();
+            self.blob.commit(self.len);
+            self.dirty = false;
+        }
+        Ok(())
+    }
+}
+
+impl<T: RawAccessMut> Seek for BlobWriter<'_, T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = match pos {
+            SeekFrom::Start(position) => position,
+            SeekFrom::End(offset) => seek_position(self.len, offset)?,
+            SeekFrom::Current(offset) => seek_position(self.position, offset)?,
+        };
+        Ok(self.position)
+    }
+}
+
+impl<T: RawAccessMut> Drop for BlobWriter<'_, T> {
+    fn drop(&mut self) {
+        self.flush().expect("Flushing blob writer cannot fail");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{access::CopyAccessExt, Database, Fork, TemporaryDB};
+
+    fn test_data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn blob_write_and_read() {
+        let db = TemporaryDB::new();
+        let fork = db.fork();
+        let data = test_data(BLOB_CHUNK_SIZE * 3 + 100);
+        {
+            let mut blob = fork.get_blob("blob");
+            assert!(blob.is_empty());
+            let empty_hash = blob.content_hash();
+            let mut writer = blob.writer();
+            for piece in data.chunks(1_000) {
+                writer.write_all(piece).unwrap();
+            }
+            drop(writer);
+            assert_eq!(blob.len(), data.len() as u64);
+            assert_ne!(blob.content_hash(), empty_hash);
+        }
+        db.merge(fork.into_patch()).unwrap();
+
+        let snapshot = db.snapshot();
+        let blob = snapshot.get_blob("blob");
+        assert_eq!(blob.to_vec(), data);
+
+        let mut reader = blob.reader();
+        let start = BLOB_CHUNK_SIZE as u64 - 10;
+        reader.seek(SeekFrom::Start(start)).unwrap();
+        let mut buffer = vec![0; 20];
+        reader.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer[..], data[start as usize..start as usize + 20]);
+        reader.seek(SeekFrom::End(-5)).unwrap();
+        let mut tail = vec![];
+        reader.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail[..], data[data.len() - 5..]);
+        assert!(reader.seek(SeekFrom::Current(-1_000_000)).is_err());
+    }
+
+    #[test]
+    fn partial_overwrite_and_truncate() {
+        let db = TemporaryDB::new();
+        let fork = db.fork();
+        let mut data = test_data(BLOB_CHUNK_SIZE * 2 + 7);
+        let mut blob = fork.get_blob("blob");
+        blob.writer().write_all(&data).unwrap();
+        let hash = blob.content_hash();
+
+        let mut writer = blob.writer();
+        writer
+            .seek(SeekFrom::Start(BLOB_CHUNK_SIZE as u64 - 2))
+            .unwrap();
+        writer.write_all(&[0xff; 4]).unwrap();
+        drop(writer);
+        data[BLOB_CHUNK_SIZE - 2..BLOB_CHUNK_SIZE + 2].copy_from_slice(&[0xff; 4]);
+        assert_eq!(blob.to_vec(), data);
+        assert_ne!(blob.content_hash(), hash);
+
+        // Content hash depends only on the contents.
+        let mut other = fork.get_blob("other");
+        other.writer().write_all(&data).unwrap();
+        assert_eq!(other.content_hash(), blob.content_hash());
+
+        // Writing past the end pads the blob with zeros.
+        let mut writer = blob.writer();
+        let gap_start = data.len() as u64 + BLOB_CHUNK_SIZE as u64;
+        writer.seek(SeekFrom::Start(gap_start)).unwrap();
+        writer.write_all(b"end").unwrap();
+        drop(writer);
+        data.resize(gap_start as usize, 0);
+        data.extend_from_slice(b"end");
+        assert_eq!(blob.to_vec(), data);
+
+        blob.truncate(BLOB_CHUNK_SIZE as u64 + 1);
+        data.truncate(BLOB_CHUNK_SIZE + 1);
+        assert_eq!(blob.to_vec(), data);
+        other.truncate(BLOB_CHUNK_SIZE as u64 + 1);
+        assert_eq!(other.content_hash(), blob.content_hash());
+
+        blob.truncate(BLOB_CHUNK_SIZE as u64 + 10);
+        data.resize(BLOB_CHUNK_SIZE + 10, 0);
+        assert_eq!(blob.to_vec(), data);
+
+        blob.clear();
+        assert!(blob.is_empty());
+        assert!(blob.to_vec().is_empty());
+        assert_eq!(blob.content_hash(), BlobState::default().hash);
+    }
+
+    #[test]
+    fn tree_is_updated_incrementally() {
+        fn stored_entries(blob: &BlobIndex<&Fork>) -> usize {
+            blob.base.iter::<_, [u8], Vec<u8>>(&()).count()
+        }
+
+        fn expected_entries(chunk_count: u64) -> usize {
+            let mut entries = 2 * chunk_count;
+            let mut level_len = chunk_count;
+            while level_len > 1 {
+                level_len = parent_level_len(level_len);
+                entries += level_len;
+            }
+            entries as usize
+        }
+
+        let db = TemporaryDB::new();
+        let fork = db.fork();
+        let mut blob = fork.get_blob("blob");
+        let mut data = test_data(BLOB_CHUNK_SIZE * 9 + 1);
+        blob.writer().write_all(&data).unwrap();
+        assert_eq!(stored_entries(&blob), expected_entries(10));
+
+        for &len in &[BLOB_CHUNK_SIZE * 9, BLOB_CHUNK_SIZE * 5 - 1, 10, 0, 3] {
+            data.resize(len, 0);
+            blob.truncate(len as u64);
+            assert_eq!(
+                stored_entries(&blob),
+                expected_entries(chunk_count(len as u64))
+            );
+
+            let mut writer = blob.writer();
+            writer.seek(SeekFrom::Start(len as u64 / 2)).unwrap();
+            writer.write_all(b"!").unwrap();
+            drop(writer);
+            let position = len / 2;
+            if position < data.len() {
+                data[position] = b'!';
+            } else {
+                data.push(b'!');
+            }
+
+            let mut fresh = fork.get_blob(("fresh", &(len as u64)));
+            fresh.writer().write_all(&data).unwrap();
+            assert_eq!(fresh.content_hash(), blob.content_hash());
+        }
+    }
+}
@@ -0,0 +1,439 @@
+//! An implementation of a map of commutative counters.
+//!
+//! `CounterIndex` maps keys implementing the [`BinaryKey`] trait to 64-bit signed counters.
+//! Unlike a `MapIndex<_, _, i64>`, counter updates are recorded as merge operands rather
+//! than computed values, so they do not require a read-modify-write cycle. As a result,
+//! increments made in several non-sequential forks compose when the forks are merged
+//! into the database.
+//!
+//! [`BinaryKey`]: ../trait.BinaryKey.html
+
+use std::marker::PhantomData;
+
+use crate::{
+    access::{Access, AccessError, FromAccess},
+    indexes::iter::{Entries, IndexIterator, Keys, Values},
+    views::{IndexAddress, IndexType, RawAccess, RawAccessMut, View, ViewWithMetadata},
+    BinaryKey,
+};
+
+/// A map of commutative 64-bit signed counters.
+///
+/// An absent counter is equivalent to a counter with the zero value. Increments
+/// and decrements are stored as merge operands and are resolved lazily by the storage
+/// backend; thus, two forks created from the same snapshot may both increment the same
+/// counter, and after merging both forks the counter will reflect both increments.
+/// Arithmetic on counters wraps on overflow.
+///
+/// Note that [`set`] and [`remove`] are *not* commutative: they override all changes to
+/// the counter made by forks merged before, in accordance with the general merge rules
+/// described in the [`Database`] docs.
+///
+/// [`set`]: #method.set
+/// [`remove`]: #method.remove
+/// [`Database`]: ../trait.Database.html
+#[derive(Debug)]
+pub struct CounterIndex<T: RawAccess, K: ?Sized> {
+    base: View<T>,
+    _k: PhantomData<K>,
+}
+
+impl<T, K> FromAccess<T> for CounterIndex<T::Base, K>
+where
+    T: Access,
+    K: BinaryKey + ?Sized,
+{
+    fn from_access(access: T, addr: IndexAddress) -> Result<Self, AccessError> {
+        let view = access.get_or_create_view(addr, IndexType::Counter)?;
+        Ok(Self::new(view))
+    }
+}
+
+impl<T, K> CounterIndex<T, K>
+where
+    T: RawAccess,
+    K: BinaryKey + ?Sized,
+{
+    fn new(view: ViewWithMetadata<T>) -> Self {
+        let base = view.into();
+        Self {
+            base,
+            _k: PhantomData,
+        }
+    }
+
+    /// Returns the value of the counter corresponding to the key. Absent counters
+    /// have the zero value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, CounterIndex};
+    ///
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_counter("name");
+    /// assert_eq!(index.get(&1_u8), 0);
+    ///
+    /// index.increment(&1_u8, 2);
+    /// assert_eq!(index.get(&1_u8), 2);
+    /// ```
+    pub fn get(&self, key: &K) -> i64 {
+        self.base.get(key).unwrap_or_default()
+    }
+
+    /// Returns `true` if the index contains a counter for the specified key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, CounterIndex};
+    ///
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_counter("name");
+    /// assert!(!index.contains(&1_u8));
+    ///
+    /// index.increment(&1_u8, 1);
+    /// assert!(index.contains(&1_u8));
+    /// ```
+    pub fn contains(&self, key: &K) -> bool {
+        self.base.contains(key)
+    }
+
+    /// Returns an iterator over the counters in ascending order of keys.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, CounterIndex};
+    ///
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let index: CounterIndex<_, u8> = fork.get_counter("name");
+    ///
+    /// for (key, value) in index.iter() {
+    ///     println!("{} = {}", key, value);
+    /// }
+    /// ```
+    pub fn iter(&self) -> Entries<'_, K, i64> {
+        self.index_iter(None)
+    }
+
+    /// Returns an iterator over the keys of the counters in ascending order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, CounterIndex};
+    ///
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let index: CounterIndex<_, u8> = fork.get_counter("name");
+    ///
+    /// for key in index.keys() {
+    ///     println!("{}", key);
+    /// }
+    /// ```
+    pub fn keys(&self) -> Keys<'_, K> {
+        self.iter().skip_values()
+    }
+
+    /// Returns an iterator over the counter values in ascending order of keys.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, CounterIndex};
+    ///
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let index: CounterIndex<_, u8> = fork.get_counter("name");
+    ///
+    /// for value in index.values() {
+    ///     println!("{}", value);
+    /// }
+    /// ```
+    pub fn values(&self) -> Values<'_, i64> {
+        self.iter().skip_keys()
+    }
+
+    /// Returns an iterator over the counters starting from the specified key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, CounterIndex};
+    ///
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let index: CounterIndex<_, u8> = fork.get_counter("name");
+    ///
+    /// for (key, value) in index.iter_from(&2) {
+    ///     println!("{} = {}", key, value);
+    /// }
+    /// ```
+    pub fn iter_from(&self, from: &K) -> Entries<'_, K, i64> {
+        self.index_iter(Some(from))
+    }
+}
+
+impl<T, K> CounterIndex<T, K>
+where
+    T: RawAccessMut,
+    K: BinaryKey + ?Sized,
+{
+    /// Adds `delta` to the counter corresponding to the key. The counter is not read
+    /// during the update, so increments from concurrent forks compose on merge.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, CounterIndex};
+    ///
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_counter("name");
+    ///
+    /// index.increment(&1_u8, 5);
+    /// index.increment(&1_u8, -2);
+    /// assert_eq!(index.get(&1_u8), 3);
+    /// ```
+    pub fn increment(&mut self, key: &K, delta: i64) {
+        self.base.merge(key, delta);
+    }
+
+    /// Subtracts `delta` from the counter corresponding to the key. Equivalent to
+    /// incrementing the counter by `-delta` (with wrapping negation).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, CounterIndex};
+    ///
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_counter("name");
+    ///
+    /// index.decrement(&1_u8, 5);
+    /// assert_eq!(index.get(&1_u8), -5);
+    /// ```
+    pub fn decrement(&mut self, key: &K, delta: i64) {
+        self.increment(key, delta.wrapping_neg());
+    }
+
+    /// Sets the counter corresponding to the key to the specified value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, CounterIndex};
+    ///
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_counter("name");
+    ///
+    /// index.set(&1_u8, 10);
+    /// index.increment(&1_u8, 1);
+    /// assert_eq!(index.get(&1_u8), 11);
+    /// ```
+    pub fn set(&mut self, key: &K, value: i64) {
+        self.base.put(key, value);
+    }
+
+    /// Removes the counter corresponding to the key, effectively resetting it to zero.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, CounterIndex};
+    ///
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_counter("name");
+    ///
+    /// index.increment(&1_u8, 2);
+    /// index.remove(&1_u8);
+    /// assert!(!index.contains(&1_u8));
+    /// assert_eq!(index.get(&1_u8), 0);
+    /// ```
+    pub fn remove(&mut self, key: &K) {
+        self.base.remove(key);
+    }
+
+    /// Clears the index, removing all counters.
+    ///
+    /// # Notes
+    ///
+    /// Currently, this method is not optimized to delete a large set of data. During the execution of
+    /// this method, the amount of allocated memory is linearly dependent on the number of elements
+    /// in the index.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, CounterIndex};
+    ///
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_counter("name");
+    ///
+    /// index.increment(&1_u8, 2);
+    /// index.clear();
+    /// assert!(!index.contains(&1_u8));
+    /// ```
+    pub fn clear(&mut self) {
+        self.base.clear();
+    }
+}
+
+impl<'a, T, K> IntoIterator for &'a CounterIndex<T, K>
+where
+    T: RawAccess,
+    K: BinaryKey + ?Sized,
+{
+    type Item = (K::Owned, i64);
+    type IntoIter = Entries<'a, K, i64>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<T, K> IndexIterator for CounterIndex<T, K>
+where
+    T: RawAccess,
+    K: BinaryKey + ?Sized,
+{
+    type Key = K;
+    type Value = i64;
+
+    fn index_iter(&self, from: Option<&K>) -> Entries<'_, K, i64> {
+        Entries::new(&self.base, from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CounterIndex;
+    use crate::{access::CopyAccessExt, DBOptions, Database, RocksDB, TemporaryDB};
+
+    const INDEX_NAME: &str = "test_counter";
+
+    fn concurrent_increments<DB: Database>(db: &DB) {
+        let fork = db.fork();
+        fork.get_counter(INDEX_NAME).set("a", 10_i64);
+        db.merge(fork.into_patch()).unwrap();
+
+        let first = db.fork();
+        let second = db.fork();
+        {
+            let mut counter: CounterIndex<_, str> = first.get_counter(INDEX_NAME);
+            counter.increment("a", 5);
+            counter.increment("b", 1);
+            assert_eq!(counter.get("a"), 15);
+        }
+        {
+            let mut counter: CounterIndex<_, str> = second.get_counter(INDEX_NAME);
+            counter.decrement("a", 3);
+            counter.increment("b", 2);
+            assert_eq!(counter.get("a"), 7);
+        }
+        db.merge(first.into_patch()).unwrap();
+        db.merge(second.into_patch()).unwrap();
+
+        let snapshot = db.snapshot();
+        let counter: CounterIndex<_, str> = snapshot.get_counter(INDEX_NAME);
+        assert_eq!(counter.get("a"), 12);
+        assert_eq!(counter.get("b"), 3);
+        assert_eq!(
+            counter.iter().collect::<Vec<_>>(),
+            vec![("a".to_owned(), 12), ("b".to_owned(), 3)]
+        );
+    }
+
+    #[test]
+    fn concurrent_increments_in_temporary_db() {
+        concurrent_increments(&TemporaryDB::new());
+    }
+
+    #[test]
+    fn concurrent_increments_in_rocksdb() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let db = RocksDB::open(&dir, &DBOptions::default()).unwrap();
+        concurrent_increments(&db);
+    }
+
+    #[test]
+    fn counters_are_readable_within_fork() {
+        let db = TemporaryDB::new();
+        let fork = db.fork();
+        fork.get_counter(INDEX_NAME).increment(&1_u8, 3);
+        db.merge(fork.into_patch()).unwrap();
+
+        let mut fork = db.fork();
+        {
+            let mut counter = fork.get_counter(INDEX_NAME);
+            counter.increment(&1_u8, 2);
+            counter.increment(&2_u8, -1);
+            counter.increment(&3_u8, 1);
+            counter.remove(&3_u8);
+            assert_eq!(counter.get(&1_u8), 5);
+            assert_eq!(counter.get(&2_u8), -1);
+            assert!(!counter.contains(&3_u8));
+            assert_eq!(
+                counter.iter().collect::<Vec<_>>(),
+                vec![(1_u8, 5_i64), (2, -1)]
+            );
+        }
+
+        fork.flush();
+        {
+            let mut counter = fork.get_counter(INDEX_NAME);
+            counter.increment(&1_u8, 1);
+            assert_eq!(counter.get(&1_u8), 6);
+        }
+        fork.rollback();
+        {
+            let counter = fork.get_counter::<_, u8>(INDEX_NAME);
+            assert_eq!(counter.get(&1_u8), 5);
+            assert_eq!(counter.values().collect::<Vec<_>>(), vec![5, -1]);
+        }
+
+        let patch = fork.into_patch();
+        let counter = patch.get_counter::<_, u8>(INDEX_NAME);
+        assert_eq!(counter.get(&1_u8), 5);
+        assert_eq!(counter.get(&2_u8), -1);
+        assert_eq!(counter.keys().collect::<Vec<_>>(), vec![1, 2]);
+    }
+
+    #[test]
+    fn increments_after_clear() {
+        let db = TemporaryDB::new();
+        let fork = db.fork();
+        fork.get_counter(INDEX_NAME).increment(&1_u8, 3);
+        db.merge(fork.into_patch()).unwrap();
+
+        let fork = db.fork();
+        {
+            let mut counter = fork.get_counter(INDEX_NAME);
+            counter.clear();
+            counter.increment(&1_u8, 2);
+            assert_eq!(counter.get(&1_u8), 2);
+            assert_eq!(counter.iter().collect::<Vec<_>>(), vec![(1_u8, 2_i64)]);
+        }
+        db.merge(fork.into_patch()).unwrap();
+        let snapshot = db.snapshot();
+        assert_eq!(snapshot.get_counter::<_, u8>(INDEX_NAME).get(&1), 2);
+    }
+
+    #[test]
+    fn counter_overflow_wraps() {
+        let db = TemporaryDB::new();
+        let fork = db.fork();
+        let mut counter = fork.get_counter(INDEX_NAME);
+        counter.set(&1_u8, i64::max_value());
+        counter.increment(&1_u8, 1);
+        assert_eq!(counter.get(&1_u8), i64::min_value());
+    }
+}
@@ -0,0 +1,342 @@
+//! Opaque cursors for paginated iteration over indexes.
+
+use anyhow::{ensure, format_err};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use thiserror::Error;
+
+use std::{
+    borrow::{Borrow, Cow},
+    fmt,
+    num::NonZeroU64,
+    str::FromStr,
+};
+
+use crate::{
+    values::{read_bytes, write_bytes},
+    views::{key_bytes, Iter, RawAccess, ResolvedAddress, View},
+    BinaryKey, BinaryValue,
+};
+
+/// Version of the cursor serialization format.
+const CURSOR_VERSION: u8 = 1;
+/// Length of the cursor signature in bytes.
+const SIGNATURE_LEN: usize = 32;
+
+/// Page of index entries together with the cursor pointing to the next page.
+pub type CursorPage<K, V> = (Vec<(K, V)>, Option<Cursor>);
+
+/// Errors that can occur when resuming iteration from a [`Cursor`].
+///
+/// [`Cursor`]: struct.Cursor.html
+#[derive(Debug, Error)]
+pub enum CursorError {
+    /// Cursor cannot be parsed.
+    #[error("Malformed cursor: {0}")]
+    Malformed(#[source] anyhow::Error),
+
+    /// Cursor signature does not match its contents, i.e., the cursor was tampered with
+    /// or was issued by a signer with another secret.
+    #[error("Cursor signature is invalid")]
+    InvalidSignature,
+
+    /// Cursor was issued for another index.
+    #[error("Cursor was issued for another index")]
+    AddressMismatch,
+}
+
+/// Signer of pagination cursors.
+///
+/// The signer authenticates cursors with HMAC-SHA256 keyed by the secret provided on creation,
+/// so that cursors passed to external clients cannot be forged or modified.
+#[derive(Clone)]
+pub struct CursorSigner {
+    mac: Hmac<Sha256>,
+}
+
+impl fmt::Debug for CursorSigner {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.debug_struct("CursorSigner").finish()
+    }
+}
+
+impl CursorSigner {
+    /// Creates a signer with the specified secret.
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            mac: Hmac::new_from_slice(secret).expect("HMAC accepts keys of any size"),
+        }
+    }
+
+    fn issue(&self, address: ResolvedAddress, next_key: Vec<u8>) -> Cursor {
+        let mut mac = self.mac.clone();
+        mac.update(&Cursor::payload(&address, &next_key));
+        Cursor {
+            address,
+            next_key,
+            signature: mac.finalize().into_bytes().to_vec(),
+        }
+    }
+
+    fn verify(&self, cursor: &Cursor) -> Result<(), CursorError> {
+        let mut mac = self.mac.clone();
+        mac.update(&Cursor::payload(&cursor.address, &cursor.next_key));
+        mac.verify_slice(&cursor.signature)
+            .map_err(|_| CursorError::InvalidSignature)
+    }
+}
+
+/// Signed position in an index allowing to resume paginated iteration.
+///
+/// A cursor encodes the resolved address of the index and the raw key of the first entry
+/// on the next page. Cursors can be serialized via the `BinaryValue` trait, or converted
+/// to a hex string via `Display` / `FromStr`. Iteration can be resumed from a cursor on any
+/// snapshot; if the index was removed and created anew in the meantime, the cursor
+/// is rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cursor {
+    address: ResolvedAddress,
+    next_key: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+impl Cursor {
+    /// Returns the resolved address of the index this cursor was issued for.
+    pub fn address(&self) -> &ResolvedAddress {
+        &self.address
+    }
+
+    /// Returns the raw key of the first entry on the next page.
+    pub fn next_key(&self) -> &[u8] {
+        &self.next_key
+    }
+
+    /// Returns the signed part of the cursor.
+    fn payload(address: &ResolvedAddress, next_key: &[u8]) -> Vec<u8> {
+        let mut buffer = vec![CURSOR_VERSION];
+        write_bytes(&mut buffer, address.name.as_bytes());
+        if let Some(id) = address.id {
+            buffer.push(1);
+            buffer.write_u64::<LittleEndian>(id.get()).unwrap();
+        } else {
+            buffer.push(0);
+        }
+        buffer.extend_from_slice(next_key);
+        buffer
+    }
+}
+
+impl BinaryValue for Cursor {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = Self::payload(&self.address, &self.next_key);
+        buffer.extend_from_slice(&self.signature);
+        buffer
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> anyhow::Result<Self> {
+        ensure!(
+            bytes.len() > SIGNATURE_LEN,
+            "Cursor is too short: {} bytes",
+            bytes.len()
+        );
+        let (mut payload, signature) = bytes.split_at(bytes.len() - SIGNATURE_LEN);
+
+        let version = payload.read_u8()?;
+        ensure!(
+            version == CURSOR_VERSION,
+            "Unsupported cursor version: {}",
+            version
+        );
+        let name = String::from_utf8(read_bytes(&mut payload)?)?;
+        let id = match payload.read_u8()? {
+            0 => None,
+            1 => {
+                let id = payload.read_u64::<LittleEndian>()?;
+                Some(NonZeroU64::new(id).ok_or_else(|| format_err!("Zero index ID"))?)
+            }
+            tag => return Err(format_err!("Invalid option tag: {}", tag)),
+        };
+
+        Ok(Self {
+            address: ResolvedAddress::new(name, id),
+            next_key: payload.to_vec(),
+            signature: signature.to_vec(),
+        })
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.to_bytes() {
+            write!(formatter, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = CursorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_hex = || -> anyhow::Result<Vec<u8>> {
+            s.as_bytes()
+                .chunks(2)
+                .map(|chunk| {
+                    ensure!(chunk.len() == 2, "Odd length of hex string");
+                    let byte = std::str::from_utf8(chunk)?;
+                    u8::from_str_radix(byte, 16).map_err(From::from)
+                })
+                .collect()
+        };
+        let bytes = parse_hex().map_err(CursorError::Malformed)?;
+        Self::from_bytes(bytes.into()).map_err(CursorError::Malformed)
+    }
+}
+
+/// Returns up to `limit` entries of the `view` starting from the `cursor` position,
+/// or from the beginning of the view if `cursor` is `None`.
+pub fn paginate<T, K, V>(
+    view: &View<T>,
+    signer: &CursorSigner,
+    cursor: Option<&Cursor>,
+    limit: usize,
+) -> Result<CursorPage<K::Owned, V>, CursorError>
+where
+    T: RawAccess,
+    K: BinaryKey + ?Sized,
+    V: BinaryValue,
+{
+    if let Some(cursor) = cursor {
+        signer.verify(cursor)?;
+    }
+    let address = match view.address() {
+        Some(address) => address,
+        // The index does not exist, so there is nothing to iterate over.
+        None => return Ok((vec![], None)),
+    };
+
+    let mut iter: Iter<'_, K, V> = match cursor {
+        Some(cursor) if cursor.address != *address => return Err(CursorError::AddressMismatch),
+        Some(cursor) => view.iter_from(&(), &cursor.next_key[..]),
+        None => view.iter(&()),
+    };
+    let items: Vec<_> = iter.by_ref().take(limit).collect();
+    let next = iter
+        .next()
+        .map(|(key, _)| signer.issue(address.clone(), key_bytes::<K>(key.borrow())));
+    Ok((items, next))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        access::{Access, CopyAccessExt},
+        Database, TemporaryDB,
+    };
+
+    fn create_db() -> TemporaryDB {
+        let db = TemporaryDB::new();
+        let fork = db.fork();
+        {
+            let mut map = fork.get_map::<_, u32, u32>("map");
+            for i in 0..10 {
+                map.put(&i, i);
+            }
+        }
+        fork.get_map::<_, u32, u32>("other").put(&1, 1);
+        db.merge(fork.into_patch()).unwrap();
+        db
+    }
+
+    #[test]
+    fn pagination_is_resumed_on_later_snapshot() {
+        let db = create_db();
+        let signer = CursorSigner::new(b"secret");
+
+        let snapshot = db.snapshot();
+        let map = snapshot.get_map::<_, u32, u32>("map");
+        let (items, cursor) = map.paginate(&signer, None, 4).unwrap();
+        assert_eq!(items.len(), 4);
+        let cursor = cursor.unwrap();
+        assert_eq!(cursor.next_key(), &key_bytes(&4_u32)[..]);
+
+        let fork = db.fork();
+        {
+            let mut map = fork.get_map::<_, u32, u32>("map");
+            map.remove(&4);
+            map.put(&100, 100);
+        }
+        db.merge(fork.into_patch()).unwrap();
+
+        let snapshot = db.snapshot();
+        let map = snapshot.get_map::<_, u32, u32>("map");
+        let (items, cursor) = map.paginate(&signer, Some(&cursor), 4).unwrap();
+        assert_eq!(items, vec![(5, 5), (6, 6), (7, 7), (8, 8)]);
+        let cursor = Cursor::from_bytes(cursor.unwrap().to_bytes().into()).unwrap();
+        let (items, cursor) = map.paginate(&signer, Some(&cursor), 4).unwrap();
+        assert_eq!(items, vec![(9, 9), (100, 100)]);
+        assert!(cursor.is_none());
+
+        // Non-existing indexes are empty.
+        let (items, cursor) = snapshot
+            .get_map::<_, u32, u32>("missing")
+            .paginate(&signer, None, 4)
+            .unwrap();
+        assert!(items.is_empty());
+        assert!(cursor.is_none());
+    }
+
+    #[test]
+    fn invalid_cursors_are_rejected() {
+        let db = create_db();
+        let signer = CursorSigner::new(b"secret");
+        let snapshot = db.snapshot();
+        let map = snapshot.get_map::<_, u32, u32>("map");
+        let (_, cursor) = map.paginate(&signer, None, 2).unwrap();
+        let cursor = cursor.unwrap();
+
+        let mut bytes = cursor.to_bytes();
+        let pos = bytes.len() - SIGNATURE_LEN - 1;
+        bytes[pos] += 1;
+        let forged = Cursor::from_bytes(bytes.into()).unwrap();
+        let err = map.paginate(&signer, Some(&forged), 2).unwrap_err();
+        assert!(matches!(err, CursorError::InvalidSignature));
+
+        let other_signer = CursorSigner::new(b"other secret");
+        let err = map.paginate(&other_signer, Some(&cursor), 2).unwrap_err();
+        assert!(matches!(err, CursorError::InvalidSignature));
+
+        let other_map = snapshot.get_map::<_, u32, u32>("other");
+        let err = other_map.paginate(&signer, Some(&cursor), 2).unwrap_err();
+        assert!(matches!(err, CursorError::AddressMismatch));
+
+        let err = "c0ffee".parse::<Cursor>().unwrap_err();
+        assert!(matches!(err, CursorError::Malformed(_)));
+        let err = "not a cursor".parse::<Cursor>().unwrap_err();
+        assert!(matches!(err, CursorError::Malformed(_)));
+        assert_eq!(cursor.to_string().parse::<Cursor>().unwrap(), cursor);
+    }
+
+    #[test]
+    fn cursor_is_rejected_after_index_is_recreated() {
+        let db = create_db();
+        let signer = CursorSigner::new(b"secret");
+        let (_, cursor) = db
+            .snapshot()
+            .get_map::<_, u32, u32>("map")
+            .paginate(&signer, None, 2)
+            .unwrap();
+
+        let fork = db.fork();
+        (&fork).remove_index("map".into()).unwrap();
+        fork.get_map::<_, u32, u32>("map").put(&3, 3);
+        db.merge(fork.into_patch()).unwrap();
+
+        let snapshot = db.snapshot();
+        let map = snapshot.get_map::<_, u32, u32>("map");
+        let err = map.paginate(&signer, cursor.as_ref(), 2).unwrap_err();
+        assert!(matches!(err, CursorError::AddressMismatch));
+    }
+}
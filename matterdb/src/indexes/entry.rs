@@ -5,7 +5,7 @@ use std::marker::PhantomData;
 use crate::{
     access::{Access, AccessError, FromAccess},
     views::{IndexAddress, IndexType, RawAccess, RawAccessMut, View, ViewWithMetadata},
-    BinaryValue,
+    BinaryValue, PinnedValue,
 };
 
 /// An index that may only contain one element.
@@ -63,6 +63,25 @@ where
         self.base.get(&())
     }
 
+    /// Returns a raw value of the entry without copying it, if possible. The value can be
+    /// decoded as any type implementing [`BorrowedBinaryValue`].
+    ///
+    /// [`BorrowedBinaryValue`]: ../trait.BorrowedBinaryValue.html
+    pub fn get_pinned(&self) -> Option<PinnedValue<'_>> {
+        self.base.get_pinned(&())
+    }
+
+    /// Returns a value of the entry using the value cache of the database, if caching
+    /// is enabled for the entry. See the [`cache`] module for details.
+    ///
+    /// [`cache`]: ../cache/index.html
+    pub fn get_cached(&self) -> Option<V>
+    where
+        V: Clone + Send + Sync + 'static,
+    {
+        self.base.get_cached(&(), IndexType::Entry)
+    }
+
     /// Returns `true` if a value of the entry exists.
     ///
     /// # Examples
@@ -0,0 +1,436 @@
+//! An implementation of an inverted full-text index.
+
+use std::{
+    borrow::Borrow,
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+use crate::{
+    access::{Access, AccessError, FromAccess},
+    indexes::{
+        multimap::{composite_key, key_prefix},
+        MapIndex, MultiMapIndex,
+    },
+    views::{key_bytes, IndexAddress, RawAccess, RawAccessMut},
+    BinaryKey, BinaryValue, Lazy,
+};
+
+/// Splits text into terms stored in a [`FullTextIndex`].
+///
+/// The analyzer is also applied to the terms in a [`TextQuery`], so that queries are normalized
+/// in the same way as the indexed text. Closures with the `Fn(&str) -> Vec<String>` signature
+/// implement this trait.
+///
+/// [`FullTextIndex`]: struct.FullTextIndex.html
+/// [`TextQuery`]: enum.TextQuery.html
+pub trait Analyzer {
+    /// Splits `text` into terms. Repeated terms are counted towards the term frequency.
+    fn tokenize(&self, text: &str) -> Vec<String>;
+}
+
+impl<F> Analyzer for F
+where
+    F: Fn(&str) -> Vec<String>,
+{
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        self(text)
+    }
+}
+
+/// Default analyzer, which splits text by non-alphanumeric chars and converts terms
+/// to lowercase.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SimpleAnalyzer;
+
+impl Analyzer for SimpleAnalyzer {
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        text.split(|c: char| !c.is_alphanumeric())
+            .filter(|term| !term.is_empty())
+            .map(str::to_lowercase)
+            .collect()
+    }
+}
+
+/// Query to a [`FullTextIndex`].
+///
+/// [`FullTextIndex`]: struct.FullTextIndex.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextQuery {
+    /// Matches documents containing the term. If the analyzer splits the term into several
+    /// terms, documents containing all of them are matched.
+    Term(String),
+    /// Matches documents containing a term starting with the prefix. If the analyzer splits
+    /// the prefix into several terms, all terms except for the last one must be contained
+    /// in the document exactly.
+    Prefix(String),
+    /// Matches documents matched by all of the queries.
+    And(Vec<Self>),
+    /// Matches documents matched by any of the queries.
+    Or(Vec<Self>),
+}
+
+impl TextQuery {
+    /// Creates a query matching the term.
+    pub fn term(term: impl Into<String>) -> Self {
+        Self::Term(term.into())
+    }
+
+    /// Creates a query matching terms with the prefix.
+    pub fn prefix(prefix: impl Into<String>) -> Self {
+        Self::Prefix(prefix.into())
+    }
+}
+
+/// Documents matching a query: serialized key -> (key, score).
+type Matches<K> = BTreeMap<Vec<u8>, (K, u32)>;
+
+/// Inverted index over text associated with keys of type `K`.
+///
+/// The index maintains posting lists of document keys for each term in a `MultiMapIndex`,
+/// and term frequencies keyed by `(document key, term)` pairs in a `MapIndex`. Both mappings
+/// are stored in single indexes regardless of the number of terms and documents, so that
+/// the index does not allocate index identifiers for them. Text is split into terms with a pluggable
+/// [`Analyzer`]. The index is not updated automatically when the indexed data changes;
+/// use [`insert`], [`remove`] or [`rebuild`] to keep the index up to date.
+///
+/// Search results are ranked by the sum of frequencies of the matched terms in the document.
+///
+/// [`Analyzer`]: trait.Analyzer.html
+/// [`insert`]: #method.insert
+/// [`remove`]: #method.remove
+/// [`rebuild`]: #method.rebuild
+///
+/// # Examples
+///
+/// ```
+/// use matterdb::{access::{CopyAccessExt, FromAccess}, Database, TemporaryDB};
+/// use matterdb::indexes::{FullTextIndex, TextQuery};
+///
+/// let db = TemporaryDB::new();
+/// let fork = db.fork();
+/// let index: FullTextIndex<_, u32> =
+///     FromAccess::from_access(&fork, "articles_text".into()).unwrap();
+/// index.insert(&1, "Rust is a systems programming language");
+/// index.insert(&2, "RocksDB is an embedded database. Rust bindings to RocksDB exist");
+/// index.insert(&3, "A database for systems");
+///
+/// let results = index.search(&TextQuery::term("rocksdb"));
+/// assert_eq!(results, vec![(2, 2)]);
+/// let results = index.search(&TextQuery::Or(vec![
+///     TextQuery::term("database"),
+///     TextQuery::prefix("sys"),
+/// ]));
+/// assert_eq!(results, vec![(3, 2), (1, 1), (2, 1)]);
+/// let results = index.search(&TextQuery::And(vec![
+///     TextQuery::term("rust"),
+///     TextQuery::term("database"),
+/// ]));
+/// assert_eq!(results, vec![(2, 2)]);
+/// ```
+pub struct FullTextIndex<T: Access, K: ?Sized, A = SimpleAnalyzer> {
+    /// Keys of the documents containing each term.
+    postings: Lazy<T, MultiMapIndex<T::Base, str, K>>,
+    /// Term frequencies keyed by the composite keys of `(document key, term)` pairs.
+    documents: Lazy<T, MapIndex<T::Base, [u8], u32>>,
+    analyzer: A,
+}
+
+impl<T, K, A> fmt::Debug for FullTextIndex<T, K, A>
+where
+    T: Access,
+    K: ?Sized,
+{
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.debug_struct("FullTextIndex").finish()
+    }
+}
+
+impl<T, K, A> FromAccess<T> for FullTextIndex<T, K, A>
+where
+    T: Access,
+    K: BinaryKey + ?Sized,
+    A: Analyzer + Default,
+{
+    fn from_access(access: T, addr: IndexAddress) -> Result<Self, AccessError> {
+        let postings = Lazy::from_access(access.clone(), addr.clone().append_name("postings"))?;
+        let documents = Lazy::from_access(access, addr.append_name("documents"))?;
+        Ok(Self {
+            postings,
+            documents,
+            analyzer: A::default(),
+        })
+    }
+}
+
+impl<T, K, A> FullTextIndex<T, K, A>
+where
+    T: Access,
+    K: BinaryKey + ?Sized,
+    A: Analyzer,
+{
+    /// Replaces the analyzer used by the index. The index should be [rebuilt] if the new
+    /// analyzer splits text differently.
+    ///
+    /// [rebuilt]: #method.rebuild
+    pub fn with_analyzer<B: Analyzer>(self, analyzer: B) -> FullTextIndex<T, K, B> {
+        FullTextIndex {
+            postings: self.postings,
+            documents: self.documents,
+            analyzer,
+        }
+    }
+
+    /// Returns the frequency of the term in the document with the specified key.
+    pub fn term_frequency(&self, key: &K, term: &str) -> u32 {
+        self.documents
+            .get()
+            .get(&composite_key(key, term)[..])
+            .unwrap_or_default()
+    }
+
+    /// Returns all terms in the index.
+    pub fn terms(&self) -> BTreeSet<String> {
+        let postings = self.postings.get();
+        let mut terms = BTreeSet::new();
+        let mut next = postings.iter().next();
+        while let Some((term, _)) = next {
+            // Skip the remaining postings of the term; the string with the appended zero char
+            // is the smallest string following the term.
+            next = postings.iter_from(&format!("{}\0", term)).next();
+            terms.insert(term);
+        }
+        terms
+    }
+
+    /// Returns keys of the documents matching the query together with their scores.
+    /// Results are ordered by the score in the descending order, and then by the key.
+    pub fn search(&self, query: &TextQuery) -> Vec<(K::Owned, u32)> {
+        let mut results: Vec<_> = self.evaluate(query).into_iter().collect();
+        results.sort_by(|(key, (_, score)), (other_key, (_, other_score))| {
+            other_score.cmp(score).then_with(|| key.cmp(other_key))
+        });
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+
+    fn evaluate(&self, query: &TextQuery) -> Matches<K::Owned> {
+        match query {
+            TextQuery::Term(term) => {
+                let terms = self.analyzer.tokenize(term);
+                Self::intersect(terms.iter().map(|term| self.term_matches(term)))
+            }
+            TextQuery::Prefix(prefix) => {
+                let mut terms = self.analyzer.tokenize(prefix);
+                let prefix = match terms.pop() {
+                    Some(prefix) => prefix,
+                    None => return Matches::new(),
+                };
+                let matches = terms
+                    .iter()
+                    .map(|term| self.term_matches(term))
+                    .chain(Some(self.prefix_matches(&prefix)));
+                Self::intersect(matches)
+            }
+            TextQuery::And(queries) => {
+                Self::intersect(queries.iter().map(|query| self.evaluate(query)))
+            }
+            TextQuery::Or(queries) => {
+                let mut results = Matches::new();
+                for matches in queries.iter().map(|query| self.evaluate(query)) {
+                    Self::unite(&mut results, matches);
+                }
+                results
+            }
+        }
+    }
+
+    /// Intersects the matches, summing up scores. An empty iterator yields no matches.
+    fn intersect(matches: impl Iterator<Item = Matches<K::Owned>>) -> Matches<K::Owned> {
+        let mut results: Option<Matches<K::Owned>> = None;
+        for matches in matches {
+            results = Some(match results {
+                None => matches,
+                Some(mut results) => {
+                    results.retain(|key, _| matches.contains_key(key));
+                    for (key, (_, score)) in &mut results {
+                        *score += matches[key].1;
+                    }
+                    results
+                }
+            });
+        }
+        results.unwrap_or_default()
+    }
+
+    /// Unites the matches, summing up scores.
+    fn unite(results: &mut Matches<K::Owned>, matches: Matches<K::Owned>) {
+        for (raw_key, (key, score)) in matches {
+            results.entry(raw_key).or_insert((key, 0)).1 += score;
+        }
+    }
+
+    fn term_matches(&self, term: &str) -> Matches<K::Owned> {
+        self.postings
+            .get()
+            .get_all(term)
+            .map(|key| {
+                let score = self.term_frequency(key.borrow(), term);
+                (key_bytes::<K>(key.borrow()), (key, score))
+            })
+            .collect()
+    }
+
+    fn prefix_matches(&self, prefix: &str) -> Matches<K::Owned> {
+        let mut results = Matches::new();
+        let postings = self.postings.get();
+        let matched_postings = postings
+            .iter_from(prefix)
+            .take_while(|(term, _)| term.starts_with(prefix));
+        for (term, key) in matched_postings {
+            let score = self.term_frequency(key.borrow(), &term);
+            results
+                .entry(key_bytes::<K>(key.borrow()))
+                .or_insert((key, 0))
+                .1 += score;
+        }
+        results
+    }
+}
+
+impl<T, K, A> FullTextIndex<T, K, A>
+where
+    T: Access,
+    T::Base: RawAccessMut,
+    K: BinaryKey + ?Sized,
+    A: Analyzer,
+{
+    /// Indexes the text of the document with the specified key, replacing the previously
+    /// indexed text of the document.
+    pub fn insert(&self, key: &K, text: &str) {
+        self.remove(key);
+
+        let mut frequencies = BTreeMap::<_, u32>::new();
+        for term in self.analyzer.tokenize(text) {
+            *frequencies.entry(term).or_default() += 1;
+        }
+        let mut postings = self.postings.get();
+        let mut documents = self.documents.get();
+        for (term, frequency) in frequencies {
+            postings.insert(&term, key);
+            documents.put(&composite_key(key, term.as_str())[..], frequency);
+        }
+    }
+
+    /// Removes the document with the specified key from the index. If the document is not
+    /// indexed, this method does nothing.
+    pub fn remove(&self, key: &K) {
+        let prefix = key_prefix(key);
+        let mut documents = self.documents.get();
+        let document_keys: Vec<_> = documents
+            .keys_from(&prefix[..])
+            .take_while(|document_key| document_key.starts_with(&prefix))
+            .collect();
+
+        let mut postings = self.postings.get();
+        for document_key in document_keys {
+            let term = <str as BinaryKey>::read(&document_key[prefix.len()..]);
+            postings.remove(&term, key);
+            documents.remove(&document_key[..]);
+        }
+    }
+
+    /// Removes all documents from the index.
+    pub fn clear(&self) {
+        self.postings.get().clear();
+        self.documents.get().clear();
+    }
+
+    /// Clears the index and indexes all entries of the `source` map. The text of each entry
+    /// is obtained with the `text` closure.
+    pub fn rebuild<U, V, F, S>(&self, source: &MapIndex<U, K, V>, text: F)
+    where
+        U: RawAccess,
+        V: BinaryValue,
+        F: Fn(&V) -> S,
+        S: AsRef<str>,
+    {
+        self.clear();
+        for (key, value) in source {
+            self.insert(key.borrow(), text(&value).as_ref());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{access::CopyAccessExt, Database, IndexType, TemporaryDB};
+
+    #[test]
+    fn updating_documents() {
+        let db = TemporaryDB::new();
+        let fork = db.fork();
+        let index: FullTextIndex<_, str> = FromAccess::from_access(&fork, "text".into()).unwrap();
+        index.insert("foo", "Hello, world! Hello again.");
+        index.insert("bar", "Goodbye, world");
+        assert_eq!(index.term_frequency("foo", "hello"), 2);
+        assert_eq!(
+            index.search(&TextQuery::term("World")),
+            vec![("bar".to_owned(), 1), ("foo".to_owned(), 1)]
+        );
+
+        index.insert("foo", "Brave new world");
+        assert_eq!(index.term_frequency("foo", "hello"), 0);
+        assert!(index.search(&TextQuery::term("hello")).is_empty());
+        assert!(index.search(&TextQuery::prefix("hel")).is_empty());
+        index.remove("bar");
+        let terms: Vec<_> = index.terms().into_iter().collect();
+        assert_eq!(terms, vec!["brave", "new", "world"]);
+        drop(index);
+        db.merge(fork.into_patch()).unwrap();
+
+        let snapshot = db.snapshot();
+        let index: FullTextIndex<_, str> =
+            FromAccess::from_access(&snapshot, "text".into()).unwrap();
+        assert_eq!(
+            index.search(&TextQuery::prefix("new wor")),
+            vec![("foo".to_owned(), 2)]
+        );
+        assert!(index.search(&TextQuery::prefix("")).is_empty());
+        assert!(index.search(&TextQuery::And(vec![])).is_empty());
+
+        // All postings and frequencies are stored in two indexes.
+        assert_eq!(
+            snapshot.index_type("text.postings"),
+            Some(IndexType::MultiMap)
+        );
+        assert_eq!(snapshot.index_type("text.documents"), Some(IndexType::Map));
+    }
+
+    #[test]
+    fn rebuilding_index_with_custom_analyzer() {
+        let db = TemporaryDB::new();
+        let fork = db.fork();
+        let mut source = fork.get_map::<_, u64, String>("source");
+        source.put(&1, "a-b a".to_owned());
+        source.put(&2, "b-c".to_owned());
+        source.put(&3, "A".to_owned());
+
+        let analyzer = |text: &str| text.split(' ').map(str::to_owned).collect::<Vec<_>>();
+        let index: FullTextIndex<_, u64> = FromAccess::from_access(&fork, "text".into()).unwrap();
+        let index = index.with_analyzer(analyzer);
+        index.insert(&100, "stale");
+        index.rebuild(&source, Clone::clone);
+
+        assert_eq!(index.search(&TextQuery::term("a")), vec![(1, 1)]);
+        assert_eq!(index.search(&TextQuery::prefix("b-")), vec![(2, 1)]);
+        assert_eq!(
+            index.search(&TextQuery::Or(vec![
+                TextQuery::prefix("a"),
+                TextQuery::term("A")
+            ])),
+            vec![(1, 2), (3, 1)]
+        );
+        assert!(index.search(&TextQuery::term("stale")).is_empty());
+    }
+}
@@ -1,8 +1,13 @@
-use std::marker::PhantomData;
+use std::{
+    borrow::Borrow,
+    fmt,
+    marker::PhantomData,
+    ops::{Bound, RangeBounds},
+};
 
 use crate::{
     access::{Access, AccessError, FromAccess},
-    views::{AsReadonly, GroupKeys, IndexAddress},
+    views::{GroupKeys, IndexAddress, RawAccessMut},
     BinaryKey,
 };
 
@@ -51,10 +56,11 @@ use crate::{
 /// // Members of the group can be accessed independently.
 /// assert_eq!(fork.get_list::<_, u64>(("group", &2_u64)).len(), 3);
 ///
-/// // It is possible to enumerate keys in the group, but only if the underlying access
-/// // is readonly.
-/// let group: Group<_, u64, ListIndex<_, u64>> = fork.readonly().get_group("group");
+/// // It is possible to enumerate keys in the group.
 /// assert_eq!(group.keys().collect::<Vec<_>>(), vec![1, 2]);
+/// // ...or to remove members of the group.
+/// group.remove(&1);
+/// assert_eq!(group.keys().collect::<Vec<_>>(), vec![2]);
 /// ```
 ///
 /// Group keys can be unsized:
@@ -113,34 +119,268 @@ where
 impl<T, K, I> Group<T, K, I>
 where
     T: Access,
-    T::Base: AsReadonly<Readonly = T::Base>,
     K: BinaryKey + ?Sized,
 {
     /// Iterator over keys in this group.
     ///
-    /// The iterator buffers keys in memory and may become inconsistent. Although
-    /// the Rust type system prevents iterating over keys in a group based on [`Fork`],
-    /// it it still possible to make the iterator return inconsistent results. Indeed,
-    /// for a group is based on [`ReadonlyFork`], it is possible to add new indexes via `Fork`
-    /// while the iteration is in progress.
+    /// The iterator buffers keys in memory and may become inconsistent if indexes
+    /// in the group are created or removed while the iteration is in progress. Changes made
+    /// before the iterator is created are always visible to it; e.g., a group based
+    /// on a [`Fork`] returns keys of the indexes created via the same fork.
     ///
-    /// For this reason, it is advised to use this method for groups based on `ReadonlyFork`
-    /// only in the case where stale reads are tolerated or are prevented on the application level.
     /// Groups based on [`Snapshot`] implementations (including [`Patch`]es) are not affected
-    /// by this issue.
+    /// by the consistency issue.
     ///
     /// [`Fork`]: ../struct.Fork.html
-    /// [`ReadonlyFork`]: ../struct.ReadonlyFork.html
     /// [`Snapshot`]: ../trait.Snapshot.html
     /// [`Patch`]: ../struct.Patch.html
     pub fn keys(&self) -> GroupKeys<T::Base, K> {
-        self.access.clone().group_keys(self.prefix.clone())
+        self.access.clone().group_keys(self.prefix.clone(), ..)
+    }
+
+    /// Iterator over keys in this group starting from the specified key. Has the same
+    /// consistency caveats as [`keys()`].
+    ///
+    /// [`keys()`]: #method.keys
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use matterdb::{access::CopyAccessExt, Database, Group, ListIndex, TemporaryDB};
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let group: Group<_, u64, ListIndex<_, u64>> = fork.get_group("group");
+    /// for i in 0..5 {
+    ///     group.get(&i).push(i);
+    /// }
+    /// assert_eq!(group.keys_from(&3).collect::<Vec<_>>(), vec![3, 4]);
+    /// ```
+    pub fn keys_from(&self, from: &K) -> GroupKeys<T::Base, K> {
+        let range = (Bound::Included(from), Bound::Unbounded);
+        self.access.clone().group_keys(self.prefix.clone(), range)
+    }
+
+    /// Iterator over keys in this group starting from the specified serialized key.
+    /// The bytes do not need to represent a valid key.
+    pub(crate) fn keys_from_bytes(&self, from: &[u8]) -> impl Iterator<Item = K::Owned> {
+        let range = (Bound::Included(from), Bound::Unbounded);
+        let keys: GroupKeys<T::Base, [u8]> =
+            self.access.clone().group_keys(self.prefix.clone(), range);
+        // Keys are decoded after they are read from the storage, so they are well-formed.
+        keys.map(|key| K::read(&key))
+    }
+
+    /// Iterator over keys in this group falling into the specified range. Keys are compared
+    /// by their binary serialization (i.e., in the same order as they are returned
+    /// by [`keys()`]). Has the same consistency caveats as `keys()`.
+    ///
+    /// [`keys()`]: #method.keys
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use matterdb::{access::CopyAccessExt, Database, Group, ListIndex, TemporaryDB};
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let group: Group<_, u64, ListIndex<_, u64>> = fork.get_group("group");
+    /// for i in 0..5 {
+    ///     group.get(&i).push(i);
+    /// }
+    /// assert_eq!(group.keys_range(1..3).collect::<Vec<_>>(), vec![1, 2]);
+    /// assert_eq!(group.keys_range(..=1).collect::<Vec<_>>(), vec![0, 1]);
+    /// ```
+    ///
+    /// For unsized keys, the range can be specified as a tuple of bounds:
+    ///
+    /// ```
+    /// # use matterdb::{access::CopyAccessExt, Database, Group, ListIndex, TemporaryDB};
+    /// use std::ops::Bound;
+    /// # let db = TemporaryDB::new();
+    /// # let fork = db.fork();
+    /// let group: Group<_, str, ListIndex<_, u64>> = fork.get_group("group");
+    /// for name in &["alice", "bob", "carol", "dave"] {
+    ///     group.get(name).push(1);
+    /// }
+    /// let keys: Vec<_> = group
+    ///     .keys_range((Bound::Included("b"), Bound::Excluded("d")))
+    ///     .collect();
+    /// assert_eq!(keys, vec!["bob".to_owned(), "carol".to_owned()]);
+    /// ```
+    pub fn keys_range<R>(&self, range: R) -> GroupKeys<T::Base, K>
+    where
+        R: RangeBounds<K>,
+    {
+        self.access.clone().group_keys(self.prefix.clone(), range)
+    }
+}
+
+impl<T, K, I> Group<T, K, I>
+where
+    T: Access,
+    K: BinaryKey + ?Sized,
+    I: FromAccess<T>,
+{
+    /// Iterator over keys in this group together with the corresponding indexes.
+    /// Has the same consistency caveats as [`keys()`].
+    ///
+    /// [`keys()`]: #method.keys
+    ///
+    /// # Panics
+    ///
+    /// The iterator panics if an index in the group has a wrong type.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use matterdb::{access::CopyAccessExt, Database, Group, ListIndex, TemporaryDB};
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let group: Group<_, u64, ListIndex<_, u64>> = fork.get_group("group");
+    /// group.get(&1).push(1);
+    /// group.get(&2).extend(vec![2, 3]);
+    ///
+    /// let lengths: Vec<_> = group.iter().map(|(key, list)| (key, list.len())).collect();
+    /// assert_eq!(lengths, vec![(1, 1), (2, 2)]);
+    /// ```
+    pub fn iter(&self) -> GroupIter<T, K, I> {
+        GroupIter {
+            keys: self.keys(),
+            access: self.access.clone(),
+            prefix: self.prefix.clone(),
+            _index: PhantomData,
+        }
+    }
+}
+
+impl<T, K, I> Group<T, K, I>
+where
+    T: Access,
+    T::Base: RawAccessMut,
+    K: BinaryKey + ?Sized,
+{
+    /// Removes the index corresponding to the specified key together with its data
+    /// and metadata. If the index does not exist, this method does nothing.
+    ///
+    /// The removed index may be recreated afterwards, e.g., via [`get()`]; the recreated
+    /// index is empty.
+    ///
+    /// [`get()`]: #method.get
+    ///
+    /// # Panics
+    ///
+    /// If the removed index is borrowed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use matterdb::{access::CopyAccessExt, Database, Group, ListIndex, TemporaryDB};
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let group: Group<_, u64, ListIndex<_, u64>> = fork.get_group("group");
+    /// group.get(&1).push(1);
+    /// group.get(&2).push(2);
+    ///
+    /// group.remove(&1);
+    /// assert_eq!(group.keys().collect::<Vec<_>>(), vec![2]);
+    /// assert!(fork.index_type(("group", &1_u64)).is_none());
+    /// ```
+    pub fn remove(&self, key: &K) {
+        let addr = self.prefix.clone().append_key(key);
+        self.access
+            .clone()
+            .remove_index(addr)
+            .unwrap_or_else(|e| panic!("MerkleDB error: {}", e));
+    }
+
+    /// Removes all indexes in this group together with their data and metadata.
+    ///
+    /// # Panics
+    ///
+    /// If any of the removed indexes are borrowed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use matterdb::{access::CopyAccessExt, Database, Group, ListIndex, TemporaryDB};
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let group: Group<_, u64, ListIndex<_, u64>> = fork.get_group("group");
+    /// group.get(&1).push(1);
+    /// group.get(&2).push(2);
+    ///
+    /// group.clear();
+    /// assert_eq!(group.keys().count(), 0);
+    /// assert!(group.get(&1).is_empty());
+    /// ```
+    pub fn clear(&self) {
+        self.access
+            .clone()
+            .remove_group(self.prefix.clone())
+            .unwrap_or_else(|e| panic!("MerkleDB error: {}", e));
+    }
+}
+
+impl<T, K, I> IntoIterator for &Group<T, K, I>
+where
+    T: Access,
+    K: BinaryKey + ?Sized,
+    I: FromAccess<T>,
+{
+    type Item = (K::Owned, I);
+    type IntoIter = GroupIter<T, K, I>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Iterator over keys and indexes in a [`Group`].
+///
+/// This struct is created by the [`iter()`] method on `Group`. See its documentation
+/// for details.
+///
+/// [`Group`]: struct.Group.html
+/// [`iter()`]: struct.Group.html#method.iter
+pub struct GroupIter<T: Access, K: BinaryKey + ?Sized, I> {
+    keys: GroupKeys<T::Base, K>,
+    access: T,
+    prefix: IndexAddress,
+    _index: PhantomData<I>,
+}
+
+impl<T, K, I> fmt::Debug for GroupIter<T, K, I>
+where
+    T: Access,
+    K: BinaryKey + ?Sized,
+{
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("GroupIter")
+            .field("prefix", &self.prefix)
+            .finish()
+    }
+}
+
+impl<T, K, I> Iterator for GroupIter<T, K, I>
+where
+    T: Access,
+    K: BinaryKey + ?Sized,
+    I: FromAccess<T>,
+{
+    type Item = (K::Owned, I);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let key = self.keys.next()?;
+        let addr = self.prefix.clone().append_key(key.borrow());
+        let index = I::from_access(self.access.clone(), addr)
+            .unwrap_or_else(|e| panic!("MerkleDB error: {}", e));
+        Some((key, index))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Access, AsReadonly, BinaryKey, FromAccess, Group};
+    use super::{Access, BinaryKey, Bound, FromAccess, Group};
     use crate::{
         access::{AccessExt, CopyAccessExt, Prefixed, RawAccessMut},
         migration::{Migration, Scratchpad},
@@ -209,11 +449,7 @@ mod tests {
         fork.get_entry("unrelated").set(23);
     }
 
-    fn test_key_iter<A>(snapshot: A)
-    where
-        A: Access,
-        A::Base: AsReadonly<Readonly = A::Base>,
-    {
+    fn test_key_iter<A: Access>(snapshot: A) {
         let group: Group<_, str, ListIndex<_, String>> = snapshot.get_group("group");
         assert_eq!(
             group.keys().collect::<Vec<_>>(),
@@ -270,4 +506,119 @@ mod tests {
         db.merge(patch).unwrap();
         test_key_iter(Scratchpad::new("namespace", &db.snapshot()));
     }
+
+    #[test]
+    fn iterating_over_keys_in_fork() {
+        let db = TemporaryDB::new();
+        let fork = db.fork();
+        prepare_key_iter(&&fork);
+        test_key_iter(&fork);
+
+        let group: Group<_, u32, ListIndex<_, String>> = fork.get_group("numbers");
+        group.get(&1).push("foo".to_owned());
+        assert_eq!(group.keys().collect::<Vec<_>>(), vec![1]);
+        group.get(&0).push("bar".to_owned());
+        assert_eq!(group.keys().collect::<Vec<_>>(), vec![0, 1]);
+
+        let pairs: Vec<_> = group
+            .iter()
+            .map(|(key, list)| (key, list.get(0).unwrap()))
+            .collect();
+        assert_eq!(pairs, vec![(0, "bar".to_owned()), (1, "foo".to_owned())]);
+    }
+
+    #[test]
+    fn iterating_over_key_ranges() {
+        let db = TemporaryDB::new();
+        let fork = db.fork();
+        let group: Group<_, str, ListIndex<_, u32>> = fork.get_group("group");
+        for &key in &["a", "ab", "b", "ba", "c"] {
+            group.get(key).push(1);
+        }
+        // Unrelated index sharing the group name prefix.
+        fork.get_entry("group_").set(1_u32);
+
+        assert_eq!(
+            group.keys_from("ab").collect::<Vec<_>>(),
+            vec!["ab", "b", "ba", "c"]
+        );
+        assert_eq!(group.keys_from("bb").collect::<Vec<_>>(), vec!["c"]);
+        assert_eq!(group.keys_from("d").count(), 0);
+        let range = (Bound::Included("a"), Bound::Excluded("b"));
+        assert_eq!(group.keys_range(range).collect::<Vec<_>>(), vec!["a", "ab"]);
+        let range = (Bound::Included("a"), Bound::Included("b"));
+        assert_eq!(
+            group.keys_range(range).collect::<Vec<_>>(),
+            vec!["a", "ab", "b"]
+        );
+        let range = (Bound::Unbounded, Bound::Excluded("ab"));
+        assert_eq!(group.keys_range(range).collect::<Vec<_>>(), vec!["a"]);
+        let range = (Bound::Excluded("b"), Bound::Unbounded);
+        assert_eq!(group.keys_range(range).collect::<Vec<_>>(), vec!["ba", "c"]);
+    }
+
+    fn test_removing_members<A>(access: A)
+    where
+        A: Access,
+        A::Base: RawAccessMut,
+    {
+        let group: Group<_, str, ListIndex<_, u32>> =
+            Group::from_access(access.clone(), "group".into()).unwrap();
+        group.get("foo").push(1);
+        group.get("foobar").push(2);
+        group.get("bar").extend(vec![3, 4]);
+        let other_group: Group<_, str, ListIndex<_, u32>> =
+            Group::from_access(access, "group_".into()).unwrap();
+        other_group.get("foo").push(5);
+
+        group.remove("foo");
+        group.remove("baz");
+        assert_eq!(group.keys().collect::<Vec<_>>(), vec!["bar", "foobar"]);
+        assert!(group.get("foo").is_empty());
+        assert_eq!(group.get("foobar").get(0), Some(2));
+
+        group.clear();
+        assert_eq!(group.keys().count(), 0);
+        assert!(group.get("bar").is_empty());
+        assert_eq!(other_group.keys().collect::<Vec<_>>(), vec!["foo"]);
+        assert_eq!(other_group.get("foo").get(0), Some(5));
+    }
+
+    #[test]
+    fn removing_group_members() {
+        let db = TemporaryDB::new();
+        let fork = db.fork();
+        test_removing_members(&fork);
+        test_removing_members(Prefixed::new("namespace", &fork));
+        test_removing_members(Migration::new("namespace", &fork));
+        test_removing_members(Scratchpad::new("namespace", &fork));
+        assert_eq!(fork.get_list::<_, u32>(("group_", "foo")).len(), 1);
+    }
+
+    #[test]
+    fn removed_members_are_cleared_in_db() {
+        let db = TemporaryDB::new();
+        let fork = db.fork();
+        {
+            let group: Group<_, u32, ListIndex<_, u32>> = fork.get_group("group");
+            group.get(&1).extend(vec![1, 2, 3]);
+            group.get(&2).push(4);
+        }
+        db.merge(fork.into_patch()).unwrap();
+
+        let fork = db.fork();
+        {
+            let group: Group<_, u32, ListIndex<_, u32>> = fork.get_group("group");
+            group.remove(&1);
+            assert!(group.get(&1).is_empty());
+            group.get(&1).push(5);
+        }
+        db.merge(fork.into_patch()).unwrap();
+
+        let snapshot = db.snapshot();
+        let group: Group<_, u32, ListIndex<_, u32>> = snapshot.get_group("group");
+        assert_eq!(group.keys().collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(group.get(&1).iter().collect::<Vec<_>>(), vec![5]);
+        assert_eq!(group.get(&2).iter().collect::<Vec<_>>(), vec![4]);
+    }
 }
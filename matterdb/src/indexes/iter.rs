@@ -37,6 +37,14 @@ where
         Self { base_iter }
     }
 
+    /// Creates a new iterator based on the provided view starting from the specified
+    /// serialized key. The bytes do not need to represent a valid key.
+    pub(crate) fn from_bytes<T: RawAccess>(view: &'a View<T>, from: &[u8]) -> Self {
+        Self {
+            base_iter: view.iter_from(&(), from),
+        }
+    }
+
     /// Skips values in the iterator output without parsing them.
     pub fn skip_values(self) -> Keys<'a, K> {
         Keys {
@@ -52,6 +60,11 @@ where
     }
 }
 
+/// Counts entries in the view by iterating over their keys.
+pub fn count_entries<T: RawAccess>(view: &View<T>) -> u64 {
+    Entries::<[u8], ()>::new(view, None).skip_values().count() as u64
+}
+
 impl<K, V> Iterator for Entries<'_, K, V>
 where
     K: BinaryKey + ?Sized,
@@ -121,4 +134,10 @@ pub trait IndexIterator {
     /// Continues iteration from the specified position. If `from` is `None`, starts the iteration
     /// from scratch.
     fn index_iter(&self, from: Option<&Self::Key>) -> Entries<'_, Self::Key, Self::Value>;
+
+    /// Returns the number of entries in the index if it can be determined without iterating
+    /// over the index. The default implementation returns `None`.
+    fn estimated_len(&self) -> Option<u64> {
+        None
+    }
 }
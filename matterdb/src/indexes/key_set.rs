@@ -3,13 +3,19 @@
 //! `KeySetIndex` implements a set that stores elements as keys with empty values.
 //! The given section contains information on the methods related to `KeySetIndex`
 //! and the iterator over the items of this set.
+//!
+//! A set may track the number of its elements in the index metadata; see
+//! [`KeySetIndex::track_len`]. Tracking is opt-in, since it requires an additional read
+//! on each modification.
+//!
+//! [`KeySetIndex::track_len`]: struct.KeySetIndex.html#method.track_len
 
 use std::marker::PhantomData;
 
 use crate::{
     access::{Access, AccessError, FromAccess},
-    indexes::iter::{Entries, IndexIterator, Keys},
-    views::{IndexAddress, IndexType, RawAccess, RawAccessMut, View, ViewWithMetadata},
+    indexes::iter::{count_entries, Entries, IndexIterator, Keys},
+    views::{IndexAddress, IndexState, IndexType, RawAccess, RawAccessMut, View, ViewWithMetadata},
     BinaryKey,
 };
 
@@ -22,6 +28,7 @@ use crate::{
 #[derive(Debug)]
 pub struct KeySetIndex<T: RawAccess, K: ?Sized> {
     base: View<T>,
+    state: IndexState<T, u64>,
     _k: PhantomData<K>,
 }
 
@@ -42,9 +49,10 @@ where
     K: BinaryKey + ?Sized,
 {
     fn new(view: ViewWithMetadata<T>) -> Self {
-        let base = view.into();
+        let (base, state) = view.into_parts();
         Self {
             base,
+            state,
             _k: PhantomData,
         }
     }
@@ -68,6 +76,61 @@ where
         self.base.contains(item)
     }
 
+    /// Returns the number of elements in the set.
+    ///
+    /// If the set tracks the number of elements (see [`track_len`]), this method takes
+    /// constant time. Otherwise, the elements are counted by iterating over the set.
+    ///
+    /// Like other index data, the tracked number of elements may become inconsistent
+    /// if conflicting forks are merged into the database; see [`Fork`] for details.
+    ///
+    /// [`track_len`]: #method.track_len
+    /// [`Fork`]: ../struct.Fork.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, KeySetIndex};
+    ///
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_key_set("name");
+    /// assert_eq!(index.len(), 0);
+    ///
+    /// index.insert(&1);
+    /// index.insert(&1);
+    /// index.insert(&2);
+    /// assert_eq!(index.len(), 2);
+    /// ```
+    pub fn len(&self) -> u64 {
+        self.tracked_len()
+            .unwrap_or_else(|| count_entries(&self.base))
+    }
+
+    /// Returns the tracked number of elements, or `None` if the set does not track it.
+    fn tracked_len(&self) -> Option<u64> {
+        self.state.get()
+    }
+
+    /// Returns `true` if the set contains no elements.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, KeySetIndex};
+    ///
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_key_set("name");
+    /// assert!(index.is_empty());
+    ///
+    /// index.insert(&1);
+    /// assert!(!index.is_empty());
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
     /// Returns an iterator over set elements.
     ///
     /// # Examples
@@ -105,6 +168,12 @@ where
     pub fn iter_from(&self, from: &K) -> Keys<'_, K> {
         self.index_iter(Some(from)).skip_values()
     }
+
+    /// Returns an iterator over the elements of a set in ascending order starting from
+    /// the specified serialized element. The bytes do not need to represent a valid element.
+    pub(crate) fn iter_from_bytes(&self, from: &[u8]) -> Entries<'_, K, ()> {
+        Entries::from_bytes(&self.base, from)
+    }
 }
 
 impl<T, K> KeySetIndex<T, K>
@@ -112,6 +181,38 @@ where
     T: RawAccessMut,
     K: BinaryKey + ?Sized,
 {
+    /// Enables tracking of the number of elements in the set, so that [`len`] takes
+    /// constant time. Once enabled, tracking persists in the index metadata and cannot
+    /// be disabled. If the set does not track the number of elements, its modifications
+    /// do not incur any overhead.
+    ///
+    /// If the set already contains elements, they are counted once when tracking is enabled;
+    /// this takes time linear in the number of elements. Calling the method on a set that
+    /// already tracks its length has no effect.
+    ///
+    /// [`len`]: #method.len
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, KeySetIndex};
+    ///
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_key_set("name");
+    /// index.track_len();
+    ///
+    /// index.insert(&1);
+    /// index.insert(&1);
+    /// index.insert(&2);
+    /// assert_eq!(index.len(), 2);
+    /// ```
+    pub fn track_len(&mut self) {
+        if self.state.get().is_none() {
+            self.state.set(count_entries(&self.base));
+        }
+    }
+
     /// Adds a key to the set.
     ///
     /// # Examples
@@ -127,6 +228,11 @@ where
     /// assert!(index.contains(&1));
     /// ```
     pub fn insert(&mut self, item: &K) {
+        if let Some(len) = self.tracked_len() {
+            if !self.base.contains(item) {
+                self.state.set(len + 1);
+            }
+        }
         self.base.put(item, ());
     }
 
@@ -148,6 +254,11 @@ where
     /// assert!(!index.contains(&1));
     /// ```
     pub fn remove(&mut self, item: &K) {
+        if let Some(len) = self.tracked_len() {
+            if self.base.contains(item) {
+                self.state.set(len - 1);
+            }
+        }
         self.base.remove(item);
     }
 
@@ -175,6 +286,9 @@ where
     /// ```
     pub fn clear(&mut self) {
         self.base.clear();
+        if self.state.get().is_some() {
+            self.state.set(0);
+        }
     }
 }
 
@@ -202,12 +316,16 @@ where
     fn index_iter(&self, from: Option<&K>) -> Entries<'_, K, ()> {
         Entries::new(&self.base, from)
     }
+
+    fn estimated_len(&self) -> Option<u64> {
+        self.tracked_len()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::KeySetIndex;
-    use crate::{access::CopyAccessExt, Database, TemporaryDB};
+    use crate::{access::CopyAccessExt, indexes::IndexIterator, Database, TemporaryDB};
 
     const INDEX_NAME: &str = "test_index_name";
 
@@ -239,6 +357,44 @@ mod tests {
         assert!(!index.contains(KEY));
     }
 
+    #[test]
+    fn key_set_len() {
+        let db = TemporaryDB::new();
+        let fork = db.fork();
+        let mut index = fork.get_key_set(INDEX_NAME);
+        index.track_len();
+        assert!(index.is_empty());
+        index.insert(&1_u8);
+        index.insert(&1_u8);
+        index.insert(&2_u8);
+        index.remove(&3_u8);
+        assert_eq!(index.len(), 2);
+        index.remove(&2_u8);
+        index.remove(&2_u8);
+        assert_eq!(index.len(), 1);
+        drop(index);
+        db.merge(fork.into_patch()).unwrap();
+
+        let fork = db.fork();
+        let mut index: KeySetIndex<_, u8> = fork.get_key_set(INDEX_NAME);
+        assert_eq!(index.len(), 1);
+        index.clear();
+        assert_eq!(index.len(), 0);
+        assert!(index.is_empty());
+
+        // Sets do not track their length by default.
+        let mut index = fork.get_key_set("other");
+        index.insert(&1_u8);
+        index.insert(&2_u8);
+        assert_eq!(index.estimated_len(), None);
+        assert_eq!(index.len(), 2);
+        // Existing elements are counted once tracking is enabled.
+        index.track_len();
+        assert_eq!(index.estimated_len(), Some(2));
+        index.insert(&3_u8);
+        assert_eq!(index.len(), 3);
+    }
+
     #[test]
     fn key_set_methods() {
         let db = TemporaryDB::default();
@@ -74,6 +74,17 @@ where
         self.base.get(&index)
     }
 
+    /// Returns an element at the indicated position using the value cache of the database,
+    /// if caching is enabled for the list. See the [`cache`] module for details.
+    ///
+    /// [`cache`]: ../cache/index.html
+    pub fn get_cached(&self, index: u64) -> Option<V>
+    where
+        V: Clone + Send + Sync + 'static,
+    {
+        self.base.get_cached(&index, IndexType::List)
+    }
+
     /// Returns elements corresponding to the supplied positions.
     /// In case if the position is out of bounds, `None` will be
     /// placed at the element position.
@@ -204,6 +215,12 @@ where
     pub fn iter_from(&self, from: u64) -> Values<'_, V> {
         self.index_iter(Some(&from)).skip_keys()
     }
+
+    /// Returns an iterator over the list entries starting from the specified serialized index.
+    /// The bytes do not need to represent a valid index.
+    pub(crate) fn iter_from_bytes(&self, from: &[u8]) -> Entries<'_, u64, V> {
+        Entries::from_bytes(&self.base, from)
+    }
 }
 
 impl<T, V> ListIndex<T, V>
@@ -403,6 +420,10 @@ where
     fn index_iter(&self, from: Option<&u64>) -> Entries<'_, u64, V> {
         Entries::new(&self.base, from)
     }
+
+    fn estimated_len(&self) -> Option<u64> {
+        Some(self.len())
+    }
 }
 
 #[cfg(test)]
@@ -3,14 +3,23 @@
 //! `MapIndex` requires that keys implement the [`BinaryKey`] trait and values implement
 //! the [`BinaryValue`] trait. The given section contains methods related to
 //! `MapIndex` and iterators over the items of this map.
+//!
+//! A map may track the number of its entries in the index metadata; see
+//! [`MapIndex::track_len`]. Tracking is opt-in, since it requires an additional read
+//! on each modification.
+//!
+//! [`MapIndex::track_len`]: struct.MapIndex.html#method.track_len
 
 use std::{borrow::Borrow, marker::PhantomData};
 
 use crate::{
     access::{Access, AccessError, FromAccess},
-    indexes::iter::{Entries, IndexIterator, Keys, Values},
-    views::{IndexAddress, IndexType, RawAccess, RawAccessMut, View, ViewWithMetadata},
-    BinaryKey, BinaryValue,
+    indexes::{
+        cursor::{self, Cursor, CursorError, CursorPage, CursorSigner},
+        iter::{count_entries, Entries, IndexIterator, Keys, Values},
+    },
+    views::{IndexAddress, IndexState, IndexType, RawAccess, RawAccessMut, View, ViewWithMetadata},
+    BinaryKey, BinaryValue, PinnedValue,
 };
 
 /// A map of keys and values. Access to the elements of this map is obtained using the keys.
@@ -23,6 +32,7 @@ use crate::{
 #[derive(Debug)]
 pub struct MapIndex<T: RawAccess, K: ?Sized, V> {
     base: View<T>,
+    state: IndexState<T, u64>,
     _k: PhantomData<K>,
     _v: PhantomData<V>,
 }
@@ -46,9 +56,10 @@ where
     V: BinaryValue,
 {
     fn new(view: ViewWithMetadata<T>) -> Self {
-        let base = view.into();
+        let (base, state) = view.into_parts();
         Self {
             base,
+            state,
             _v: PhantomData,
             _k: PhantomData,
         }
@@ -73,6 +84,41 @@ where
         self.base.get(key)
     }
 
+    /// Returns a raw value corresponding to the key without copying it, if possible.
+    /// The value can be decoded as any type implementing [`BorrowedBinaryValue`],
+    /// e.g., `&str` for string values.
+    ///
+    /// [`BorrowedBinaryValue`]: ../trait.BorrowedBinaryValue.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, MapIndex};
+    ///
+    /// let db = TemporaryDB::default();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_map("name");
+    /// index.put(&1, b"value".to_vec());
+    ///
+    /// let value = index.get_pinned(&1).unwrap();
+    /// let value: &[u8] = value.decode().unwrap();
+    /// assert_eq!(value, b"value");
+    /// ```
+    pub fn get_pinned(&self, key: &K) -> Option<PinnedValue<'_>> {
+        self.base.get_pinned(key)
+    }
+
+    /// Returns a value corresponding to the key using the value cache of the database,
+    /// if caching is enabled for the index. See the [`cache`] module for details.
+    ///
+    /// [`cache`]: ../cache/index.html
+    pub fn get_cached(&self, key: &K) -> Option<V>
+    where
+        V: Clone + Send + Sync + 'static,
+    {
+        self.base.get_cached(key, IndexType::Map)
+    }
+
     /// Returns values corresponding to the keys.
     ///
     /// # Examples
@@ -116,6 +162,63 @@ where
         self.base.contains(key)
     }
 
+    /// Returns the number of entries in the map.
+    ///
+    /// If the map tracks the number of entries (see [`track_len`]), this method takes
+    /// constant time. Otherwise, the entries are counted by iterating over the map.
+    ///
+    /// Like other index data, the tracked number of entries may become inconsistent
+    /// if conflicting forks are merged into the database; see [`Fork`] for details.
+    ///
+    /// [`track_len`]: #method.track_len
+    /// [`Fork`]: ../struct.Fork.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, MapIndex};
+    ///
+    /// let db = TemporaryDB::default();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_map("name");
+    /// assert_eq!(index.len(), 0);
+    ///
+    /// index.put(&1, 2);
+    /// index.put(&1, 3);
+    /// index.put(&2, 3);
+    /// assert_eq!(index.len(), 2);
+    /// index.remove(&3);
+    /// assert_eq!(index.len(), 2);
+    /// ```
+    pub fn len(&self) -> u64 {
+        self.tracked_len()
+            .unwrap_or_else(|| count_entries(&self.base))
+    }
+
+    /// Returns the tracked number of entries, or `None` if the map does not track it.
+    fn tracked_len(&self) -> Option<u64> {
+        self.state.get()
+    }
+
+    /// Returns `true` if the map contains no entries.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, MapIndex};
+    ///
+    /// let db = TemporaryDB::default();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_map("name");
+    /// assert!(index.is_empty());
+    ///
+    /// index.put(&1, 2);
+    /// assert!(!index.is_empty());
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        self.keys().next().is_none()
+    }
+
     /// Returns an iterator over the entries of the map in ascending order.
     ///
     /// # Examples
@@ -193,6 +296,12 @@ where
         self.index_iter(Some(from))
     }
 
+    /// Returns an iterator over the entries of a map in ascending order starting from
+    /// the specified serialized key. The bytes do not need to represent a valid key.
+    pub(crate) fn iter_from_bytes(&self, from: &[u8]) -> Entries<'_, K, V> {
+        Entries::from_bytes(&self.base, from)
+    }
+
     /// Returns an iterator over the keys of a map in ascending order starting from the
     /// specified key.
     ///
@@ -231,6 +340,45 @@ where
     pub fn values_from(&self, from: &K) -> Values<'_, V> {
         self.iter_from(from).skip_keys()
     }
+
+    /// Returns up to `limit` entries of the map in ascending order of keys together with
+    /// the cursor pointing to the next page, or `None` if there are no more entries.
+    /// If `cursor` is specified, the page starts from the cursor position; otherwise,
+    /// it starts from the beginning of the map.
+    ///
+    /// Cursors are signed by the `signer` and may be resumed on any later snapshot.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, indexes::CursorSigner, TemporaryDB, Database, MapIndex};
+    ///
+    /// let db = TemporaryDB::default();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_map("name");
+    /// for i in 0_u8..5 {
+    ///     index.put(&i, u32::from(i));
+    /// }
+    ///
+    /// let signer = CursorSigner::new(b"secret");
+    /// let (items, cursor) = index.paginate(&signer, None, 3).unwrap();
+    /// assert_eq!(items, vec![(0, 0), (1, 1), (2, 2)]);
+    /// // The cursor can be passed to a client as a string...
+    /// let cursor = cursor.unwrap().to_string();
+    /// // ...and used to retrieve the next page.
+    /// let cursor = cursor.parse().unwrap();
+    /// let (items, cursor) = index.paginate(&signer, Some(&cursor), 3).unwrap();
+    /// assert_eq!(items, vec![(3, 3), (4, 4)]);
+    /// assert!(cursor.is_none());
+    /// ```
+    pub fn paginate(
+        &self,
+        signer: &CursorSigner,
+        cursor: Option<&Cursor>,
+        limit: usize,
+    ) -> Result<CursorPage<K::Owned, V>, CursorError> {
+        cursor::paginate::<_, K, V>(&self.base, signer, cursor, limit)
+    }
 }
 
 impl<T, K, V> MapIndex<T, K, V>
@@ -239,6 +387,46 @@ where
     K: BinaryKey + ?Sized,
     V: BinaryValue,
 {
+    /// Enables tracking of the number of entries in the map, so that [`len`] takes
+    /// constant time. Once enabled, tracking persists in the index metadata and cannot
+    /// be disabled. If the map does not track the number of entries, its modifications
+    /// do not incur any overhead.
+    ///
+    /// If the map already contains entries, they are counted once when tracking is enabled;
+    /// this takes time linear in the number of entries. Calling the method on a map that
+    /// already tracks its length has no effect.
+    ///
+    /// [`len`]: #method.len
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, MapIndex};
+    ///
+    /// let db = TemporaryDB::default();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_map("name");
+    /// index.track_len();
+    ///
+    /// index.put(&1, 2);
+    /// index.put(&1, 3);
+    /// index.put(&2, 3);
+    /// assert_eq!(index.len(), 2);
+    ///
+    /// // Entries of an existing map are counted when tracking is enabled.
+    /// let mut other_index = fork.get_map("other");
+    /// other_index.put(&1, 2);
+    /// other_index.put(&2, 3);
+    /// other_index.track_len();
+    /// other_index.remove(&1);
+    /// assert_eq!(other_index.len(), 1);
+    /// ```
+    pub fn track_len(&mut self) {
+        if self.state.get().is_none() {
+            self.state.set(count_entries(&self.base));
+        }
+    }
+
     /// Inserts a key-value pair into a map.
     ///
     /// # Examples
@@ -254,6 +442,11 @@ where
     /// assert!(index.contains(&1));
     /// ```
     pub fn put(&mut self, key: &K, value: V) {
+        if let Some(len) = self.tracked_len() {
+            if !self.base.contains(key) {
+                self.state.set(len + 1);
+            }
+        }
         self.base.put(key, value);
     }
 
@@ -279,6 +472,11 @@ where
         K: Borrow<Q>,
         Q: BinaryKey + ?Sized,
     {
+        if let Some(len) = self.tracked_len() {
+            if self.base.contains(key) {
+                self.state.set(len - 1);
+            }
+        }
         self.base.remove(key);
     }
 
@@ -306,6 +504,9 @@ where
     /// ```
     pub fn clear(&mut self) {
         self.base.clear();
+        if self.state.get().is_some() {
+            self.state.set(0);
+        }
     }
 }
 
@@ -335,6 +536,10 @@ where
     fn index_iter(&self, from: Option<&K>) -> Entries<'_, K, V> {
         Entries::new(&self.base, from)
     }
+
+    fn estimated_len(&self) -> Option<u64> {
+        self.tracked_len()
+    }
 }
 
 #[cfg(test)]
@@ -398,6 +603,87 @@ mod tests {
         assert!(!map_index.contains(&3_u8));
     }
 
+    #[test]
+    fn test_len() {
+        use crate::{indexes::IndexIterator, MapIndex};
+
+        let db = TemporaryDB::default();
+        let fork = db.fork();
+        let mut map_index = fork.get_map(IDX_NAME);
+        map_index.track_len();
+        assert_eq!(map_index.len(), 0);
+        assert!(map_index.is_empty());
+        map_index.put(&1_u8, 1_u8);
+        map_index.put(&1_u8, 2_u8);
+        map_index.put(&2_u8, 2_u8);
+        map_index.remove(&3_u8);
+        assert_eq!(map_index.len(), 2);
+        assert_eq!(map_index.estimated_len(), Some(2));
+        map_index.remove(&1_u8);
+        map_index.remove(&1_u8);
+        assert_eq!(map_index.len(), 1);
+        map_index.clear();
+        assert_eq!(map_index.len(), 0);
+        assert!(map_index.is_empty());
+        map_index.put(&3_u8, 3_u8);
+        map_index.put(&4_u8, 4_u8);
+        drop(map_index);
+        db.merge(fork.into_patch()).unwrap();
+
+        let fork = db.fork();
+        let mut map_index: MapIndex<_, u8, u8> = fork.get_map(IDX_NAME);
+        assert_eq!(map_index.estimated_len(), Some(2));
+        map_index.put(&5_u8, 5);
+        drop(map_index);
+        db.merge(fork.into_patch()).unwrap();
+
+        let snapshot = db.snapshot();
+        let map_index: MapIndex<_, u8, u8> = snapshot.get_map(IDX_NAME);
+        assert_eq!(map_index.estimated_len(), Some(3));
+        assert_eq!(map_index.len(), 3);
+    }
+
+    #[test]
+    fn test_len_without_tracking() {
+        use crate::{access::Access, indexes::IndexIterator, MapIndex};
+
+        let db = TemporaryDB::default();
+        let fork = db.fork();
+        let mut map_index = fork.get_map(IDX_NAME);
+        map_index.put(&1_u8, 1_u8);
+        map_index.put(&2_u8, 2_u8);
+        map_index.remove(&1_u8);
+        assert_eq!(map_index.estimated_len(), None);
+        assert_eq!(map_index.len(), 1);
+        drop(map_index);
+
+        // Modifications of the map do not touch its metadata.
+        let metadata_before = (&fork).get_index_metadata(IDX_NAME.into()).unwrap();
+        let mut map_index: MapIndex<_, u8, u8> = fork.get_map(IDX_NAME);
+        map_index.put(&3_u8, 3_u8);
+        map_index.remove(&2_u8);
+        map_index.put(&4_u8, 4_u8);
+        drop(map_index);
+        let metadata_after = (&fork).get_index_metadata(IDX_NAME.into()).unwrap();
+        assert_eq!(metadata_before, metadata_after);
+        db.merge(fork.into_patch()).unwrap();
+
+        // Existing entries are counted once tracking is enabled.
+        let fork = db.fork();
+        let mut map_index: MapIndex<_, u8, u8> = fork.get_map(IDX_NAME);
+        map_index.track_len();
+        assert_eq!(map_index.estimated_len(), Some(2));
+        map_index.put(&5_u8, 5_u8);
+        map_index.track_len();
+        assert_eq!(map_index.estimated_len(), Some(3));
+        drop(map_index);
+        db.merge(fork.into_patch()).unwrap();
+
+        let snapshot = db.snapshot();
+        let map_index: MapIndex<_, u8, u8> = snapshot.get_map(IDX_NAME);
+        assert_eq!(map_index.estimated_len(), Some(3));
+    }
+
     #[test]
     fn test_iter() {
         let db = TemporaryDB::default();
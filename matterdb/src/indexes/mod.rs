@@ -1,19 +1,31 @@
 //! All available `MerkleDB` indexes.
 
 pub use self::{
+    bitmap::{BitmapIndex, BitmapIter},
+    blob::{BlobIndex, BlobReader, BlobWriter, BLOB_CHUNK_SIZE},
+    counter::CounterIndex,
+    cursor::{Cursor, CursorError, CursorPage, CursorSigner},
     entry::Entry,
-    group::Group,
+    full_text::{Analyzer, FullTextIndex, SimpleAnalyzer, TextQuery},
+    group::{Group, GroupIter},
     iter::{Entries, IndexIterator, Keys, Values},
     key_set::KeySetIndex,
     list::ListIndex,
     map::MapIndex,
+    multimap::{MultiMapIndex, MultiMapIter, MultiMapValues},
     sparse_list::SparseListIndex,
 };
 
+mod bitmap;
+mod blob;
+mod counter;
+mod cursor;
 mod entry;
+mod full_text;
 mod group;
 mod iter;
 mod key_set;
 mod list;
 mod map;
+mod multimap;
 mod sparse_list;
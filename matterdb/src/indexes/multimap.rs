@@ -0,0 +1,498 @@
+//! An implementation of a map with multiple values per key.
+//!
+//! `MultiMapIndex` stores each `(key, value)` pair as a single composite key with an empty
+//! value in the underlying KV storage. The composite key consists of the key bytes with
+//! escaped zero bytes, a two-byte terminator and the value bytes. Escaping preserves
+//! the lexicographic order of keys and ensures that the encoding of a key is not a prefix
+//! of the encoding of another key, so that all values for a key occupy a contiguous range
+//! of the storage.
+
+use std::marker::PhantomData;
+
+use crate::{
+    access::{Access, AccessError, FromAccess},
+    indexes::iter::{Entries, Keys},
+    views::{key_bytes, IndexAddress, IndexType, RawAccess, RawAccessMut, View, ViewWithMetadata},
+    BinaryKey,
+};
+
+/// Byte following an escaped zero byte in the encoding of a key.
+const ESCAPED_ZERO: u8 = 0xff;
+/// Terminator of an encoded key.
+const TERMINATOR: [u8; 2] = [0, 0];
+
+/// Encodes the key so that it can be used as a prefix of composite keys.
+pub fn key_prefix<K: BinaryKey + ?Sized>(key: &K) -> Vec<u8> {
+    let bytes = key_bytes(key);
+    let mut prefix = Vec::with_capacity(bytes.len() + TERMINATOR.len());
+    for byte in bytes {
+        prefix.push(byte);
+        if byte == 0 {
+            prefix.push(ESCAPED_ZERO);
+        }
+    }
+    prefix.extend_from_slice(&TERMINATOR);
+    prefix
+}
+
+/// Encodes the key-value pair into a composite key.
+pub fn composite_key<K, V>(key: &K, value: &V) -> Vec<u8>
+where
+    K: BinaryKey + ?Sized,
+    V: BinaryKey + ?Sized,
+{
+    let mut bytes = key_prefix(key);
+    bytes.extend_from_slice(&key_bytes(value));
+    bytes
+}
+
+/// Splits a composite key into the unescaped key bytes and the value bytes.
+fn split_composite_key(bytes: &[u8]) -> (Vec<u8>, &[u8]) {
+    let mut key = Vec::with_capacity(bytes.len());
+    let mut pos = 0;
+    loop {
+        match (bytes.get(pos), bytes.get(pos + 1)) {
+            (Some(0), Some(0)) => return (key, &bytes[pos + TERMINATOR.len()..]),
+            (Some(0), Some(&ESCAPED_ZERO)) => {
+                key.push(0);
+                pos += 2;
+            }
+            (Some(&byte), _) if byte != 0 => {
+                key.push(byte);
+                pos += 1;
+            }
+            _ => panic!("MerkleDB error: invalid composite key in a multimap index"),
+        }
+    }
+}
+
+/// A map with multiple values per key.
+///
+/// `MultiMapIndex` requires that both keys and values implement the [`BinaryKey`] trait.
+/// All values are stored in a single index, so unlike a `Group` of `KeySetIndex`es,
+/// the index does not allocate an index identifier and a metadata entry per key. Values
+/// for each key form a set; they are iterated in the order of their binary representation,
+/// and keys are iterated in the order of their binary representation as well.
+///
+/// [`BinaryKey`]: ../trait.BinaryKey.html
+///
+/// # Examples
+///
+/// ```
+/// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, MultiMapIndex};
+///
+/// let db = TemporaryDB::new();
+/// let fork = db.fork();
+/// let mut index: MultiMapIndex<_, str, u64> = fork.get_multimap("name");
+/// index.insert("alice", &3);
+/// index.insert("alice", &1);
+/// index.insert("bob", &2);
+///
+/// assert_eq!(index.get_all("alice").collect::<Vec<_>>(), vec![1, 3]);
+/// assert_eq!(index.count("bob"), 1);
+/// assert!(!index.contains("bob", &1));
+/// ```
+#[derive(Debug)]
+pub struct MultiMapIndex<T: RawAccess, K: ?Sized, V: ?Sized> {
+    base: View<T>,
+    _k: PhantomData<K>,
+    _v: PhantomData<V>,
+}
+
+impl<T, K, V> FromAccess<T> for MultiMapIndex<T::Base, K, V>
+where
+    T: Access,
+    K: BinaryKey + ?Sized,
+    V: BinaryKey + ?Sized,
+{
+    fn from_access(access: T, addr: IndexAddress) -> Result<Self, AccessError> {
+        let view = access.get_or_create_view(addr, IndexType::MultiMap)?;
+        Ok(Self::new(view))
+    }
+}
+
+impl<T, K, V> MultiMapIndex<T, K, V>
+where
+    T: RawAccess,
+    K: BinaryKey + ?Sized,
+    V: BinaryKey + ?Sized,
+{
+    fn new(view: ViewWithMetadata<T>) -> Self {
+        let base = view.into();
+        Self {
+            base,
+            _k: PhantomData,
+            _v: PhantomData,
+        }
+    }
+
+    /// Returns `true` if the index contains the specified value for the key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, MultiMapIndex};
+    ///
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_multimap("name");
+    /// assert!(!index.contains(&1_u8, &2_u8));
+    ///
+    /// index.insert(&1_u8, &2_u8);
+    /// assert!(index.contains(&1_u8, &2_u8));
+    /// assert!(!index.contains(&2_u8, &1_u8));
+    /// ```
+    pub fn contains(&self, key: &K, value: &V) -> bool {
+        self.base.contains(&composite_key(key, value))
+    }
+
+    /// Returns `true` if the index contains at least one value for the key.
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get_all(key).next().is_some()
+    }
+
+    /// Returns the number of values for the key.
+    ///
+    /// The values are not stored together with the count, so this method takes time
+    /// proportional to the number of values for the key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, MultiMapIndex};
+    ///
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_multimap("name");
+    /// index.insert(&1_u8, &2_u8);
+    /// index.insert(&1_u8, &3_u8);
+    /// index.insert(&1_u8, &3_u8);
+    /// assert_eq!(index.count(&1), 2);
+    /// assert_eq!(index.count(&2), 0);
+    /// ```
+    pub fn count(&self, key: &K) -> u64 {
+        self.get_all(key).count() as u64
+    }
+
+    /// Returns an iterator over values for the key in ascending order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, MultiMapIndex};
+    ///
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_multimap("name");
+    /// index.insert(&1_u8, &3_u8);
+    /// index.insert(&1_u8, &2_u8);
+    /// index.insert(&2_u8, &1_u8);
+    /// assert_eq!(index.get_all(&1).collect::<Vec<_>>(), vec![2, 3]);
+    /// ```
+    pub fn get_all(&self, key: &K) -> MultiMapValues<'_, V> {
+        let prefix = key_prefix(key);
+        MultiMapValues {
+            base_iter: Entries::<[u8], ()>::with_prefix(&self.base, &prefix, Some(&prefix[..]))
+                .skip_values(),
+            _v: PhantomData,
+        }
+    }
+
+    /// Returns an iterator over key-value pairs of the index. The pairs are ordered
+    /// by the key, and then by the value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, MultiMapIndex};
+    ///
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_multimap("name");
+    /// index.insert(&2_u8, &1_u8);
+    /// index.insert(&1_u8, &3_u8);
+    /// index.insert(&1_u8, &2_u8);
+    /// let pairs: Vec<_> = index.iter().collect();
+    /// assert_eq!(pairs, vec![(1, 2), (1, 3), (2, 1)]);
+    /// ```
+    pub fn iter(&self) -> MultiMapIter<'_, K, V> {
+        MultiMapIter {
+            base_iter: Entries::<[u8], ()>::new(&self.base, None).skip_values(),
+            _k: PhantomData,
+            _v: PhantomData,
+        }
+    }
+
+    /// Returns an iterator over key-value pairs of the index starting from the specified key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, MultiMapIndex};
+    ///
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_multimap("name");
+    /// index.insert(&2_u8, &1_u8);
+    /// index.insert(&1_u8, &3_u8);
+    /// let pairs: Vec<_> = index.iter_from(&2).collect();
+    /// assert_eq!(pairs, vec![(2, 1)]);
+    /// ```
+    pub fn iter_from(&self, from: &K) -> MultiMapIter<'_, K, V> {
+        let from = key_prefix(from);
+        MultiMapIter {
+            base_iter: Entries::<[u8], ()>::new(&self.base, Some(&from[..])).skip_values(),
+            _k: PhantomData,
+            _v: PhantomData,
+        }
+    }
+}
+
+impl<T, K, V> MultiMapIndex<T, K, V>
+where
+    T: RawAccessMut,
+    K: BinaryKey + ?Sized,
+    V: BinaryKey + ?Sized,
+{
+    /// Adds a value for the key. If the value is already present for the key,
+    /// the index does not change.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, MultiMapIndex};
+    ///
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_multimap("name");
+    /// index.insert(&1_u8, &2_u8);
+    /// assert!(index.contains(&1_u8, &2_u8));
+    /// ```
+    pub fn insert(&mut self, key: &K, value: &V) {
+        self.base.put(&composite_key(key, value), ());
+    }
+
+    /// Removes a value for the key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, MultiMapIndex};
+    ///
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_multimap("name");
+    /// index.insert(&1_u8, &2_u8);
+    /// index.insert(&1_u8, &3_u8);
+    /// index.remove(&1_u8, &2_u8);
+    /// assert!(!index.contains(&1_u8, &2_u8));
+    /// assert!(index.contains(&1_u8, &3_u8));
+    /// ```
+    pub fn remove(&mut self, key: &K, value: &V) {
+        self.base.remove(&composite_key(key, value));
+    }
+
+    /// Removes all values for the key.
+    ///
+    /// # Notes
+    ///
+    /// During the execution of this method, the amount of allocated memory is linearly
+    /// dependent on the number of values for the key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, MultiMapIndex};
+    ///
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_multimap("name");
+    /// index.insert(&1_u8, &2_u8);
+    /// index.insert(&1_u8, &3_u8);
+    /// index.insert(&2_u8, &3_u8);
+    /// index.remove_all(&1_u8);
+    /// assert_eq!(index.count(&1), 0);
+    /// assert_eq!(index.count(&2), 1);
+    /// ```
+    pub fn remove_all(&mut self, key: &K) {
+        let prefix = key_prefix(key);
+        let keys: Vec<_> = Entries::<[u8], ()>::with_prefix(&self.base, &prefix, Some(&prefix[..]))
+            .skip_values()
+            .collect();
+        for key in keys {
+            self.base.remove(&key);
+        }
+    }
+
+    /// Clears the index, removing all key-value pairs.
+    ///
+    /// # Notes
+    ///
+    /// Currently, this method is not optimized to delete a large set of data. During the execution of
+    /// this method, the amount of allocated memory is linearly dependent on the number of elements
+    /// in the index.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, MultiMapIndex};
+    ///
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_multimap("name");
+    /// index.insert(&1_u8, &2_u8);
+    /// index.clear();
+    /// assert!(!index.contains(&1_u8, &2_u8));
+    /// ```
+    pub fn clear(&mut self) {
+        self.base.clear();
+    }
+}
+
+impl<'a, T, K, V> IntoIterator for &'a MultiMapIndex<T, K, V>
+where
+    T: RawAccess,
+    K: BinaryKey + ?Sized,
+    V: BinaryKey + ?Sized,
+{
+    type Item = (K::Owned, V::Owned);
+    type IntoIter = MultiMapIter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Iterator over key-value pairs of a [`MultiMapIndex`].
+///
+/// This structure is returned by the [`iter`] and [`iter_from`] methods of the index.
+///
+/// [`MultiMapIndex`]: struct.MultiMapIndex.html
+/// [`iter`]: struct.MultiMapIndex.html#method.iter
+/// [`iter_from`]: struct.MultiMapIndex.html#method.iter_from
+#[derive(Debug)]
+pub struct MultiMapIter<'a, K: ?Sized, V: ?Sized> {
+    base_iter: Keys<'a, [u8]>,
+    _k: PhantomData<K>,
+    _v: PhantomData<V>,
+}
+
+impl<K, V> Iterator for MultiMapIter<'_, K, V>
+where
+    K: BinaryKey + ?Sized,
+    V: BinaryKey + ?Sized,
+{
+    type Item = (K::Owned, V::Owned);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bytes = self.base_iter.next()?;
+        let (key, value) = split_composite_key(&bytes);
+        Some((K::read(&key), V::read(value)))
+    }
+}
+
+/// Iterator over values for a single key of a [`MultiMapIndex`].
+///
+/// This structure is returned by the [`get_all`] method of the index.
+///
+/// [`MultiMapIndex`]: struct.MultiMapIndex.html
+/// [`get_all`]: struct.MultiMapIndex.html#method.get_all
+#[derive(Debug)]
+pub struct MultiMapValues<'a, V: ?Sized> {
+    base_iter: Keys<'a, [u8]>,
+    _v: PhantomData<V>,
+}
+
+impl<V> Iterator for MultiMapValues<'_, V>
+where
+    V: BinaryKey + ?Sized,
+{
+    type Item = V::Owned;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bytes = self.base_iter.next()?;
+        let (_, value) = split_composite_key(&bytes);
+        Some(V::read(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{access::CopyAccessExt, Database, TemporaryDB};
+
+    #[test]
+    fn composite_key_encoding() {
+        let keys: &[&[u8]] = &[b"", b"\0", b"\0\0", b"a\0b", b"\xff", b"\0\xff"];
+        for &key in keys {
+            let encoded = composite_key(key, &[1_u8, 0][..]);
+            let (decoded_key, value) = split_composite_key(&encoded);
+            assert_eq!(decoded_key, key);
+            assert_eq!(value, [1, 0]);
+        }
+
+        // The encoding preserves the order of keys.
+        let mut encoded: Vec<_> = keys.iter().map(|&key| key_prefix(key)).collect();
+        encoded.sort();
+        let decoded: Vec<_> = encoded
+            .iter()
+            .map(|bytes| split_composite_key(bytes).0)
+            .collect();
+        let mut sorted_keys = keys.to_vec();
+        sorted_keys.sort_unstable();
+        assert_eq!(decoded, sorted_keys);
+    }
+
+    #[test]
+    fn multimap_methods() {
+        let db = TemporaryDB::new();
+        let fork = db.fork();
+        let mut index: MultiMapIndex<_, str, str> = fork.get_multimap("multimap");
+        index.insert("a", "x");
+        index.insert("a", "y");
+        index.insert("a", "x");
+        // Keys which are prefixes of each other must not mix up their values.
+        index.insert("ab", "z");
+        index.insert("", "empty");
+        index.insert("a\0", "zero");
+
+        assert_eq!(index.count("a"), 2);
+        assert_eq!(index.count("ab"), 1);
+        assert_eq!(index.count("b"), 0);
+        assert!(index.contains("a", "x"));
+        assert!(!index.contains("a", "z"));
+        assert!(!index.contains("a", ""));
+        assert!(index.contains_key("a\0"));
+        assert!(!index.contains_key("a\0\0"));
+        assert_eq!(index.get_all("a").collect::<Vec<_>>(), vec!["x", "y"]);
+
+        let pairs: Vec<_> = index.iter().collect();
+        let expected = vec![
+            ("", "empty"),
+            ("a", "x"),
+            ("a", "y"),
+            ("a\0", "zero"),
+            ("ab", "z"),
+        ];
+        let expected: Vec<_> = expected
+            .into_iter()
+            .map(|(key, value)| (key.to_owned(), value.to_owned()))
+            .collect();
+        assert_eq!(pairs, expected);
+        assert_eq!(index.iter_from("a\0").count(), 2);
+
+        index.remove("a", "x");
+        assert_eq!(index.get_all("a").collect::<Vec<_>>(), vec!["y"]);
+        index.remove_all("a");
+        assert!(!index.contains_key("a"));
+        assert_eq!(index.count("ab"), 1);
+        assert_eq!(index.count("a\0"), 1);
+
+        drop(index);
+        db.merge(fork.into_patch()).unwrap();
+        let snapshot = db.snapshot();
+        let index: MultiMapIndex<_, str, str> = snapshot.get_multimap("multimap");
+        assert_eq!(index.iter().count(), 3);
+        assert_eq!(snapshot.index_type("multimap"), Some(IndexType::MultiMap));
+    }
+}
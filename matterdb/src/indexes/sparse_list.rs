@@ -9,7 +9,10 @@ use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
 use crate::{
     access::{Access, AccessError, FromAccess},
-    indexes::iter::{Entries, IndexIterator, Keys, Values},
+    indexes::{
+        cursor::{self, Cursor, CursorError, CursorPage, CursorSigner},
+        iter::{Entries, IndexIterator, Keys, Values},
+    },
     views::{
         BinaryAttribute, IndexAddress, IndexState, IndexType, RawAccess, RawAccessMut, View,
         ViewWithMetadata,
@@ -300,6 +303,40 @@ where
     pub fn iter_from(&self, from: u64) -> Entries<'_, u64, V> {
         self.index_iter(Some(&from))
     }
+
+    /// Returns up to `limit` elements of the list with the corresponding indexes together with
+    /// the cursor pointing to the next page, or `None` if there are no more elements.
+    /// If `cursor` is specified, the page starts from the cursor position; otherwise,
+    /// it starts from the beginning of the list.
+    ///
+    /// Cursors are signed by the `signer` and may be resumed on any later snapshot.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matterdb::{access::CopyAccessExt, indexes::CursorSigner, TemporaryDB, Database, SparseListIndex};
+    ///
+    /// let db = TemporaryDB::new();
+    /// let fork = db.fork();
+    /// let mut index = fork.get_sparse_list("name");
+    /// index.extend(vec![1_u32, 2, 3, 4, 5]);
+    /// index.remove(1);
+    ///
+    /// let signer = CursorSigner::new(b"secret");
+    /// let (items, cursor) = index.paginate(&signer, None, 2).unwrap();
+    /// assert_eq!(items, vec![(0, 1), (2, 3)]);
+    /// let (items, cursor) = index.paginate(&signer, cursor.as_ref(), 2).unwrap();
+    /// assert_eq!(items, vec![(3, 4), (4, 5)]);
+    /// assert!(cursor.is_none());
+    /// ```
+    pub fn paginate(
+        &self,
+        signer: &CursorSigner,
+        cursor: Option<&Cursor>,
+        limit: usize,
+    ) -> Result<CursorPage<u64, V>, CursorError> {
+        cursor::paginate::<_, u64, V>(&self.base, signer, cursor, limit)
+    }
 }
 
 impl<T, V> SparseListIndex<T, V>
@@ -518,6 +555,10 @@ where
     fn index_iter(&self, from: Option<&u64>) -> Entries<'_, u64, V> {
         Entries::new(&self.base, from)
     }
+
+    fn estimated_len(&self) -> Option<u64> {
+        Some(self.len())
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,536 @@
+//! Resumable background jobs.
+//!
+//! # Stability
+//!
+//! The entirety of this module is considered unstable. While the supported functionality
+//! is unlikely to break, the implementation details may change in the following releases.
+//!
+//! # Overview
+//!
+//! Maintenance tasks, such as reindexing, compaction of application data or TTL sweeps,
+//! often need to process large amounts of data without blocking other I/O for a long time.
+//! A [`JobHelper`] allows to split such a task into chunks, each of which is merged
+//! to the database separately. The job position is stored in named [persistent iterators]
+//! (*cursors*) residing in a dedicated system namespace, so that the job can be resumed
+//! after an interruption (e.g., a process restart) from the last merged position.
+//!
+//! Unlike the cursors used in migrations, job cursors are not tied to a migration namespace
+//! and are not cleared by [`flush_migration`]; they are cleared once the job is finished.
+//! The state of jobs and the number of items processed by them are recorded in the
+//! [`JobRegistry`], which can be read from any snapshot.
+//!
+//! # Examples
+//!
+//! ```
+//! # use matterdb::{access::CopyAccessExt, Database, TemporaryDB};
+//! # use matterdb::jobs::{JobHelper, JobRegistry, JobState};
+//! # use std::sync::Arc;
+//! let db: Arc<dyn Database> = Arc::new(TemporaryDB::new());
+//! let fork = db.fork();
+//! fork.get_list("items").extend(0_u32..100);
+//! db.merge(fork.into_patch()).unwrap();
+//!
+//! // Copy even items into a separate index, merging changes every 10 items.
+//! let mut job = JobHelper::new(Arc::clone(&db), "copy_even");
+//! job.iter_loop(|job, cursors| {
+//!     let items = job.fork().readonly().get_list::<_, u32>("items");
+//!     let mut even_items = job.fork().get_key_set::<_, u32>("even_items");
+//!     for (_, item) in cursors.create("items", &items).take(10) {
+//!         if item % 2 == 0 {
+//!             even_items.insert(&item);
+//!         }
+//!     }
+//! })
+//! .unwrap();
+//! job.finish().unwrap();
+//!
+//! let snapshot = db.snapshot();
+//! assert_eq!(snapshot.get_key_set::<_, u32>("even_items").iter().count(), 50);
+//! let status = JobRegistry::new(&snapshot).status("copy_even").unwrap();
+//! assert_eq!(status.state(), JobState::Completed);
+//! assert_eq!(status.processed(), 100);
+//! ```
+//!
+//! [`JobHelper`]: struct.JobHelper.html
+//! [persistent iterators]: ../migration/struct.PersistentIter.html
+//! [`flush_migration`]: ../migration/fn.flush_migration.html
+//! [`JobRegistry`]: struct.JobRegistry.html
+
+use anyhow::{ensure, format_err};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use chrono::{DateTime, Utc};
+use thiserror::Error;
+
+use std::{borrow::Cow, convert::TryFrom, fmt, sync::Arc};
+
+use crate::{
+    access::RawAccess,
+    migration::{AbortHandle, AbortMigration, PersistentIters, Scratchpad},
+    validation::assert_valid_name_component,
+    values::{read_bytes, write_bytes},
+    views::{RawAccessMut, View},
+    BinaryValue, Database, Fork, ResolvedAddress,
+};
+
+/// Name of the column family used to store the job registry.
+const JOBS_NAME: &str = "__JOBS__";
+/// Name of the column family used to store job cursors.
+const JOB_CURSORS_NAME: &str = "__job_cursors__";
+
+/// State of a job recorded in the [`JobRegistry`].
+///
+/// [`JobRegistry`]: struct.JobRegistry.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum JobState {
+    /// The job is running, or was interrupted without being aborted (e.g., by a process
+    /// shutdown).
+    Running = 0,
+    /// The job was aborted. It can be resumed by creating a new `JobHelper` with the same name.
+    Aborted = 1,
+    /// The job has completed.
+    Completed = 2,
+}
+
+impl TryFrom<u8> for JobState {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Running),
+            1 => Ok(Self::Aborted),
+            2 => Ok(Self::Completed),
+            _ => Err("Unknown job state"),
+        }
+    }
+}
+
+/// Status of a job recorded in the [`JobRegistry`].
+///
+/// [`JobRegistry`]: struct.JobRegistry.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobStatus {
+    name: String,
+    state: JobState,
+    started_at: DateTime<Utc>,
+    finished_at: Option<DateTime<Utc>>,
+    processed: u64,
+}
+
+impl JobStatus {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            state: JobState::Running,
+            started_at: Utc::now(),
+            finished_at: None,
+            processed: 0,
+        }
+    }
+
+    /// Returns the name of the job.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the current state of the job.
+    pub fn state(&self) -> JobState {
+        self.state
+    }
+
+    /// Returns the time when the job was started. If the job was resumed, this is the time
+    /// of the initial start.
+    pub fn started_at(&self) -> DateTime<Utc> {
+        self.started_at
+    }
+
+    /// Returns the time when the job has completed, or `None` if the job is not completed.
+    pub fn finished_at(&self) -> Option<DateTime<Utc>> {
+        self.finished_at
+    }
+
+    /// Returns the total number of items processed by the job cursors, including
+    /// the items processed before the job was resumed.
+    pub fn processed(&self) -> u64 {
+        self.processed
+    }
+}
+
+impl BinaryValue for JobStatus {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        write_bytes(&mut buffer, self.name.as_bytes());
+        buffer.push(self.state as u8);
+        write_bytes(&mut buffer, &self.started_at.to_bytes());
+        if let Some(finished_at) = self.finished_at {
+            buffer.push(1);
+            write_bytes(&mut buffer, &finished_at.to_bytes());
+        } else {
+            buffer.push(0);
+        }
+        buffer.write_u64::<LittleEndian>(self.processed).unwrap();
+        buffer
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> anyhow::Result<Self> {
+        let mut bytes = bytes.as_ref();
+        let name = String::from_utf8(read_bytes(&mut bytes)?)?;
+        let state = bytes.read_u8()?;
+        let state =
+            JobState::try_from(state).map_err(|_| format_err!("Unknown job state: {}", state))?;
+        let started_at = DateTime::<Utc>::from_bytes(read_bytes(&mut bytes)?.into())?;
+        let finished_at = match bytes.read_u8()? {
+            0 => None,
+            1 => Some(DateTime::<Utc>::from_bytes(read_bytes(&mut bytes)?.into())?),
+            tag => return Err(format_err!("Invalid option tag: {}", tag)),
+        };
+        let processed = bytes.read_u64::<LittleEndian>()?;
+        ensure!(bytes.is_empty(), "Unexpected trailing bytes in job status");
+
+        Ok(Self {
+            name,
+            state,
+            started_at,
+            finished_at,
+            processed,
+        })
+    }
+}
+
+/// Persistent registry of jobs performed in the database.
+///
+/// See the [module docs](index.html) for an example of usage.
+#[derive(Debug)]
+pub struct JobRegistry<T: RawAccess> {
+    view: View<T>,
+}
+
+impl<T: RawAccess> JobRegistry<T> {
+    /// Creates a registry based on the provided access.
+    pub fn new(access: T) -> Self {
+        Self {
+            view: View::new(access, ResolvedAddress::system(JOBS_NAME)),
+        }
+    }
+
+    /// Returns the status of a job with the specified name.
+    pub fn status(&self, name: &str) -> Option<JobStatus> {
+        self.view.get(name)
+    }
+
+    /// Returns statuses of all recorded jobs, ordered by name.
+    pub fn statuses(&self) -> impl Iterator<Item = JobStatus> + '_ {
+        self.view
+            .iter::<_, str, JobStatus>(&())
+            .map(|(_, status)| status)
+    }
+}
+
+impl<T: RawAccessMut> JobRegistry<T> {
+    /// Records the start of a job. If the job was not completed, it is resumed; otherwise,
+    /// a new run of the job is started.
+    fn start(&mut self, name: &str) {
+        let status = match self.status(name) {
+            Some(mut status) if status.state != JobState::Completed => {
+                status.state = JobState::Running;
+                status
+            }
+            _ => JobStatus::new(name),
+        };
+        self.view.put(name, status);
+    }
+
+    fn update(&mut self, name: &str, update: impl FnOnce(&mut JobStatus)) {
+        if let Some(mut status) = self.status(name) {
+            update(&mut status);
+            self.view.put(name, status);
+        }
+    }
+}
+
+/// Errors emitted by `JobHelper` methods.
+#[derive(Debug, Error)]
+pub enum JobError {
+    /// Failed to merge job changes to database.
+    #[error("Failed to merge job changes to the database: {0}")]
+    Merge(#[source] crate::Error),
+
+    /// Job has been aborted.
+    #[error("Job was aborted")]
+    Aborted,
+}
+
+/// Helper for resumable jobs.
+///
+/// The helper provides a [`Fork`] to read and modify data, and named persistent cursors
+/// to track the job position. Changes are merged to the database periodically
+/// (e.g., after each iteration of [`iter_loop`]); if the job is interrupted, it can be resumed
+/// by creating a helper with the same name.
+///
+/// See the [module docs](index.html) for an example of usage.
+///
+/// [`Fork`]: ../struct.Fork.html
+/// [`iter_loop`]: #method.iter_loop
+pub struct JobHelper {
+    db: Arc<dyn Database>,
+    fork: Option<Fork>,
+    abort_handle: Box<dyn AbortMigration>,
+    name: String,
+}
+
+impl fmt::Debug for JobHelper {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("JobHelper")
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+impl JobHelper {
+    /// Creates a new helper. If a job with the same name was not completed, the job is resumed
+    /// from the positions stored in its cursors.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the name is not a valid name component, or if the job start cannot be recorded
+    /// in the database.
+    pub fn new(db: impl Into<Arc<dyn Database>>, name: &str) -> Self {
+        assert_valid_name_component(name);
+
+        let db = db.into();
+        let fork = db.fork();
+        JobRegistry::new(&fork).start(name);
+        db.merge(fork.into_patch())
+            .unwrap_or_else(|e| panic!("MerkleDB error: {}", e));
+        Self {
+            fork: Some(db.fork()),
+            db,
+            abort_handle: Box::new(()),
+            name: name.to_owned(),
+        }
+    }
+
+    /// Creates a new helper together with the abort handle. The handle may be sent between
+    /// threads; it allows to determine whether the job was completed, and to abort the job
+    /// by preventing further writes to the database.
+    pub fn with_handle(db: impl Into<Arc<dyn Database>>, name: &str) -> (Self, AbortHandle) {
+        let mut this = Self::new(db, name);
+        let abort_handle = AbortHandle::new();
+        this.abort_handle = Box::new(abort_handle.clone_inner());
+        (this, abort_handle)
+    }
+
+    /// Returns the name of the job.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the fork with the uncommitted job changes.
+    pub fn fork(&self) -> &Fork {
+        // `unwrap` is safe due to the way we define `fork`
+        self.fork.as_ref().unwrap()
+    }
+
+    /// Returns the storage for job cursors and other small pieces of job state. The storage
+    /// is cleared once the job is finished.
+    pub fn cursors(&self) -> Scratchpad<&Fork> {
+        Scratchpad::with_root(JOB_CURSORS_NAME, &self.name, self.fork())
+    }
+
+    /// Merges the job changes to the database. Returns an error if the merge has failed
+    /// or the job has been aborted.
+    pub fn merge(&mut self) -> Result<(), JobError> {
+        let fork = self.fork.take().unwrap();
+        let patch = fork.into_patch();
+        if self.abort_handle.is_aborted() {
+            record_abort(&*self.db, &self.name);
+            Err(JobError::Aborted)
+        } else {
+            self.db.merge(patch).map_err(JobError::Merge)?;
+            self.fork = Some(self.db.fork());
+            Ok(())
+        }
+    }
+
+    /// Executes the provided closure in a loop until all cursors instantiated within
+    /// the closure have ended. After each iteration, the changes are merged to the database;
+    /// an error is returned if this merge fails.
+    ///
+    /// If no cursors are instantiated within the closure, a single iteration will be performed.
+    pub fn iter_loop(
+        &mut self,
+        mut step: impl FnMut(&Self, &mut PersistentIters<Scratchpad<&Fork>>),
+    ) -> Result<(), JobError> {
+        let mut should_break = false;
+        while !should_break {
+            let mut cursors = PersistentIters::new(self.cursors());
+            step(self, &mut cursors);
+            should_break = cursors.all_ended();
+            let processed = cursors.processed();
+            JobRegistry::new(self.fork()).update(&self.name, |status| {
+                status.processed += processed;
+            });
+            self.merge()?;
+        }
+        Ok(())
+    }
+
+    /// Marks the job as completed, clears its cursors and merges the remaining changes
+    /// to the database.
+    pub fn finish(mut self) -> Result<(), JobError> {
+        self.cursors().clear();
+        JobRegistry::new(self.fork()).update(&self.name, |status| {
+            status.state = JobState::Completed;
+            status.finished_at = Some(Utc::now());
+        });
+        self.merge()
+    }
+}
+
+/// Records that the job was aborted in the job registry. Other changes are not merged
+/// to the database after an abort, so the record is merged separately.
+fn record_abort(db: &dyn Database, name: &str) {
+    let fork = db.fork();
+    JobRegistry::new(&fork).update(name, |status| status.state = JobState::Aborted);
+    // The registry is auxiliary, so we do not report failures to update it.
+    db.merge(fork.into_patch()).ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        access::{AccessExt, CopyAccessExt},
+        migration::{flush_migration, MigrationHelper},
+        TemporaryDB,
+    };
+
+    use assert_matches::assert_matches;
+
+    fn create_db() -> Arc<dyn Database> {
+        let db = TemporaryDB::new();
+        let fork = db.fork();
+        fork.get_list("items").extend(0_u32..25);
+        db.merge(fork.into_patch()).unwrap();
+        Arc::new(db)
+    }
+
+    fn sum_step(job: &JobHelper, cursors: &mut PersistentIters<Scratchpad<&Fork>>) {
+        let items = job.fork().readonly().get_list::<_, u32>("items");
+        let mut sum = job.fork().get_entry::<_, u64>("sum");
+        for (_, item) in cursors.create("items", &items).take(10) {
+            sum.set(sum.get().unwrap_or_default() + u64::from(item));
+        }
+    }
+
+    #[test]
+    fn job_is_resumed_after_abort() {
+        let db = create_db();
+        let (mut job, handle) = JobHelper::with_handle(Arc::clone(&db), "sum");
+        let mut handle = Some(handle);
+        let res = job.iter_loop(|job, cursors| {
+            sum_step(job, cursors);
+            // Abort the job after the first chunk; the chunk itself is not merged.
+            drop(handle.take());
+        });
+        assert_matches!(res, Err(JobError::Aborted));
+
+        let snapshot = db.snapshot();
+        assert_eq!(snapshot.get_entry::<_, u64>("sum").get(), None);
+        let status = JobRegistry::new(&snapshot).status("sum").unwrap();
+        assert_eq!(status.state(), JobState::Aborted);
+        assert_eq!(status.processed(), 0);
+
+        let mut job = JobHelper::new(Arc::clone(&db), "sum");
+        let mut iterations = 0;
+        job.iter_loop(|job, cursors| {
+            sum_step(job, cursors);
+            iterations += 1;
+        })
+        .unwrap();
+        assert_eq!(iterations, 3);
+        job.finish().unwrap();
+
+        let snapshot = db.snapshot();
+        assert_eq!(snapshot.get_entry::<_, u64>("sum").get(), Some(300));
+        let status = JobRegistry::new(&snapshot).status("sum").unwrap();
+        assert_eq!(status.state(), JobState::Completed);
+        assert_eq!(status.processed(), 25);
+        assert!(status.finished_at().unwrap() >= status.started_at());
+    }
+
+    #[test]
+    fn job_is_resumed_from_merged_position() {
+        let db = create_db();
+        let mut job = JobHelper::new(Arc::clone(&db), "sum");
+        {
+            let mut cursors = PersistentIters::new(job.cursors());
+            sum_step(&job, &mut cursors);
+        }
+        job.merge().unwrap();
+        // Emulate a process shutdown.
+        drop(job);
+
+        let status = JobRegistry::new(&db.snapshot()).status("sum").unwrap();
+        assert_eq!(status.state(), JobState::Running);
+
+        let mut job = JobHelper::new(Arc::clone(&db), "sum");
+        let mut iterations = 0;
+        job.iter_loop(|job, cursors| {
+            sum_step(job, cursors);
+            iterations += 1;
+        })
+        .unwrap();
+        assert_eq!(iterations, 2);
+        job.finish().unwrap();
+
+        let snapshot = db.snapshot();
+        assert_eq!(snapshot.get_entry::<_, u64>("sum").get(), Some(300));
+        let statuses: Vec<_> = JobRegistry::new(&snapshot).statuses().collect();
+        assert_eq!(statuses.len(), 1);
+        assert_eq!(statuses[0].started_at(), status.started_at());
+    }
+
+    #[test]
+    fn job_cursors_are_independent_from_migrations() {
+        let db = create_db();
+        let mut job = JobHelper::new(Arc::clone(&db), "test");
+        {
+            let mut cursors = PersistentIters::new(job.cursors());
+            sum_step(&job, &mut cursors);
+        }
+        job.merge().unwrap();
+
+        // Flushing a migration with the same name does not affect job cursors.
+        MigrationHelper::new(Arc::clone(&db), "test")
+            .finish()
+            .unwrap();
+        let mut fork = db.fork();
+        flush_migration(&mut fork, "test");
+        db.merge(fork.into_patch()).unwrap();
+
+        assert!(job.cursors().index_type("items").is_some());
+        job.iter_loop(sum_step).unwrap();
+        job.finish().unwrap();
+        assert_eq!(db.snapshot().get_entry::<_, u64>("sum").get(), Some(300));
+
+        // Cursors are cleared once the job is finished, so the job may be restarted.
+        let snapshot = db.snapshot();
+        let cursors = Scratchpad::with_root(JOB_CURSORS_NAME, "test", &snapshot);
+        assert_eq!(cursors.index_type("items"), None);
+        let mut job = JobHelper::new(Arc::clone(&db), "test");
+        let mut iterations = 0;
+        job.iter_loop(|job, cursors| {
+            sum_step(job, cursors);
+            iterations += 1;
+        })
+        .unwrap();
+        assert_eq!(iterations, 3);
+        job.finish().unwrap();
+        let snapshot = db.snapshot();
+        assert_eq!(snapshot.get_entry::<_, u64>("sum").get(), Some(600));
+        let status = JobRegistry::new(&snapshot).status("test").unwrap();
+        assert_eq!(status.processed(), 25);
+    }
+}
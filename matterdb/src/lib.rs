@@ -30,7 +30,8 @@
 //!
 //! If you need to use your own data types as keys or values in the storage, you need to implement
 //! the [`BinaryKey`] or [`BinaryValue`] traits respectively. These traits have already been
-//! implemented for most standard types.
+//! implemented for most standard types. Values can also be read without copying with
+//! the help of `get_pinned` methods of indexes and the [`BorrowedBinaryValue`] trait.
 //!
 //! # Indexes
 //!
@@ -53,12 +54,26 @@
 //! - [`MapIndex`] is a map of keys and values. Similar to [`BTreeMap`].
 //! - [`KeySetIndex`] and [`ValueSetIndex`] are sets of items, similar to [`BTreeSet`] and
 //!   [`HashSet`] accordingly.
+//! - [`CounterIndex`] is a map of 64-bit signed counters. Increments are applied as merge
+//!   operands, so that concurrent forks updating the same counter do not conflict.
+//! - [`BitmapIndex`] is a compressed set of 64-bit integers supporting rank queries
+//!   and set algebra. Similar to a roaring bitmap.
+//! - [`BlobIndex`] is a large binary value split into chunks, which can be read and written
+//!   in a streaming fashion. Similar to a file.
+//! - [`MultiMapIndex`] is a map with multiple values per key, which are stored in a single
+//!   index. Similar to a [`BTreeMap`] of [`BTreeSet`]s.
+//!
+//! Besides point lookups and iteration, indexes can be scanned with key ranges, filters
+//! and pagination using [typed queries](query/index.html). Large amounts of sorted data
+//! can be imported into new indexes with the help of the [bulk loader](bulk/index.html).
 //!
 //! # Migrations
 //!
 //! The database [provides tooling](migration/index.html) for data migrations. With the help
 //! of migration, it is possible to gradually accumulate changes to a set of indexes (including
 //! across process restarts) and then atomically apply or discard these changes.
+//! Similar [resumable jobs](jobs/index.html) can be used for maintenance tasks that modify
+//! data in place, such as reindexing or TTL sweeps.
 //!
 //! [`Database`]: trait.Database.html
 //! [`RocksDB`]: struct.RocksDB.html
@@ -71,11 +86,16 @@
 //! [`merge`]: trait.Database.html#tymethod.merge
 //! [`BinaryKey`]: trait.BinaryKey.html
 //! [`BinaryValue`]: trait.BinaryValue.html
+//! [`BorrowedBinaryValue`]: trait.BorrowedBinaryValue.html
 //! [`Entry`]: indexes/struct.Entry.html
 //! [`ListIndex`]: indexes/struct.ListIndex.html
 //! [`SparseListIndex`]: indexes/struct.SparseListIndex.html
 //! [`MapIndex`]: indexes/struct.MapIndex.html
 //! [`KeySetIndex`]: indexes/struct.KeySetIndex.html
+//! [`CounterIndex`]: indexes/struct.CounterIndex.html
+//! [`BitmapIndex`]: indexes/struct.BitmapIndex.html
+//! [`BlobIndex`]: indexes/struct.BlobIndex.html
+//! [`MultiMapIndex`]: indexes/struct.MultiMapIndex.html
 //! [`ValueSetIndex`]: indexes/struct.ValueSetIndex.html
 //! [`ObjectHash`]: trait.ObjectHash.html
 //! [`Option`]: https://doc.rust-lang.org/std/option/enum.Option.html
@@ -118,33 +138,43 @@ pub use self::{
         temporarydb::TemporaryDB,
     },
     db::{
-        Database, DatabaseExt, Fork, Iter, Iterator, OwnedReadonlyFork, Patch, ReadonlyFork,
-        Snapshot,
+        Change, Database, DatabaseExt, Fork, Iter, Iterator, OwnedReadonlyFork, Patch, PinnedValue,
+        ReadonlyFork, Savepoint, Snapshot,
     },
+    detached::{DetachedPatch, DetachedView},
     error::Error,
     keys::BinaryKey,
     lazy::Lazy,
-    options::DBOptions,
-    values::BinaryValue,
-    views::{AsReadonly, IndexAddress, IndexType, ResolvedAddress},
+    options::{ColumnFamilyMapping, DBOptions},
+    values::{BinaryValue, BorrowedBinaryValue},
+    views::{AsReadonly, IndexAddress, IndexType, MetadataCache, ResolvedAddress},
 };
 // Workaround for 'Linked file at path {matterdb_path}/struct.MapIndex.html
 // does not exist!'
 #[doc(no_inline)]
-pub use self::indexes::{Entry, Group, KeySetIndex, ListIndex, MapIndex, SparseListIndex};
+pub use self::indexes::{
+    BitmapIndex, BlobIndex, CounterIndex, Entry, Group, KeySetIndex, ListIndex, MapIndex,
+    MultiMapIndex, SparseListIndex,
+};
 
 #[macro_use]
 mod macros;
 pub mod access;
 mod backends;
+pub mod bulk;
+pub mod cache;
 mod db;
+mod detached;
 mod error;
 pub mod generic;
 pub mod indexes;
+pub mod jobs;
 mod keys;
 mod lazy;
 pub mod migration;
 mod options;
+pub mod query;
+mod spill;
 pub mod validation;
 mod values;
 mod views;
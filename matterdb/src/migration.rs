@@ -39,55 +39,99 @@
 //! (indeed, this is a best practice to avoid out-of-memory errors). It is even possible
 //! to restart the process handling the migration, provided it can recover from such a restart
 //! on the application level. To assist with fault tolerance, use [persistent iterators].
+//! Large `MapIndex`es can be migrated on several threads at once with [`ParallelMigration`].
+//! The state and progress of migrations are recorded in the [`MigrationRegistry`], which
+//! can be read from any snapshot. Changes to the old data made while the migration
+//! is in progress can be recorded with [`MigrationHelper::track_live_changes`] and replayed
+//! with [`MigrationHelper::catch_up`].
+//! Versioned [migration scripts] can be applied with
+//! a [`MigrationRunner`], which records data versions of migrated namespaces.
+//! If migrated data needs to be merged atomically, a memory limit can be set for the fork
+//! with [`Fork::set_memory_limit`]; in this case, flushed changes exceeding the limit
+//! are moved to a temporary on-disk store.
 //!
 //! # Finalizing Migration
 //!
 //! To finalize a migration, one needs to call [`flush_migration`]. This will replace
 //! old index data with new, remove indexes marked with tombstones, and return migrated indexes
-//! to the default state aggregator. To roll back a migration,
+//! to the default state aggregator. Changes that `flush_migration` would perform can be reviewed
+//! beforehand with [`diff_migration`]. To roll back a migration,
 //! use [`rollback_migration`]. This will remove the new index data and corresponding metadata.
 //! Both `flush_migration` and `rollback_migration` will remove the `Scratchpad` associated
 //! with the migration.
 //!
+//! For large namespaces, removing the old data at once may be undesirable. In this case,
+//! use [`flush_migration_deferred`], which only swaps index metadata; the old data is removed
+//! afterwards by a [`GarbageCollector`] in several merges. The deferred flush can be
+//! reverted with [`undo_flush`] within the specified grace period.
+//!
 //! [`Migration`]: struct.Migration.html
 //! [`Prefixed`]: ../access/struct.Prefixed.html
 //! [`create_tombstone`]: struct.Migration.html#method.create_tombstone
 //! [`Scratchpad`]: struct.Scratchpad.html
 //! [aggregated]: ../index.html#state-aggregation
 //! [persistent iterators]: struct.PersistentIter.html
+//! [`ParallelMigration`]: struct.ParallelMigration.html
+//! [`MigrationRegistry`]: struct.MigrationRegistry.html
+//! [migration scripts]: struct.MigrationScript.html
+//! [`MigrationRunner`]: struct.MigrationRunner.html
+//! [`MigrationHelper::catch_up`]: struct.MigrationHelper.html#method.catch_up
+//! [`MigrationHelper::track_live_changes`]: struct.MigrationHelper.html#method.track_live_changes
+//! [`Fork::set_memory_limit`]: ../struct.Fork.html#method.set_memory_limit
 //! [`flush_migration`]: fn.flush_migration.html
 //! [`rollback_migration`]: fn.rollback_migration.html
+//! [`diff_migration`]: fn.diff_migration.html
+//! [`flush_migration_deferred`]: fn.flush_migration_deferred.html
+//! [`GarbageCollector`]: struct.GarbageCollector.html
+//! [`undo_flush`]: fn.undo_flush.html
 //!
 //! # Examples
 //!
 //! None yet.
 
-pub use self::persistent_iter::{PersistentIter, PersistentIters, PersistentKeys};
+pub use self::{
+    changelog::OldDataChange,
+    diff::{diff_migration, EntryDiff, IndexChangeKind, IndexDiff, MigrationDiff},
+    gc::{deferred_flush, flush_migration_deferred, undo_flush, DeferredFlush, GarbageCollector},
+    parallel::{MigrationWorker, ParallelMigration},
+    persistent_iter::{PersistentIter, PersistentIters, PersistentKeys},
+    registry::{IteratorProgress, MigrationRegistry, MigrationState, MigrationStatus},
+    scripts::{data_version, MigrationRunner, MigrationScript},
+};
 
 use thiserror::Error;
 
 use std::{
     fmt,
+    ops::RangeBounds,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
 };
 
+pub(crate) use self::changelog::log_live_changes;
+
+use self::changelog::ChangeLog;
 use crate::{
     access::{Access, AccessError, Prefixed, RawAccess},
     validation::{assert_valid_name_component, check_index_valid_full_name},
-    views::{
-        AsReadonly, GroupKeys, IndexAddress, IndexMetadata, IndexType, IndexesPool, RawAccessMut,
-        View, ViewWithMetadata,
-    },
+    views::{GroupKeys, IndexAddress, IndexMetadata, IndexType, RawAccessMut, ViewWithMetadata},
     BinaryKey, Database, Fork, ReadonlyFork,
 };
 
+mod changelog;
+mod diff;
+mod gc;
+mod parallel;
 mod persistent_iter;
+mod registry;
+mod scripts;
 
 /// Name of the column family used to store `Scratchpad`s.
 const SCRATCHPAD_NAME: &str = "__scratchpad__";
+/// Maximum number of changes replayed by `MigrationHelper::catch_up` before a merge.
+const CATCH_UP_CHUNK_SIZE: usize = 1_000;
 
 /// Access to migrated indexes.
 ///
@@ -161,14 +205,32 @@ impl<T: RawAccess> Access for Migration<T> {
         self.access.get_or_create_view(prefixed_addr, index_type)
     }
 
-    fn group_keys<K>(self, base_addr: IndexAddress) -> GroupKeys<Self::Base, K>
+    fn group_keys<K, R>(self, base_addr: IndexAddress, range: R) -> GroupKeys<Self::Base, K>
     where
         K: BinaryKey + ?Sized,
-        Self::Base: AsReadonly<Readonly = Self::Base>,
+        R: RangeBounds<K>,
+    {
+        let mut prefixed_addr = base_addr.prepend_name(&self.namespace);
+        prefixed_addr.set_in_migration();
+        self.access.group_keys(prefixed_addr, range)
+    }
+
+    fn remove_index(self, addr: IndexAddress) -> Result<(), AccessError>
+    where
+        Self::Base: RawAccessMut,
+    {
+        let mut prefixed_addr = addr.prepend_name(&self.namespace);
+        prefixed_addr.set_in_migration();
+        self.access.remove_index(prefixed_addr)
+    }
+
+    fn remove_group(self, base_addr: IndexAddress) -> Result<(), AccessError>
+    where
+        Self::Base: RawAccessMut,
     {
         let mut prefixed_addr = base_addr.prepend_name(&self.namespace);
         prefixed_addr.set_in_migration();
-        self.access.group_keys(prefixed_addr)
+        self.access.remove_group(prefixed_addr)
     }
 }
 
@@ -182,6 +244,8 @@ impl<T: RawAccess> Access for Migration<T> {
 pub struct Scratchpad<T> {
     access: T,
     namespace: String,
+    /// Name of the column family used to store the scratchpad.
+    root: &'static str,
 }
 
 // **NB.** Must not be made public! This would allow the caller to violate access restrictions
@@ -199,20 +263,27 @@ impl<T> Scratchpad<T> {
 impl<T: RawAccess> Scratchpad<T> {
     /// Creates a scratchpad in the specified namespace.
     pub fn new(namespace: impl Into<String>, access: T) -> Self {
+        Self::with_root(SCRATCHPAD_NAME, namespace, access)
+    }
+
+    /// Creates a scratchpad in the specified namespace, which is stored in a column family
+    /// different from the one used by migrations.
+    pub(crate) fn with_root(root: &'static str, namespace: impl Into<String>, access: T) -> Self {
         Self {
             namespace: namespace.into(),
             access,
+            root,
         }
     }
 
     fn get_scratchpad_addr(&self, addr: IndexAddress) -> IndexAddress {
         let prefixed_addr = addr.prepend_name(&self.namespace);
-        IndexAddress::from_root(SCRATCHPAD_NAME).append_key(&prefixed_addr.fully_qualified_name())
+        IndexAddress::from_root(self.root).append_key(&prefixed_addr.fully_qualified_name())
     }
 
     fn get_scratchpad_prefix(&self, addr: IndexAddress) -> IndexAddress {
         let prefixed_addr = addr.prepend_name(&self.namespace);
-        IndexAddress::from_root(SCRATCHPAD_NAME).append_key(&prefixed_addr.qualified_prefix())
+        IndexAddress::from_root(self.root).append_key(&prefixed_addr.qualified_prefix())
     }
 }
 
@@ -222,13 +293,10 @@ impl<T: RawAccessMut> Scratchpad<T> {
     /// # Panics
     ///
     /// This operation will panic if any of the removed indexes are borrowed.
-    fn clear(&self) {
+    pub(crate) fn clear(&self) {
         let addr = self.get_scratchpad_addr(IndexAddress::default());
         let addr = addr.append_key(&b'.');
-        let removed = IndexesPool::new(self.access.clone()).remove_indexes(&addr);
-        for resolved_addr in removed {
-            View::new(self.access.clone(), resolved_addr).clear();
-        }
+        ViewWithMetadata::remove_group_unchecked(&self.access, &addr);
     }
 }
 
@@ -254,13 +322,40 @@ impl<T: RawAccess> Access for Scratchpad<T> {
         ViewWithMetadata::get_or_create_unchecked(self.access, &addr, index_type)
     }
 
-    fn group_keys<K>(self, base_addr: IndexAddress) -> GroupKeys<Self::Base, K>
+    fn group_keys<K, R>(self, base_addr: IndexAddress, range: R) -> GroupKeys<Self::Base, K>
     where
         K: BinaryKey + ?Sized,
-        Self::Base: AsReadonly<Readonly = Self::Base>,
+        R: RangeBounds<K>,
+    {
+        let base_addr = self.get_scratchpad_prefix(base_addr);
+        self.access.group_keys(base_addr, range)
+    }
+
+    fn remove_index(self, addr: IndexAddress) -> Result<(), AccessError>
+    where
+        Self::Base: RawAccessMut,
+    {
+        if let Err(kind) = check_index_valid_full_name(addr.name()) {
+            return Err(AccessError { addr, kind });
+        }
+        let addr = self.get_scratchpad_addr(addr);
+        ViewWithMetadata::remove_unchecked(self.access, &addr);
+        Ok(())
+    }
+
+    fn remove_group(self, base_addr: IndexAddress) -> Result<(), AccessError>
+    where
+        Self::Base: RawAccessMut,
     {
+        if let Err(kind) = check_index_valid_full_name(base_addr.name()) {
+            return Err(AccessError {
+                addr: base_addr,
+                kind,
+            });
+        }
         let base_addr = self.get_scratchpad_prefix(base_addr);
-        self.access.group_keys(base_addr)
+        ViewWithMetadata::remove_group_unchecked(&self.access, &base_addr);
+        Ok(())
     }
 }
 
@@ -368,8 +463,10 @@ impl MigrationHelper {
         assert_valid_name_component(namespace);
 
         let db = db.into();
+        let fork = db.fork();
+        MigrationRegistry::new(&fork).start(namespace);
         Self {
-            fork: Some(db.fork()),
+            fork: Some(fork),
             db,
             abort_handle: Box::new(()),
             namespace: namespace.to_owned(),
@@ -382,9 +479,7 @@ impl MigrationHelper {
     /// to the database.
     pub fn with_handle(db: impl Into<Arc<dyn Database>>, namespace: &str) -> (Self, AbortHandle) {
         let mut this = Self::new(db, namespace);
-        let abort_handle = AbortHandle {
-            inner: Arc::new(AtomicBool::default()),
-        };
+        let abort_handle = AbortHandle::new();
         this.set_abort_handle(abort_handle.clone_inner());
         (this, abort_handle)
     }
@@ -436,6 +531,7 @@ impl MigrationHelper {
         let fork = self.fork.take().unwrap();
         let patch = fork.into_patch();
         if self.is_aborted() {
+            record_abort(&*self.db, &self.namespace);
             Err(MigrationError::Aborted)
         } else {
             self.db.merge(patch).map_err(MigrationError::Merge)?;
@@ -458,11 +554,102 @@ impl MigrationHelper {
             let mut iterators = PersistentIters::new(self.scratchpad());
             step(self, &mut iterators);
             should_break = iterators.all_ended();
+            MigrationRegistry::new(self.fork_ref()).update(&self.namespace, |status| {
+                iterators.record_progress(status);
+            });
             self.merge()?;
         }
         Ok(())
     }
 
+    /// Enables logging of changes to the old data for [`catch_up`]. The setting is recorded
+    /// in the [`MigrationRegistry`] and is merged to the database immediately, together
+    /// with other changes in the helper; an error is returned if the merge has failed.
+    ///
+    /// Once logging is enabled, keys of the old data changed in a fork are recorded when
+    /// the fork is converted into a patch with [`Fork::into_patch`]. Changes to the migrated
+    /// data are not recorded. Forks created before logging was enabled do not see the setting,
+    /// so their changes are not recorded either. Logging stops once the migration leaves
+    /// the running state, i.e., when it is finished or aborted. If an aborted migration
+    /// is resumed, logging needs to be enabled anew; changes made while the migration
+    /// was not running are not recorded.
+    ///
+    /// [`catch_up`]: #method.catch_up
+    /// [`MigrationRegistry`]: struct.MigrationRegistry.html
+    /// [`Fork::into_patch`]: ../struct.Fork.html#method.into_patch
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use matterdb::{access::CopyAccessExt, Database, TemporaryDB};
+    /// # use matterdb::migration::MigrationHelper;
+    /// # use std::sync::Arc;
+    /// let db: Arc<dyn Database> = Arc::new(TemporaryDB::new());
+    /// let mut helper = MigrationHelper::new(Arc::clone(&db), "test");
+    /// helper.track_live_changes().unwrap();
+    ///
+    /// let fork = db.fork();
+    /// fork.get_entry("test.entry").set(1_u32);
+    /// db.merge(fork.into_patch()).unwrap();
+    ///
+    /// let mut replayed = 0;
+    /// helper.catch_up(|_, change| {
+    ///     assert_eq!(change.address().name(), "entry");
+    ///     replayed += 1;
+    /// }).unwrap();
+    /// assert_eq!(replayed, 1);
+    /// ```
+    pub fn track_live_changes(&mut self) -> Result<(), MigrationError> {
+        MigrationRegistry::new(self.fork_ref()).enable_live_changes(&self.namespace);
+        self.merge()
+    }
+
+    /// Replays changes to the old data made since logging was enabled with
+    /// [`track_live_changes`].
+    ///
+    /// Since the old data is read through a separate fork on each iteration, changes made
+    /// after a persistent iterator has passed a key would otherwise be lost. Changes are recorded
+    /// when forks are converted into patches. `catch_up` calls `replay` for each recorded change; the closure
+    /// should read the current value of the changed entry from the [`old_data`] and transform
+    /// it into the [`new_data`] in the same way as the main migration logic (including removing
+    /// the migrated entry if the old entry was removed). Replayed changes are removed from the log,
+    /// and the changes are merged to the database in chunks.
+    ///
+    /// Changes are replayed until the log is empty. Changes to indexes which were removed
+    /// from the old data are skipped. To ensure that no changes are lost, writes to the old
+    /// data should be stopped before the last call to `catch_up` preceding [`flush_migration`].
+    ///
+    /// # Return value
+    ///
+    /// Returns the number of replayed changes, or an error if a merge has failed.
+    ///
+    /// [`track_live_changes`]: #method.track_live_changes
+    /// [`old_data`]: #method.old_data
+    /// [`new_data`]: #method.new_data
+    /// [`flush_migration`]: fn.flush_migration.html
+    pub fn catch_up(
+        &mut self,
+        mut replay: impl FnMut(&Self, &OldDataChange),
+    ) -> Result<u64, MigrationError> {
+        let mut replayed = 0;
+        loop {
+            // Merging replaces the fork with a new one, so the log and the old data are read
+            // from the latest database state.
+            self.merge()?;
+            let entries = ChangeLog::new(self.fork_ref().readonly(), &self.namespace)
+                .entries(CATCH_UP_CHUNK_SIZE);
+            if entries.is_empty() {
+                return Ok(replayed);
+            }
+
+            for change in entries.iter().filter_map(|(_, change)| change.as_ref()) {
+                replay(self, change);
+                replayed += 1;
+            }
+            ChangeLog::new(self.fork_ref(), &self.namespace).remove(&entries);
+        }
+    }
+
     /// Merges the changes to the migrated data and the migration scratchpad to the database.
     /// Returns hash representing migrated data state, or an error if the merge has failed.
     ///
@@ -471,8 +658,11 @@ impl MigrationHelper {
     ///
     /// [`flush_migration`]: fn.flush_migration.html
     pub fn finish(mut self) -> Result<(), MigrationError> {
-        let patch = self.fork.take().unwrap().into_patch();
+        let fork = self.fork.take().unwrap();
+        MigrationRegistry::new(&fork).set_state(&self.namespace, MigrationState::AwaitingFlush);
+        let patch = fork.into_patch();
         if self.is_aborted() {
+            record_abort(&*self.db, &self.namespace);
             Err(MigrationError::Aborted)
         } else {
             self.db.merge(patch).map_err(MigrationError::Merge)?;
@@ -530,7 +720,13 @@ impl Drop for AbortHandle {
 }
 
 impl AbortHandle {
-    fn clone_inner(&self) -> Self {
+    pub(crate) fn new() -> Self {
+        Self {
+            inner: Arc::new(AtomicBool::default()),
+        }
+    }
+
+    pub(crate) fn clone_inner(&self) -> Self {
         Self {
             inner: Arc::clone(&self.inner),
         }
@@ -555,6 +751,7 @@ impl AbortHandle {
 /// - Migrated indexes will be aggregated in the default namespace
 /// - Indexes marked with tombstones will be removed
 /// - Scratchpad associated with the migration will be cleared
+/// - Changes to the old data recorded for [`MigrationHelper::catch_up`] will be discarded
 ///
 /// # Safety
 ///
@@ -571,9 +768,13 @@ impl AbortHandle {
 /// In this scenario, a fork may not have the latest migration data because it was created before
 /// the migration is complete. The correct workflow would be to swap steps 2 and 3, i.e.,
 /// first ensure that the migration is complete and *then* create a fork in which it will be flushed.
+///
+/// [`MigrationHelper::catch_up`]: struct.MigrationHelper.html#method.catch_up
 pub fn flush_migration(fork: &mut Fork, namespace: &str) {
     fork.flush_migration(namespace);
     Scratchpad::new(namespace, &*fork).clear();
+    ChangeLog::new(&*fork, namespace).clear();
+    MigrationRegistry::new(&*fork).set_state(namespace, MigrationState::Flushed);
 }
 
 /// Rolls back the migration.
@@ -582,9 +783,23 @@ pub fn flush_migration(fork: &mut Fork, namespace: &str) {
 ///
 /// - Migrated indexes will be erased (both data and metadata)
 /// - Scratchpad associated with the migration will be cleared
+/// - Changes to the old data recorded for [`MigrationHelper::catch_up`] will be discarded
+///
+/// [`MigrationHelper::catch_up`]: struct.MigrationHelper.html#method.catch_up
 pub fn rollback_migration(fork: &mut Fork, namespace: &str) {
     fork.rollback_migration(namespace);
     Scratchpad::new(namespace, &*fork).clear();
+    ChangeLog::new(&*fork, namespace).clear();
+    MigrationRegistry::new(&*fork).set_state(namespace, MigrationState::RolledBack);
+}
+
+/// Records that the migration was aborted in the migration registry. Other changes
+/// are not merged to the database after an abort, so the record is merged separately.
+fn record_abort(db: &dyn Database, namespace: &str) {
+    let fork = db.fork();
+    MigrationRegistry::new(&fork).set_state(namespace, MigrationState::Aborted);
+    // The registry is auxiliary, so we do not report failures to update it.
+    db.merge(fork.into_patch()).ok();
 }
 
 #[cfg(test)]
@@ -0,0 +1,441 @@
+//! Log of changes to the old data made while a migration is in progress.
+
+use std::{collections::HashMap, num::NonZeroU64};
+
+use super::{registry::LOGGING_NAME, MigrationRegistry};
+use crate::{
+    access::RawAccess,
+    views::{IndexesPool, RawAccessMut, View},
+    BinaryKey, Fork, IndexAddress, Patch, ResolvedAddress,
+};
+
+/// Name of the column family used to store the change log.
+const CHANGE_LOG_NAME: &str = "__MIGRATION_CHANGES__";
+/// Log value for a changed key.
+const KEY_CHANGED: u8 = 0;
+/// Log value for a cleared index. The log key for a cleared index has an empty key suffix.
+const INDEX_CLEARED: u8 = 1;
+
+/// Change to the old data made after the migration has started.
+///
+/// Changes are produced by [`MigrationHelper::catch_up`]. A change does not contain
+/// the new value of the changed entry; the value should be read from the old data.
+///
+/// [`MigrationHelper::catch_up`]: struct.MigrationHelper.html#method.catch_up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OldDataChange {
+    address: IndexAddress,
+    key: Option<Vec<u8>>,
+}
+
+impl OldDataChange {
+    /// Returns the address of the changed index relative to the migration namespace.
+    pub fn address(&self) -> &IndexAddress {
+        &self.address
+    }
+
+    /// Returns the raw key of the changed entry, or `None` if the entire index was cleared.
+    /// In the latter case, all entries of the index should be migrated anew.
+    pub fn raw_key(&self) -> Option<&[u8]> {
+        self.key.as_deref()
+    }
+
+    /// Returns the key of the changed entry, or `None` if the entire index was cleared.
+    pub fn key<K: BinaryKey + ?Sized>(&self) -> Option<K::Owned> {
+        self.key.as_ref().map(|key| K::read(key))
+    }
+}
+
+/// Entry in the change log: the log key together with the change, or `None` if the changed
+/// index no longer exists.
+pub type LogEntry = (Vec<u8>, Option<OldDataChange>);
+
+/// Change log of a single migration namespace.
+#[derive(Debug)]
+pub struct ChangeLog<T: RawAccess> {
+    view: View<T>,
+    namespace: String,
+}
+
+impl<T: RawAccess> ChangeLog<T> {
+    pub fn new(access: T, namespace: &str) -> Self {
+        Self {
+            view: View::new(access, ResolvedAddress::system(CHANGE_LOG_NAME)),
+            namespace: namespace.to_owned(),
+        }
+    }
+
+    fn prefix(namespace: &str) -> Vec<u8> {
+        let mut prefix = namespace.as_bytes().to_vec();
+        prefix.push(0);
+        prefix
+    }
+
+    fn log_key(namespace: &str, index_id: NonZeroU64, key: &[u8]) -> Vec<u8> {
+        let mut log_key = Self::prefix(namespace);
+        log_key.extend_from_slice(&index_id.get().to_be_bytes());
+        log_key.extend_from_slice(key);
+        log_key
+    }
+
+    /// Returns up to `limit` first entries of the log.
+    pub fn entries(&self, limit: usize) -> Vec<LogEntry> {
+        let prefix = Self::prefix(&self.namespace);
+        let entries: Vec<(Vec<u8>, u8)> = self
+            .view
+            .iter::<_, [u8], u8>(&prefix[..])
+            .take(limit)
+            .collect();
+        if entries.is_empty() {
+            return vec![];
+        }
+
+        let access = self.view.access().unwrap().clone();
+        let addresses = IndexesPool::new(access).index_addresses(&self.namespace);
+
+        entries
+            .into_iter()
+            .map(|(log_key, value)| {
+                let suffix = &log_key[prefix.len()..];
+                let mut id_bytes = [0_u8; 8];
+                id_bytes.copy_from_slice(&suffix[..8]);
+                let index_id = NonZeroU64::new(u64::from_be_bytes(id_bytes));
+                let change = index_id.and_then(|id| addresses.get(&id)).map(|address| {
+                    // Index addresses are reported relative to the namespace.
+                    let name = &address.name()[prefix.len()..];
+                    let address = match address.id_in_group() {
+                        Some(id_in_group) => IndexAddress::from_root(name).append_key(id_in_group),
+                        None => IndexAddress::from_root(name),
+                    };
+                    let key = if value == INDEX_CLEARED {
+                        None
+                    } else {
+                        Some(suffix[8..].to_vec())
+                    };
+                    OldDataChange { address, key }
+                });
+                (log_key, change)
+            })
+            .collect()
+    }
+}
+
+impl<T: RawAccessMut> ChangeLog<T> {
+    /// Removes the specified entries from the log.
+    pub fn remove(&mut self, entries: &[LogEntry]) {
+        for (log_key, _) in entries {
+            self.view.remove(&log_key[..]);
+        }
+    }
+
+    /// Removes all entries from the log.
+    pub fn clear(&mut self) {
+        let prefix = Self::prefix(&self.namespace);
+        let log_keys: Vec<_> = self
+            .view
+            .iter::<_, [u8], u8>(&prefix[..])
+            .map(|(log_key, _)| log_key)
+            .collect();
+        for log_key in log_keys {
+            self.view.remove(&log_key[..]);
+        }
+    }
+}
+
+/// Checks whether live changes need to be logged for the `patch`, i.e., whether any migration
+/// logs live changes in the snapshot the patch is based on, or the patch changes the set
+/// of such migrations. The check is cached for snapshots of the same database state.
+fn has_logging_migrations(patch: &Patch) -> bool {
+    if patch.changes_view(&ResolvedAddress::system(LOGGING_NAME)) {
+        return true;
+    }
+    let snapshot = patch.base_snapshot();
+    let load = || MigrationRegistry::new(snapshot).has_logging_migrations();
+    match snapshot.metadata_cache() {
+        Some(cache) => cache.logs_live_changes(load),
+        None => load(),
+    }
+}
+
+/// Records keys of the old data changed in the `fork` into the change log, so that
+/// they can be replayed with [`MigrationHelper::catch_up`]. The fork is expected
+/// to be flushed. The function is called automatically when converting a fork into a patch.
+///
+/// Changes are recorded only for migrations with logging enabled via
+/// [`MigrationHelper::track_live_changes`], and only while the migration is running.
+/// Changes to the migrated data (i.e., indexes in the `^namespace` form) and to system
+/// views are not recorded. If no migration has logging enabled, the call costs
+/// a lookup in the metadata cache of the snapshot.
+///
+/// [`MigrationHelper::catch_up`]: struct.MigrationHelper.html#method.catch_up
+/// [`MigrationHelper::track_live_changes`]: struct.MigrationHelper.html#method.track_live_changes
+pub fn log_live_changes(fork: &mut Fork) {
+    if !has_logging_migrations(fork.flushed_patch()) {
+        return;
+    }
+    let namespaces = MigrationRegistry::new(fork.readonly()).logging_namespaces();
+    if namespaces.is_empty() {
+        return;
+    }
+
+    // Only indexes of the old data are tracked. Migrated indexes are resolved to the same names
+    // as the old ones, so they are filtered out by their identifiers.
+    let pool = IndexesPool::new(fork.readonly());
+    let mut namespace_by_id = HashMap::new();
+    for namespace in &namespaces {
+        for &id in pool.index_addresses(namespace).keys() {
+            namespace_by_id.insert(id, namespace.as_str());
+        }
+    }
+    drop(pool);
+    let namespace_of =
+        |address: &ResolvedAddress| address.id.and_then(|id| namespace_by_id.get(&id).copied());
+    let changed_views = fork
+        .flushed_patch()
+        .changed_keys(|address| namespace_of(address).is_some());
+    if changed_views.is_empty() {
+        return;
+    }
+
+    let mut log = View::new(&*fork, ResolvedAddress::system(CHANGE_LOG_NAME));
+    for (address, is_cleared, keys) in changed_views {
+        let namespace = namespace_of(&address).unwrap();
+        let index_id = address.id.unwrap();
+        let cleared_key = ChangeLog::<&Fork>::log_key(namespace, index_id, &[]);
+        if is_cleared {
+            log.put(&cleared_key[..], INDEX_CLEARED);
+            continue;
+        }
+        if log.get::<_, u8>(&cleared_key[..]) == Some(INDEX_CLEARED) {
+            // All entries of the index will be replayed anyway.
+            continue;
+        }
+        for key in keys {
+            let log_key = ChangeLog::<&Fork>::log_key(namespace, index_id, &key);
+            log.put(&log_key[..], KEY_CHANGED);
+        }
+    }
+    drop(log);
+    fork.flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        access::{AccessExt, CopyAccessExt},
+        migration::{flush_migration, Migration, MigrationHelper, MigrationState},
+        Database, TemporaryDB,
+    };
+
+    use std::sync::Arc;
+
+    fn create_db() -> Arc<dyn Database> {
+        let db = TemporaryDB::new();
+        let fork = db.fork();
+        {
+            let mut map = fork.get_map("test.map");
+            for i in 0_u32..20 {
+                map.put(&i, i);
+            }
+        }
+        fork.get_entry("test.entry").set(1_u32);
+        db.merge(fork.into_patch()).unwrap();
+        Arc::new(db)
+    }
+
+    fn migrate_value(helper: &MigrationHelper, key: u32) {
+        let value = helper.old_data().get_map::<_, u32, u32>("map").get(&key);
+        let mut new_map = helper.new_data().get_map::<_, u32, u64>("map");
+        match value {
+            Some(value) => new_map.put(&key, u64::from(value) * 2),
+            None => new_map.remove(&key),
+        }
+    }
+
+    #[test]
+    fn live_changes_are_replayed() {
+        let db = create_db();
+        let mut helper = MigrationHelper::new(Arc::clone(&db), "test");
+        helper.track_live_changes().unwrap();
+
+        let mut iteration = 0;
+        helper
+            .iter_loop(|helper, iters| {
+                let old_map = helper.old_data().get_map::<_, u32, u32>("map");
+                let keys: Vec<_> = iters
+                    .create("map", &old_map)
+                    .take(5)
+                    .map(|(k, _)| k)
+                    .collect();
+                for key in keys {
+                    migrate_value(helper, key);
+                }
+
+                // Emulate concurrent writes to the old data, including the keys already migrated.
+                iteration += 1;
+                if iteration == 2 {
+                    let fork = db.fork();
+                    {
+                        let mut map = fork.get_map::<_, u32, u32>("test.map");
+                        map.put(&1, 100);
+                        map.remove(&2);
+                        map.put(&30, 30);
+                    }
+                    fork.get_entry("other.entry").set(1_u32);
+                    db.merge(fork.into_patch()).unwrap();
+                }
+            })
+            .unwrap();
+
+        let snapshot = db.snapshot();
+        let new_map = Migration::new("test", &snapshot).get_map::<_, u32, u64>("map");
+        assert_eq!(new_map.get(&1), Some(2));
+        assert_eq!(new_map.get(&2), Some(4));
+
+        let mut changes = vec![];
+        let replayed = helper
+            .catch_up(|helper, change| {
+                assert_eq!(change.address().name(), "map");
+                let key = change.key::<u32>().unwrap();
+                migrate_value(helper, key);
+                changes.push(key);
+            })
+            .unwrap();
+        assert_eq!(replayed, 3);
+        assert_eq!(changes, vec![1, 2, 30]);
+        // The log is empty now.
+        assert_eq!(helper.catch_up(|_, _| unreachable!()).unwrap(), 0);
+        helper.finish().unwrap();
+
+        let mut fork = db.fork();
+        flush_migration(&mut fork, "test");
+        db.merge(fork.into_patch()).unwrap();
+        let snapshot = db.snapshot();
+        let map = snapshot.get_map::<_, u32, u64>("test.map");
+        assert_eq!(map.get(&1), Some(200));
+        assert_eq!(map.get(&2), None);
+        assert_eq!(map.get(&30), Some(60));
+        assert_eq!(map.values().count(), 20);
+    }
+
+    #[test]
+    fn cleared_indexes_are_logged() {
+        let db = create_db();
+        let mut helper = MigrationHelper::new(Arc::clone(&db), "test");
+        helper.track_live_changes().unwrap();
+
+        let fork = db.fork();
+        fork.get_entry::<_, u32>("test.entry").set(2);
+        fork.get_map::<_, u32, u32>("test.map").put(&5, 5);
+        fork.get_map::<_, u32, u32>("test.map").clear();
+        fork.get_map::<_, u32, u32>("test.map").put(&7, 7);
+        db.merge(fork.into_patch()).unwrap();
+
+        let mut changes = vec![];
+        helper
+            .catch_up(|_, change| changes.push(change.clone()))
+            .unwrap();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].address().name(), "map");
+        assert_eq!(changes[0].raw_key(), None);
+        assert_eq!(changes[1].address().name(), "entry");
+        assert_eq!(changes[1].raw_key(), Some(&[][..]));
+    }
+
+    #[test]
+    fn changes_are_not_logged_without_opt_in() {
+        let db = create_db();
+        let mut helper = MigrationHelper::new(Arc::clone(&db), "test");
+
+        let fork = db.fork();
+        fork.get_entry::<_, u32>("test.entry").set(2);
+        db.merge(fork.into_patch()).unwrap();
+        assert_eq!(helper.catch_up(|_, _| unreachable!()).unwrap(), 0);
+    }
+
+    #[test]
+    fn migrated_data_is_not_logged() {
+        let db = create_db();
+        let mut helper = MigrationHelper::new(Arc::clone(&db), "test");
+        helper.track_live_changes().unwrap();
+
+        let fork = db.fork();
+        {
+            let migration = Migration::new("test", &fork);
+            migration.get_map::<_, u32, u64>("map").put(&1, 2);
+            migration.get_entry::<_, u32>("entry").set(5);
+        }
+        db.merge(fork.into_patch()).unwrap();
+        assert_eq!(helper.catch_up(|_, _| unreachable!()).unwrap(), 0);
+    }
+
+    #[test]
+    fn changes_are_not_logged_after_abort() {
+        let db = create_db();
+        let mut helper = MigrationHelper::new(Arc::clone(&db), "test");
+        helper.track_live_changes().unwrap();
+
+        let fork = db.fork();
+        MigrationRegistry::new(&fork).set_state("test", MigrationState::Aborted);
+        fork.get_entry::<_, u32>("test.entry").set(2);
+        db.merge(fork.into_patch()).unwrap();
+        assert!(ChangeLog::new(&db.snapshot(), "test")
+            .entries(10)
+            .is_empty());
+    }
+
+    #[test]
+    fn changes_are_not_logged_after_flush() {
+        let db = create_db();
+        let mut helper = MigrationHelper::new(Arc::clone(&db), "test");
+        helper.track_live_changes().unwrap();
+        let fork = db.fork();
+        fork.get_entry::<_, u32>("test.entry").set(2);
+        db.merge(fork.into_patch()).unwrap();
+        helper.finish().unwrap();
+        assert_eq!(ChangeLog::new(&db.snapshot(), "test").entries(10).len(), 1);
+
+        // Logging stops as soon as the migration is finished.
+        let fork = db.fork();
+        fork.get_map::<_, u32, u32>("test.map").put(&3, 3);
+        db.merge(fork.into_patch()).unwrap();
+        assert_eq!(ChangeLog::new(&db.snapshot(), "test").entries(10).len(), 1);
+
+        let mut fork = db.fork();
+        flush_migration(&mut fork, "test");
+        db.merge(fork.into_patch()).unwrap();
+        assert!(ChangeLog::new(&db.snapshot(), "test")
+            .entries(10)
+            .is_empty());
+
+        let fork = db.fork();
+        fork.get_entry::<_, u32>("test.entry").set(3);
+        db.merge(fork.into_patch()).unwrap();
+        assert!(ChangeLog::new(&db.snapshot(), "test")
+            .entries(10)
+            .is_empty());
+    }
+
+    #[test]
+    fn logging_check_is_cached() {
+        let db = create_db();
+        let fork = db.fork();
+        fork.get_entry::<_, u32>("test.entry").set(2);
+        let patch = fork.into_patch();
+        // The check performed for the patch is cached for the current database state.
+        let snapshot = db.snapshot();
+        let cache = snapshot.metadata_cache().unwrap();
+        assert!(!cache.logs_live_changes(|| unreachable!()));
+        db.merge(patch).unwrap();
+
+        let mut helper = MigrationHelper::new(Arc::clone(&db), "test");
+        helper.track_live_changes().unwrap();
+        let fork = db.fork();
+        fork.get_entry::<_, u32>("test.entry").set(3);
+        db.merge(fork.into_patch()).unwrap();
+        let snapshot = db.snapshot();
+        assert!(MigrationRegistry::new(&snapshot).has_logging_migrations());
+        assert_eq!(ChangeLog::new(&snapshot, "test").entries(10).len(), 1);
+    }
+}
@@ -0,0 +1,441 @@
+//! Dry-run reports for migrations.
+
+use std::{collections::BTreeMap, convert::TryFrom};
+
+use crate::{
+    access::RawAccess,
+    views::{IndexMetadata, IndexesPool, View},
+    IndexAddress, IndexType, ResolvedAddress,
+};
+
+/// Kind of change to an index performed when a migration is flushed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexChangeKind {
+    /// The index does not exist outside the migration and will be created.
+    Created,
+    /// The existing index will be replaced with the migrated one.
+    Replaced,
+    /// The index is marked with a tombstone and will be removed (if it exists).
+    Tombstoned,
+}
+
+/// Difference between a single entry of the old and the migrated index.
+///
+/// Keys and values are given in the raw form, as they are stored in the database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryDiff {
+    /// Entry is present only in the migrated index.
+    Added {
+        /// Raw key of the entry.
+        key: Vec<u8>,
+        /// Raw value of the entry.
+        value: Vec<u8>,
+    },
+    /// Entry is present only in the old index.
+    Removed {
+        /// Raw key of the entry.
+        key: Vec<u8>,
+        /// Raw value of the entry.
+        value: Vec<u8>,
+    },
+    /// Entry is present in both indexes, but its value differs.
+    Changed {
+        /// Raw key of the entry.
+        key: Vec<u8>,
+        /// Raw value of the entry in the old index.
+        old_value: Vec<u8>,
+        /// Raw value of the entry in the migrated index.
+        new_value: Vec<u8>,
+    },
+}
+
+impl EntryDiff {
+    /// Returns the raw key of the entry.
+    pub fn key(&self) -> &[u8] {
+        match self {
+            Self::Added { key, .. } | Self::Removed { key, .. } | Self::Changed { key, .. } => key,
+        }
+    }
+}
+
+/// Change to a single index performed when a migration is flushed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexDiff {
+    address: IndexAddress,
+    kind: IndexChangeKind,
+    old_type: Option<IndexType>,
+    new_type: IndexType,
+    old_len: u64,
+    new_len: u64,
+    entries: Option<Vec<EntryDiff>>,
+}
+
+impl IndexDiff {
+    /// Returns the address of the index after the migration is flushed.
+    pub fn address(&self) -> &IndexAddress {
+        &self.address
+    }
+
+    /// Returns the kind of the change.
+    pub fn kind(&self) -> IndexChangeKind {
+        self.kind
+    }
+
+    /// Returns the type of the old index, or `None` if the index does not exist
+    /// outside the migration.
+    pub fn old_type(&self) -> Option<IndexType> {
+        self.old_type
+    }
+
+    /// Returns the type of the migrated index. For removed indexes, this is
+    /// `IndexType::Tombstone`.
+    pub fn new_type(&self) -> IndexType {
+        self.new_type
+    }
+
+    /// Checks if the index type changes as a result of the migration. Created indexes
+    /// are not considered to change their type.
+    pub fn is_type_changed(&self) -> bool {
+        self.old_type
+            .map_or(false, |old_type| old_type != self.new_type)
+    }
+
+    /// Returns the number of entries in the old index.
+    pub fn old_len(&self) -> u64 {
+        self.old_len
+    }
+
+    /// Returns the number of entries in the migrated index.
+    pub fn new_len(&self) -> u64 {
+        self.new_len
+    }
+
+    /// Returns the change in the number of entries in the index. The change saturates
+    /// at the `i64` bounds; use [`old_len()`] and [`new_len()`] to get exact lengths.
+    ///
+    /// [`old_len()`]: #method.old_len
+    /// [`new_len()`]: #method.new_len
+    pub fn len_delta(&self) -> i64 {
+        let old_len = i64::try_from(self.old_len).unwrap_or(i64::MAX);
+        let new_len = i64::try_from(self.new_len).unwrap_or(i64::MAX);
+        new_len.saturating_sub(old_len)
+    }
+
+    /// Returns per-entry differences between the old and the migrated index ordered by key,
+    /// or `None` if the differences were not computed (e.g., because the index is too large).
+    pub fn entries(&self) -> Option<&[EntryDiff]> {
+        self.entries.as_deref()
+    }
+}
+
+/// Report on the changes a migration will perform when flushed.
+///
+/// The report is produced by [`diff_migration`] and can be used to review a migration
+/// before calling [`flush_migration`].
+///
+/// [`diff_migration`]: fn.diff_migration.html
+/// [`flush_migration`]: fn.flush_migration.html
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrationDiff {
+    namespace: String,
+    indexes: Vec<IndexDiff>,
+}
+
+impl MigrationDiff {
+    /// Returns the namespace of the migration.
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    /// Returns changes to indexes ordered by the fully qualified index name.
+    pub fn indexes(&self) -> &[IndexDiff] {
+        &self.indexes
+    }
+
+    /// Returns changes of the specified kind.
+    pub fn indexes_of_kind(&self, kind: IndexChangeKind) -> impl Iterator<Item = &IndexDiff> {
+        self.indexes.iter().filter(move |diff| diff.kind == kind)
+    }
+
+    /// Checks if flushing the migration will not change any indexes.
+    pub fn is_empty(&self) -> bool {
+        self.indexes.is_empty()
+    }
+}
+
+/// Computes changes that [`flush_migration`] would perform for the specified namespace,
+/// without modifying the `access`.
+///
+/// Indexes in the migration are compared to the indexes they will replace. Entry counts
+/// are computed by iterating over raw index data, so the call takes time proportional
+/// to the total size of the compared indexes. If `entry_diff_limit` is specified, per-entry
+/// differences are computed for indexes in which both the old and the migrated versions
+/// have at most `entry_diff_limit` entries.
+///
+/// To inspect a migration accumulated in a `Fork`, it is recommended to pass
+/// `fork.readonly()` as the `access`.
+///
+/// # Examples
+///
+/// ```
+/// # use matterdb::{access::{AccessExt, CopyAccessExt}, Database, TemporaryDB};
+/// # use matterdb::migration::{diff_migration, IndexChangeKind, Migration};
+/// let db = TemporaryDB::new();
+/// let fork = db.fork();
+/// fork.get_list("test.list").extend(vec![1_u32, 2, 3]);
+/// fork.get_entry("test.entry").set(1_u8);
+/// let migration = Migration::new("test", &fork);
+/// migration.get_list("list").extend(vec![1_u32, 2]);
+/// migration.create_tombstone("entry");
+/// migration.get_map("map").put(&1_u8, 1_u8);
+///
+/// let diff = diff_migration(fork.readonly(), "test", Some(10));
+/// assert_eq!(diff.indexes().len(), 3);
+/// let list_diff = diff
+///     .indexes_of_kind(IndexChangeKind::Replaced)
+///     .next()
+///     .unwrap();
+/// assert_eq!(list_diff.address().name(), "test.list");
+/// assert_eq!(list_diff.len_delta(), -1);
+/// assert_eq!(list_diff.entries().unwrap().len(), 1);
+/// ```
+///
+/// [`flush_migration`]: fn.flush_migration.html
+#[allow(clippy::needless_pass_by_value)] // Mirrors other constructors taking an access.
+pub fn diff_migration<T: RawAccess>(
+    access: T,
+    namespace: &str,
+    entry_diff_limit: Option<u64>,
+) -> MigrationDiff {
+    let migrated_indexes = IndexesPool::new(access.clone()).migrated_indexes(namespace);
+    let indexes = migrated_indexes
+        .into_iter()
+        .map(|(address, new_metadata, old_metadata)| {
+            diff_index(
+                access.clone(),
+                address,
+                &new_metadata,
+                old_metadata.as_ref(),
+                entry_diff_limit,
+            )
+        })
+        .collect();
+
+    MigrationDiff {
+        namespace: namespace.to_owned(),
+        indexes,
+    }
+}
+
+fn diff_index<T: RawAccess>(
+    access: T,
+    address: IndexAddress,
+    new_metadata: &IndexMetadata,
+    old_metadata: Option<&IndexMetadata>,
+    entry_diff_limit: Option<u64>,
+) -> IndexDiff {
+    let new_type = new_metadata.index_type();
+    let kind = if new_type == IndexType::Tombstone {
+        IndexChangeKind::Tombstoned
+    } else if old_metadata.is_some() {
+        IndexChangeKind::Replaced
+    } else {
+        IndexChangeKind::Created
+    };
+
+    let new_view = View::new(
+        access.clone(),
+        ResolvedAddress::new(address.name(), Some(new_metadata.identifier())),
+    );
+    let old_view = old_metadata.map(|metadata| {
+        View::new(
+            access,
+            ResolvedAddress::new(address.name(), Some(metadata.identifier())),
+        )
+    });
+
+    let new_len = count_entries(&new_view);
+    let old_len = old_view.as_ref().map_or(0, count_entries);
+    let entries = entry_diff_limit
+        .filter(|&limit| new_len <= limit && old_len <= limit)
+        .map(|_| diff_entries(old_view.as_ref(), &new_view));
+
+    IndexDiff {
+        address,
+        kind,
+        old_type: old_metadata.map(IndexMetadata::index_type),
+        new_type,
+        old_len,
+        new_len,
+        entries,
+    }
+}
+
+fn count_entries<T: RawAccess>(view: &View<T>) -> u64 {
+    view.iter::<_, [u8], Vec<u8>>(&()).count() as u64
+}
+
+fn diff_entries<T: RawAccess>(old_view: Option<&View<T>>, new_view: &View<T>) -> Vec<EntryDiff> {
+    let mut old_entries: BTreeMap<Vec<u8>, Vec<u8>> = old_view
+        .map(|view| view.iter::<_, [u8], Vec<u8>>(&()).collect())
+        .unwrap_or_default();
+    let new_entries: BTreeMap<Vec<u8>, Vec<u8>> = new_view.iter::<_, [u8], Vec<u8>>(&()).collect();
+
+    let mut diffs = Vec::new();
+    for (key, value) in new_entries {
+        match old_entries.remove(&key) {
+            None => diffs.push(EntryDiff::Added { key, value }),
+            Some(old_value) if old_value != value => diffs.push(EntryDiff::Changed {
+                key,
+                old_value,
+                new_value: value,
+            }),
+            Some(_) => { /* The entry is unchanged. */ }
+        }
+    }
+    diffs.extend(
+        old_entries
+            .into_iter()
+            .map(|(key, value)| EntryDiff::Removed { key, value }),
+    );
+    diffs.sort_unstable_by(|x, y| x.key().cmp(y.key()));
+    diffs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        access::{AccessExt, CopyAccessExt},
+        migration::{flush_migration, Migration},
+        Database, TemporaryDB,
+    };
+
+    fn prepare_migration(db: &TemporaryDB) {
+        let fork = db.fork();
+        fork.get_list("test.list").extend(vec![1_u32, 2, 3]);
+        fork.get_entry("test.entry").set(1_u8);
+        fork.get_map("test.unchanged").put(&1_u8, 1_u8);
+        fork.get_entry("other.entry").set(1_u8);
+
+        let migration = Migration::new("test", &fork);
+        migration.get_list("list").extend(vec![1_u32, 5]);
+        migration.create_tombstone("entry");
+        migration.get_map("map").put(&1_u8, 1_u8);
+        migration.get_key_set(("group", &2_u8)).insert(&3_u16);
+        migration.get_map::<_, u8, u8>("empty");
+        db.merge(fork.into_patch()).unwrap();
+    }
+
+    #[test]
+    fn diff_reports_index_changes() {
+        let db = TemporaryDB::new();
+        prepare_migration(&db);
+        let snapshot = db.snapshot();
+        let diff = diff_migration(&snapshot, "test", None);
+        assert_eq!(diff.namespace(), "test");
+
+        let addresses: Vec<_> = diff
+            .indexes()
+            .iter()
+            .map(|index| (index.address().name(), index.kind()))
+            .collect();
+        assert_eq!(
+            addresses,
+            vec![
+                ("test.empty", IndexChangeKind::Created),
+                ("test.entry", IndexChangeKind::Tombstoned),
+                ("test.group", IndexChangeKind::Created),
+                ("test.list", IndexChangeKind::Replaced),
+                ("test.map", IndexChangeKind::Created),
+            ]
+        );
+
+        let entry = &diff.indexes()[1];
+        assert_eq!(entry.old_type(), Some(IndexType::Entry));
+        assert_eq!(entry.new_type(), IndexType::Tombstone);
+        assert!(entry.is_type_changed());
+        assert_eq!(entry.len_delta(), -1);
+
+        let group = &diff.indexes()[2];
+        assert_eq!(group.address().id_in_group(), Some(&[2_u8][..]));
+        assert_eq!(group.new_type(), IndexType::KeySet);
+        assert!(!group.is_type_changed());
+        assert_eq!(group.new_len(), 1);
+
+        let list = &diff.indexes()[3];
+        assert_eq!(list.old_type(), Some(IndexType::List));
+        assert!(!list.is_type_changed());
+        assert_eq!((list.old_len(), list.new_len()), (3, 2));
+        assert!(list.entries().is_none());
+
+        assert_eq!(diff.indexes_of_kind(IndexChangeKind::Created).count(), 3);
+        assert!(diff_migration(&snapshot, "other", None).is_empty());
+    }
+
+    #[test]
+    fn diff_with_entries() {
+        let db = TemporaryDB::new();
+        prepare_migration(&db);
+        let snapshot = db.snapshot();
+        let diff = diff_migration(&snapshot, "test", Some(2));
+
+        // The old list has 3 entries, so its entries are not compared.
+        let list = &diff.indexes()[3];
+        assert!(list.entries().is_none());
+        let entry = &diff.indexes()[1];
+        assert_eq!(
+            entry.entries().unwrap(),
+            [EntryDiff::Removed {
+                key: vec![],
+                value: vec![1],
+            }]
+        );
+
+        let diff = diff_migration(&snapshot, "test", Some(3));
+        let list = &diff.indexes()[3];
+        assert_eq!(
+            list.entries().unwrap(),
+            [
+                EntryDiff::Changed {
+                    key: 1_u64.to_be_bytes().to_vec(),
+                    old_value: 2_u32.to_le_bytes().to_vec(),
+                    new_value: 5_u32.to_le_bytes().to_vec(),
+                },
+                EntryDiff::Removed {
+                    key: 2_u64.to_be_bytes().to_vec(),
+                    value: 3_u32.to_le_bytes().to_vec(),
+                },
+            ]
+        );
+        let map = &diff.indexes()[4];
+        assert_eq!(
+            map.entries().unwrap(),
+            [EntryDiff::Added {
+                key: vec![1],
+                value: vec![1],
+            }]
+        );
+        assert_eq!(diff.indexes()[0].entries().unwrap(), []);
+    }
+
+    #[test]
+    fn diff_does_not_modify_fork() {
+        let db = TemporaryDB::new();
+        prepare_migration(&db);
+        let mut fork = db.fork();
+        Migration::new("test", &fork)
+            .get_entry("new_entry")
+            .set(1_u8);
+
+        let diff = diff_migration(fork.readonly(), "test", Some(10));
+        assert_eq!(diff.indexes().len(), 6);
+        assert_eq!(diff.indexes()[5].address().name(), "test.new_entry");
+
+        flush_migration(&mut fork, "test");
+        let patch = fork.into_patch();
+        assert_eq!(patch.get_list::<_, u32>("test.list").len(), 2);
+        assert!(patch.index_type("test.entry").is_none());
+        assert_eq!(patch.get_entry::<_, u8>("test.new_entry").get(), Some(1));
+    }
+}
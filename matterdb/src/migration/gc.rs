@@ -0,0 +1,556 @@
+//! Deferred migration flushes and garbage collection of replaced index data.
+
+use anyhow::{ensure, format_err};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use chrono::{DateTime, Utc};
+
+use std::{
+    borrow::Cow, collections::HashSet, convert::TryFrom, fmt, num::NonZeroU64, sync::Arc,
+    time::Duration,
+};
+
+use super::{changelog::ChangeLog, MigrationRegistry, MigrationState, Scratchpad};
+use crate::{
+    access::RawAccess,
+    validation::assert_valid_name_component,
+    values::{read_bytes, write_bytes},
+    views::{IndexMetadata, IndexesPool, View},
+    BinaryValue, Database, Error, Fork, IndexAddress, ResolvedAddress, Result,
+};
+
+/// Name of the column family used to store deferred flushes.
+const DEFERRED_FLUSHES_NAME: &str = "__DEFERRED_FLUSHES__";
+/// Default maximum number of entries removed by `GarbageCollector` in a single merge.
+const DEFAULT_CHUNK_SIZE: usize = 10_000;
+
+/// Index moved out of the migration by a deferred flush.
+#[derive(Debug, Clone, PartialEq)]
+struct FlushedIndex {
+    address: IndexAddress,
+    old_metadata: Option<IndexMetadata>,
+    new_metadata: IndexMetadata,
+}
+
+impl FlushedIndex {
+    fn write(&self, buffer: &mut Vec<u8>) {
+        write_bytes(buffer, self.address.name().as_bytes());
+        write_optional_bytes(buffer, self.address.id_in_group());
+        let old_metadata = self.old_metadata.as_ref().map(BinaryValue::to_bytes);
+        write_optional_bytes(buffer, old_metadata.as_deref());
+        write_bytes(buffer, &self.new_metadata.to_bytes());
+    }
+
+    fn read(bytes: &mut &[u8]) -> anyhow::Result<Self> {
+        let name = String::from_utf8(read_bytes(bytes)?)?;
+        let address = match read_optional_bytes(bytes)? {
+            Some(id_in_group) => IndexAddress::from_root(name).append_key(&id_in_group[..]),
+            None => IndexAddress::from_root(name),
+        };
+        let old_metadata = read_optional_bytes(bytes)?
+            .map(|metadata| IndexMetadata::from_bytes(metadata.into()))
+            .transpose()?;
+        let new_metadata = IndexMetadata::from_bytes(read_bytes(bytes)?.into())?;
+        Ok(Self {
+            address,
+            old_metadata,
+            new_metadata,
+        })
+    }
+}
+
+fn write_optional_bytes(buffer: &mut Vec<u8>, bytes: Option<&[u8]>) {
+    if let Some(bytes) = bytes {
+        buffer.push(1);
+        write_bytes(buffer, bytes);
+    } else {
+        buffer.push(0);
+    }
+}
+
+fn read_optional_bytes(bytes: &mut &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+    match bytes.read_u8()? {
+        0 => Ok(None),
+        1 => read_bytes(bytes).map(Some),
+        tag => Err(format_err!("Invalid option tag: {}", tag)),
+    }
+}
+
+/// Migration flushed with [`flush_migration_deferred`], data of the replaced indexes
+/// for which is not yet removed.
+///
+/// [`flush_migration_deferred`]: fn.flush_migration_deferred.html
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeferredFlush {
+    flushed_at: DateTime<Utc>,
+    grace_period_millis: u64,
+    garbage: Vec<ResolvedAddress>,
+    flushed_indexes: Vec<FlushedIndex>,
+}
+
+impl DeferredFlush {
+    /// Returns the time of the flush.
+    pub fn flushed_at(&self) -> DateTime<Utc> {
+        self.flushed_at
+    }
+
+    /// Returns the grace period of the flush, during which the data of replaced indexes
+    /// is retained.
+    pub fn grace_period(&self) -> Duration {
+        Duration::from_millis(self.grace_period_millis)
+    }
+
+    /// Checks if the grace period of the flush has expired at the specified time.
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        let grace_period = i64::try_from(self.grace_period_millis).unwrap_or(i64::MAX);
+        now.signed_duration_since(self.flushed_at) >= chrono::Duration::milliseconds(grace_period)
+    }
+
+    /// Checks if the flush can be undone with [`undo_flush`] at the specified time.
+    ///
+    /// [`undo_flush`]: fn.undo_flush.html
+    pub fn can_undo(&self, now: DateTime<Utc>) -> bool {
+        !self.flushed_indexes.is_empty() && !self.is_expired(now)
+    }
+
+    /// Returns the number of indexes the data of which is yet to be removed.
+    pub fn garbage_len(&self) -> usize {
+        self.garbage.len()
+    }
+}
+
+impl BinaryValue for DeferredFlush {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        write_bytes(&mut buffer, &self.flushed_at.to_bytes());
+        buffer
+            .write_u64::<LittleEndian>(self.grace_period_millis)
+            .unwrap();
+
+        buffer
+            .write_u32::<LittleEndian>(self.garbage.len() as u32)
+            .unwrap();
+        for address in &self.garbage {
+            write_bytes(&mut buffer, address.name.as_bytes());
+            let id = address.id.map_or(0, NonZeroU64::get);
+            buffer.write_u64::<LittleEndian>(id).unwrap();
+        }
+
+        buffer
+            .write_u32::<LittleEndian>(self.flushed_indexes.len() as u32)
+            .unwrap();
+        for index in &self.flushed_indexes {
+            index.write(&mut buffer);
+        }
+        buffer
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> anyhow::Result<Self> {
+        let mut bytes = bytes.as_ref();
+        let flushed_at = DateTime::<Utc>::from_bytes(read_bytes(&mut bytes)?.into())?;
+        let grace_period_millis = bytes.read_u64::<LittleEndian>()?;
+
+        let garbage_len = bytes.read_u32::<LittleEndian>()? as usize;
+        let garbage = (0..garbage_len)
+            .map(|_| {
+                let name = String::from_utf8(read_bytes(&mut bytes)?)?;
+                let id = NonZeroU64::new(bytes.read_u64::<LittleEndian>()?);
+                Ok(ResolvedAddress::new(name, id))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        let flushed_len = bytes.read_u32::<LittleEndian>()? as usize;
+        let flushed_indexes = (0..flushed_len)
+            .map(|_| FlushedIndex::read(&mut bytes))
+            .collect::<anyhow::Result<_>>()?;
+        ensure!(
+            bytes.is_empty(),
+            "Unexpected trailing bytes in deferred flush"
+        );
+
+        Ok(Self {
+            flushed_at,
+            grace_period_millis,
+            garbage,
+            flushed_indexes,
+        })
+    }
+}
+
+/// Returns information about the deferred flush of the specified namespace, or `None`
+/// if the namespace has no deferred flush with uncollected data.
+pub fn deferred_flush<T: RawAccess>(access: T, namespace: &str) -> Option<DeferredFlush> {
+    View::new(access, ResolvedAddress::system(DEFERRED_FLUSHES_NAME)).get(namespace)
+}
+
+/// Flushes the migration to the fork without removing the data of the replaced indexes.
+///
+/// Similar to [`flush_migration`], migrated indexes replace their old versions and indexes
+/// marked with tombstones are removed once the `fork` is merged. Unlike `flush_migration`,
+/// only index metadata is changed; the data of the replaced and removed indexes is left
+/// in the database and is recorded for removal by the [`GarbageCollector`] after
+/// the `grace_period` has passed. Until then, the flush may be reverted with [`undo_flush`].
+///
+/// The metadata swap and the garbage record are a part of the same fork, so the flush
+/// is atomic and safe against crashes.
+///
+/// # Safety
+///
+/// The same considerations as for `flush_migration` apply.
+///
+/// # Examples
+///
+/// ```
+/// # use matterdb::{access::{AccessExt, CopyAccessExt}, Database, TemporaryDB};
+/// # use matterdb::migration::{flush_migration_deferred, GarbageCollector, MigrationHelper};
+/// # use std::{sync::Arc, time::Duration};
+/// let db: Arc<dyn Database> = Arc::new(TemporaryDB::new());
+/// let fork = db.fork();
+/// fork.get_list("test.list").extend(vec![1_u32, 2, 3]);
+/// db.merge(fork.into_patch()).unwrap();
+///
+/// let helper = MigrationHelper::new(Arc::clone(&db), "test");
+/// helper.new_data().get_list("list").push(4_u64);
+/// helper.finish().unwrap();
+///
+/// let mut fork = db.fork();
+/// flush_migration_deferred(&mut fork, "test", Duration::from_secs(0));
+/// db.merge(fork.into_patch()).unwrap();
+/// assert_eq!(db.snapshot().get_list::<_, u64>("test.list").len(), 1);
+///
+/// // Old data may be removed in a background thread.
+/// let gc = GarbageCollector::new(Arc::clone(&db));
+/// assert_eq!(gc.collect().unwrap(), 3);
+/// ```
+///
+/// [`flush_migration`]: fn.flush_migration.html
+/// [`GarbageCollector`]: struct.GarbageCollector.html
+/// [`undo_flush`]: fn.undo_flush.html
+pub fn flush_migration_deferred(fork: &mut Fork, namespace: &str, grace_period: Duration) {
+    assert_valid_name_component(namespace);
+    // Flushing is necessary to keep the fork up to date, since the flush is performed
+    // via the pool rather than the `Fork` itself.
+    fork.flush();
+
+    let flushed_indexes: Vec<_> = {
+        let mut pool = IndexesPool::new(&*fork);
+        let migrated_indexes = pool.migrated_indexes(namespace);
+        pool.flush_migration(namespace);
+        migrated_indexes
+            .into_iter()
+            .map(|(address, new_metadata, old_metadata)| FlushedIndex {
+                address,
+                old_metadata,
+                new_metadata,
+            })
+            .collect()
+    };
+
+    let mut flushes = View::new(&*fork, ResolvedAddress::system(DEFERRED_FLUSHES_NAME));
+    let mut garbage = flushes
+        .get::<_, DeferredFlush>(namespace)
+        .map_or_else(Vec::new, |flush| flush.garbage);
+    garbage.extend(flushed_indexes.iter().filter_map(|index| {
+        let old_metadata = index.old_metadata.as_ref()?;
+        Some(ResolvedAddress::new(
+            index.address.name(),
+            Some(old_metadata.identifier()),
+        ))
+    }));
+    let flush = DeferredFlush {
+        flushed_at: Utc::now(),
+        grace_period_millis: u64::try_from(grace_period.as_millis()).unwrap_or(u64::MAX),
+        garbage,
+        flushed_indexes,
+    };
+    flushes.put(namespace, flush);
+    drop(flushes);
+
+    Scratchpad::new(namespace, &*fork).clear();
+    ChangeLog::new(&*fork, namespace).clear();
+    MigrationRegistry::new(&*fork).set_state(namespace, MigrationState::Flushed);
+}
+
+/// Reverts a migration flushed with [`flush_migration_deferred`].
+///
+/// The old indexes are restored, and the migrated indexes are moved back to the migration,
+/// so that the migration can be flushed or rolled back again.
+///
+/// # Errors
+///
+/// Returns an error if the namespace has no deferred flush, or if its grace period has expired.
+/// The flush should be undone well before the grace period expires, since
+/// the [`GarbageCollector`] may start removing data of the old indexes right after that.
+///
+/// [`flush_migration_deferred`]: fn.flush_migration_deferred.html
+/// [`GarbageCollector`]: struct.GarbageCollector.html
+pub fn undo_flush(fork: &mut Fork, namespace: &str) -> Result<()> {
+    fork.flush();
+    let mut flushes = View::new(&*fork, ResolvedAddress::system(DEFERRED_FLUSHES_NAME));
+    let mut flush = flushes
+        .get::<_, DeferredFlush>(namespace)
+        .ok_or_else(|| Error::new(format!("No deferred flush for namespace `{}`", namespace)))?;
+    if !flush.can_undo(Utc::now()) {
+        return Err(Error::new(format!(
+            "Flush of namespace `{}` cannot be undone: grace period has expired",
+            namespace
+        )));
+    }
+
+    let mut pool = IndexesPool::new(&*fork);
+    let mut restored_ids = HashSet::new();
+    for index in flush.flushed_indexes.drain(..) {
+        if let Some(ref metadata) = index.old_metadata {
+            restored_ids.insert(metadata.identifier());
+        }
+        pool.unflush_index(&index.address, index.old_metadata, index.new_metadata);
+    }
+    flush
+        .garbage
+        .retain(|address| !address.id.map_or(false, |id| restored_ids.contains(&id)));
+    if flush.garbage.is_empty() {
+        flushes.remove(namespace);
+    } else {
+        flushes.put(namespace, flush);
+    }
+    drop(flushes);
+    drop(pool);
+
+    MigrationRegistry::new(&*fork).set_state(namespace, MigrationState::AwaitingFlush);
+    Ok(())
+}
+
+/// Removes data of indexes replaced by deferred migration flushes.
+///
+/// The collector removes data of indexes for flushes with an expired grace period
+/// in chunks, merging changes to the database after each chunk. Thus, the collector does not
+/// create large write batches and can be run in a background thread concurrently
+/// with other I/O. If the process is interrupted, the collector continues from where
+/// it has stopped on the next run.
+///
+/// See [`flush_migration_deferred`] for an example of usage.
+///
+/// [`flush_migration_deferred`]: fn.flush_migration_deferred.html
+pub struct GarbageCollector {
+    db: Arc<dyn Database>,
+    chunk_size: usize,
+}
+
+impl fmt::Debug for GarbageCollector {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("GarbageCollector")
+            .field("chunk_size", &self.chunk_size)
+            .finish()
+    }
+}
+
+impl GarbageCollector {
+    /// Creates a new garbage collector.
+    pub fn new(db: impl Into<Arc<dyn Database>>) -> Self {
+        Self {
+            db: db.into(),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+
+    /// Sets the maximum number of entries removed in a single merge.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is zero.
+    pub fn set_chunk_size(&mut self, chunk_size: usize) {
+        assert!(chunk_size > 0, "Chunk size must be positive");
+        self.chunk_size = chunk_size;
+    }
+
+    /// Removes data of the indexes replaced by deferred flushes with an expired grace period.
+    ///
+    /// # Return value
+    ///
+    /// Returns the number of removed entries, or an error if a merge has failed.
+    pub fn collect(&self) -> Result<u64> {
+        let now = Utc::now();
+        let snapshot = self.db.snapshot();
+        let namespaces: Vec<String> =
+            View::new(&snapshot, ResolvedAddress::system(DEFERRED_FLUSHES_NAME))
+                .iter::<_, str, DeferredFlush>(&())
+                .filter(|(_, flush)| flush.is_expired(now))
+                .map(|(namespace, _)| namespace)
+                .collect();
+        drop(snapshot);
+
+        let mut removed = 0;
+        for namespace in namespaces {
+            while let Some(count) = self.collect_chunk(&namespace)? {
+                removed += count;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Removes a single chunk of data for the specified namespace. Returns `None` if there
+    /// is no more data to remove.
+    fn collect_chunk(&self, namespace: &str) -> Result<Option<u64>> {
+        let fork = self.db.fork();
+        let mut flushes = View::new(&fork, ResolvedAddress::system(DEFERRED_FLUSHES_NAME));
+        let mut flush = match flushes.get::<_, DeferredFlush>(namespace) {
+            // The namespace may have been flushed again since the collection has started.
+            Some(flush) if flush.is_expired(Utc::now()) => flush,
+            _ => return Ok(None),
+        };
+
+        let removed = if let Some(address) = flush.garbage.last().cloned() {
+            let mut view = View::new(&fork, address);
+            let keys: Vec<_> = view
+                .iter::<_, [u8], ()>(&())
+                .take(self.chunk_size)
+                .map(|(key, ())| key)
+                .collect();
+            for key in &keys {
+                view.remove(&key[..]);
+            }
+            if keys.len() < self.chunk_size {
+                flush.garbage.pop();
+            }
+            keys.len() as u64
+        } else {
+            0
+        };
+
+        if flush.garbage.is_empty() {
+            flushes.remove(namespace);
+        } else {
+            flushes.put(namespace, flush);
+        }
+        drop(flushes);
+        self.db.merge(fork.into_patch())?;
+        Ok(Some(removed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        access::{AccessExt, CopyAccessExt},
+        migration::{flush_migration, MigrationHelper},
+        TemporaryDB,
+    };
+
+    fn create_db() -> Arc<dyn Database> {
+        let db: Arc<dyn Database> = Arc::new(TemporaryDB::new());
+        let fork = db.fork();
+        fork.get_list("test.list").extend(0_u32..5);
+        fork.get_entry("test.entry").set(1_u32);
+        db.merge(fork.into_patch()).unwrap();
+
+        let helper = MigrationHelper::new(Arc::clone(&db), "test");
+        helper.new_data().get_list("list").push(10_u64);
+        helper.new_data().create_tombstone("entry");
+        helper.new_data().get_map("map").put(&1_u8, 1_u8);
+        helper.finish().unwrap();
+        db
+    }
+
+    fn flush(db: &dyn Database, grace_period: Duration) {
+        let mut fork = db.fork();
+        flush_migration_deferred(&mut fork, "test", grace_period);
+        db.merge(fork.into_patch()).unwrap();
+    }
+
+    fn check_migrated(db: &dyn Database) {
+        let snapshot = db.snapshot();
+        let list = snapshot.get_list::<_, u64>("test.list");
+        assert_eq!(list.iter().collect::<Vec<_>>(), vec![10]);
+        assert!(snapshot.index_type("test.entry").is_none());
+        assert_eq!(snapshot.get_map::<_, u8, u8>("test.map").get(&1), Some(1));
+    }
+
+    fn check_old(db: &dyn Database) {
+        let snapshot = db.snapshot();
+        let list = snapshot.get_list::<_, u32>("test.list");
+        assert_eq!(list.iter().collect::<Vec<_>>(), vec![0, 1, 2, 3, 4]);
+        assert_eq!(snapshot.get_entry::<_, u32>("test.entry").get(), Some(1));
+        assert!(snapshot.index_type("test.map").is_none());
+    }
+
+    fn count_entries(db: &dyn Database, address: ResolvedAddress) -> usize {
+        let snapshot = db.snapshot();
+        let view = View::new(&snapshot, address);
+        let count = view.iter::<_, [u8], ()>(&()).count();
+        count
+    }
+
+    #[test]
+    fn deferred_flush_binary_round_trip() {
+        let db = create_db();
+        flush(&*db, Duration::from_secs(1_000));
+        let flush = deferred_flush(&db.snapshot(), "test").unwrap();
+        assert_eq!(flush.garbage_len(), 2);
+        assert_eq!(flush.flushed_indexes.len(), 3);
+        assert_eq!(flush.grace_period(), Duration::from_secs(1_000));
+        let restored = DeferredFlush::from_bytes(flush.to_bytes().into()).unwrap();
+        assert_eq!(restored, flush);
+    }
+
+    #[test]
+    fn garbage_is_collected_in_chunks() {
+        let db = create_db();
+        flush(&*db, Duration::from_secs(0));
+        check_migrated(&*db);
+
+        let garbage = deferred_flush(&db.snapshot(), "test").unwrap().garbage;
+        let total: usize = garbage
+            .iter()
+            .map(|address| count_entries(&*db, address.clone()))
+            .sum();
+        assert_eq!(total, 6);
+
+        let mut gc = GarbageCollector::new(Arc::clone(&db));
+        gc.set_chunk_size(2);
+        assert_eq!(gc.collect().unwrap(), 6);
+        for address in garbage {
+            assert_eq!(count_entries(&*db, address), 0);
+        }
+        assert!(deferred_flush(&db.snapshot(), "test").is_none());
+        assert!(undo_flush(&mut db.fork(), "test").is_err());
+        check_migrated(&*db);
+
+        // Repeated collection is a no-op.
+        assert_eq!(gc.collect().unwrap(), 0);
+    }
+
+    #[test]
+    fn garbage_is_retained_during_grace_period() {
+        let db = create_db();
+        flush(&*db, Duration::from_secs(1_000));
+        let gc = GarbageCollector::new(Arc::clone(&db));
+        assert_eq!(gc.collect().unwrap(), 0);
+        let flush = deferred_flush(&db.snapshot(), "test").unwrap();
+        assert!(flush.can_undo(Utc::now()));
+        assert!(!flush.can_undo(Utc::now() + chrono::Duration::hours(1)));
+    }
+
+    #[test]
+    fn undoing_flush() {
+        let db = create_db();
+        flush(&*db, Duration::from_secs(1_000));
+        check_migrated(&*db);
+
+        let mut fork = db.fork();
+        undo_flush(&mut fork, "test").unwrap();
+        db.merge(fork.into_patch()).unwrap();
+        check_old(&*db);
+        assert!(deferred_flush(&db.snapshot(), "test").is_none());
+        let status = MigrationRegistry::new(&db.snapshot())
+            .status("test")
+            .unwrap();
+        assert_eq!(status.state(), MigrationState::AwaitingFlush);
+
+        // The migration can be flushed again.
+        let mut fork = db.fork();
+        flush_migration(&mut fork, "test");
+        db.merge(fork.into_patch()).unwrap();
+        check_migrated(&*db);
+    }
+}
@@ -0,0 +1,592 @@
+//! Parallel migration driver.
+
+use crossbeam::thread;
+
+use std::{
+    borrow::Borrow,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use super::{
+    persistent_iter::IteratorPosition, record_abort, AbortHandle, AbortMigration, Migration,
+    MigrationError, MigrationRegistry, MigrationState, Scratchpad,
+};
+use crate::{
+    access::{AccessExt, Prefixed},
+    validation::assert_valid_name_component,
+    views::key_bytes,
+    BinaryKey, BinaryValue, Database, Fork, ReadonlyFork,
+};
+
+/// Default number of worker threads.
+const DEFAULT_WORKERS: usize = 4;
+/// Default number of entries processed by a worker between merges.
+const DEFAULT_CHUNK_SIZE: usize = 1_000;
+
+/// Driver for migrating a `MapIndex` on several threads at once.
+///
+/// The key space of the source index is split into ranges by the caller-provided split keys.
+/// Each range (*partition*) is processed with its own cursor, which is stored in the migration
+/// [`Scratchpad`]; thus, like [`MigrationHelper::iter_loop`], the driver can be restarted
+/// after a process shutdown and will resume processing from the stored positions.
+///
+/// Workers take partitions from a shared queue. Each worker processes its partition in chunks;
+/// for each chunk, a separate fork is created and merged into the database after the chunk
+/// is processed. Since partitions do not overlap, forks from different workers do not
+/// overlap either, as long as the migration logic writes only to keys derived
+/// from the processed entries.
+///
+/// Progress of each partition is recorded in the [`MigrationRegistry`] as an iterator named
+/// `{source}_partition_{index}` in the same fork as the processed chunk. Once all partitions are processed,
+/// the migration is marked as awaiting flush.
+///
+/// # Creating indexes
+///
+/// Workers must not create new indexes, since concurrently created forks may assign
+/// the same identifiers to different indexes. All indexes written to by workers should be
+/// created before calling [`run`], e.g., with the help of [`MigrationHelper`].
+///
+/// # Examples
+///
+/// ```
+/// # use matterdb::{access::{AccessExt, CopyAccessExt}, Database, TemporaryDB};
+/// # use std::sync::Arc;
+/// # use matterdb::migration::{
+/// #     flush_migration, MigrationError, MigrationHelper, ParallelMigration,
+/// # };
+/// # fn main() -> Result<(), MigrationError> {
+/// let db = TemporaryDB::new();
+/// let fork = db.fork();
+/// for i in 0_u64..1_000 {
+///     fork.get_map("test.wallets").put(&i, i.to_string());
+/// }
+/// db.merge(fork.into_patch()).unwrap();
+///
+/// let db: Arc<dyn Database> = Arc::new(db);
+/// // Create indexes for the migrated data beforehand.
+/// let helper = MigrationHelper::new(Arc::clone(&db), "test");
+/// helper.new_data().get_map::<_, u64, u64>("wallets");
+/// helper.finish()?;
+///
+/// let mut migration = ParallelMigration::new(Arc::clone(&db), "test");
+/// migration.set_chunk_size(100);
+/// let split_keys = [250_u64, 500, 750];
+/// migration.run::<u64, String, _>("wallets", &split_keys, |worker, key, value| {
+///     let mut new_map = worker.new_data().get_map::<_, u64, u64>("wallets");
+///     new_map.put(&key, value.parse().unwrap());
+/// })?;
+///
+/// // Finalize the migration.
+/// let mut fork = db.fork();
+/// flush_migration(&mut fork, "test");
+/// db.merge(fork.into_patch()).unwrap();
+/// let snapshot = db.snapshot();
+/// let map = snapshot.get_map::<_, u64, u64>("test.wallets");
+/// assert_eq!(map.get(&42), Some(42));
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`Scratchpad`]: struct.Scratchpad.html
+/// [`MigrationRegistry`]: struct.MigrationRegistry.html
+/// [`MigrationHelper`]: struct.MigrationHelper.html
+/// [`MigrationHelper::iter_loop`]: struct.MigrationHelper.html#method.iter_loop
+/// [`run`]: #method.run
+pub struct ParallelMigration {
+    db: Arc<dyn Database>,
+    abort_handle: Box<dyn AbortMigration + Sync>,
+    namespace: String,
+    workers: usize,
+    chunk_size: usize,
+}
+
+impl fmt::Debug for ParallelMigration {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("ParallelMigration")
+            .field("namespace", &self.namespace)
+            .field("workers", &self.workers)
+            .field("chunk_size", &self.chunk_size)
+            .finish()
+    }
+}
+
+impl ParallelMigration {
+    /// Creates a new driver for the specified migration namespace.
+    pub fn new(db: impl Into<Arc<dyn Database>>, namespace: &str) -> Self {
+        assert_valid_name_component(namespace);
+
+        Self {
+            db: db.into(),
+            abort_handle: Box::new(()),
+            namespace: namespace.to_owned(),
+            workers: DEFAULT_WORKERS,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+
+    /// Creates a new driver together with the abort handle. Aborting the migration via the handle
+    /// stops all workers; changes not merged to the database by that moment are discarded.
+    pub fn with_handle(db: impl Into<Arc<dyn Database>>, namespace: &str) -> (Self, AbortHandle) {
+        let mut this = Self::new(db, namespace);
+        let abort_handle = AbortHandle {
+            inner: Arc::new(AtomicBool::default()),
+        };
+        this.abort_handle = Box::new(abort_handle.clone_inner());
+        (this, abort_handle)
+    }
+
+    /// Sets the number of worker threads.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `workers` is zero.
+    pub fn set_workers(&mut self, workers: usize) {
+        assert!(workers > 0, "Number of workers must be positive");
+        self.workers = workers;
+    }
+
+    /// Sets the number of entries processed by a worker between merges to the database.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is zero.
+    pub fn set_chunk_size(&mut self, chunk_size: usize) {
+        assert!(chunk_size > 0, "Chunk size must be positive");
+        self.chunk_size = chunk_size;
+    }
+
+    /// Migrates entries of the `MapIndex` with the specified name in the old data, calling
+    /// `step` for each entry. `split_keys` define boundaries of partitions; `n` split keys
+    /// produce `n + 1` partitions. Split keys must be sorted and must be the same
+    /// if the migration is restarted.
+    ///
+    /// Returns an error if any of the merges fails or the migration is aborted. In this case,
+    /// other workers are stopped as well. If all partitions are processed successfully,
+    /// the migration state in the registry is set to `AwaitingFlush`.
+    pub fn run<K, V, F>(
+        &self,
+        source: &str,
+        split_keys: &[K::Owned],
+        step: F,
+    ) -> Result<(), MigrationError>
+    where
+        K: BinaryKey + ?Sized,
+        V: BinaryValue,
+        F: Fn(&MigrationWorker<'_>, K::Owned, V) + Sync,
+    {
+        let split_keys: Vec<_> = split_keys
+            .iter()
+            .map(|key| key_bytes::<K>(key.borrow()))
+            .collect();
+        assert!(
+            split_keys.windows(2).all(|pair| pair[0] < pair[1]),
+            "Split keys must be sorted"
+        );
+        let partitions = split_keys.len() + 1;
+        self.init_cursors::<K, V>(source, &split_keys)?;
+
+        let next_partition = AtomicUsize::new(0);
+        let stopped = AtomicBool::new(false);
+        let error = Mutex::new(None);
+        let registry_lock = Mutex::new(());
+
+        thread::scope(|scope| {
+            for _ in 0..self.workers.min(partitions) {
+                scope.spawn(|_| loop {
+                    let partition = next_partition.fetch_add(1, Ordering::SeqCst);
+                    if partition >= partitions || stopped.load(Ordering::SeqCst) {
+                        break;
+                    }
+
+                    let end = split_keys.get(partition).map(Vec::as_slice);
+                    let res = self.run_partition::<K, V, F>(
+                        source,
+                        partition,
+                        end,
+                        &stopped,
+                        &registry_lock,
+                        &step,
+                    );
+                    if let Err(e) = res {
+                        stopped.store(true, Ordering::SeqCst);
+                        error.lock().unwrap().get_or_insert(e);
+                        break;
+                    }
+                });
+            }
+        })
+        .unwrap_or_else(|_| panic!("MerkleDB error: migration worker panicked"));
+
+        match error.into_inner().unwrap() {
+            Some(MigrationError::Aborted) => {
+                record_abort(&*self.db, &self.namespace);
+                Err(MigrationError::Aborted)
+            }
+            Some(e) => Err(e),
+            None => {
+                let fork = self.db.fork();
+                MigrationRegistry::new(&fork)
+                    .set_state(&self.namespace, MigrationState::AwaitingFlush);
+                self.db
+                    .merge(fork.into_patch())
+                    .map_err(MigrationError::Merge)
+            }
+        }
+    }
+
+    fn cursor_name(source: &str, partition: usize) -> String {
+        format!("{}_partition_{}", source, partition)
+    }
+
+    fn is_aborted(&self) -> bool {
+        self.abort_handle.is_aborted()
+    }
+
+    /// Records progress of a partition in the fork of the processed chunk, so that the progress
+    /// is merged together with the chunk. Workers update the same migration status, so
+    /// the status is read from the latest database snapshot rather than from the fork,
+    /// and the caller must hold `registry_lock` until the fork is merged; otherwise, concurrent
+    /// updates could overwrite each other.
+    fn record_progress(
+        &self,
+        fork: &Fork,
+        cursor_name: &str,
+        processed: u64,
+        position: IteratorPosition<[u8]>,
+    ) {
+        let snapshot = self.db.snapshot();
+        if let Some(mut status) = MigrationRegistry::new(&snapshot).status(&self.namespace) {
+            status
+                .iterator_mut(cursor_name)
+                .record_step(processed, None, Some(position));
+            MigrationRegistry::new(fork).put(&self.namespace, status);
+        }
+    }
+
+    /// Creates cursors for all partitions in a single fork. Cursors cannot be created
+    /// by workers, since creating indexes in concurrent forks may lead to identifier conflicts.
+    fn init_cursors<K, V>(&self, source: &str, split_keys: &[Vec<u8>]) -> Result<(), MigrationError>
+    where
+        K: BinaryKey + ?Sized,
+        V: BinaryValue,
+    {
+        let fork = self.db.fork();
+        MigrationRegistry::new(&fork).start(&self.namespace);
+        let scratchpad = Scratchpad::new(&self.namespace, &fork);
+        for partition in 0..=split_keys.len() {
+            let mut cursor = scratchpad
+                .clone()
+                .get_entry::<_, IteratorPosition<K>>(Self::cursor_name(source, partition));
+            if cursor.exists() {
+                continue;
+            }
+
+            let position = if partition == 0 {
+                let old_data = Prefixed::new(&self.namespace, fork.readonly());
+                let map = old_data.get_map::<_, K, V>(source);
+                let first_key = map.keys().next();
+                first_key.map_or(IteratorPosition::Ended, IteratorPosition::NextKey)
+            } else {
+                IteratorPosition::NextKey(K::read(&split_keys[partition - 1]))
+            };
+            cursor.set(position);
+        }
+
+        if self.is_aborted() {
+            record_abort(&*self.db, &self.namespace);
+            return Err(MigrationError::Aborted);
+        }
+        self.db
+            .merge(fork.into_patch())
+            .map_err(MigrationError::Merge)
+    }
+
+    fn run_partition<K, V, F>(
+        &self,
+        source: &str,
+        partition: usize,
+        end: Option<&[u8]>,
+        stopped: &AtomicBool,
+        registry_lock: &Mutex<()>,
+        step: &F,
+    ) -> Result<(), MigrationError>
+    where
+        K: BinaryKey + ?Sized,
+        V: BinaryValue,
+        F: Fn(&MigrationWorker<'_>, K::Owned, V),
+    {
+        let cursor_name = Self::cursor_name(source, partition);
+        loop {
+            if self.is_aborted() {
+                return Err(MigrationError::Aborted);
+            }
+            if stopped.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+
+            let fork = self.db.fork();
+            let worker = MigrationWorker {
+                fork: &fork,
+                namespace: &self.namespace,
+                partition,
+            };
+
+            let (processed, position) = {
+                let mut cursor = worker
+                    .scratchpad()
+                    .get_entry::<_, IteratorPosition<K>>(cursor_name.as_str());
+                let start = match cursor.get() {
+                    Some(IteratorPosition::NextKey(key)) => key,
+                    _ => return Ok(()),
+                };
+
+                let map = worker.old_data().get_map::<_, K, V>(source);
+                let is_in_range = |key: &K::Owned| {
+                    end.map_or(true, |end| key_bytes::<K>(key.borrow()).as_slice() < end)
+                };
+                let mut iter = map
+                    .iter_from(start.borrow())
+                    .take_while(|(key, _)| is_in_range(key));
+
+                let mut processed = 0;
+                for (key, value) in iter.by_ref().take(self.chunk_size) {
+                    step(&worker, key, value);
+                    processed += 1;
+                }
+                let (position, raw_position) = match iter.next() {
+                    Some((key, _)) => {
+                        let raw_key = key_bytes::<K>(key.borrow());
+                        (
+                            IteratorPosition::NextKey(key),
+                            IteratorPosition::NextKey(raw_key),
+                        )
+                    }
+                    None => (IteratorPosition::Ended, IteratorPosition::Ended),
+                };
+                cursor.set(position);
+                (processed, raw_position)
+            };
+            let ended = matches!(position, IteratorPosition::Ended);
+
+            if self.is_aborted() {
+                return Err(MigrationError::Aborted);
+            }
+            {
+                let _guard = registry_lock.lock().unwrap();
+                self.record_progress(&fork, &cursor_name, processed, position);
+                self.db
+                    .merge(fork.into_patch())
+                    .map_err(MigrationError::Merge)?;
+            }
+            if ended {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Access to migration data for a single worker of a [`ParallelMigration`].
+///
+/// [`ParallelMigration`]: struct.ParallelMigration.html
+pub struct MigrationWorker<'a> {
+    fork: &'a Fork,
+    namespace: &'a str,
+    partition: usize,
+}
+
+impl fmt::Debug for MigrationWorker<'_> {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("MigrationWorker")
+            .field("namespace", &self.namespace)
+            .field("partition", &self.partition)
+            .finish()
+    }
+}
+
+impl<'a> MigrationWorker<'a> {
+    /// Returns the zero-based index of the partition processed by the worker.
+    pub fn partition(&self) -> usize {
+        self.partition
+    }
+
+    /// Returns full access to the new version of migrated data.
+    pub fn new_data(&self) -> Migration<&'a Fork> {
+        Migration::new(self.namespace, self.fork)
+    }
+
+    /// Returns the scratchpad for temporary data to use during migration.
+    pub fn scratchpad(&self) -> Scratchpad<&'a Fork> {
+        Scratchpad::new(self.namespace, self.fork)
+    }
+
+    /// Returns readonly access to the old version of migrated data.
+    pub fn old_data(&self) -> Prefixed<ReadonlyFork<'a>> {
+        Prefixed::new(self.namespace, self.fork.readonly())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        access::CopyAccessExt,
+        migration::{IteratorProgress, MigrationHelper},
+        TemporaryDB,
+    };
+
+    use std::collections::HashSet;
+
+    const ENTRIES: u64 = 1_000;
+
+    fn create_db() -> Arc<dyn Database> {
+        let db = TemporaryDB::new();
+        let fork = db.fork();
+        {
+            let mut map = fork.get_map("test.map");
+            for i in 0..ENTRIES {
+                map.put(&i, i.to_string());
+            }
+        }
+        db.merge(fork.into_patch()).unwrap();
+
+        let db: Arc<dyn Database> = Arc::new(db);
+        let helper = MigrationHelper::new(Arc::clone(&db), "test");
+        helper.new_data().get_map::<_, u64, u64>("map");
+        helper.finish().unwrap();
+        db
+    }
+
+    fn migrate(worker: &MigrationWorker<'_>, key: u64, value: &str) {
+        let mut map = worker.new_data().get_map::<_, u64, u64>("map");
+        map.put(&key, value.parse::<u64>().unwrap() * 2);
+    }
+
+    #[test]
+    fn parallel_migration_processes_all_entries() {
+        let db = create_db();
+        let mut migration = ParallelMigration::new(Arc::clone(&db), "test");
+        migration.set_chunk_size(64);
+        migration.set_workers(3);
+
+        let partitions = Mutex::new(HashSet::new());
+        migration
+            .run::<u64, String, _>("map", &[100, 400, 401, 900, 5_000], |worker, key, value| {
+                partitions.lock().unwrap().insert(worker.partition());
+                migrate(worker, key, &value);
+            })
+            .unwrap();
+        // The last partition is empty.
+        assert_eq!(partitions.into_inner().unwrap().len(), 5);
+
+        let snapshot = db.snapshot();
+        let migration = Migration::new("test", &snapshot);
+        let map = migration.get_map::<_, u64, u64>("map");
+        assert_eq!(map.iter().count() as u64, ENTRIES);
+        assert!(map.iter().all(|(key, value)| value == key * 2));
+
+        let scratchpad = Scratchpad::new("test", &snapshot);
+        for partition in 0..6 {
+            let name = ParallelMigration::cursor_name("map", partition);
+            let position = scratchpad.get_entry::<_, IteratorPosition<u64>>(name).get();
+            assert_eq!(position, Some(IteratorPosition::Ended));
+        }
+    }
+
+    #[test]
+    fn parallel_migration_records_progress() {
+        let db = create_db();
+        let mut migration = ParallelMigration::new(Arc::clone(&db), "test");
+        migration.set_chunk_size(50);
+        migration.set_workers(2);
+
+        let processed = AtomicUsize::new(0);
+        let checked = AtomicBool::new(false);
+        migration
+            .run::<u64, String, _>("map", &[500], |worker, key, value| {
+                // Check the registry after several chunks are merged by each worker.
+                if processed.fetch_add(1, Ordering::SeqCst) == 800 {
+                    let snapshot = db.snapshot();
+                    let status = MigrationRegistry::new(&snapshot).status("test").unwrap();
+                    assert_eq!(status.state(), MigrationState::Running);
+                    assert!(status.processed() > 0 && status.processed() < ENTRIES);
+                    assert_eq!(status.processed() % 50, 0);
+                    assert!(status.iterators().iter().any(|iter| !iter.is_ended()));
+                    checked.store(true, Ordering::SeqCst);
+                }
+                migrate(worker, key, &value);
+            })
+            .unwrap();
+        assert!(checked.into_inner());
+
+        let snapshot = db.snapshot();
+        let status = MigrationRegistry::new(&snapshot).status("test").unwrap();
+        assert_eq!(status.state(), MigrationState::AwaitingFlush);
+        assert_eq!(status.processed(), ENTRIES);
+        let names: Vec<_> = status
+            .iterators()
+            .iter()
+            .map(IteratorProgress::name)
+            .collect();
+        assert_eq!(names, vec!["map_partition_0", "map_partition_1"]);
+        for iter in status.iterators() {
+            assert_eq!(iter.processed(), ENTRIES / 2);
+            assert!(iter.is_ended());
+        }
+    }
+
+    #[test]
+    fn parallel_migration_with_empty_source() {
+        let db: Arc<dyn Database> = Arc::new(TemporaryDB::new());
+        let migration = ParallelMigration::new(Arc::clone(&db), "test");
+        migration
+            .run::<u64, String, _>("map", &[10], |_, _, _| unreachable!())
+            .unwrap();
+    }
+
+    #[test]
+    fn parallel_migration_resumes_after_abort() {
+        let db = create_db();
+        let (mut migration, handle) = ParallelMigration::with_handle(Arc::clone(&db), "test");
+        migration.set_chunk_size(10);
+
+        let processed = AtomicUsize::new(0);
+        let handle = Mutex::new(Some(handle));
+        let res = migration.run::<u64, String, _>("map", &[500], |worker, key, value| {
+            if processed.fetch_add(1, Ordering::SeqCst) == 100 {
+                // Dropping the handle aborts the migration.
+                handle.lock().unwrap().take();
+            }
+            migrate(worker, key, &value);
+        });
+        assert!(matches!(res, Err(MigrationError::Aborted)));
+
+        let snapshot = db.snapshot();
+        let migrated = Migration::new("test", &snapshot)
+            .get_map::<_, u64, u64>("map")
+            .keys()
+            .count();
+        assert!(migrated > 0 && (migrated as u64) < ENTRIES);
+
+        // Restart the migration; entries should not be processed twice.
+        let mut migration = ParallelMigration::new(Arc::clone(&db), "test");
+        migration.set_chunk_size(10);
+        let processed = AtomicUsize::new(0);
+        migration
+            .run::<u64, String, _>("map", &[500], |worker, key, value| {
+                processed.fetch_add(1, Ordering::SeqCst);
+                migrate(worker, key, &value);
+            })
+            .unwrap();
+        assert_eq!(processed.into_inner(), ENTRIES as usize - migrated);
+
+        let snapshot = db.snapshot();
+        let map = Migration::new("test", &snapshot).get_map::<_, u64, u64>("map");
+        assert_eq!(map.keys().count() as u64, ENTRIES);
+    }
+}
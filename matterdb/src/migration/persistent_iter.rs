@@ -4,11 +4,14 @@ use anyhow::{bail, ensure};
 
 use std::{
     borrow::{Borrow, Cow},
-    collections::HashSet,
+    cell::Cell,
+    collections::HashMap,
     fmt,
     iter::Peekable,
+    rc::Rc,
 };
 
+use super::registry::MigrationStatus;
 use crate::{
     access::{Access, AccessExt, RawAccess, RawAccessMut},
     indexes::{Entries, IndexIterator},
@@ -17,7 +20,7 @@ use crate::{
 
 /// Persistent iterator position.
 #[derive(PartialEq)]
-enum IteratorPosition<K: BinaryKey + ?Sized> {
+pub(super) enum IteratorPosition<K: BinaryKey + ?Sized> {
     /// There is a next key to start iteration from.
     NextKey(K::Owned),
     /// The iterator has ended.
@@ -122,6 +125,8 @@ where
 /// [`MigrationHelper`]: struct.MigrationHelper.html
 pub struct PersistentIter<'a, T: RawAccess, I: IndexIterator> {
     inner: Inner<'a, T, I>,
+    /// Number of items yielded by the iterator.
+    processed: Rc<Cell<u64>>,
 }
 
 impl<T, I> fmt::Debug for PersistentIter<'_, T, I>
@@ -186,6 +191,7 @@ where
             Some(IteratorPosition::Ended) => {
                 return Self {
                     inner: Inner::Ended,
+                    processed: Rc::default(),
                 };
             }
         };
@@ -197,6 +203,7 @@ where
                     .peekable(),
                 position_entry,
             },
+            processed: Rc::default(),
         }
     }
 
@@ -221,6 +228,7 @@ where
         {
             let next = iter.next();
             if next.is_some() {
+                self.processed.set(self.processed.get() + 1);
                 position_entry.set(if let Some((key, _)) = iter.peek() {
                     // Slightly clumsy way to clone the key.
                     IteratorPosition::NextKey(key.borrow().to_owned())
@@ -288,11 +296,18 @@ where
     }
 }
 
+/// Statistics for an iterator created by `PersistentIters`.
+#[derive(Debug, Default)]
+struct IterStats {
+    processed: Rc<Cell<u64>>,
+    total: Option<u64>,
+}
+
 /// Factory for persistent iterators.
 #[derive(Debug)]
 pub struct PersistentIters<T> {
     access: T,
-    names: HashSet<String>,
+    iterators: HashMap<String, IterStats>,
 }
 
 impl<T> PersistentIters<T>
@@ -304,7 +319,7 @@ where
     pub fn new(access: T) -> Self {
         Self {
             access,
-            names: HashSet::new(),
+            iterators: HashMap::new(),
         }
     }
 
@@ -314,16 +329,19 @@ where
         name: &str,
         index: &'a I,
     ) -> PersistentIter<'a, T::Base, I> {
-        self.names.insert(name.to_owned());
-        PersistentIter::new(&self.access, name, index)
+        let stats = self.iterators.entry(name.to_owned()).or_default();
+        stats.total = index.estimated_len();
+        let mut iter = PersistentIter::new(&self.access, name, index);
+        iter.processed = Rc::clone(&stats.processed);
+        iter
     }
 
     /// Checks if all iterators instantiated via this instance have ended.
     ///
     /// This method will panic if any of iterators are borrowed and thus should only be called
     /// when this is a priori not the case.
-    pub(super) fn all_ended(&self) -> bool {
-        for name in &self.names {
+    pub(crate) fn all_ended(&self) -> bool {
+        for name in self.iterators.keys() {
             let pos = self
                 .access
                 .clone()
@@ -335,6 +353,31 @@ where
         }
         true
     }
+
+    /// Returns the total number of items yielded by iterators instantiated via this instance.
+    pub(crate) fn processed(&self) -> u64 {
+        self.iterators
+            .values()
+            .map(|stats| stats.processed.get())
+            .sum()
+    }
+
+    /// Records progress of iterators instantiated via this instance in the migration status.
+    ///
+    /// This method will panic if any of iterators are borrowed and thus should only be called
+    /// when this is a priori not the case.
+    pub(super) fn record_progress(&self, status: &mut MigrationStatus) {
+        for (name, stats) in &self.iterators {
+            let position = self
+                .access
+                .clone()
+                .get_entry::<_, IteratorPosition<[u8]>>(name.as_str())
+                .get();
+            status
+                .iterator_mut(name)
+                .record_step(stats.processed.get(), stats.total, position);
+        }
+    }
 }
 
 #[cfg(test)]
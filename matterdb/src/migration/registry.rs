@@ -0,0 +1,593 @@
+//! Persistent registry of migrations.
+
+use anyhow::{bail, ensure, format_err};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use chrono::{DateTime, Utc};
+
+use std::{borrow::Cow, convert::TryFrom};
+
+use super::persistent_iter::IteratorPosition;
+use crate::{
+    access::RawAccess,
+    values::{read_bytes, write_bytes},
+    views::{RawAccessMut, View},
+    BinaryValue, ResolvedAddress,
+};
+
+/// Name of the column family used to store the migration registry.
+const REGISTRY_NAME: &str = "__MIGRATIONS__";
+/// Name of the column family listing namespaces of migrations which log live changes.
+/// The list allows to check cheaply whether any changes need to be logged.
+pub const LOGGING_NAME: &str = "__MIGRATIONS_LOGGING__";
+
+/// State of a migration recorded in the [`MigrationRegistry`].
+///
+/// [`MigrationRegistry`]: struct.MigrationRegistry.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MigrationState {
+    /// The migration is being performed.
+    Running = 0,
+    /// The migration was aborted via an `AbortHandle`. It may be resumed later.
+    Aborted = 1,
+    /// All migration changes are merged to the database; the migration awaits
+    /// to be flushed or rolled back.
+    AwaitingFlush = 2,
+    /// The migration was flushed.
+    Flushed = 3,
+    /// The migration was rolled back.
+    RolledBack = 4,
+}
+
+impl MigrationState {
+    /// Returns `true` if the migration has been completed, either by flushing it or by rolling
+    /// it back.
+    pub fn is_completed(self) -> bool {
+        matches!(self, Self::Flushed | Self::RolledBack)
+    }
+}
+
+impl TryFrom<u8> for MigrationState {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        let value = match value {
+            0 => Self::Running,
+            1 => Self::Aborted,
+            2 => Self::AwaitingFlush,
+            3 => Self::Flushed,
+            4 => Self::RolledBack,
+            _ => return Err("Unknown migration state"),
+        };
+        Ok(value)
+    }
+}
+
+/// Progress of a persistent iterator used in a migration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IteratorProgress {
+    name: String,
+    processed: u64,
+    total: Option<u64>,
+    position: IteratorPositionRecord,
+}
+
+/// Raw position of a persistent iterator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum IteratorPositionRecord {
+    NotStarted,
+    NextKey(Vec<u8>),
+    Ended,
+}
+
+impl IteratorProgress {
+    pub(super) fn new(name: String) -> Self {
+        Self {
+            name,
+            processed: 0,
+            total: None,
+            position: IteratorPositionRecord::NotStarted,
+        }
+    }
+
+    /// Returns the name of the iterator.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the number of items processed by the iterator.
+    pub fn processed(&self) -> u64 {
+        self.processed
+    }
+
+    /// Returns the number of items in the iterated index, if it is known.
+    pub fn total(&self) -> Option<u64> {
+        self.total
+    }
+
+    /// Returns the raw key the iterator will continue from, or `None` if the iterator
+    /// has not started or has ended.
+    pub fn next_key(&self) -> Option<&[u8]> {
+        match self.position {
+            IteratorPositionRecord::NextKey(ref key) => Some(key),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if the iterator has ended.
+    pub fn is_ended(&self) -> bool {
+        self.position == IteratorPositionRecord::Ended
+    }
+
+    pub(super) fn record_step(
+        &mut self,
+        processed: u64,
+        total: Option<u64>,
+        position: Option<IteratorPosition<[u8]>>,
+    ) {
+        self.processed += processed;
+        if total.is_some() {
+            self.total = total;
+        }
+        match position {
+            None => {}
+            Some(IteratorPosition::NextKey(key)) => {
+                self.position = IteratorPositionRecord::NextKey(key);
+            }
+            Some(IteratorPosition::Ended) => self.position = IteratorPositionRecord::Ended,
+        }
+    }
+
+    fn write(&self, buffer: &mut Vec<u8>) {
+        write_bytes(buffer, self.name.as_bytes());
+        buffer.write_u64::<LittleEndian>(self.processed).unwrap();
+        match self.total {
+            Some(total) => {
+                buffer.push(1);
+                buffer.write_u64::<LittleEndian>(total).unwrap();
+            }
+            None => buffer.push(0),
+        }
+        match self.position {
+            IteratorPositionRecord::NotStarted => buffer.push(0),
+            IteratorPositionRecord::NextKey(ref key) => {
+                buffer.push(1);
+                write_bytes(buffer, key);
+            }
+            IteratorPositionRecord::Ended => buffer.push(2),
+        }
+    }
+
+    fn read(bytes: &mut &[u8]) -> anyhow::Result<Self> {
+        let name = String::from_utf8(read_bytes(bytes)?)?;
+        let processed = bytes.read_u64::<LittleEndian>()?;
+        let total = match bytes.read_u8()? {
+            0 => None,
+            1 => Some(bytes.read_u64::<LittleEndian>()?),
+            _ => bail!("Invalid tag for iterator total"),
+        };
+        let position = match bytes.read_u8()? {
+            0 => IteratorPositionRecord::NotStarted,
+            1 => IteratorPositionRecord::NextKey(read_bytes(bytes)?),
+            2 => IteratorPositionRecord::Ended,
+            _ => bail!("Invalid tag for iterator position"),
+        };
+        Ok(Self {
+            name,
+            processed,
+            total,
+            position,
+        })
+    }
+}
+
+/// Status of a migration recorded in the [`MigrationRegistry`].
+///
+/// [`MigrationRegistry`]: struct.MigrationRegistry.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationStatus {
+    namespace: String,
+    started_at: DateTime<Utc>,
+    state: MigrationState,
+    iterators: Vec<IteratorProgress>,
+    logs_live_changes: bool,
+}
+
+impl MigrationStatus {
+    pub(super) fn new(namespace: &str) -> Self {
+        Self {
+            namespace: namespace.to_owned(),
+            started_at: Utc::now(),
+            state: MigrationState::Running,
+            iterators: Vec::new(),
+            logs_live_changes: false,
+        }
+    }
+
+    /// Returns the namespace of the migration.
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    /// Returns the time when the migration was started.
+    pub fn started_at(&self) -> DateTime<Utc> {
+        self.started_at
+    }
+
+    /// Returns the current state of the migration.
+    pub fn state(&self) -> MigrationState {
+        self.state
+    }
+
+    /// Returns `true` if changes to the old data are recorded for the migration.
+    ///
+    /// Recording is enabled with [`MigrationHelper::track_live_changes`] and is stopped
+    /// once the migration leaves the running state (i.e., is aborted, finished, flushed
+    /// or rolled back).
+    ///
+    /// [`MigrationHelper::track_live_changes`]: struct.MigrationHelper.html#method.track_live_changes
+    pub fn logs_live_changes(&self) -> bool {
+        self.logs_live_changes
+    }
+
+    /// Returns progress of persistent iterators used in the migration, ordered by name.
+    pub fn iterators(&self) -> &[IteratorProgress] {
+        &self.iterators
+    }
+
+    /// Returns the total number of items processed by the migration iterators.
+    pub fn processed(&self) -> u64 {
+        self.iterators.iter().map(IteratorProgress::processed).sum()
+    }
+
+    /// Returns the estimated total number of items to be processed by the migration iterators,
+    /// based on the sizes of iterated indexes. Returns `None` if the size of neither
+    /// of iterated indexes is known.
+    ///
+    /// The estimate counts all items of indexes of a known size and the already processed
+    /// items for other indexes.
+    pub fn estimated_total(&self) -> Option<u64> {
+        if self.iterators.iter().all(|iter| iter.total.is_none()) {
+            return None;
+        }
+        let total = self
+            .iterators
+            .iter()
+            .map(|iter| {
+                iter.total
+                    .map_or(iter.processed, |total| total.max(iter.processed))
+            })
+            .sum();
+        Some(total)
+    }
+
+    pub(super) fn iterator_mut(&mut self, name: &str) -> &mut IteratorProgress {
+        let idx = match self
+            .iterators
+            .binary_search_by(|iter| iter.name.as_str().cmp(name))
+        {
+            Ok(idx) => idx,
+            Err(idx) => {
+                self.iterators
+                    .insert(idx, IteratorProgress::new(name.to_owned()));
+                idx
+            }
+        };
+        &mut self.iterators[idx]
+    }
+}
+
+impl BinaryValue for MigrationStatus {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        write_bytes(&mut buffer, self.namespace.as_bytes());
+        write_bytes(&mut buffer, &self.started_at.to_bytes());
+        buffer.push(self.state as u8);
+        let iterators_len =
+            u32::try_from(self.iterators.len()).expect("MerkleDB error: too many iterators");
+        buffer.write_u32::<LittleEndian>(iterators_len).unwrap();
+        for iter in &self.iterators {
+            iter.write(&mut buffer);
+        }
+        buffer.push(u8::from(self.logs_live_changes));
+        buffer
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> anyhow::Result<Self> {
+        let mut bytes = bytes.as_ref();
+        let namespace = String::from_utf8(read_bytes(&mut bytes)?)?;
+        let started_at = DateTime::<Utc>::from_bytes(read_bytes(&mut bytes)?.into())?;
+        let state = bytes.read_u8()?;
+        let state = MigrationState::try_from(state)
+            .map_err(|_| format_err!("Unknown migration state: {}", state))?;
+
+        let iterators_len = bytes.read_u32::<LittleEndian>()? as usize;
+        let iterators = (0..iterators_len)
+            .map(|_| IteratorProgress::read(&mut bytes))
+            .collect::<anyhow::Result<_>>()?;
+        let logs_live_changes = match bytes.read_u8()? {
+            0 => false,
+            1 => true,
+            tag => bail!("Invalid flag for logging live changes: {}", tag),
+        };
+        ensure!(
+            bytes.is_empty(),
+            "Unexpected trailing bytes in migration status"
+        );
+
+        Ok(Self {
+            namespace,
+            started_at,
+            state,
+            iterators,
+            logs_live_changes,
+        })
+    }
+}
+
+/// Persistent registry of migrations performed in the database.
+///
+/// The registry is updated by [`MigrationHelper`] and by [`flush_migration`] /
+/// [`rollback_migration`]; it records the state and the progress of each migration namespace.
+/// Since the registry is stored in the database, it can be read from any snapshot, e.g.,
+/// to monitor a migration performed in a background thread.
+///
+/// # Examples
+///
+/// ```
+/// # use matterdb::{access::AccessExt, Database, TemporaryDB};
+/// # use matterdb::migration::{MigrationHelper, MigrationRegistry, MigrationState};
+/// # use std::sync::Arc;
+/// let db: Arc<dyn Database> = Arc::new(TemporaryDB::new());
+/// let helper = MigrationHelper::new(Arc::clone(&db), "test");
+/// helper.new_data().get_entry("entry").set(1_u32);
+/// helper.finish().unwrap();
+///
+/// let snapshot = db.snapshot();
+/// let registry = MigrationRegistry::new(&snapshot);
+/// let status = registry.status("test").unwrap();
+/// assert_eq!(status.state(), MigrationState::AwaitingFlush);
+/// ```
+///
+/// [`MigrationHelper`]: struct.MigrationHelper.html
+/// [`flush_migration`]: fn.flush_migration.html
+/// [`rollback_migration`]: fn.rollback_migration.html
+#[derive(Debug)]
+pub struct MigrationRegistry<T: RawAccess> {
+    view: View<T>,
+    logging: View<T>,
+}
+
+impl<T: RawAccess> MigrationRegistry<T> {
+    /// Creates a registry based on the specified access.
+    pub fn new(access: T) -> Self {
+        Self {
+            view: View::new(access.clone(), ResolvedAddress::system(REGISTRY_NAME)),
+            logging: View::new(access, ResolvedAddress::system(LOGGING_NAME)),
+        }
+    }
+
+    /// Returns the status of a migration in the specified namespace.
+    pub fn status(&self, namespace: &str) -> Option<MigrationStatus> {
+        self.view.get(namespace)
+    }
+
+    /// Returns statuses of all recorded migrations, ordered by namespace.
+    pub fn statuses(&self) -> impl Iterator<Item = MigrationStatus> + '_ {
+        self.view
+            .iter::<_, str, MigrationStatus>(&())
+            .map(|(_, status)| status)
+    }
+
+    /// Checks whether any migration logs live changes.
+    pub(super) fn has_logging_migrations(&self) -> bool {
+        self.logging.iter::<_, str, ()>(&()).next().is_some()
+    }
+
+    /// Returns namespaces of migrations which log live changes.
+    pub(super) fn logging_namespaces(&self) -> Vec<String> {
+        self.logging
+            .iter::<_, str, ()>(&())
+            .map(|(namespace, ())| namespace)
+            .collect()
+    }
+}
+
+impl<T: RawAccessMut> MigrationRegistry<T> {
+    /// Records the start of the migration. If the migration is already recorded and is not
+    /// completed, it is marked as running and its progress is retained.
+    pub(super) fn start(&mut self, namespace: &str) {
+        let status = match self.status(namespace) {
+            Some(mut status) if !status.state.is_completed() => {
+                status.state = MigrationState::Running;
+                status
+            }
+            _ => MigrationStatus::new(namespace),
+        };
+        self.put(namespace, status);
+    }
+
+    /// Updates the state of an existing migration. Logging of live changes is stopped
+    /// unless the migration is running.
+    pub(super) fn set_state(&mut self, namespace: &str, state: MigrationState) {
+        if let Some(mut status) = self.status(namespace) {
+            status.state = state;
+            status.logs_live_changes &= state == MigrationState::Running;
+            self.put(namespace, status);
+        }
+    }
+
+    /// Enables logging of live changes for an existing running migration.
+    pub(super) fn enable_live_changes(&mut self, namespace: &str) {
+        if let Some(mut status) = self.status(namespace) {
+            status.logs_live_changes = status.state == MigrationState::Running;
+            self.put(namespace, status);
+        }
+    }
+
+    /// Stores the migration status and updates the list of migrations logging live changes.
+    pub(super) fn put(&mut self, namespace: &str, status: MigrationStatus) {
+        if status.logs_live_changes {
+            self.logging.put(namespace, ());
+        } else if self.logging.contains(namespace) {
+            self.logging.remove(namespace);
+        }
+        self.view.put(namespace, status);
+    }
+
+    /// Updates an existing migration.
+    pub(super) fn update(&mut self, namespace: &str, update: impl FnOnce(&mut MigrationStatus)) {
+        if let Some(mut status) = self.status(namespace) {
+            update(&mut status);
+            self.put(namespace, status);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        access::{AccessExt, CopyAccessExt},
+        migration::{flush_migration, rollback_migration, MigrationError, MigrationHelper},
+        Database, TemporaryDB,
+    };
+
+    use std::sync::Arc;
+
+    fn create_db() -> Arc<dyn Database> {
+        let db = TemporaryDB::new();
+        let fork = db.fork();
+        fork.get_list("test.list").extend(0_u32..25);
+        db.merge(fork.into_patch()).unwrap();
+        Arc::new(db)
+    }
+
+    #[test]
+    fn migration_status_binary_form() {
+        let mut status = MigrationStatus::new("test");
+        status.state = MigrationState::AwaitingFlush;
+        status.iterator_mut("list").record_step(
+            10,
+            Some(25),
+            Some(IteratorPosition::NextKey(vec![1, 2])),
+        );
+        status.iterator_mut("map").record_step(3, None, None);
+        status
+            .iterator_mut("keys")
+            .record_step(5, None, Some(IteratorPosition::Ended));
+        status.logs_live_changes = true;
+
+        let restored = MigrationStatus::from_bytes(status.to_bytes().into()).unwrap();
+        assert_eq!(restored, status);
+        let names: Vec<_> = restored
+            .iterators()
+            .iter()
+            .map(IteratorProgress::name)
+            .collect();
+        assert_eq!(names, vec!["keys", "list", "map"]);
+        assert_eq!(restored.processed(), 18);
+        assert_eq!(restored.estimated_total(), Some(33));
+
+        // The logging flag is required and must be valid.
+        let mut bytes = status.to_bytes();
+        *bytes.last_mut().unwrap() = 2;
+        assert!(MigrationStatus::from_bytes(bytes.clone().into()).is_err());
+        bytes.pop();
+        assert!(MigrationStatus::from_bytes(bytes.into()).is_err());
+    }
+
+    #[test]
+    fn registry_tracks_migration_progress() {
+        const CHUNK_SIZE: usize = 10;
+
+        let db = create_db();
+        let mut helper = MigrationHelper::new(Arc::clone(&db), "test");
+        let mut steps = 0;
+        helper
+            .iter_loop(|helper, iters| {
+                let old_list = helper.old_data().get_list::<_, u32>("list");
+                let mut new_list = helper.new_data().get_list::<_, u32>("list");
+                new_list.extend(
+                    iters
+                        .create("list", &old_list)
+                        .take(CHUNK_SIZE)
+                        .map(|(_, x)| x),
+                );
+
+                if steps == 1 {
+                    // Check the intermediate state.
+                    let snapshot = helper.db.snapshot();
+                    let status = MigrationRegistry::new(&snapshot).status("test").unwrap();
+                    assert_eq!(status.state(), MigrationState::Running);
+                    let iter = &status.iterators()[0];
+                    assert_eq!(iter.processed(), 10);
+                    assert_eq!(iter.total(), Some(25));
+                    assert_eq!(iter.next_key(), Some(&10_u64.to_be_bytes()[..]));
+                    assert!(!iter.is_ended());
+                }
+                steps += 1;
+            })
+            .unwrap();
+        helper.finish().unwrap();
+
+        let snapshot = db.snapshot();
+        let status = MigrationRegistry::new(&snapshot).status("test").unwrap();
+        assert_eq!(status.state(), MigrationState::AwaitingFlush);
+        assert_eq!(status.processed(), 25);
+        assert_eq!(status.estimated_total(), Some(25));
+        assert!(status.iterators()[0].is_ended());
+
+        let mut fork = db.fork();
+        flush_migration(&mut fork, "test");
+        db.merge(fork.into_patch()).unwrap();
+        let snapshot = db.snapshot();
+        let registry = MigrationRegistry::new(&snapshot);
+        assert_eq!(
+            registry.status("test").unwrap().state(),
+            MigrationState::Flushed
+        );
+        assert_eq!(registry.statuses().count(), 1);
+
+        // Restarting a completed migration resets the status.
+        let helper = MigrationHelper::new(Arc::clone(&db), "test");
+        helper.finish().unwrap();
+        let snapshot = db.snapshot();
+        let status = MigrationRegistry::new(&snapshot).status("test").unwrap();
+        assert!(status.iterators().is_empty());
+    }
+
+    #[test]
+    fn registry_records_abort_and_rollback() {
+        let db = create_db();
+        let (mut helper, handle) = MigrationHelper::with_handle(Arc::clone(&db), "test");
+        helper.new_data().get_entry("entry").set(1_u8);
+        helper.merge().unwrap();
+        drop(handle);
+        assert_matches::assert_matches!(helper.merge(), Err(MigrationError::Aborted));
+
+        let snapshot = db.snapshot();
+        let status = MigrationRegistry::new(&snapshot).status("test").unwrap();
+        assert_eq!(status.state(), MigrationState::Aborted);
+        let started_at = status.started_at();
+
+        // Resuming the migration retains the start time.
+        let mut helper = MigrationHelper::new(Arc::clone(&db), "test");
+        helper.merge().unwrap();
+        let snapshot = db.snapshot();
+        let status = MigrationRegistry::new(&snapshot).status("test").unwrap();
+        assert_eq!(status.state(), MigrationState::Running);
+        assert_eq!(status.started_at(), started_at);
+
+        let mut fork = db.fork();
+        rollback_migration(&mut fork, "test");
+        db.merge(fork.into_patch()).unwrap();
+        let snapshot = db.snapshot();
+        let registry = MigrationRegistry::new(&snapshot);
+        assert_eq!(
+            registry.status("test").unwrap().state(),
+            MigrationState::RolledBack
+        );
+        assert!(registry.status("other").is_none());
+    }
+}
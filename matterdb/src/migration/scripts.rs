@@ -0,0 +1,364 @@
+//! Versioned migration scripts and their runner.
+
+use std::{fmt, sync::Arc};
+
+use super::{flush_migration, MigrationError, MigrationHelper};
+use crate::{
+    access::RawAccess,
+    validation::assert_valid_name_component,
+    views::{RawAccessMut, View},
+    Database, ResolvedAddress,
+};
+
+/// Name of the column family used to store data versions of migration namespaces.
+const DATA_VERSIONS_NAME: &str = "__DATA_VERSIONS__";
+
+type ScriptLogic = dyn Fn(&mut MigrationHelper) -> Result<(), MigrationError> + Send + Sync;
+
+/// Migration script transforming data in a namespace from one data version to another.
+///
+/// The script logic is a closure receiving a [`MigrationHelper`]. The closure should not
+/// flush the migration; this is performed by the [`MigrationRunner`] once the closure
+/// has completed.
+///
+/// If the runner is interrupted (e.g., by a process shutdown), the script will be executed
+/// again on the next run. Hence, the script logic should be resumable; the simplest way to
+/// achieve this is to use persistent iterators, e.g., via [`MigrationHelper::iter_loop`].
+///
+/// [`MigrationHelper`]: struct.MigrationHelper.html
+/// [`MigrationRunner`]: struct.MigrationRunner.html
+/// [`MigrationHelper::iter_loop`]: struct.MigrationHelper.html#method.iter_loop
+pub struct MigrationScript {
+    namespace: String,
+    from_version: u64,
+    to_version: u64,
+    logic: Box<ScriptLogic>,
+}
+
+impl fmt::Debug for MigrationScript {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("MigrationScript")
+            .field("namespace", &self.namespace)
+            .field("from_version", &self.from_version)
+            .field("to_version", &self.to_version)
+            .finish()
+    }
+}
+
+impl MigrationScript {
+    /// Creates a new migration script.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `to_version` is not greater than `from_version`, or if the namespace
+    /// is not a valid name component.
+    pub fn new<F>(namespace: &str, from_version: u64, to_version: u64, logic: F) -> Self
+    where
+        F: Fn(&mut MigrationHelper) -> Result<(), MigrationError> + Send + Sync + 'static,
+    {
+        assert_valid_name_component(namespace);
+        assert!(
+            from_version < to_version,
+            "Migration script must increase the data version"
+        );
+
+        Self {
+            namespace: namespace.to_owned(),
+            from_version,
+            to_version,
+            logic: Box::new(logic),
+        }
+    }
+
+    /// Returns the migration namespace of the script.
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    /// Returns the data version the script migrates from.
+    pub fn from_version(&self) -> u64 {
+        self.from_version
+    }
+
+    /// Returns the data version the script migrates to.
+    pub fn to_version(&self) -> u64 {
+        self.to_version
+    }
+}
+
+/// Returns the data version of the specified namespace, or `None` if no migration scripts
+/// were applied to the namespace.
+pub fn data_version<T: RawAccess>(access: T, namespace: &str) -> Option<u64> {
+    data_versions(access).get(namespace)
+}
+
+fn data_versions<T: RawAccess>(access: T) -> View<T> {
+    View::new(access, ResolvedAddress::system(DATA_VERSIONS_NAME))
+}
+
+fn set_data_version<T: RawAccessMut>(access: T, namespace: &str, version: u64) {
+    data_versions(access).put(namespace, version);
+}
+
+/// Runner for migration scripts.
+///
+/// The runner keeps data versions of migration namespaces in the database. A namespace
+/// without a recorded version is considered to have version 0. On each [`run`], the runner
+/// applies pending scripts (i.e., scripts with `from_version` equal to the current data version
+/// of the namespace) in the order of the namespace registration, until there are no pending
+/// scripts left. Each script is finalized by calling [`flush_migration`] in the same fork that
+/// records the new data version, so the migration and the version update are merged atomically.
+///
+/// # Examples
+///
+/// ```
+/// # use matterdb::{access::{AccessExt, CopyAccessExt}, Database, TemporaryDB};
+/// # use matterdb::migration::{data_version, MigrationRunner, MigrationScript};
+/// # use std::sync::Arc;
+/// let db: Arc<dyn Database> = Arc::new(TemporaryDB::new());
+/// let fork = db.fork();
+/// fork.get_list("test.list").extend(vec![1_u32, 2, 3]);
+/// db.merge(fork.into_patch()).unwrap();
+///
+/// let mut runner = MigrationRunner::new(Arc::clone(&db));
+/// runner.add_script(MigrationScript::new("test", 0, 1, |helper| {
+///     let old_list = helper.old_data().get_list::<_, u32>("list");
+///     let sum: u32 = old_list.iter().sum();
+///     helper.new_data().get_entry("sum").set(sum);
+///     helper.new_data().create_tombstone("list");
+///     Ok(())
+/// }));
+/// runner.add_script(MigrationScript::new("test", 1, 2, |helper| {
+///     let sum = helper.old_data().get_entry::<_, u32>("sum").get().unwrap();
+///     helper.new_data().get_entry("sum").set(u64::from(sum));
+///     Ok(())
+/// }));
+///
+/// assert_eq!(runner.run().unwrap(), 2);
+/// let snapshot = db.snapshot();
+/// assert_eq!(snapshot.get_entry::<_, u64>("test.sum").get(), Some(6));
+/// assert_eq!(data_version(&snapshot, "test"), Some(2));
+/// // Repeated runs do not apply scripts again.
+/// assert_eq!(runner.run().unwrap(), 0);
+/// ```
+///
+/// [`run`]: #method.run
+/// [`flush_migration`]: fn.flush_migration.html
+pub struct MigrationRunner {
+    db: Arc<dyn Database>,
+    scripts: Vec<MigrationScript>,
+}
+
+impl fmt::Debug for MigrationRunner {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("MigrationRunner")
+            .field("scripts", &self.scripts)
+            .finish()
+    }
+}
+
+impl MigrationRunner {
+    /// Creates a runner without scripts.
+    pub fn new(db: impl Into<Arc<dyn Database>>) -> Self {
+        Self {
+            db: db.into(),
+            scripts: Vec::new(),
+        }
+    }
+
+    /// Adds a script to the runner.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a script for the same namespace and `from_version` is already added.
+    pub fn add_script(&mut self, script: MigrationScript) {
+        let is_duplicate = self.scripts.iter().any(|other| {
+            other.namespace == script.namespace && other.from_version == script.from_version
+        });
+        assert!(
+            !is_duplicate,
+            "Migration script for namespace `{}` from version {} is already added",
+            script.namespace, script.from_version
+        );
+        self.scripts.push(script);
+    }
+
+    /// Returns scripts that will be applied on the next `run`, in the order of application.
+    pub fn pending_scripts(&self) -> Vec<&MigrationScript> {
+        let snapshot = self.db.snapshot();
+        let mut pending = Vec::new();
+        for namespace in self.namespaces() {
+            let mut version = data_version(&snapshot, namespace).unwrap_or(0);
+            while let Some(script) = self.find_script(namespace, version) {
+                pending.push(script);
+                version = script.to_version;
+            }
+        }
+        pending
+    }
+
+    /// Applies all pending scripts. Returns the number of applied scripts.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the script logic returns an error or if merging the changes
+    /// to the database fails. In this case, the remaining scripts are not applied.
+    pub fn run(&self) -> Result<usize, MigrationError> {
+        let pending = self.pending_scripts();
+        for script in &pending {
+            self.apply(script)?;
+        }
+        Ok(pending.len())
+    }
+
+    /// Returns namespaces of the scripts in the registration order.
+    fn namespaces(&self) -> Vec<&str> {
+        let mut namespaces: Vec<&str> = Vec::new();
+        for script in &self.scripts {
+            if !namespaces.contains(&script.namespace.as_str()) {
+                namespaces.push(&script.namespace);
+            }
+        }
+        namespaces
+    }
+
+    fn find_script(&self, namespace: &str, from_version: u64) -> Option<&MigrationScript> {
+        self.scripts
+            .iter()
+            .find(|script| script.namespace == namespace && script.from_version == from_version)
+    }
+
+    fn apply(&self, script: &MigrationScript) -> Result<(), MigrationError> {
+        let mut helper = MigrationHelper::new(Arc::clone(&self.db), &script.namespace);
+        (script.logic)(&mut helper)?;
+        helper.finish()?;
+
+        let mut fork = self.db.fork();
+        flush_migration(&mut fork, &script.namespace);
+        set_data_version(&fork, &script.namespace, script.to_version);
+        self.db
+            .merge(fork.into_patch())
+            .map_err(MigrationError::Merge)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        access::{AccessExt, CopyAccessExt},
+        migration::{MigrationRegistry, MigrationState},
+        TemporaryDB,
+    };
+
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    fn create_db() -> Arc<dyn Database> {
+        let db = TemporaryDB::new();
+        let fork = db.fork();
+        fork.get_list("test.list").extend(0_u32..50);
+        fork.get_entry("other.entry").set(1_u32);
+        db.merge(fork.into_patch()).unwrap();
+        Arc::new(db)
+    }
+
+    fn copy_list(helper: &mut MigrationHelper) -> Result<(), MigrationError> {
+        helper.iter_loop(|helper, iters| {
+            let old_list = helper.old_data().get_list::<_, u32>("list");
+            let mut new_list = helper.new_data().get_list::<_, u64>("list");
+            let items = iters.create("list", &old_list).take(10);
+            new_list.extend(items.map(|(_, item)| u64::from(item)));
+        })
+    }
+
+    #[test]
+    fn runner_applies_scripts_in_order() {
+        let db = create_db();
+        let mut runner = MigrationRunner::new(Arc::clone(&db));
+        // Scripts are added in the arbitrary order.
+        runner.add_script(MigrationScript::new("test", 1, 3, |helper| {
+            let len = helper.old_data().get_list::<_, u64>("list").len();
+            helper.new_data().get_entry("len").set(len);
+            Ok(())
+        }));
+        runner.add_script(MigrationScript::new("other", 0, 1, |helper| {
+            helper.new_data().get_entry("entry").set(2_u32);
+            Ok(())
+        }));
+        runner.add_script(MigrationScript::new("test", 0, 1, copy_list));
+        // This script is never applied since there is no version 2.
+        runner.add_script(MigrationScript::new("test", 2, 3, |_| unreachable!()));
+
+        let pending: Vec<_> = runner
+            .pending_scripts()
+            .into_iter()
+            .map(|script| (script.namespace(), script.to_version()))
+            .collect();
+        assert_eq!(pending, vec![("test", 1), ("test", 3), ("other", 1)]);
+        assert_eq!(runner.run().unwrap(), 3);
+
+        let snapshot = db.snapshot();
+        assert_eq!(data_version(&snapshot, "test"), Some(3));
+        assert_eq!(data_version(&snapshot, "other"), Some(1));
+        assert_eq!(snapshot.get_list::<_, u64>("test.list").len(), 50);
+        assert_eq!(snapshot.get_entry::<_, u64>("test.len").get(), Some(50));
+        assert_eq!(snapshot.get_entry::<_, u32>("other.entry").get(), Some(2));
+
+        let registry = MigrationRegistry::new(&snapshot);
+        assert!(registry
+            .statuses()
+            .all(|status| status.state() == MigrationState::Flushed));
+        assert!(runner.pending_scripts().is_empty());
+    }
+
+    #[test]
+    fn runner_resumes_interrupted_script() {
+        let db = create_db();
+        let should_fail = Arc::new(AtomicBool::new(true));
+        let steps = Arc::new(AtomicUsize::new(0));
+
+        let mut runner = MigrationRunner::new(Arc::clone(&db));
+        let (should_fail_, steps_) = (Arc::clone(&should_fail), Arc::clone(&steps));
+        runner.add_script(MigrationScript::new("test", 0, 1, move |helper| {
+            let mut step = 0;
+            helper.iter_loop(|helper, iters| {
+                let old_list = helper.old_data().get_list::<_, u32>("list");
+                let mut new_list = helper.new_data().get_list::<_, u64>("list");
+                let items = iters.create("list", &old_list).take(10);
+                new_list.extend(items.map(|(_, item)| u64::from(item)));
+                steps_.fetch_add(1, Ordering::SeqCst);
+                step += 1;
+            })?;
+            if should_fail_.load(Ordering::SeqCst) {
+                Err(MigrationError::Aborted)
+            } else {
+                assert_eq!(step, 1);
+                Ok(())
+            }
+        }));
+
+        runner.run().unwrap_err();
+        let snapshot = db.snapshot();
+        assert_eq!(data_version(&snapshot, "test"), None);
+        assert_eq!(snapshot.get_list::<_, u32>("test.list").len(), 50);
+
+        // The second run only checks that the persistent iterator has ended.
+        should_fail.store(false, Ordering::SeqCst);
+        assert_eq!(runner.run().unwrap(), 1);
+        assert_eq!(steps.load(Ordering::SeqCst), 5 + 1);
+        let snapshot = db.snapshot();
+        assert_eq!(data_version(&snapshot, "test"), Some(1));
+        let list = snapshot.get_list::<_, u64>("test.list");
+        assert_eq!(list.iter().collect::<Vec<_>>(), (0..50).collect::<Vec<_>>());
+    }
+
+    #[test]
+    #[should_panic(expected = "is already added")]
+    fn duplicate_scripts_are_rejected() {
+        let mut runner = MigrationRunner::new(TemporaryDB::new());
+        runner.add_script(MigrationScript::new("test", 0, 1, |_| Ok(())));
+        runner.add_script(MigrationScript::new("test", 0, 2, |_| Ok(())));
+    }
+}
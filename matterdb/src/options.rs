@@ -41,6 +41,17 @@ pub struct DBOptions {
     ///
     /// Defaults to `None`, meaning that there will be no cache used.
     pub max_cache_size: Option<usize>,
+    /// Mapping of indexes to column families.
+    ///
+    /// The mapping is persisted in the database on creation and cannot be changed
+    /// by reopening the database with other options. Use [`RocksDB::migrate_column_families`]
+    /// to change the mapping of an existing database.
+    ///
+    /// Defaults to `ColumnFamilyMapping::PerIndex`.
+    ///
+    /// [`RocksDB::migrate_column_families`]: struct.RocksDB.html#method.migrate_column_families
+    #[serde(default)]
+    pub column_families: ColumnFamilyMapping,
 }
 
 impl DBOptions {
@@ -58,10 +69,33 @@ impl DBOptions {
             compression_type,
             max_total_wal_size,
             max_cache_size,
+            column_families: ColumnFamilyMapping::default(),
         }
     }
 }
 
+/// Mapping of indexes to column families of the underlying database.
+///
+/// Keys of all indexes are prefixed by the index identifier, which is unique within
+/// the database. Thus, indexes can share a column family without clashing. Having
+/// fewer column families reduces memory consumption (each column family has its own memtable)
+/// and speeds up opening the database. System data of the database is always stored
+/// in separate column families.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ColumnFamilyMapping {
+    /// Each root index name (i.e., the name of a standalone index or an index group)
+    /// is stored in a separate column family named after the index.
+    #[default]
+    PerIndex,
+    /// Indexes are distributed among the fixed number of column families by the hash
+    /// of the root index name. The number of column families must be positive.
+    Hashed {
+        /// Number of column families.
+        count: u16,
+    },
+}
+
 /// Algorithms of compression for the database.
 ///
 /// Database contents are stored in a set of blocks, each of which holds a
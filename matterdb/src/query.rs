@@ -0,0 +1,916 @@
+//! Typed queries over indexes.
+//!
+//! # Stability
+//!
+//! The entirety of this module is considered unstable. While the supported functionality
+//! is unlikely to break, the implementation details may change in the following releases.
+//!
+//! # Overview
+//!
+//! A [`Query`] describes a scan over a [`QuerySource`] (a [`MapIndex`], [`KeySetIndex`],
+//! [`ListIndex`] or [`Group`]) with optional key range, filters, projection, offset, limit
+//! and ordering. Key ranges are pushed down to the storage: iteration starts from the lower
+//! bound of the range and stops at its upper bound, so that entries outside the range
+//! are not decoded. Other predicates are evaluated on decoded entries.
+//!
+//! Results can be obtained either as an iterator via [`iter()`], or as a [`Page`] of bounded
+//! size via [`page()`]. A page contains a [`ContinuationToken`] that can be used to retrieve
+//! the next page, possibly from a later snapshot.
+//!
+//! Entries can also be looked up via a [`SecondaryIndex`] mapping values of a certain field
+//! to the keys of the source index. The secondary index is maintained by the caller.
+//!
+//! # Examples
+//!
+//! ```
+//! # use matterdb::{access::CopyAccessExt, Database, TemporaryDB};
+//! # use matterdb::query::{Order, Query};
+//! let db = TemporaryDB::new();
+//! let fork = db.fork();
+//! let mut map = fork.get_map("balances");
+//! for i in 0_u32..100 {
+//!     map.put(&i, u64::from(i) * 10);
+//! }
+//!
+//! let balances: Vec<u64> = Query::new(&map)
+//!     .range(10..50)
+//!     .filter(|_, balance| balance % 20 == 0)
+//!     .order(Order::Descending)
+//!     .offset(1)
+//!     .limit(3)
+//!     .select(|_, balance| balance)
+//!     .iter()
+//!     .collect();
+//! assert_eq!(balances, vec![460, 440, 420]);
+//! ```
+//!
+//! [`Query`]: struct.Query.html
+//! [`QuerySource`]: trait.QuerySource.html
+//! [`MapIndex`]: ../struct.MapIndex.html
+//! [`KeySetIndex`]: ../struct.KeySetIndex.html
+//! [`ListIndex`]: ../struct.ListIndex.html
+//! [`Group`]: ../struct.Group.html
+//! [`iter()`]: struct.Query.html#method.iter
+//! [`Page`]: struct.Page.html
+//! [`page()`]: struct.Query.html#method.page
+//! [`ContinuationToken`]: struct.ContinuationToken.html
+//! [`SecondaryIndex`]: struct.SecondaryIndex.html
+
+use anyhow::format_err;
+use byteorder::ReadBytesExt;
+
+use std::{
+    borrow::{Borrow, Cow},
+    collections::VecDeque,
+    fmt,
+    ops::{Bound, RangeBounds},
+};
+
+use crate::{
+    access::{Access, AccessError, FromAccess},
+    indexes::IndexIterator,
+    values::{read_bytes, write_bytes},
+    views::{key_bytes, IndexAddress, RawAccess, RawAccessMut},
+    BinaryKey, BinaryValue, Group, KeySetIndex, ListIndex, MapIndex,
+};
+
+/// Owned version of keys in a query source.
+type OwnedKey<S> = <<S as QuerySource>::Key as ToOwned>::Owned;
+/// Boxed iterator over query source entries.
+type SourceIter<'a, K, V> = Box<dyn Iterator<Item = (<K as ToOwned>::Owned, V)> + 'a>;
+/// Boxed iterator over matching entries together with their positions.
+type Matches<'a, K, V> = Box<dyn Iterator<Item = (ContinuationToken, K, V)> + 'a>;
+/// Boxed scan over a secondary index.
+type IndexScan<'a, K> =
+    Box<dyn Fn(Option<&[u8]>) -> SourceIter<'a, [u8], <K as ToOwned>::Owned> + 'a>;
+/// Boxed query filter.
+type Filter<'a, K, V> = Box<dyn Fn(&K, &V) -> bool + 'a>;
+/// Boxed query projection.
+type Projection<'a, K, V, O> = Box<dyn Fn(K, V) -> O + 'a>;
+
+/// Collection that can be queried.
+pub trait QuerySource {
+    /// Type of keys in the collection.
+    type Key: BinaryKey + ?Sized;
+    /// Type of items associated with the keys.
+    type Item;
+
+    /// Iterates over the collection starting from the specified serialized key (inclusive),
+    /// or from the beginning of the collection if `from` is `None`. Entries must be ordered
+    /// by the binary serialization of their keys.
+    ///
+    /// `from` may originate from a client-supplied [`ContinuationToken`] and is not guaranteed
+    /// to be a valid serialized key, so implementations must not deserialize it.
+    ///
+    /// [`ContinuationToken`]: struct.ContinuationToken.html
+    fn scan_from(&self, from: Option<&[u8]>) -> SourceIter<'_, Self::Key, Self::Item>;
+
+    /// Returns the item associated with the specified key.
+    fn lookup(&self, key: &Self::Key) -> Option<Self::Item>;
+}
+
+impl<T, K, V> QuerySource for MapIndex<T, K, V>
+where
+    T: RawAccess,
+    K: BinaryKey + ?Sized,
+    V: BinaryValue,
+{
+    type Key = K;
+    type Item = V;
+
+    fn scan_from(&self, from: Option<&[u8]>) -> SourceIter<'_, K, V> {
+        match from {
+            Some(from) => Box::new(self.iter_from_bytes(from)),
+            None => Box::new(self.index_iter(None)),
+        }
+    }
+
+    fn lookup(&self, key: &K) -> Option<V> {
+        self.get(key)
+    }
+}
+
+impl<T, K> QuerySource for KeySetIndex<T, K>
+where
+    T: RawAccess,
+    K: BinaryKey + ?Sized,
+{
+    type Key = K;
+    type Item = ();
+
+    fn scan_from(&self, from: Option<&[u8]>) -> SourceIter<'_, K, ()> {
+        match from {
+            Some(from) => Box::new(self.iter_from_bytes(from)),
+            None => Box::new(self.index_iter(None)),
+        }
+    }
+
+    fn lookup(&self, key: &K) -> Option<()> {
+        if self.contains(key) {
+            Some(())
+        } else {
+            None
+        }
+    }
+}
+
+impl<T, V> QuerySource for ListIndex<T, V>
+where
+    T: RawAccess,
+    V: BinaryValue,
+{
+    type Key = u64;
+    type Item = V;
+
+    fn scan_from(&self, from: Option<&[u8]>) -> SourceIter<'_, u64, V> {
+        match from {
+            Some(from) => Box::new(self.iter_from_bytes(from)),
+            None => Box::new(self.index_iter(None)),
+        }
+    }
+
+    fn lookup(&self, key: &u64) -> Option<V> {
+        self.get(*key)
+    }
+}
+
+impl<T, K, I> QuerySource for Group<T, K, I>
+where
+    T: Access,
+    K: BinaryKey + ?Sized,
+    I: FromAccess<T>,
+{
+    type Key = K;
+    type Item = I;
+
+    fn scan_from(&self, from: Option<&[u8]>) -> SourceIter<'_, K, I> {
+        let keys: Box<dyn Iterator<Item = K::Owned>> = match from {
+            Some(from) => Box::new(self.keys_from_bytes(from)),
+            None => Box::new(self.keys()),
+        };
+        Box::new(keys.map(move |key| {
+            let index = self.get(key.borrow());
+            (key, index)
+        }))
+    }
+
+    fn lookup(&self, key: &K) -> Option<I> {
+        self.keys_range((Bound::Included(key), Bound::Included(key)))
+            .next()
+            .map(|_| self.get(key))
+    }
+}
+
+/// Secondary index mapping values of a certain field to the keys of a primary index.
+///
+/// The secondary index is stored as a group of key sets: each set contains keys of the entries
+/// with a specific field value. The index is not updated automatically; the caller should call
+/// [`insert`] and [`remove`] whenever the primary index changes.
+///
+/// [`insert`]: #method.insert
+/// [`remove`]: #method.remove
+///
+/// # Examples
+///
+/// ```
+/// # use matterdb::{access::{CopyAccessExt, FromAccess}, Database, TemporaryDB};
+/// # use matterdb::query::{Query, SecondaryIndex};
+/// use std::ops::Bound;
+/// let db = TemporaryDB::new();
+/// let fork = db.fork();
+/// let mut users = fork.get_map::<_, u32, String>("users");
+/// let by_name: SecondaryIndex<_, str, u32> =
+///     FromAccess::from_access(&fork, "users_by_name".into()).unwrap();
+/// for (id, name) in vec![(1, "bob"), (2, "alice"), (3, "carol"), (4, "alice")] {
+///     users.put(&id, name.to_owned());
+///     by_name.insert(name, &id);
+/// }
+///
+/// let ids: Vec<u32> = Query::new(&users)
+///     .using_index(&by_name, (Bound::Included("alice"), Bound::Included("bob")))
+///     .select(|id, _| id)
+///     .iter()
+///     .collect();
+/// assert_eq!(ids, vec![2, 4, 1]);
+/// ```
+pub struct SecondaryIndex<T: Access, F: ?Sized, K: ?Sized> {
+    group: Group<T, F, KeySetIndex<T::Base, K>>,
+}
+
+impl<T, F, K> fmt::Debug for SecondaryIndex<T, F, K>
+where
+    T: Access,
+    F: ?Sized,
+    K: ?Sized,
+{
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.debug_struct("SecondaryIndex").finish()
+    }
+}
+
+impl<T, F, K> FromAccess<T> for SecondaryIndex<T, F, K>
+where
+    T: Access,
+    F: BinaryKey + ?Sized,
+    K: BinaryKey + ?Sized,
+{
+    fn from_access(access: T, addr: IndexAddress) -> Result<Self, AccessError> {
+        Group::from_access(access, addr).map(|group| Self { group })
+    }
+}
+
+impl<T, F, K> SecondaryIndex<T, F, K>
+where
+    T: Access,
+    F: BinaryKey + ?Sized,
+    K: BinaryKey + ?Sized,
+{
+    /// Returns keys of the primary index entries with the specified field value.
+    pub fn keys(&self, value: &F) -> Vec<K::Owned> {
+        self.group.get(value).iter().collect()
+    }
+
+    /// Iterates over field values in the specified range and the corresponding primary keys.
+    /// Positions of the entries are ordered by the serialized field value and then
+    /// by the serialized primary key.
+    fn scan(&self, from: Option<&[u8]>, end: &Bound<Vec<u8>>) -> SourceIter<'_, [u8], K::Owned> {
+        let values: Box<dyn Iterator<Item = F::Owned>> = match from {
+            Some(from) => Box::new(self.group.keys_from_bytes(from)),
+            None => Box::new(self.group.keys()),
+        };
+        let end = end.clone();
+        let entries = values
+            .map(|value| (key_bytes::<F>(value.borrow()), value))
+            .take_while(move |(value_bytes, _)| is_below(value_bytes, &end))
+            .flat_map(move |(value_bytes, value)| {
+                let keys: Vec<_> = self
+                    .group
+                    .get(value.borrow())
+                    .iter()
+                    .map(|key| (value_bytes.clone(), key))
+                    .collect();
+                keys
+            });
+        Box::new(entries)
+    }
+}
+
+impl<T, F, K> SecondaryIndex<T, F, K>
+where
+    T: Access,
+    T::Base: RawAccessMut,
+    F: BinaryKey + ?Sized,
+    K: BinaryKey + ?Sized,
+{
+    /// Records that the primary index entry with the specified key has the specified
+    /// field value.
+    pub fn insert(&self, value: &F, key: &K) {
+        self.group.get(value).insert(key);
+    }
+
+    /// Removes the record for the primary index entry with the specified key and field value.
+    pub fn remove(&self, value: &F, key: &K) {
+        self.group.get(value).remove(key);
+    }
+}
+
+/// Order of query results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    /// Results are ordered by the key (or the indexed field value, if a secondary index is used)
+    /// in the ascending order. This is the default order of queries.
+    Ascending,
+    /// Results are ordered by the key (or the indexed field value, if a secondary index is used)
+    /// in the descending order.
+    ///
+    /// Storage only supports forward iteration, so descending queries read all entries
+    /// in the queried range up to the continuation token (or up to the range end if there
+    /// is no token). Only the entries that can be returned (i.e., no more than `offset + limit`)
+    /// are buffered in memory, but reading cost is proportional to the range size,
+    /// so descending queries should be used together with a narrow key range.
+    Descending,
+}
+
+/// Position in the query results allowing to resume the query.
+///
+/// Tokens are returned in [`Page`]s and can be serialized via the `BinaryValue` trait.
+///
+/// [`Page`]: struct.Page.html
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ContinuationToken {
+    /// Serialized value of the indexed field if a secondary index is used.
+    index_key: Option<Vec<u8>>,
+    /// Serialized key of the last returned entry.
+    key: Vec<u8>,
+}
+
+impl BinaryValue for ContinuationToken {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        if let Some(index_key) = &self.index_key {
+            buffer.push(1);
+            write_bytes(&mut buffer, index_key);
+        } else {
+            buffer.push(0);
+        }
+        buffer.extend_from_slice(&self.key);
+        buffer
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> anyhow::Result<Self> {
+        let mut bytes = bytes.as_ref();
+        let index_key = match bytes.read_u8()? {
+            0 => None,
+            1 => Some(read_bytes(&mut bytes)?),
+            tag => return Err(format_err!("Invalid option tag: {}", tag)),
+        };
+        Ok(Self {
+            index_key,
+            key: bytes.to_vec(),
+        })
+    }
+}
+
+/// Page of query results.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page<O> {
+    items: Vec<O>,
+    next: Option<ContinuationToken>,
+}
+
+impl<O> Page<O> {
+    /// Returns items on this page.
+    pub fn items(&self) -> &[O] {
+        &self.items
+    }
+
+    /// Converts this page into items.
+    pub fn into_items(self) -> Vec<O> {
+        self.items
+    }
+
+    /// Returns the token to retrieve the next page, or `None` if this page is the last one.
+    pub fn next_token(&self) -> Option<&ContinuationToken> {
+        self.next.as_ref()
+    }
+}
+
+/// Checks whether `key` is above the lower bound.
+fn is_above(key: &[u8], bound: &Bound<Vec<u8>>) -> bool {
+    match bound {
+        Bound::Included(start) => key >= start.as_slice(),
+        Bound::Excluded(start) => key > start.as_slice(),
+        Bound::Unbounded => true,
+    }
+}
+
+/// Checks whether `key` is below the upper bound.
+fn is_below(key: &[u8], bound: &Bound<Vec<u8>>) -> bool {
+    match bound {
+        Bound::Included(end) => key <= end.as_slice(),
+        Bound::Excluded(end) => key < end.as_slice(),
+        Bound::Unbounded => true,
+    }
+}
+
+/// Serializes keys in the bound.
+fn bound_bytes<K: BinaryKey + ?Sized>(bound: Bound<&K>) -> Bound<Vec<u8>> {
+    match bound {
+        Bound::Included(key) => Bound::Included(key_bytes(key)),
+        Bound::Excluded(key) => Bound::Excluded(key_bytes(key)),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+/// Returns the position to start iteration from for the specified lower bound and token.
+fn seek_position<'b>(bound: &'b Bound<Vec<u8>>, token: Option<&'b [u8]>) -> Option<&'b [u8]> {
+    let bound = match bound {
+        Bound::Included(start) | Bound::Excluded(start) => Some(start.as_slice()),
+        Bound::Unbounded => None,
+    };
+    bound.max(token)
+}
+
+/// Secondary index lookup used by a query.
+struct IndexLookup<'a, K: BinaryKey + ?Sized> {
+    scan: IndexScan<'a, K>,
+    start: Bound<Vec<u8>>,
+}
+
+/// Query over a [`QuerySource`].
+///
+/// The query is built using the builder pattern; see the [module docs](index.html)
+/// for an example of usage.
+///
+/// [`QuerySource`]: trait.QuerySource.html
+pub struct Query<'a, S, O = (OwnedKey<S>, <S as QuerySource>::Item)>
+where
+    S: QuerySource + ?Sized,
+{
+    source: &'a S,
+    start: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
+    filters: Vec<Filter<'a, OwnedKey<S>, S::Item>>,
+    projection: Projection<'a, OwnedKey<S>, S::Item, O>,
+    offset: usize,
+    limit: Option<usize>,
+    order: Order,
+    index: Option<IndexLookup<'a, S::Key>>,
+}
+
+impl<S, O> fmt::Debug for Query<'_, S, O>
+where
+    S: QuerySource + ?Sized,
+{
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("Query")
+            .field("start", &self.start)
+            .field("end", &self.end)
+            .field("filters", &self.filters.len())
+            .field("offset", &self.offset)
+            .field("limit", &self.limit)
+            .field("order", &self.order)
+            .field("uses_index", &self.index.is_some())
+            .finish()
+    }
+}
+
+impl<'a, S> Query<'a, S>
+where
+    S: QuerySource + ?Sized,
+{
+    /// Creates a query returning all entries of the `source` in the ascending key order.
+    pub fn new(source: &'a S) -> Self {
+        Self {
+            source,
+            start: Bound::Unbounded,
+            end: Bound::Unbounded,
+            filters: vec![],
+            projection: Box::new(|key, item| (key, item)),
+            offset: 0,
+            limit: None,
+            order: Order::Ascending,
+            index: None,
+        }
+    }
+}
+
+impl<'a, S, O> Query<'a, S, O>
+where
+    S: QuerySource + ?Sized,
+{
+    /// Restricts the query to keys within the specified range. Keys are compared by their
+    /// binary serialization. The range is pushed down to the storage, i.e., entries outside
+    /// of the range are not read.
+    #[must_use]
+    pub fn range<R>(mut self, range: R) -> Self
+    where
+        R: RangeBounds<S::Key>,
+    {
+        self.start = bound_bytes(range.start_bound());
+        self.end = bound_bytes(range.end_bound());
+        self
+    }
+
+    /// Adds a filter for the query results. Filters are applied to decoded entries before
+    /// the projection.
+    #[must_use]
+    pub fn filter<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&OwnedKey<S>, &S::Item) -> bool + 'a,
+    {
+        self.filters.push(Box::new(predicate));
+        self
+    }
+
+    /// Skips the specified number of first matching entries.
+    ///
+    /// When the query is executed via [`page()`], the offset is only applied
+    /// to the first page.
+    ///
+    /// [`page()`]: #method.page
+    #[must_use]
+    pub fn offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Limits the number of returned entries.
+    #[must_use]
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Sets the order of results.
+    #[must_use]
+    pub fn order(mut self, order: Order) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Looks up entries via the secondary index, restricting the indexed field values
+    /// to the specified range. Results are ordered by the field value and then by the key.
+    ///
+    /// Keys in the secondary index that are absent from the source are skipped. The key range
+    /// set with [`range()`] is applied to the entries returned by the secondary index.
+    ///
+    /// [`range()`]: #method.range
+    #[must_use]
+    pub fn using_index<T, F, R>(
+        mut self,
+        index: &'a SecondaryIndex<T, F, S::Key>,
+        values: R,
+    ) -> Self
+    where
+        T: Access,
+        F: BinaryKey + ?Sized,
+        R: RangeBounds<F>,
+    {
+        let start = bound_bytes(values.start_bound());
+        let end = bound_bytes(values.end_bound());
+        self.index = Some(IndexLookup {
+            scan: Box::new(move |from| index.scan(from, &end)),
+            start,
+        });
+        self
+    }
+
+    /// Sets the projection of the query results, replacing the previously set projection.
+    pub fn select<P, F>(self, projection: F) -> Query<'a, S, P>
+    where
+        F: Fn(OwnedKey<S>, S::Item) -> P + 'a,
+    {
+        Query {
+            source: self.source,
+            start: self.start,
+            end: self.end,
+            filters: self.filters,
+            projection: Box::new(projection),
+            offset: self.offset,
+            limit: self.limit,
+            order: self.order,
+            index: self.index,
+        }
+    }
+
+    /// Returns an iterator over the query results.
+    pub fn iter(&self) -> Box<dyn Iterator<Item = O> + '_> {
+        let limit = self.limit.unwrap_or(usize::MAX);
+        let results = self
+            .matches(None, self.offset.saturating_add(limit))
+            .skip(self.offset)
+            .take(limit)
+            .map(move |(_, key, item)| (self.projection)(key, item));
+        Box::new(results)
+    }
+
+    /// Returns a page of query results. If the `token` is specified, the page starts
+    /// after the position encoded in the token; otherwise, the page starts from the beginning
+    /// of the results, taking the offset into account.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the query limit is not set.
+    pub fn page(&self, token: Option<&ContinuationToken>) -> Page<O> {
+        let limit = self
+            .limit
+            .expect("Query limit must be set to retrieve a page of results");
+        let offset = if token.is_some() { 0 } else { self.offset };
+
+        // One extra entry is necessary to determine whether there is a next page.
+        let window = offset.saturating_add(limit).saturating_add(1);
+        let mut matches = self.matches(token, window).skip(offset);
+        let mut items = Vec::with_capacity(limit);
+        let mut last_position = None;
+        for (position, key, item) in matches.by_ref().take(limit) {
+            items.push((self.projection)(key, item));
+            last_position = Some(position);
+        }
+        let next = if matches.next().is_some() {
+            last_position
+        } else {
+            None
+        };
+        Page { items, next }
+    }
+
+    /// Returns matching entries after the `token` position together with their positions.
+    /// No more than `window` first entries may be consumed from the returned iterator.
+    fn matches(
+        &self,
+        token: Option<&ContinuationToken>,
+        window: usize,
+    ) -> Matches<'_, OwnedKey<S>, S::Item> {
+        let order = self.order;
+        let token = token.cloned();
+        let ascending_token = token.clone().filter(|_| order == Order::Ascending);
+        let entries = self.entries(ascending_token.as_ref());
+        let entries = entries.filter(move |(_, key, item)| {
+            self.filters.iter().all(|predicate| predicate(key, item))
+        });
+
+        match order {
+            Order::Ascending => {
+                Box::new(entries.skip_while(move |(position, ..)| Some(position) <= token.as_ref()))
+            }
+            Order::Descending => {
+                // Keep only the last `window` entries before the token.
+                let mut buffer = VecDeque::new();
+                let entries = entries
+                    .take_while(|(position, ..)| token.as_ref().map_or(true, |t| position < t));
+                for entry in entries {
+                    if buffer.len() == window {
+                        buffer.pop_front();
+                    }
+                    buffer.push_back(entry);
+                }
+                Box::new(buffer.into_iter().rev())
+            }
+        }
+    }
+
+    /// Returns entries in the ascending order starting approximately from the `token`
+    /// position.
+    fn entries(&self, token: Option<&ContinuationToken>) -> Matches<'_, OwnedKey<S>, S::Item> {
+        if let Some(index) = &self.index {
+            let token_position = token.and_then(|token| token.index_key.as_deref());
+            let from = seek_position(&index.start, token_position);
+            let start = index.start.clone();
+            let entries = (index.scan)(from)
+                .filter(move |(value, _)| is_above(value, &start))
+                .filter_map(move |(value, key)| {
+                    let key_bytes = key_bytes::<S::Key>(key.borrow());
+                    if !is_above(&key_bytes, &self.start) || !is_below(&key_bytes, &self.end) {
+                        return None;
+                    }
+                    let item = self.source.lookup(key.borrow())?;
+                    let position = ContinuationToken {
+                        index_key: Some(value),
+                        key: key_bytes,
+                    };
+                    Some((position, key, item))
+                });
+            Box::new(entries)
+        } else {
+            let token_position = token.map(|token| token.key.as_slice());
+            let from = seek_position(&self.start, token_position);
+            let entries = self
+                .source
+                .scan_from(from)
+                .map(|(key, item)| {
+                    let position = ContinuationToken {
+                        index_key: None,
+                        key: key_bytes::<S::Key>(key.borrow()),
+                    };
+                    (position, key, item)
+                })
+                .skip_while(move |(position, ..)| !is_above(&position.key, &self.start))
+                .take_while(move |(position, ..)| is_below(&position.key, &self.end));
+            Box::new(entries)
+        }
+    }
+}
+
+impl<'q, S, O> IntoIterator for &'q Query<'_, S, O>
+where
+    S: QuerySource + ?Sized,
+{
+    type Item = O;
+    type IntoIter = Box<dyn Iterator<Item = O> + 'q>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{access::CopyAccessExt, Database, TemporaryDB};
+
+    #[test]
+    fn query_over_map() {
+        let db = TemporaryDB::new();
+        let fork = db.fork();
+        let mut map = fork.get_map::<_, str, u32>("map");
+        for (i, name) in ["alice", "bob", "carol", "dave", "eve"].iter().enumerate() {
+            map.put(name, i as u32);
+        }
+
+        let query = Query::new(&map);
+        assert_eq!(query.iter().count(), 5);
+        let query = Query::new(&map).range((Bound::Excluded("alice"), Bound::Included("dave")));
+        let keys: Vec<_> = query.select(|key, _| key).iter().collect();
+        assert_eq!(keys, vec!["bob", "carol", "dave"]);
+
+        let query = Query::new(&map)
+            .range((Bound::Included("b"), Bound::Excluded("e")))
+            .filter(|_, &value| value != 2)
+            .order(Order::Descending)
+            .select(|key, value| format!("{}={}", key, value));
+        assert_eq!(query.iter().collect::<Vec<_>>(), vec!["dave=3", "bob=1"]);
+        assert_eq!(query.iter().count(), 2);
+        let query = query.offset(1).limit(1);
+        assert_eq!(query.iter().collect::<Vec<_>>(), vec!["bob=1"]);
+    }
+
+    #[test]
+    fn query_over_list_and_group() {
+        let db = TemporaryDB::new();
+        let fork = db.fork();
+        fork.get_list("list").extend(10_u32..20);
+        let group: Group<_, u64, ListIndex<_, u32>> = fork.get_group("group");
+        for i in 0..5 {
+            group.get(&i).extend(0..i as u32);
+        }
+
+        let list = fork.get_list::<_, u32>("list");
+        let items: Vec<_> = Query::new(&list)
+            .range(3..)
+            .offset(2)
+            .limit(3)
+            .iter()
+            .collect();
+        assert_eq!(items, vec![(5, 15), (6, 16), (7, 17)]);
+
+        let lengths: Vec<_> = Query::new(&group)
+            .range(1..=3)
+            .filter(|_, list| list.len() % 2 == 1)
+            .select(|key, list| (key, list.len()))
+            .iter()
+            .collect();
+        assert_eq!(lengths, vec![(1, 1), (3, 3)]);
+    }
+
+    #[test]
+    fn paginated_queries() {
+        let db = TemporaryDB::new();
+        let fork = db.fork();
+        let mut set = fork.get_key_set::<_, u32>("set");
+        for i in 0..10 {
+            set.insert(&i);
+        }
+
+        for &order in &[Order::Ascending, Order::Descending] {
+            let query = Query::new(&set)
+                .range(1..9)
+                .filter(|&key, &()| key != 5)
+                .order(order)
+                .offset(1)
+                .limit(3)
+                .select(|key, ()| key);
+
+            let mut pages = vec![];
+            let mut token = None;
+            loop {
+                let page = query.page(token.as_ref());
+                token = page
+                    .next_token()
+                    .map(|token| ContinuationToken::from_bytes(token.to_bytes().into()).unwrap());
+                pages.push(page.into_items());
+                if token.is_none() {
+                    break;
+                }
+            }
+            if order == Order::Ascending {
+                assert_eq!(pages, vec![vec![2, 3, 4], vec![6, 7, 8]]);
+            } else {
+                assert_eq!(pages, vec![vec![7, 6, 4], vec![3, 2, 1]]);
+            }
+        }
+
+        // The query can be resumed after the data is changed.
+        let page = Query::new(&set).limit(4).select(|key, ()| key).page(None);
+        assert_eq!(page.items(), &[0, 1, 2, 3]);
+        set.remove(&4);
+        set.insert(&20);
+        let query = Query::new(&set).limit(4).select(|key, ()| key);
+        let page = query.page(page.next_token());
+        assert_eq!(page.items(), &[5, 6, 7, 8]);
+        let page = query.page(page.next_token());
+        assert_eq!(page.items(), &[9, 20]);
+        assert!(page.next_token().is_none());
+    }
+
+    #[test]
+    fn query_with_secondary_index() {
+        let db = TemporaryDB::new();
+        let fork = db.fork();
+        let mut users = fork.get_map::<_, u32, String>("users");
+        let by_age: SecondaryIndex<_, u8, u32> =
+            FromAccess::from_access(&fork, "users_by_age".into()).unwrap();
+        for id in 0_u32..20 {
+            let age = (30 - id % 7) as u8;
+            users.put(&id, format!("user #{}", id));
+            by_age.insert(&age, &id);
+        }
+        // Stale index entries are skipped.
+        by_age.insert(&25, &100);
+        by_age.remove(&30, &14);
+        assert_eq!(by_age.keys(&30), vec![0, 7]);
+
+        let query = Query::new(&users)
+            .using_index(&by_age, 25..=26)
+            .select(|id, _| id);
+        assert_eq!(query.iter().collect::<Vec<_>>(), vec![5, 12, 19, 4, 11, 18]);
+
+        let query = Query::new(&users)
+            .using_index(&by_age, ..=29)
+            .range(..10)
+            .order(Order::Descending)
+            .limit(4)
+            .select(|id, _| id);
+        let page = query.page(None);
+        assert_eq!(page.items(), &[8, 1, 9, 2]);
+        let page = query.page(page.next_token());
+        assert_eq!(page.items(), &[3, 4, 5, 6]);
+        assert!(page.next_token().is_none());
+
+        let query = Query::new(&users)
+            .using_index(&by_age, 29..)
+            .limit(3)
+            .select(|id, _| id);
+        let page = query.page(None);
+        assert_eq!(page.items(), &[1, 8, 15]);
+        let page = query.page(page.next_token());
+        assert_eq!(page.items(), &[0, 7]);
+        assert!(page.next_token().is_none());
+    }
+    #[test]
+    fn malformed_tokens_do_not_panic() {
+        let db = TemporaryDB::new();
+        let fork = db.fork();
+        let mut map = fork.get_map::<_, u32, u32>("map");
+        let group: Group<_, u64, ListIndex<_, u32>> = fork.get_group("group");
+        let by_value: SecondaryIndex<_, u16, u32> =
+            FromAccess::from_access(&fork, "by_value".into()).unwrap();
+        for i in 0_u32..10 {
+            map.put(&i, i);
+            group.get(&u64::from(i)).push(i);
+            by_value.insert(&(i as u16), &i);
+        }
+
+        // Keys are truncated: a 2-byte key cannot be read as `u32`.
+        let token = ContinuationToken::from_bytes(vec![0, 0, 0].into()).unwrap();
+        let page = Query::new(&map)
+            .limit(3)
+            .select(|key, _| key)
+            .page(Some(&token));
+        assert_eq!(page.items(), &[0, 1, 2]);
+        let page = Query::new(&group)
+            .limit(3)
+            .select(|key, _| key)
+            .page(Some(&token));
+        assert_eq!(page.items(), &[0, 1, 2]);
+
+        let token = ContinuationToken::from_bytes(vec![1, 1, 0, 0, 0, 0, 5].into()).unwrap();
+        let page = Query::new(&map)
+            .using_index(&by_value, ..)
+            .limit(3)
+            .select(|key, _| key)
+            .page(Some(&token));
+        assert_eq!(page.items(), &[0, 1, 2]);
+    }
+}
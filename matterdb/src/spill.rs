@@ -0,0 +1,701 @@
+//! Spilling of fork changes to a temporary on-disk store.
+//!
+//! Changes flushed into a `Fork` are stored in memory. If the fork has a memory limit and
+//! the changes exceed it, the changes are moved to a temporary `RocksDB` instance, which
+//! is removed together with the patch. Changes are stored in the instance in the encoded
+//! form (see `SpilledChange`), so that the spilled layer may contain deletions and merge operands
+//! in addition to ordinary values. Reads through the patch see a combined view of in-memory
+//! changes, spilled changes and the underlying snapshot; merging a patch into a database
+//! streams spilled changes in bounded chunks.
+
+use tempfile::TempDir;
+
+use std::{
+    collections::{btree_map, BTreeMap, HashMap, VecDeque},
+    fmt,
+    iter::{Iterator as StdIterator, Peekable},
+    sync::Arc,
+};
+
+use crate::{
+    db::{merge_change, merge_counter, Change, ViewChanges},
+    DBOptions, Database, Iter, Iterator, Patch, ResolvedAddress, RocksDB, Snapshot,
+};
+
+/// Number of spilled changes loaded into memory at once when streaming changes.
+const STREAM_BUFFER_SIZE: usize = 1_024;
+
+const PUT_TAG: u8 = 0;
+const DELETE_TAG: u8 = 1;
+const MERGE_TAG: u8 = 2;
+
+/// Change stored in the spill store, borrowed from the encoded representation.
+#[derive(Debug, Clone, Copy)]
+enum SpilledChange<'a> {
+    Put(&'a [u8]),
+    Delete,
+    Merge(&'a [u8]),
+}
+
+impl<'a> SpilledChange<'a> {
+    fn encode(change: &Change) -> Vec<u8> {
+        let (tag, payload): (_, &[u8]) = match change {
+            Change::Put(value) => (PUT_TAG, value),
+            Change::Delete => (DELETE_TAG, &[]),
+            Change::Merge(operand) => (MERGE_TAG, operand),
+        };
+        let mut buffer = Vec::with_capacity(payload.len() + 1);
+        buffer.push(tag);
+        buffer.extend_from_slice(payload);
+        buffer
+    }
+
+    fn decode(bytes: &'a [u8]) -> Self {
+        match bytes.split_first() {
+            Some((&PUT_TAG, value)) => SpilledChange::Put(value),
+            Some((&DELETE_TAG, _)) => SpilledChange::Delete,
+            Some((&MERGE_TAG, operand)) => SpilledChange::Merge(operand),
+            _ => panic!("MerkleDB error: invalid spilled change {:?}", bytes),
+        }
+    }
+
+    fn to_change(self) -> Change {
+        match self {
+            SpilledChange::Put(value) => Change::Put(value.to_vec()),
+            SpilledChange::Delete => Change::Delete,
+            SpilledChange::Merge(operand) => Change::Merge(operand.to_vec()),
+        }
+    }
+}
+
+/// Temporary database together with the directory it is stored in.
+struct SpillStore {
+    // The database must be dropped before the directory.
+    db: RocksDB,
+    _dir: TempDir,
+}
+
+/// Changes of a `Patch` spilled to a temporary on-disk store.
+pub struct SpilledChanges {
+    snapshot: Box<dyn Snapshot>,
+    store: Arc<SpillStore>,
+    /// Addresses of views with spilled changes, mapped to the flag indicating whether
+    /// the view was cleared.
+    views: HashMap<ResolvedAddress, bool>,
+}
+
+impl fmt::Debug for SpilledChanges {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("SpilledChanges")
+            .field("views", &self.views)
+            .finish()
+    }
+}
+
+impl SpilledChanges {
+    /// Creates an empty spill store in a new temporary directory.
+    pub fn new() -> crate::Result<Self> {
+        let dir = TempDir::new()?;
+        let db = RocksDB::open(dir.path(), &DBOptions::default())?;
+        Ok(Self {
+            snapshot: db.snapshot(),
+            store: Arc::new(SpillStore { db, _dir: dir }),
+            views: HashMap::new(),
+        })
+    }
+
+    /// Moves `changes` into the store. The changes are considered newer than the changes
+    /// already in the store.
+    pub fn spill(&mut self, changes: HashMap<ResolvedAddress, ViewChanges>) -> crate::Result<()> {
+        let mut spilled = HashMap::with_capacity(changes.len());
+        for (address, changes) in changes {
+            let is_cleared = changes.is_cleared();
+            let was_cleared = self.views.get(&address).copied().unwrap_or(false);
+            let data = changes
+                .into_data()
+                .into_iter()
+                .map(|(key, change)| {
+                    let change = match change {
+                        Change::Merge(ref operand) if !is_cleared => {
+                            let existing = self.get_change(&address, &key);
+                            merge_change(existing.as_ref(), was_cleared, operand)
+                        }
+                        change => change,
+                    };
+                    (key, Change::Put(SpilledChange::encode(&change)))
+                })
+                .collect();
+
+            self.views
+                .insert(address.clone(), is_cleared || was_cleared);
+            spilled.insert(address, ViewChanges::from_parts(data, is_cleared));
+        }
+        self.write(spilled)
+    }
+
+    /// Removes spilled changes for the specified view.
+    pub fn discard_view(&mut self, address: &ResolvedAddress) -> crate::Result<()> {
+        if self.views.remove(address).is_none() {
+            return Ok(());
+        }
+        let mut changes = HashMap::with_capacity(1);
+        changes.insert(
+            address.clone(),
+            ViewChanges::from_parts(BTreeMap::new(), true),
+        );
+        self.write(changes)
+    }
+
+    fn write(&mut self, changes: HashMap<ResolvedAddress, ViewChanges>) -> crate::Result<()> {
+        let db = &self.store.db;
+        db.merge(Patch::from_changes(db.snapshot(), changes))?;
+        self.snapshot = db.snapshot();
+        Ok(())
+    }
+
+    fn get_change(&self, address: &ResolvedAddress, key: &[u8]) -> Option<Change> {
+        let bytes = self.snapshot.get(address, key)?;
+        Some(SpilledChange::decode(&bytes).to_change())
+    }
+
+    /// Returns addresses of views with spilled changes.
+    pub fn views(&self) -> impl StdIterator<Item = &ResolvedAddress> + '_ {
+        self.views.keys()
+    }
+
+    /// Returns `true` if the store contains changes for the specified view.
+    pub fn contains_view(&self, address: &ResolvedAddress) -> bool {
+        self.views.contains_key(address)
+    }
+
+    /// Gets a value from the spilled changes on top of the `base` snapshot.
+    pub fn get(
+        &self,
+        address: &ResolvedAddress,
+        key: &[u8],
+        base: &dyn Snapshot,
+    ) -> Option<Vec<u8>> {
+        let is_cleared = match self.views.get(address) {
+            Some(&is_cleared) => is_cleared,
+            None => return base.get(address, key),
+        };
+
+        if let Some(bytes) = self.snapshot.get(address, key) {
+            match SpilledChange::decode(&bytes) {
+                SpilledChange::Put(value) => Some(value.to_vec()),
+                SpilledChange::Delete => None,
+                SpilledChange::Merge(operand) => {
+                    let existing = if is_cleared {
+                        None
+                    } else {
+                        base.get(address, key)
+                    };
+                    Some(merge_counter(existing.as_deref(), operand))
+                }
+            }
+        } else if is_cleared {
+            None
+        } else {
+            base.get(address, key)
+        }
+    }
+
+    /// Checks whether a value exists in the spilled changes on top of the `base` snapshot.
+    pub fn contains(&self, address: &ResolvedAddress, key: &[u8], base: &dyn Snapshot) -> bool {
+        let is_cleared = match self.views.get(address) {
+            Some(&is_cleared) => is_cleared,
+            None => return base.contains(address, key),
+        };
+
+        if let Some(bytes) = self.snapshot.get(address, key) {
+            match SpilledChange::decode(&bytes) {
+                SpilledChange::Put(_) | SpilledChange::Merge(_) => true,
+                SpilledChange::Delete => false,
+            }
+        } else {
+            !is_cleared && base.contains(address, key)
+        }
+    }
+
+    /// Iterates over the spilled changes on top of the `base` snapshot.
+    pub fn iter<'a>(
+        &'a self,
+        address: &ResolvedAddress,
+        from: &[u8],
+        base: &'a dyn Snapshot,
+    ) -> Iter<'a> {
+        let is_cleared = match self.views.get(address) {
+            Some(&is_cleared) => is_cleared,
+            None => return base.iter(address, from),
+        };
+
+        Box::new(SpillIter {
+            spilled: self.snapshot.iter(address, from),
+            base: if is_cleared {
+                None
+            } else {
+                Some(base.iter(address, from))
+            },
+            merged_value: Vec::new(),
+        })
+    }
+
+    /// Converts spilled changes into per-view streams.
+    pub fn streams(&self) -> HashMap<ResolvedAddress, SpilledStream> {
+        self.views
+            .iter()
+            .map(|(address, &is_cleared)| {
+                let stream = SpilledStream {
+                    snapshot: self.store.db.snapshot(),
+                    _store: Arc::clone(&self.store),
+                    address: address.clone(),
+                    is_cleared,
+                    buffer: VecDeque::new(),
+                    next_key: Some(Vec::new()),
+                };
+                (address.clone(), stream)
+            })
+            .collect()
+    }
+}
+
+/// Kind of the next entry yielded by `SpillIter`.
+#[derive(Debug, Clone, Copy)]
+enum NextSpillValue {
+    /// Spilled value, possibly overriding the value from the base snapshot.
+    Put {
+        replaced: bool,
+    },
+    /// Spilled deletion, possibly deleting the value from the base snapshot.
+    Delete {
+        replaced: bool,
+    },
+    /// Spilled merge operand, possibly applied to the value from the base snapshot.
+    Merge {
+        replaced: bool,
+    },
+    /// Value from the base snapshot.
+    Stored,
+    Finished,
+}
+
+/// Iterator over spilled changes on top of a base snapshot.
+struct SpillIter<'a> {
+    spilled: Iter<'a>,
+    /// Iterator over the base snapshot; `None` if the view was cleared.
+    base: Option<Iter<'a>>,
+    merged_value: Vec<u8>,
+}
+
+impl fmt::Debug for SpillIter<'_> {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.debug_struct("SpillIter").finish()
+    }
+}
+
+impl SpillIter<'_> {
+    fn step(&mut self) -> NextSpillValue {
+        use std::cmp::Ordering::{Equal, Greater, Less};
+
+        let base_key = self
+            .base
+            .as_mut()
+            .and_then(|base| base.peek())
+            .map(|(key, _)| key);
+        let (spilled_key, change) = match self.spilled.peek() {
+            Some((key, change)) => (key, SpilledChange::decode(change)),
+            None if base_key.is_some() => return NextSpillValue::Stored,
+            None => return NextSpillValue::Finished,
+        };
+
+        let replaced = match base_key.map(|base_key| spilled_key.cmp(base_key)) {
+            Some(Greater) => return NextSpillValue::Stored,
+            Some(Equal) => true,
+            Some(Less) | None => false,
+        };
+        match change {
+            SpilledChange::Put(_) => NextSpillValue::Put { replaced },
+            SpilledChange::Delete => NextSpillValue::Delete { replaced },
+            SpilledChange::Merge(_) => NextSpillValue::Merge { replaced },
+        }
+    }
+
+    fn skip_base(&mut self, replaced: bool) {
+        if replaced {
+            self.base.as_mut().unwrap().next();
+        }
+    }
+
+    /// Applies the next spilled merge operand and stores the result in `merged_value`.
+    fn merge_next(&mut self, replaced: bool) {
+        let operand = match self
+            .spilled
+            .peek()
+            .map(|(_, change)| SpilledChange::decode(change))
+        {
+            Some(SpilledChange::Merge(operand)) => operand,
+            _ => unreachable!(),
+        };
+        let existing = if replaced {
+            self.base.as_mut().unwrap().peek().map(|(_, value)| value)
+        } else {
+            None
+        };
+        self.merged_value = merge_counter(existing, operand);
+    }
+}
+
+impl Iterator for SpillIter<'_> {
+    fn next(&mut self) -> Option<(&[u8], &[u8])> {
+        loop {
+            match self.step() {
+                NextSpillValue::Stored => return self.base.as_mut().unwrap().next(),
+                NextSpillValue::Put { replaced } => {
+                    self.skip_base(replaced);
+                    return self.spilled.next().map(|(key, change)| {
+                        match SpilledChange::decode(change) {
+                            SpilledChange::Put(value) => (key, value),
+                            _ => unreachable!(),
+                        }
+                    });
+                }
+                NextSpillValue::Merge { replaced } => {
+                    self.merge_next(replaced);
+                    self.skip_base(replaced);
+                    let (key, _) = self.spilled.next()?;
+                    return Some((key, &self.merged_value));
+                }
+                NextSpillValue::Delete { replaced } => {
+                    self.skip_base(replaced);
+                    self.spilled.next();
+                }
+                NextSpillValue::Finished => return None,
+            }
+        }
+    }
+
+    fn peek(&mut self) -> Option<(&[u8], &[u8])> {
+        loop {
+            match self.step() {
+                NextSpillValue::Stored => return self.base.as_mut().unwrap().peek(),
+                NextSpillValue::Put { .. } => {
+                    return self.spilled.peek().map(|(key, change)| {
+                        match SpilledChange::decode(change) {
+                            SpilledChange::Put(value) => (key, value),
+                            _ => unreachable!(),
+                        }
+                    });
+                }
+                NextSpillValue::Merge { replaced } => {
+                    self.merge_next(replaced);
+                    let (key, _) = self.spilled.peek()?;
+                    return Some((key, &self.merged_value));
+                }
+                NextSpillValue::Delete { replaced } => {
+                    self.skip_base(replaced);
+                    self.spilled.next();
+                }
+                NextSpillValue::Finished => return None,
+            }
+        }
+    }
+}
+
+/// Owned stream of spilled changes for a single view, which loads changes
+/// from the store in chunks.
+pub struct SpilledStream {
+    snapshot: Box<dyn Snapshot>,
+    _store: Arc<SpillStore>,
+    address: ResolvedAddress,
+    is_cleared: bool,
+    buffer: VecDeque<(Vec<u8>, Change)>,
+    /// Key to continue loading changes from, or `None` if all changes are loaded.
+    next_key: Option<Vec<u8>>,
+}
+
+impl fmt::Debug for SpilledStream {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("SpilledStream")
+            .field("address", &self.address)
+            .field("is_cleared", &self.is_cleared)
+            .finish()
+    }
+}
+
+impl SpilledStream {
+    /// Returns `true` if the view was cleared before the spilled changes were made.
+    pub fn is_cleared(&self) -> bool {
+        self.is_cleared
+    }
+
+    fn load_chunk(&mut self) {
+        let from = match self.next_key.take() {
+            Some(key) => key,
+            None => return,
+        };
+        let mut iter = self.snapshot.iter(&self.address, &from);
+        while let Some((key, change)) = iter.next() {
+            let change = SpilledChange::decode(change).to_change();
+            self.buffer.push_back((key.to_vec(), change));
+            if self.buffer.len() == STREAM_BUFFER_SIZE {
+                let mut next_key = key.to_vec();
+                next_key.push(0);
+                self.next_key = Some(next_key);
+                break;
+            }
+        }
+    }
+}
+
+impl StdIterator for SpilledStream {
+    type Item = (Vec<u8>, Change);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buffer.is_empty() {
+            self.load_chunk();
+        }
+        self.buffer.pop_front()
+    }
+}
+
+/// Stream of changes for a view combining older spilled changes with newer in-memory ones.
+pub struct CombinedStream {
+    older: Peekable<SpilledStream>,
+    newer: Peekable<btree_map::IntoIter<Vec<u8>, Change>>,
+    older_cleared: bool,
+}
+
+impl fmt::Debug for CombinedStream {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("CombinedStream")
+            .field("older_cleared", &self.older_cleared)
+            .finish()
+    }
+}
+
+impl CombinedStream {
+    /// Combines `older` spilled changes with `newer` changes. The newer changes
+    /// must not be cleared.
+    pub fn new(older: SpilledStream, newer: ViewChanges) -> Self {
+        debug_assert!(!newer.is_cleared());
+        Self {
+            older_cleared: older.is_cleared(),
+            older: older.peekable(),
+            newer: newer.into_data().into_iter().peekable(),
+        }
+    }
+}
+
+impl StdIterator for CombinedStream {
+    type Item = (Vec<u8>, Change);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        use std::cmp::Ordering::{Equal, Greater, Less};
+
+        let ordering = match (self.older.peek(), self.newer.peek()) {
+            (Some((older_key, _)), Some((newer_key, _))) => older_key.cmp(newer_key),
+            (Some(_), None) => Less,
+            (None, Some(_)) => Greater,
+            (None, None) => return None,
+        };
+
+        let (key, older, newer) = match ordering {
+            Less => return self.older.next(),
+            Greater => {
+                let (key, change) = self.newer.next()?;
+                (key, None, change)
+            }
+            Equal => {
+                let (_, older) = self.older.next()?;
+                let (key, newer) = self.newer.next()?;
+                (key, Some(older), newer)
+            }
+        };
+        let change = match newer {
+            Change::Merge(ref operand) => merge_change(older.as_ref(), self.older_cleared, operand),
+            change => change,
+        };
+        Some((key, change))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::STREAM_BUFFER_SIZE;
+    use crate::{
+        access::CopyAccessExt, DBOptions, Database, DatabaseExt, Fork, Patch, RocksDB, TemporaryDB,
+    };
+
+    use std::collections::HashMap;
+
+    /// Performs the same operations on the fork in several flushed batches.
+    fn fill_fork(fork: &mut Fork) {
+        {
+            let mut map = fork.get_map("map");
+            for i in 0_u32..100 {
+                map.put(&i, i.to_string());
+            }
+            fork.get_counter("counter").increment("a", 1);
+        }
+        fork.flush();
+
+        {
+            let mut map = fork.get_map::<_, u32, String>("map");
+            for i in (0_u32..100).step_by(3) {
+                map.remove(&i);
+            }
+            map.put(&1_000, "thousand".to_owned());
+            let mut counter = fork.get_counter("counter");
+            counter.increment("a", 2);
+            counter.increment("b", 5);
+            fork.get_list("list").extend(0_u64..10);
+        }
+        fork.flush();
+
+        {
+            let mut list = fork.get_list::<_, u64>("list");
+            list.clear();
+            list.push(42);
+            fork.get_counter::<_, str>("counter").decrement("b", 1);
+            fork.get_entry("entry").set("value".to_owned());
+        }
+        fork.flush();
+    }
+
+    fn check_contents(fork: &Fork) {
+        let fork = fork.readonly();
+        let map = fork.get_map::<_, u32, String>("map");
+        assert_eq!(map.get(&1), Some("1".to_owned()));
+        assert_eq!(map.get(&3), None);
+        assert!(map.contains(&1_000));
+        assert!(!map.contains(&99));
+        let keys: Vec<_> = map.keys().collect();
+        let mut expected_keys: Vec<_> = (0_u32..100).filter(|i| i % 3 != 0).collect();
+        expected_keys.push(1_000);
+        assert_eq!(keys, expected_keys);
+        assert_eq!(
+            map.multi_get(vec![&1, &3]),
+            vec![Some("1".to_owned()), None]
+        );
+
+        let counter = fork.get_counter::<_, str>("counter");
+        assert_eq!(counter.get("a"), 3);
+        assert_eq!(
+            counter.iter().collect::<Vec<_>>(),
+            vec![("a".to_owned(), 3), ("b".to_owned(), 4)]
+        );
+
+        let list = fork.get_list::<_, u64>("list");
+        assert_eq!(list.iter().collect::<Vec<_>>(), vec![42]);
+        assert_eq!(fork.get_entry::<_, String>("entry").get().unwrap(), "value");
+    }
+
+    fn check_spilled_changes<DB: Database>(db: &DB) {
+        // Put some data into the database so that spilled changes interact with it.
+        let fork = db.fork();
+        {
+            fork.get_map("map").put(&99_u32, "old".to_owned());
+            fork.get_counter("counter").increment("a", 10);
+            fork.get_list("list").extend(vec![1_u64, 2, 3]);
+        }
+        db.merge(fork.into_patch()).unwrap();
+
+        let mut fork = db.fork();
+        fork.set_memory_limit(0);
+        fill_fork(&mut fork);
+        assert!(fork.has_spilled_changes());
+
+        // Counter increments are applied on top of the database value.
+        fork.get_counter::<_, str>("counter").decrement("a", 10);
+        // Spilled removal overrides the database value.
+        fork.get_map::<_, u32, String>("map").remove(&99);
+        check_contents(&fork);
+
+        db.merge(fork.into_patch()).unwrap();
+        let snapshot = db.snapshot();
+        let fork = Fork::from(Patch::from_changes(snapshot, HashMap::new()));
+        check_contents(&fork);
+    }
+
+    #[test]
+    fn spilled_changes_in_temporary_db() {
+        check_spilled_changes(&TemporaryDB::new());
+    }
+
+    #[test]
+    fn spilled_changes_in_rocksdb() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let db = RocksDB::open(&dir, &DBOptions::default()).unwrap();
+        check_spilled_changes(&db);
+    }
+
+    #[test]
+    fn changes_are_not_spilled_below_limit() {
+        let db = TemporaryDB::new();
+        let mut fork = db.fork();
+        fork.set_memory_limit(1 << 20);
+        fill_fork(&mut fork);
+        assert!(!fork.has_spilled_changes());
+        check_contents(&fork);
+    }
+
+    #[test]
+    fn changes_are_streamed_in_chunks() {
+        let db = TemporaryDB::new();
+        let mut fork = db.fork();
+        fork.set_memory_limit(0);
+        let len = STREAM_BUFFER_SIZE as u64 * 2 + 5;
+        fork.get_list("list").extend(0..len);
+        fork.flush();
+        fork.get_list("list").set(len - 1, 0_u64);
+        db.merge(fork.into_patch()).unwrap();
+
+        let snapshot = db.snapshot();
+        let list = snapshot.get_list::<_, u64>("list");
+        assert_eq!(list.len(), len);
+        assert_eq!(
+            list.get(STREAM_BUFFER_SIZE as u64),
+            Some(STREAM_BUFFER_SIZE as u64)
+        );
+        assert_eq!(list.last(), Some(0));
+    }
+
+    #[test]
+    fn backup_of_spilled_changes() {
+        let db = TemporaryDB::new();
+        let fork = db.fork();
+        fork.get_entry("entry").set(1_u32);
+        db.merge(fork.into_patch()).unwrap();
+
+        let mut fork = db.fork();
+        fork.set_memory_limit(0);
+        fork.get_entry("entry").set(2_u32);
+        fork.flush();
+        let backup = db.merge_with_backup(fork.into_patch()).unwrap();
+        assert_eq!(db.snapshot().get_entry::<_, u32>("entry").get(), Some(2));
+        db.merge(backup).unwrap();
+        assert_eq!(db.snapshot().get_entry::<_, u32>("entry").get(), Some(1));
+    }
+
+    #[test]
+    fn no_spilling_with_active_savepoints() {
+        let db = TemporaryDB::new();
+        let mut fork = db.fork();
+        fork.set_memory_limit(0);
+        let savepoint = fork.savepoint();
+        fork.get_entry("entry").set(1_u32);
+        fork.flush();
+        assert!(!fork.has_spilled_changes());
+        fork.rollback_to(savepoint);
+        assert_eq!(fork.get_entry::<_, u32>("entry").get(), None);
+
+        fork.release(savepoint);
+        fork.get_entry("entry").set(2_u32);
+        fork.flush();
+        assert!(fork.has_spilled_changes());
+        assert_eq!(fork.get_entry::<_, u32>("entry").get(), Some(2));
+    }
+}
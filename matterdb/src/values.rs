@@ -1,9 +1,9 @@
 //! A definition of `BinaryValue` trait and implementations for common types.
 
-use std::{borrow::Cow, io::Read};
+use std::{borrow::Cow, convert::TryFrom, io::Read};
 
-use anyhow::{self, format_err};
-use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
+use anyhow::{self, ensure, format_err};
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
 use chrono::{DateTime, NaiveDateTime, Utc};
 use rust_decimal::Decimal;
 use uuid::Uuid;
@@ -59,6 +59,53 @@ pub trait BinaryValue: Sized {
     fn from_bytes(bytes: Cow<'_, [u8]>) -> anyhow::Result<Self>;
 }
 
+/// A type that can be deserialized from bytes borrowed from the storage without allocation.
+///
+/// The trait is implemented for `&[u8]` and `&str`, which borrow the underlying bytes,
+/// as well as for all `BinaryValue` types, which are decoded as usual. It is used together
+/// with [`PinnedValue`] returned by `get_pinned` methods of indexes.
+///
+/// [`PinnedValue`]: struct.PinnedValue.html
+///
+/// # Examples
+///
+/// ```
+/// use matterdb::{access::CopyAccessExt, TemporaryDB, Database, MapIndex};
+///
+/// let db = TemporaryDB::new();
+/// let fork = db.fork();
+/// fork.get_map("name").put(&1_u8, "value".to_owned());
+/// db.merge(fork.into_patch()).unwrap();
+///
+/// let snapshot = db.snapshot();
+/// let index: MapIndex<_, u8, String> = snapshot.get_map("name");
+/// let value = index.get_pinned(&1).unwrap();
+/// let value: &str = value.decode().unwrap();
+/// assert_eq!(value, "value");
+/// ```
+pub trait BorrowedBinaryValue<'a>: Sized {
+    /// Deserializes the value from the given borrowed bytes.
+    fn from_borrowed_bytes(bytes: &'a [u8]) -> anyhow::Result<Self>;
+}
+
+impl<'a, T: BinaryValue> BorrowedBinaryValue<'a> for T {
+    fn from_borrowed_bytes(bytes: &'a [u8]) -> anyhow::Result<Self> {
+        Self::from_bytes(Cow::Borrowed(bytes))
+    }
+}
+
+impl<'a> BorrowedBinaryValue<'a> for &'a [u8] {
+    fn from_borrowed_bytes(bytes: &'a [u8]) -> anyhow::Result<Self> {
+        Ok(bytes)
+    }
+}
+
+impl<'a> BorrowedBinaryValue<'a> for &'a str {
+    fn from_borrowed_bytes(bytes: &'a [u8]) -> anyhow::Result<Self> {
+        std::str::from_utf8(bytes).map_err(From::from)
+    }
+}
+
 macro_rules! impl_binary_value_scalar {
     ($type:tt, $read:ident) => {
         #[allow(clippy::use_self)]
@@ -199,6 +246,27 @@ impl BinaryValue for Decimal {
     }
 }
 
+/// Writes a byte sequence prefixed with its length. Used in the binary encoding
+/// of composite values.
+///
+/// # Panics
+///
+/// Panics if the sequence is longer than `u32::MAX` bytes.
+pub fn write_bytes(buffer: &mut Vec<u8>, bytes: &[u8]) {
+    let len = u32::try_from(bytes.len()).expect("MerkleDB error: byte sequence is too long");
+    buffer.write_u32::<LittleEndian>(len).unwrap();
+    buffer.extend_from_slice(bytes);
+}
+
+/// Reads a byte sequence written with `write_bytes()`, advancing `bytes` past it.
+pub fn read_bytes(bytes: &mut &[u8]) -> anyhow::Result<Vec<u8>> {
+    let len = bytes.read_u32::<LittleEndian>()? as usize;
+    ensure!(bytes.len() >= len, "Unexpected end of byte sequence");
+    let mut buffer = vec![0; len];
+    bytes.read_exact(&mut buffer)?;
+    Ok(buffer)
+}
+
 #[cfg(test)]
 mod tests {
     use std::fmt::Debug;
@@ -1,3 +1,5 @@
+use serde::{Deserialize, Serialize};
+
 use std::{borrow::Cow, num::NonZeroU64};
 
 use crate::BinaryKey;
@@ -249,7 +251,7 @@ impl<'a, K: BinaryKey + ?Sized> From<(&'a str, &'a K)> for IndexAddress {
 /// and `ResolvedAddress`es is internal to the database logic.
 ///
 /// [`IndexAddress`]: struct.IndexAddress.html
-#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ResolvedAddress {
     /// Name of the column family where the view is stored.
     pub name: String,
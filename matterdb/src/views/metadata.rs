@@ -2,17 +2,28 @@ use anyhow::{ensure, format_err};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use serde::{Deserialize, Serialize};
 
-use std::{borrow::Cow, convert::TryFrom, io::Error, mem, num::NonZeroU64, vec};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    convert::TryFrom,
+    io::Error,
+    mem,
+    num::NonZeroU64,
+    ops::{Bound, RangeBounds},
+    vec,
+};
 
 use crate::{
     access::{AccessError, AccessErrorKind},
     validation::check_index_valid_full_name,
-    views::{IndexAddress, RawAccess, RawAccessMut, ResolvedAddress, View},
+    views::{
+        address::key_bytes, ChangeSet, IndexAddress, RawAccess, RawAccessMut, ResolvedAddress, View,
+    },
     BinaryKey, BinaryValue,
 };
 
 /// Name of the column family used to store `IndexesPool`.
-const INDEXES_POOL_NAME: &str = "__INDEXES_POOL__";
+pub(super) const INDEXES_POOL_NAME: &str = "__INDEXES_POOL__";
 
 /// Type of an index supported by `MatterDB`.
 ///
@@ -31,6 +42,14 @@ pub enum IndexType {
     KeySet = 5,
     /// Sparse list index.
     SparseList = 6,
+    /// Map of commutative 64-bit counters updated via merge operands.
+    Counter = 7,
+    /// Set of 64-bit integers stored as a roaring bitmap.
+    Bitmap = 8,
+    /// Large binary value split into fixed-size chunks.
+    Blob = 9,
+    /// Map with multiple values per key.
+    MultiMap = 10,
 
     /// Tombstone indicating necessity to remove an index after migration is completed.
     Tombstone = 254,
@@ -49,6 +68,10 @@ impl TryFrom<u32> for IndexType {
             3 => Self::Entry,
             5 => Self::KeySet,
             6 => Self::SparseList,
+            7 => Self::Counter,
+            8 => Self::Bitmap,
+            9 => Self::Blob,
+            10 => Self::MultiMap,
             254 => Self::Tombstone,
             255 => Self::Unknown,
             _ => return Err("Unknown index type"),
@@ -288,8 +311,39 @@ impl<T: RawAccess> IndexesPool<T> {
         self.0.get(&()).unwrap_or_default()
     }
 
+    /// Returns metadata for the index. If the metadata is not affected by the changes
+    /// in the access, it is retrieved via the metadata cache of the snapshot, if any.
     fn index_metadata(&self, index_name: &[u8]) -> Option<IndexMetadata> {
-        self.0.get(index_name)
+        let inner = match &self.0 {
+            View::Real(inner) => inner,
+            View::Phantom => return None,
+        };
+        let change = inner
+            .changes
+            .as_ref()
+            .map_or(Err(None), |changes| changes.get_ref(index_name));
+        let decode = |bytes: &[u8]| {
+            IndexMetadata::from_bytes(bytes.into()).expect("Error while deserializing value")
+        };
+
+        let snapshot = inner.snapshot();
+        match change {
+            Ok(value) => value.map(decode),
+            Err(None) => {
+                let load = || {
+                    snapshot
+                        .get(&inner.address, index_name)
+                        .as_deref()
+                        .map(decode)
+                };
+                match snapshot.metadata_cache() {
+                    Some(cache) => cache.get_or_insert_with(index_name, load),
+                    None => load(),
+                }
+            }
+            // Merge operands are never used in the pool.
+            Err(_) => self.0.get(index_name),
+        }
     }
 
     fn set_len(&mut self, len: u64) {
@@ -318,9 +372,103 @@ impl<T: RawAccess> IndexesPool<T> {
         self.set_len(len + 1);
         (metadata, is_phantom)
     }
+
+    /// Lists indexes in the migration with the specified namespace without modifying the pool.
+    ///
+    /// # Return value
+    ///
+    /// For each index in the migration, returns the address the index will have after
+    /// the migration is flushed, the metadata of the migrated index, and the metadata
+    /// of the index it will replace (if any).
+    pub(crate) fn migrated_indexes(
+        &self,
+        namespace: &str,
+    ) -> Vec<(IndexAddress, IndexMetadata, Option<IndexMetadata>)> {
+        let prefix = IndexAddress::qualify_migration_namespace(namespace);
+        // See `flush_migration` for the explanation.
+        let min_name_len = prefix.len() - 1;
+
+        self.0
+            .iter::<_, Vec<u8>, IndexMetadata>(&prefix)
+            .map(|(key, metadata)| {
+                let migrated_key = IndexAddress::migrate_qualified_name(&key);
+                let (name, is_in_group) =
+                    IndexAddress::parse_fully_qualified_name(migrated_key, min_name_len);
+                let old_metadata = self.index_metadata(migrated_key);
+
+                let address = if is_in_group {
+                    // Skip the name and the separator char.
+                    let id_in_group = &migrated_key[(name.len() + 1)..];
+                    IndexAddress::from_root(name).append_key(id_in_group)
+                } else {
+                    IndexAddress::from_root(name)
+                };
+                (address, metadata, old_metadata)
+            })
+            .collect()
+    }
+
+    /// Returns resolved addresses of all indexes in the pool, including indexes in migrations.
+    pub(crate) fn resolved_addresses(&self) -> Vec<ResolvedAddress> {
+        self.0
+            .iter::<_, Vec<u8>, Vec<u8>>(&())
+            // The empty key corresponds to the pool length.
+            .filter(|(key, _)| !key.is_empty())
+            .map(|(key, metadata)| {
+                let metadata = IndexMetadata::<Vec<u8>>::from_bytes(metadata.into())
+                    .expect("Error while deserializing value");
+                let (name, _) = IndexAddress::parse_fully_qualified_name(&key, 0);
+                ResolvedAddress::new(name, Some(metadata.identifier))
+            })
+            .collect()
+    }
+
+    /// Returns addresses of indexes in the specified namespace (i.e., with the name part
+    /// starting with `namespace.`), keyed by the index identifier. Indexes in migrations
+    /// are not included.
+    pub(crate) fn index_addresses(&self, namespace: &str) -> HashMap<NonZeroU64, IndexAddress> {
+        let prefix = [namespace, "."].concat().into_bytes();
+        self.0
+            .iter::<_, Vec<u8>, IndexMetadata>(&prefix)
+            .map(|(key, metadata)| {
+                let (name, is_in_group) =
+                    IndexAddress::parse_fully_qualified_name(&key, prefix.len());
+                let address = if is_in_group {
+                    let id_in_group = &key[(name.len() + 1)..];
+                    IndexAddress::from_root(name).append_key(id_in_group)
+                } else {
+                    IndexAddress::from_root(name)
+                };
+                (metadata.identifier, address)
+            })
+            .collect()
+    }
 }
 
 impl<T: RawAccessMut> IndexesPool<T> {
+    /// Reserves an identifier for an index without creating the index metadata.
+    pub(crate) fn reserve_identifier(&mut self) -> NonZeroU64 {
+        let len = self.len();
+        self.set_len(len + 1);
+        NonZeroU64::new(len + 1).unwrap()
+    }
+
+    /// Creates metadata for an index with a previously reserved identifier.
+    pub(crate) fn insert_index_metadata<V: BinaryAttribute>(
+        &mut self,
+        addr: &IndexAddress,
+        identifier: NonZeroU64,
+        index_type: IndexType,
+        state: Option<V>,
+    ) {
+        let metadata = IndexMetadata {
+            identifier,
+            index_type,
+            state,
+        };
+        self.0.put(&addr.fully_qualified_name()[..], metadata);
+    }
+
     /// Moves indexes with the specified prefix from the next version (i.e., `^prefix.*` form)
     /// to the current version (`prefix.*` form). The existing old indexes are replaced, or
     /// removed if the new index is a `Tombstone`. If there is no overriding index, an old
@@ -365,6 +513,27 @@ impl<T: RawAccessMut> IndexesPool<T> {
         removed_addrs
     }
 
+    /// Reverts `flush_migration` for a single index: restores the old index metadata (or removes
+    /// the index if it did not exist before the flush) and moves the flushed index back
+    /// to the migration.
+    pub(crate) fn unflush_index(
+        &mut self,
+        addr: &IndexAddress,
+        old_metadata: Option<IndexMetadata>,
+        new_metadata: IndexMetadata,
+    ) {
+        let full_name = addr.fully_qualified_name();
+        match old_metadata {
+            Some(metadata) => self.0.put(&full_name[..], metadata),
+            None => self.0.remove(&full_name[..]),
+        }
+
+        let mut migrated_addr = addr.clone();
+        migrated_addr.set_in_migration();
+        self.0
+            .put(&migrated_addr.fully_qualified_name()[..], new_metadata);
+    }
+
     pub(crate) fn rollback_migration(&mut self, prefix: &str) -> Vec<ResolvedAddress> {
         let prefix = IndexAddress::qualify_migration_namespace(prefix);
         self.remove_by_prefix(&prefix, |key| {
@@ -380,10 +549,23 @@ impl<T: RawAccessMut> IndexesPool<T> {
     /// Returns resolved addresses of the removed indexes.
     pub(crate) fn remove_indexes(&mut self, prefix: &IndexAddress) -> Vec<ResolvedAddress> {
         let name = prefix.name();
-        let prefix = prefix.fully_qualified_name();
+        let prefix = prefix.qualified_prefix();
         self.remove_by_prefix(&prefix, |_| name.to_owned())
     }
 
+    /// Removes the index with the specified address. Unlike `remove_indexes`, indexes
+    /// with addresses starting with `addr` are left intact.
+    ///
+    /// # Return value
+    ///
+    /// Returns the resolved address of the removed index, or `None` if the index does not exist.
+    pub(crate) fn remove_index(&mut self, addr: &IndexAddress) -> Option<ResolvedAddress> {
+        let full_name = addr.fully_qualified_name();
+        let metadata = self.index_metadata(&full_name)?;
+        self.0.remove(&full_name);
+        Some(ResolvedAddress::new(addr.name(), Some(metadata.identifier)))
+    }
+
     /// Removes views with the full name starting with the specified prefix. The `extract_name`
     /// argument provides a way to map from a full name to the name of the column family
     /// where the view is stored.
@@ -411,6 +593,7 @@ impl<T: RawAccessMut> IndexesPool<T> {
 pub struct GroupKeys<T: RawAccess, K: BinaryKey + ?Sized> {
     access: T,
     key_prefix: Vec<u8>,
+    end_key: Bound<Vec<u8>>,
     next_key: Option<Vec<u8>>,
     buffered_keys: vec::IntoIter<K::Owned>,
     buffer_size: usize,
@@ -421,33 +604,85 @@ where
     T: RawAccess,
     K: BinaryKey + ?Sized,
 {
+    const DEFAULT_BUFFER_SIZE: usize = 1_000;
+
     pub fn new(access: T, addr: &IndexAddress) -> Self {
-        const DEFAULT_BUFFER_SIZE: usize = 1_000;
-        Self::with_custom_buffer(access, addr, DEFAULT_BUFFER_SIZE)
+        Self::with_custom_buffer(access, addr, Self::DEFAULT_BUFFER_SIZE)
+    }
+
+    /// Creates an iterator over keys of the group that fall into the specified `range`.
+    pub fn with_range<R>(access: T, addr: &IndexAddress, range: R) -> Self
+    where
+        R: RangeBounds<K>,
+    {
+        Self::with_range_and_buffer(access, addr, range, Self::DEFAULT_BUFFER_SIZE)
     }
 
     fn with_custom_buffer(access: T, addr: &IndexAddress, buffer_size: usize) -> Self {
+        Self::with_range_and_buffer(access, addr, .., buffer_size)
+    }
+
+    fn with_range_and_buffer<R>(
+        access: T,
+        addr: &IndexAddress,
+        range: R,
+        buffer_size: usize,
+    ) -> Self
+    where
+        R: RangeBounds<K>,
+    {
         assert!(buffer_size > 0);
 
         let key_prefix = addr.qualified_prefix();
+        let prefixed_key = |key: &K| {
+            let mut buffer = key_prefix.clone();
+            buffer.extend_from_slice(&key_bytes(key));
+            buffer
+        };
+
+        let start_key = match range.start_bound() {
+            Bound::Included(key) => prefixed_key(key),
+            Bound::Excluded(key) => {
+                // The least byte sequence greater than the key.
+                let mut start_key = prefixed_key(key);
+                start_key.push(0);
+                start_key
+            }
+            Bound::Unbounded => key_prefix.clone(),
+        };
+        let end_key = match range.end_bound() {
+            Bound::Included(key) => Bound::Included(prefixed_key(key)),
+            Bound::Excluded(key) => Bound::Excluded(prefixed_key(key)),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+
         let mut this = Self {
             access,
-            key_prefix: key_prefix.clone(),
+            key_prefix,
+            end_key,
             next_key: None,
             buffered_keys: Vec::new().into_iter(),
             buffer_size,
         };
-        this.buffer_keys(&key_prefix);
+        this.buffer_keys(&start_key);
         this
     }
 
+    fn is_past_end(&self, key: &[u8]) -> bool {
+        match &self.end_key {
+            Bound::Included(end_key) => key > end_key.as_slice(),
+            Bound::Excluded(end_key) => key >= end_key.as_slice(),
+            Bound::Unbounded => false,
+        }
+    }
+
     fn buffer_keys(&mut self, start_key: &[u8]) {
         let indexes_pool = IndexesPool::new(self.access.clone());
         let mut buffer = Vec::with_capacity(self.buffer_size);
 
         let mut iter = indexes_pool.0.iter_bytes(start_key);
         while let Some((key, _)) = iter.next() {
-            if !key.starts_with(&self.key_prefix) {
+            if !key.starts_with(&self.key_prefix) || self.is_past_end(key) {
                 // We've run out of keys.
                 break;
             } else if buffer.len() == self.buffer_size {
@@ -609,6 +844,27 @@ where
     }
 }
 
+impl<T: RawAccessMut> ViewWithMetadata<T> {
+    /// Removes an index with the specified address together with its data. Unlike `remove`
+    /// methods in `Access`, this method does not check if the name of the index is reserved.
+    pub(crate) fn remove_unchecked(index_access: T, index_address: &IndexAddress) {
+        let removed = IndexesPool::new(index_access.clone()).remove_index(index_address);
+        if let Some(resolved_addr) = removed {
+            View::new(index_access, resolved_addr).clear();
+        }
+    }
+
+    /// Removes all indexes in the group with the specified address together with their data.
+    /// Unlike `remove` methods in `Access`, this method does not check if the name
+    /// of the group is reserved.
+    pub(crate) fn remove_group_unchecked(index_access: &T, group_address: &IndexAddress) {
+        let removed = IndexesPool::new(index_access.clone()).remove_indexes(group_address);
+        for resolved_addr in removed {
+            View::new(index_access.clone(), resolved_addr).clear();
+        }
+    }
+}
+
 impl<T: RawAccess> From<ViewWithMetadata<T>> for View<T> {
     fn from(view_with_metadata: ViewWithMetadata<T>) -> Self {
         view_with_metadata.view
@@ -0,0 +1,301 @@
+//! Caching of index metadata.
+//!
+//! Each index access resolves the index address via `IndexesPool`, which requires
+//! a lookup in the underlying storage. Metadata caches allow to skip these lookups
+//! for indexes that were already accessed.
+//!
+//! There are two kinds of caches:
+//!
+//! - Database backends maintain a [`SharedMetadataCache`], which is shared among snapshots
+//!   of the same database generation and is replaced on each merge.
+//! - Each `Fork` has its own cache, which is invalidated on flushing changes to the indexes
+//!   pool and on rolling back to a savepoint.
+//!
+//! [`SharedMetadataCache`]: struct.SharedMetadataCache.html
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+};
+
+use super::{metadata::INDEXES_POOL_NAME, IndexMetadata, ResolvedAddress};
+use crate::db::ViewChanges;
+
+/// Maximum number of entries in a cache. Once this number is reached, the cache is cleared.
+const MAX_ENTRIES: usize = 65_536;
+
+/// Cache of index metadata keyed by the fully qualified index name.
+///
+/// The cache also stores the absence of metadata, so that repeated accesses to a non-existing
+/// index do not hit the storage either. The cache does not grow beyond a fixed number
+/// of entries; once the limit is reached, the cache is cleared.
+#[derive(Default)]
+pub struct MetadataCache {
+    entries: RwLock<HashMap<Vec<u8>, Option<IndexMetadata>>>,
+    /// Whether any migration logs changes to its old data.
+    logs_live_changes: RwLock<Option<bool>>,
+}
+
+impl fmt::Debug for MetadataCache {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("MetadataCache")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+impl MetadataCache {
+    /// Creates an empty cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of cached entries.
+    pub fn len(&self) -> usize {
+        self.entries.read().expect("Couldn't get read lock").len()
+    }
+
+    /// Checks whether the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the cached metadata for the index, or loads it using the provided closure
+    /// and caches the result.
+    pub fn get_or_insert_with(
+        &self,
+        index_name: &[u8],
+        load: impl FnOnce() -> Option<IndexMetadata>,
+    ) -> Option<IndexMetadata> {
+        if let Some(metadata) = self
+            .entries
+            .read()
+            .expect("Couldn't get read lock")
+            .get(index_name)
+        {
+            return metadata.clone();
+        }
+
+        let metadata = load();
+        let mut entries = self.entries.write().expect("Couldn't get write lock");
+        if entries.len() >= MAX_ENTRIES {
+            entries.clear();
+        }
+        entries.insert(index_name.to_vec(), metadata.clone());
+        metadata
+    }
+
+    /// Returns the cached flag indicating whether any migration logs changes to its old data,
+    /// or loads it using the provided closure and caches the result.
+    pub fn logs_live_changes(&self, load: impl FnOnce() -> bool) -> bool {
+        if let Some(flag) = *self
+            .logs_live_changes
+            .read()
+            .expect("Couldn't get read lock")
+        {
+            return flag;
+        }
+
+        let flag = load();
+        *self
+            .logs_live_changes
+            .write()
+            .expect("Couldn't get write lock") = Some(flag);
+        flag
+    }
+
+    /// Removes cached entries affected by the changes of the view with the specified address.
+    #[doc(hidden)]
+    pub fn invalidate(&self, address: &ResolvedAddress, changes: &ViewChanges) {
+        if !is_indexes_pool(address) {
+            return;
+        }
+
+        let mut entries = self.entries.write().expect("Couldn't get write lock");
+        if changes.is_cleared() {
+            entries.clear();
+        } else {
+            for key in changes.data.keys() {
+                entries.remove(key);
+            }
+        }
+    }
+
+    /// Removes all cached entries.
+    pub fn clear(&self) {
+        self.entries
+            .write()
+            .expect("Couldn't get write lock")
+            .clear();
+        *self
+            .logs_live_changes
+            .write()
+            .expect("Couldn't get write lock") = None;
+    }
+}
+
+/// Checks whether the address corresponds to the indexes pool.
+pub fn is_indexes_pool(address: &ResolvedAddress) -> bool {
+    address.id.is_none() && address.name == INDEXES_POOL_NAME
+}
+
+/// Metadata cache shared by snapshots of the same generation of a database.
+///
+/// The generation is incremented on each merge. A snapshot receives the cache only if it
+/// was created outside of a merge and the generation did not change during its creation;
+/// otherwise, it is not known whether the snapshot reflects the merged changes.
+#[derive(Debug, Default)]
+pub struct SharedMetadataCache {
+    current: Mutex<Arc<MetadataCache>>,
+    generation: AtomicU64,
+    merges_in_progress: AtomicUsize,
+}
+
+impl SharedMetadataCache {
+    /// Creates a new shared cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Signals that the database is about to be modified. The current cache is replaced
+    /// with an empty one; until the returned guard is dropped, newly created snapshots
+    /// do not use caching.
+    pub fn start_merge(&self) -> MergeGuard<'_> {
+        self.merges_in_progress.fetch_add(1, Ordering::SeqCst);
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        *self.current.lock().expect("Couldn't lock metadata cache") = Arc::default();
+        MergeGuard { cache: self }
+    }
+
+    /// Creates a snapshot using the provided closure and returns it together with
+    /// the cache for the snapshot, if the cache can be used.
+    pub fn snapshot<S>(
+        &self,
+        create_snapshot: impl FnOnce() -> S,
+    ) -> (S, Option<Arc<MetadataCache>>) {
+        let generation = self.generation.load(Ordering::SeqCst);
+        let is_merging = self.merges_in_progress.load(Ordering::SeqCst) > 0;
+        let snapshot = create_snapshot();
+        let cache = Arc::clone(&self.current.lock().expect("Couldn't lock metadata cache"));
+
+        let is_consistent = !is_merging && self.generation.load(Ordering::SeqCst) == generation;
+        (snapshot, if is_consistent { Some(cache) } else { None })
+    }
+}
+
+/// Guard returned by [`SharedMetadataCache::start_merge`]. The merge is considered finished
+/// when the guard is dropped.
+///
+/// [`SharedMetadataCache::start_merge`]: struct.SharedMetadataCache.html#method.start_merge
+#[derive(Debug)]
+pub struct MergeGuard<'a> {
+    cache: &'a SharedMetadataCache,
+}
+
+impl Drop for MergeGuard<'_> {
+    fn drop(&mut self) {
+        self.cache.merges_in_progress.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{access::CopyAccessExt, Database, IndexType, Snapshot, TemporaryDB};
+
+    fn cache_ptr(snapshot: &dyn Snapshot) -> *const MetadataCache {
+        snapshot.metadata_cache().unwrap()
+    }
+
+    #[test]
+    fn snapshot_caches_are_shared_within_generation() {
+        let db = TemporaryDB::new();
+        let fork = db.fork();
+        fork.get_list("list").extend(vec![1_u32, 2, 3]);
+        db.merge(fork.into_patch()).unwrap();
+
+        let snapshot = db.snapshot();
+        assert_eq!(snapshot.get_list::<_, u32>("list").len(), 3);
+        assert!(snapshot.index_type("other").is_none());
+        assert_eq!(snapshot.metadata_cache().unwrap().len(), 2);
+        let other_snapshot = db.snapshot();
+        assert_eq!(cache_ptr(&*snapshot), cache_ptr(&*other_snapshot));
+
+        // Merging the changes replaces the cache.
+        let fork = db.fork();
+        fork.get_list("list").push(4_u32);
+        fork.get_entry("other").set(1_u8);
+        db.merge(fork.into_patch()).unwrap();
+        let new_snapshot = db.snapshot();
+        assert_ne!(cache_ptr(&*snapshot), cache_ptr(&*new_snapshot));
+        assert_eq!(new_snapshot.get_list::<_, u32>("list").len(), 4);
+        assert_eq!(new_snapshot.index_type("other"), Some(IndexType::Entry));
+        // Old snapshots retain their view of the data.
+        assert_eq!(snapshot.get_list::<_, u32>("list").len(), 3);
+        assert!(snapshot.index_type("other").is_none());
+    }
+
+    #[test]
+    fn snapshots_during_merge_are_not_cached() {
+        let shared = SharedMetadataCache::new();
+        let ((), cache) = shared.snapshot(|| ());
+        assert!(cache.is_some());
+        let guard = shared.start_merge();
+        let ((), cache) = shared.snapshot(|| ());
+        assert!(cache.is_none());
+        drop(guard);
+        let ((), cache) = shared.snapshot(|| ());
+        assert!(cache.is_some());
+
+        // Merge starting during snapshot creation.
+        let (_, cache) = shared.snapshot(|| shared.start_merge());
+        assert!(cache.is_none());
+    }
+
+    #[test]
+    fn fork_cache_invalidation() {
+        let db = TemporaryDB::new();
+        let mut fork = db.fork();
+        assert!(fork.index_type("list").is_none());
+        fork.get_list("list").push(1_u32);
+        assert_eq!(fork.index_type("list"), Some(IndexType::List));
+        fork.flush();
+        assert_eq!(fork.get_list::<_, u32>("list").len(), 1);
+
+        let savepoint = fork.savepoint();
+        fork.get_list("list").push(2_u32);
+        fork.get_entry("entry").set(1_u8);
+        fork.flush();
+        assert_eq!(fork.get_list::<_, u32>("list").len(), 2);
+        assert_eq!(fork.index_type("entry"), Some(IndexType::Entry));
+
+        fork.rollback_to(savepoint);
+        assert_eq!(fork.get_list::<_, u32>("list").len(), 1);
+        assert!(fork.index_type("entry").is_none());
+
+        db.merge(fork.into_patch()).unwrap();
+        let snapshot = db.snapshot();
+        assert_eq!(snapshot.get_list::<_, u32>("list").len(), 1);
+        assert!(snapshot.index_type("entry").is_none());
+    }
+
+    #[test]
+    fn caching_can_be_disabled() {
+        let db = TemporaryDB::new().without_metadata_cache();
+        let fork = db.fork();
+        fork.get_list("list").extend(vec![1_u32, 2, 3]);
+        assert_eq!(fork.get_list::<_, u32>("list").len(), 3);
+        let patch = fork.into_patch();
+        assert!(patch.metadata_cache().is_none());
+        db.merge(patch).unwrap();
+
+        let snapshot = db.snapshot();
+        assert_eq!(snapshot.get_list::<_, u32>("list").len(), 3);
+        assert!(snapshot.metadata_cache().is_none());
+    }
+}
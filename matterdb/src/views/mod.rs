@@ -1,9 +1,10 @@
 pub use self::{
-    address::{IndexAddress, ResolvedAddress},
+    address::{key_bytes, IndexAddress, ResolvedAddress},
     metadata::{
         BinaryAttribute, GroupKeys, IndexMetadata, IndexState, IndexType, IndexesPool,
         ViewWithMetadata,
     },
+    metadata_cache::{MetadataCache, SharedMetadataCache},
 };
 
 use std::{
@@ -14,13 +15,13 @@ use std::{
 };
 
 use crate::{
-    db::{Change, ChangesMut, ChangesRef, ForkIter, ViewChanges},
-    views::address::key_bytes,
-    BinaryKey, BinaryValue, Iter as BytesIter, Iterator as BytesIterator, Snapshot,
+    db::{resolve_merge, Change, ChangesMut, ChangesRef, ForkIter, ViewChanges},
+    BinaryKey, BinaryValue, Iter as BytesIter, Iterator as BytesIterator, PinnedValue, Snapshot,
 };
 
 mod address;
 mod metadata;
+mod metadata_cache;
 #[cfg(test)]
 mod tests;
 
@@ -177,9 +178,24 @@ impl<T: RawAccess> ViewInner<T> {
     fn get_bytes(&self, key: &[u8]) -> Option<Vec<u8>> {
         self.changes
             .as_ref()
-            .map_or(Err(()), |changes| changes.get(key))
+            .map_or(Err(None), |changes| changes.get(key))
             // At this point, `Err(_)` signifies that we need to retrieve data from the snapshot.
-            .unwrap_or_else(|()| self.snapshot().get(&self.address, key))
+            .unwrap_or_else(|operand| {
+                resolve_merge(self.snapshot().get(&self.address, key), operand)
+            })
+    }
+
+    fn get_pinned(&self, key: &[u8]) -> Option<PinnedValue<'_>> {
+        let value = self
+            .changes
+            .as_ref()
+            .map_or(Err(None), |changes| changes.get_ref(key));
+        match value {
+            Ok(value) => value.map(PinnedValue::borrowed),
+            Err(None) => self.snapshot().get_pinned(&self.address, key),
+            Err(operand) => resolve_merge(self.snapshot().get(&self.address, key), operand)
+                .map(PinnedValue::owned),
+        }
     }
 
     fn multi_get_bytes<I>(&self, keys: I) -> Vec<Option<Vec<u8>>>
@@ -191,14 +207,12 @@ impl<T: RawAccess> ViewInner<T> {
         let (mut res, db_keys) = keys.into_iter().enumerate().fold(
             (Vec::new(), Vec::new()),
             |(mut res, mut db_keys), (idx, key)| {
-                if let Ok(item) = changes
-                    .and_then(|changes| changes.get(&key).transpose())
-                    .transpose()
-                {
-                    res.push(item);
-                } else {
-                    res.push(None);
-                    db_keys.push((idx, key));
+                match changes.map_or(Err(None), |changes| changes.get(&key)) {
+                    Ok(item) => res.push(item),
+                    Err(operand) => {
+                        res.push(None);
+                        db_keys.push((idx, key, operand));
+                    }
                 }
 
                 (res, db_keys)
@@ -207,11 +221,11 @@ impl<T: RawAccess> ViewInner<T> {
 
         let db_res = self.snapshot().multi_get(
             &self.address,
-            &mut db_keys.iter().map(|(_, key)| key.as_ref()),
+            &mut db_keys.iter().map(|(_, key, _)| key.as_ref()),
         );
 
-        for ((idx, _), item) in db_keys.into_iter().zip(db_res) {
-            res[idx] = item;
+        for ((idx, _, operand), item) in db_keys.into_iter().zip(db_res) {
+            res[idx] = resolve_merge(item, operand);
         }
 
         res
@@ -272,6 +286,14 @@ impl<T: RawAccess> View<T> {
         }
     }
 
+    /// Returns the resolved address of this view. If this view is phantom, returns `None`.
+    pub(crate) fn address(&self) -> Option<&ResolvedAddress> {
+        match self {
+            Self::Real(ViewInner { address, .. }) => Some(address),
+            Self::Phantom => None,
+        }
+    }
+
     fn get_bytes(&self, key: &[u8]) -> Option<Vec<u8>> {
         match self {
             Self::Real(inner) => inner.get_bytes(key),
@@ -303,6 +325,50 @@ impl<T: RawAccess> View<T> {
         }
     }
 
+    /// Returns a raw value corresponding to the key of *any* type without copying it, if possible.
+    pub fn get_pinned<K>(&self, key: &K) -> Option<PinnedValue<'_>>
+    where
+        K: BinaryKey + ?Sized,
+    {
+        match self {
+            Self::Real(inner) => inner.get_pinned(&key_bytes(key)),
+            Self::Phantom => None,
+        }
+    }
+
+    /// Returns a value of *any* type corresponding to the key of *any* type, using the value
+    /// cache of the snapshot if caching is enabled for the view with the specified index type.
+    pub fn get_cached<K, V>(&self, key: &K, index_type: IndexType) -> Option<V>
+    where
+        K: BinaryKey + ?Sized,
+        V: BinaryValue + Clone + Send + Sync + 'static,
+    {
+        let inner = match self {
+            Self::Real(inner) => inner,
+            Self::Phantom => return None,
+        };
+        let key = key_bytes(key);
+        let change = inner
+            .changes
+            .as_ref()
+            .map_or(Err(None), |changes| changes.get_ref(&key));
+        let decode =
+            |bytes: &[u8]| V::from_bytes(bytes.into()).expect("Error while deserializing value");
+
+        match change {
+            Ok(value) => value.map(decode),
+            Err(None) => {
+                let snapshot = inner.snapshot();
+                let load = || snapshot.get(&inner.address, &key).as_deref().map(decode);
+                match snapshot.value_cache() {
+                    Some(cache) => cache.get_or_insert_with(&inner.address, index_type, &key, load),
+                    None => load(),
+                }
+            }
+            Err(_) => inner.get_bytes(&key).as_deref().map(decode),
+        }
+    }
+
     /// Returns a value of *any* type corresponding to the key of *any* type.
     pub fn get<K, V>(&self, key: &K) -> Option<V>
     where
@@ -438,6 +504,17 @@ impl<T: RawAccessMut> View<T> {
             .insert(concat_keys!(key), Change::Delete);
     }
 
+    /// Merges an operand into the value for the specified key. See `Change::Merge`
+    /// for details.
+    pub fn merge<K, V>(&mut self, key: &K, operand: V)
+    where
+        K: BinaryKey + ?Sized,
+        V: BinaryValue,
+    {
+        self.changes_mut()
+            .merge(concat_keys!(key), &operand.into_bytes());
+    }
+
     /// Clears the view removing all its elements.
     pub fn clear(&mut self) {
         self.changes_mut().clear();
@@ -486,6 +563,9 @@ where
                     return Some((key.as_slice(), value.as_slice()));
                 }
                 Some((_, &Change::Delete)) => {}
+                Some((_, &Change::Merge(..))) => {
+                    unreachable!("Merge operands are always resolved in cleared views")
+                }
                 None => {
                     return None;
                 }
@@ -504,6 +584,9 @@ where
                     // we can safely drop it.
                     self.inner.next();
                 }
+                Some((_, Change::Merge(..))) => {
+                    unreachable!("Merge operands are always resolved in cleared views")
+                }
                 None => {
                     return None;
                 }
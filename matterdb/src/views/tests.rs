@@ -165,6 +165,48 @@ fn _views_in_same_family<T: Database>(db: &T) {
     assert_iter(&view2, 0, &[(0, 0), (1, 2), (2, 4)]);
 }
 
+fn test_pinned_values<T, I>(db: &T, address: I)
+where
+    T: Database,
+    I: Into<ResolvedAddress> + Copy,
+{
+    let fork = db.fork();
+    {
+        let mut view = View::new(&fork, address);
+        view.put(&1_u8, b"one".to_vec());
+        view.put(&2_u8, b"two".to_vec());
+        view.merge(&3_u8, 5_i64);
+    }
+    db.merge(fork.into_patch()).unwrap();
+
+    let snapshot = db.snapshot();
+    let view = View::new(&snapshot, address);
+    let value = view.get_pinned(&1_u8).unwrap();
+    assert_eq!(value.decode::<&str>().unwrap(), "one");
+    assert_eq!(value.decode::<String>().unwrap(), "one");
+    assert!(view.get_pinned(&4_u8).is_none());
+
+    let fork = db.fork();
+    {
+        let mut view = View::new(&fork, address);
+        view.put(&1_u8, b"uno".to_vec());
+        view.remove(&2_u8);
+        view.merge(&3_u8, 2_i64);
+        assert_eq!(&*view.get_pinned(&1_u8).unwrap(), b"uno");
+        assert!(view.get_pinned(&2_u8).is_none());
+        let value = view.get_pinned(&3_u8).unwrap();
+        assert_eq!(value.decode::<i64>().unwrap(), 7);
+    }
+
+    let patch = fork.into_patch();
+    let view = View::new(&patch, address);
+    assert_eq!(view.get_pinned(&1_u8).unwrap().into_vec(), b"uno");
+    assert!(view.get_pinned(&2_u8).is_none());
+    assert_eq!(view.get::<_, i64>(&3_u8), Some(7));
+    let value = view.get_pinned(&3_u8).unwrap();
+    assert_eq!(value.decode::<i64>().unwrap(), 7);
+}
+
 fn test_two_mutable_borrows<T, I>(db: &T, address: I)
 where
     T: Database,
@@ -398,6 +440,16 @@ fn changelog_prefixed() {
     test_changelog(&TemporaryDB::new(), PREFIXED_IDX);
 }
 
+#[test]
+fn pinned_values() {
+    test_pinned_values(&TemporaryDB::new(), IDX_NAME);
+}
+
+#[test]
+fn pinned_values_prefixed() {
+    test_pinned_values(&TemporaryDB::new(), PREFIXED_IDX);
+}
+
 #[test]
 fn multiple_views() {
     let db = TemporaryDB::new();